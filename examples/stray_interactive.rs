@@ -1,152 +1,150 @@
-/// Interactive example for stray - allows sending menu commands
+/// Interactive example for the stray host - browse item menus and click entries
 use anyhow::Result;
-use stray::{SystemTray, message::{NotifierItemMessage, NotifierItemCommand}};
-use tokio_stream::StreamExt;
+use system_tray_linux_aio::stray_impl::{HostHandle, MenuKind, MenuNode, StrayTrayApp};
+use system_tray_linux_aio::AppConfig;
 use tokio::sync::mpsc;
 use tracing::info;
-use std::collections::HashMap;
-use std::io::{self, Write};
-
-struct InteractiveApp {
-    ui_tx: mpsc::Sender<NotifierItemCommand>,
-    notifiers: HashMap<String, (String, String)>, // address -> (title, menu_path)
-}
+use std::io::{self, BufRead, Write};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter("info")
         .init();
-    
+
     info!("Starting interactive stray example");
-    
-    let (ui_tx, ui_rx) = mpsc::channel::<NotifierItemCommand>(32);
-    let mut app = InteractiveApp {
-        ui_tx: ui_tx.clone(),
-        notifiers: HashMap::new(),
-    };
-    
-    // Create system tray
-    let mut tray = SystemTray::new(ui_rx).await;
-    
-    // Spawn input handler
-    let tx_clone = ui_tx.clone();
-    let input_task = tokio::spawn(async move {
-        let mut notifiers_clone = HashMap::new();
-        
-        loop {
-            // Show menu
-            println!("\n╔════════════════════════════════════╗");
-            println!("║      STRAY INTERACTIVE MENU        ║");
-            println!("╠════════════════════════════════════╣");
-            println!("║ Commands:                          ║");
-            println!("║   l - List all tray icons          ║");
-            println!("║   c - Click menu item              ║");
-            println!("║   q - Quit                         ║");
-            println!("╚════════════════════════════════════╝");
-            print!("Enter command: ");
-            io::stdout().flush().unwrap();
-            
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
-            let input = input.trim();
-            
-            match input {
-                "l" => {
-                    println!("\nActive Tray Icons:");
-                    println!("─────────────────────────────────");
-                    for (i, (addr, (title, _))) in notifiers_clone.iter().enumerate() {
-                        println!("{}: {} ({})", i, title, addr);
-                    }
-                    if notifiers_clone.is_empty() {
-                        println!("No tray icons detected yet.");
-                    }
-                }
-                
-                "c" => {
-                    if notifiers_clone.is_empty() {
-                        println!("No tray icons available. Wait for icons to appear.");
-                        continue;
-                    }
-                    
-                    print!("Enter icon index: ");
-                    io::stdout().flush().unwrap();
-                    let mut idx_input = String::new();
-                    io::stdin().read_line(&mut idx_input).unwrap();
-                    
-                    if let Ok(idx) = idx_input.trim().parse::<usize>() {
-                        let items: Vec<_> = notifiers_clone.iter().collect();
-                        if let Some((addr, (title, menu_path))) = items.get(idx) {
-                            print!("Enter submenu ID (usually 0-10): ");
-                            io::stdout().flush().unwrap();
-                            let mut id_input = String::new();
-                            io::stdin().read_line(&mut id_input).unwrap();
-                            
-                            if let Ok(submenu_id) = id_input.trim().parse::<i32>() {
-                                let cmd = NotifierItemCommand::MenuItemClicked {
-                                    submenu_id,
-                                    menu_path: menu_path.to_string(),
-                                    notifier_address: addr.to_string(),
-                                };
-                                
-                                if let Err(e) = tx_clone.try_send(cmd) {
-                                    println!("Failed to send command: {}", e);
-                                } else {
-                                    println!("Sent click command for '{}' menu item {}", title, submenu_id);
-                                }
-                            }
-                        } else {
-                            println!("Invalid index");
-                        }
-                    }
-                }
-                
-                "q" => {
-                    println!("Exiting...");
-                    std::process::exit(0);
-                }
-                
-                _ => {
-                    println!("Unknown command: {}", input);
-                }
+
+    let host = StrayTrayApp::new(AppConfig::default()).spawn();
+    let handle = host.handle();
+
+    // stdin blocks, so read it on its own thread and forward lines
+    let (line_tx, mut line_rx) = mpsc::channel::<String>(8);
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(|line| line.ok()) {
+            if line_tx.blocking_send(line).is_err() {
+                break;
             }
         }
     });
-    
-    // Main event loop
-    info!("Listening for system tray icons...\n");
-    
+
+    print_help();
     loop {
+        prompt("Enter command: ");
+
         tokio::select! {
-            Some(message) = tray.next() => {
-                match message {
-                    NotifierItemMessage::Update { address, item, menu } => {
-                        if let Some(item) = item {
-                            let title = item.title.unwrap_or_else(|| "Untitled".to_string());
-                            let menu_path = item.menu.unwrap_or_default();
-                            
-                            println!("\n🔔 Tray icon updated: {} ({})", title, address);
-                            app.notifiers.insert(address.clone(), (title, menu_path));
-                            
-                            // Update the input task's copy
-                            // In a real app, you'd use Arc<Mutex<>> or similar
+            line = line_rx.recv() => {
+                let Some(line) = line else { break };
+                match line.trim() {
+                    "l" => list(&handle).await,
+                    "m" => {
+                        if let Some(address) = pick_item(&handle, &mut line_rx).await {
+                            match handle.menu(&address).await {
+                                Some(menu) => print_menu(&menu, 0),
+                                None => println!("This item has no menu."),
+                            }
                         }
                     }
-                    
-                    NotifierItemMessage::Remove { address } => {
-                        if let Some((title, _)) = app.notifiers.remove(&address) {
-                            println!("\n❌ Tray icon removed: {} ({})", title, address);
+                    "c" => {
+                        if let Some(address) = pick_item(&handle, &mut line_rx).await {
+                            prompt("Enter a menu item ID or a path like Settings/Preferences: ");
+                            let Some(entry) = line_rx.recv().await else { break };
+                            let entry = entry.trim();
+
+                            let result = match entry.parse::<i32>() {
+                                Ok(submenu_id) => handle.click_menu_item(submenu_id, &address).await,
+                                Err(_) => {
+                                    let path: Vec<&str> = entry.split('/').collect();
+                                    handle.click_by_path(&address, &path).await
+                                }
+                            };
+
+                            match result {
+                                Ok(()) => println!("Clicked '{}'", entry),
+                                Err(e) => println!("Click failed: {}", e),
+                            }
                         }
                     }
+                    "q" => break,
+                    "" => {}
+                    other => {
+                        println!("Unknown command: {}", other);
+                        print_help();
+                    }
                 }
             }
-            
+
             _ = tokio::signal::ctrl_c() => {
                 info!("Received Ctrl+C");
                 break;
             }
         }
     }
-    
+
+    println!("Exiting...");
+    host.stop().await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+fn print_help() {
+    println!("\n╔════════════════════════════════════╗");
+    println!("║      STRAY INTERACTIVE MENU        ║");
+    println!("╠════════════════════════════════════╣");
+    println!("║ Commands:                          ║");
+    println!("║   l - List all tray icons          ║");
+    println!("║   m - Show an icon's menu          ║");
+    println!("║   c - Click menu item              ║");
+    println!("║   q - Quit                         ║");
+    println!("╚════════════════════════════════════╝");
+}
+
+fn prompt(text: &str) {
+    print!("{}", text);
+    io::stdout().flush().unwrap();
+}
+
+async fn list(handle: &HostHandle) {
+    let items = handle.items().await;
+
+    println!("\nActive Tray Icons:");
+    println!("─────────────────────────────────");
+    for (i, item) in items.iter().enumerate() {
+        println!("{}: {} ({})", i, item.display_name(), item.address);
+    }
+    if items.is_empty() {
+        println!("No tray icons detected yet.");
+    }
+}
+
+/// Ask for an index into the current item list and return that item's address
+async fn pick_item(handle: &HostHandle, lines: &mut mpsc::Receiver<String>) -> Option<String> {
+    let items = handle.items().await;
+    if items.is_empty() {
+        println!("No tray icons available. Wait for icons to appear.");
+        return None;
+    }
+
+    list(handle).await;
+    prompt("Enter icon index: ");
+    let index = lines.recv().await?.trim().parse::<usize>().ok();
+    match index.and_then(|index| items.get(index)) {
+        Some(item) => Some(item.address.clone()),
+        None => {
+            println!("Invalid index");
+            None
+        }
+    }
+}
+
+fn print_menu(node: &MenuNode, depth: usize) {
+    for child in node.children.iter().filter(|child| child.visible) {
+        let indent = "  ".repeat(depth);
+        match child.kind {
+            MenuKind::Separator => println!("{}────", indent),
+            MenuKind::Standard => {
+                let disabled = if child.enabled { "" } else { " (disabled)" };
+                println!("{}[{}] {}{}", indent, child.id, child.label, disabled);
+            }
+        }
+        print_menu(child, depth + 1);
+    }
+}
@@ -160,42 +160,12 @@ impl SystemTrayIconComponent {
 
 /// Helper to create images similar to aloe-graphics
 pub fn create_icon_image() -> Image {
-    let mut img = RgbaImage::new(32, 32);
-    
-    // Fill with blue background
-    let blue = Rgba([52, 152, 219, 255]);
-    let white = Rgba([255, 255, 255, 255]);
-    
-    for pixel in img.pixels_mut() {
-        *pixel = blue;
-    }
-    
-    // Draw white circle
-    let center = 16;
-    let radius = 12;
-    
-    for (x, y, pixel) in img.enumerate_pixels_mut() {
-        let dx = x as i32 - center;
-        let dy = y as i32 - center;
-        if dx * dx + dy * dy <= radius * radius {
-            *pixel = white;
-        }
-    }
-    
-    // Draw blue "A" in center
-    for y in 8..24 {
-        for x in 8..24 {
-            if (x == 15 && y >= 10 && y <= 20) || // Left line
-               (x == 17 && y >= 10 && y <= 20) || // Right line
-               (y == 10 && x >= 15 && x <= 17) || // Top
-               (y == 15 && x >= 14 && x <= 18) {  // Middle bar
-                img.put_pixel(x, y, blue);
-            }
-        }
-    }
-    
-    let raw = img.into_raw();
-    Image::from_rgba(raw, 32, 32)
+    crate::icon::IconGenerator::text("A")
+        .foreground(Rgba([255, 255, 255, 255]))
+        .background(Rgba([52, 152, 219, 255]))
+        .background_shape(crate::icon::Shape::Circle)
+        .padding(0.25)
+        .render_image(32)
 }
 
 /// Event handling compatibility
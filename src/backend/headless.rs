@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::config::{AppConfig, ItemStatus};
+use crate::error::Result;
+use crate::stray_impl::ScrollOrientation;
+use crate::tray::{Image, PopupMenu, Rectangle, SystemTrayIconComponentInterface, TrayEvent};
+use super::{BackendEvent, BackendKind, TrayBackend};
+
+/// Bounds reported until a test sets others
+const DEFAULT_BOUNDS: Rectangle = Rectangle::new(0, 0, 32, 32);
+
+/// One thing the application did to its icon
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrayChange {
+    Icon(Image),
+    Tooltip(String),
+    Highlighted(bool),
+    Status(ItemStatus),
+    BubbleShown { title: String, content: String },
+    BubbleHidden,
+    MenuShown(PopupMenu),
+}
+
+/// A change and when it happened, counted from the backend's creation
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEntry {
+    pub at: Duration,
+    pub change: TrayChange,
+}
+
+#[derive(Debug)]
+struct Recording {
+    started: Instant,
+    timeline: Vec<TimelineEntry>,
+    icon: Image,
+    tooltip: String,
+    highlighted: bool,
+    status: ItemStatus,
+    bubble: Option<(String, String)>,
+    menu: Option<PopupMenu>,
+    bounds: Rectangle,
+    events: VecDeque<TrayEvent>,
+}
+
+impl Recording {
+    fn record(&mut self, change: TrayChange) {
+        self.timeline.push(TimelineEntry { at: self.started.elapsed(), change });
+    }
+}
+
+/// No icon at all, for servers, CI and tests
+///
+/// Every icon, tooltip, status, bubble and menu change is recorded in a
+/// timeline, and clicks, scrolls and menu choices can be injected. Clones
+/// share the recording, so a test keeps one while the application owns the
+/// other:
+///
+/// ```no_run
+/// # async fn example() -> system_tray_linux_aio::Result<()> {
+/// use system_tray_linux_aio::backend::{HeadlessBackend, TrayChange};
+/// use system_tray_linux_aio::{AppConfig, TrayIcon};
+///
+/// let tray = HeadlessBackend::default();
+/// let mut icon = TrayIcon::with_component(AppConfig::default(), tray.clone()).await?;
+/// icon.show_info_bubble("Sync", "Done").await;
+///
+/// assert!(tray.changes().contains(&TrayChange::BubbleShown {
+///     title: "Sync".into(),
+///     content: "Done".into(),
+/// }));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeadlessBackend {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl Default for HeadlessBackend {
+    fn default() -> Self {
+        Self {
+            recording: Arc::new(Mutex::new(Recording {
+                started: Instant::now(),
+                timeline: Vec::new(),
+                icon: Image::new(),
+                tooltip: String::new(),
+                highlighted: false,
+                status: ItemStatus::Active,
+                bubble: None,
+                menu: None,
+                bounds: DEFAULT_BOUNDS,
+                events: VecDeque::new(),
+            })),
+        }
+    }
+}
+
+impl HeadlessBackend {
+    /// Start with the tooltip from `config`, without recording it
+    pub fn new(config: &AppConfig) -> Self {
+        let backend = Self::default();
+        backend.lock().tooltip = config.tooltip.clone();
+        backend
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Recording> {
+        self.recording.lock().unwrap()
+    }
+
+    pub fn timeline(&self) -> Vec<TimelineEntry> {
+        self.lock().timeline.clone()
+    }
+
+    /// The timeline without times
+    pub fn changes(&self) -> Vec<TrayChange> {
+        self.lock().timeline.iter().map(|entry| entry.change.clone()).collect()
+    }
+
+    /// Forget what was recorded so far, keeping the current state
+    pub fn clear(&self) {
+        self.lock().timeline.clear();
+    }
+
+    pub fn icon(&self) -> Image {
+        self.lock().icon.clone()
+    }
+
+    pub fn tooltip(&self) -> String {
+        self.lock().tooltip.clone()
+    }
+
+    pub fn is_highlighted(&self) -> bool {
+        self.lock().highlighted
+    }
+
+    pub fn status(&self) -> ItemStatus {
+        self.lock().status
+    }
+
+    /// Title and content of the bubble on screen
+    pub fn bubble(&self) -> Option<(String, String)> {
+        self.lock().bubble.clone()
+    }
+
+    /// The menu last dropped down
+    pub fn menu(&self) -> Option<PopupMenu> {
+        self.lock().menu.clone()
+    }
+
+    /// Where the icon claims to be, for code placing windows next to it
+    pub fn set_bounds(&self, bounds: Rectangle) {
+        self.lock().bounds = bounds;
+    }
+
+    /// Queue an event as if the user caused it
+    pub fn push_event(&self, event: BackendEvent) {
+        self.lock().events.push_back(event);
+    }
+
+    pub fn click(&self, x: i32, y: i32) {
+        self.push_event(BackendEvent::Activate { x, y });
+    }
+
+    pub fn middle_click(&self, x: i32, y: i32) {
+        self.push_event(BackendEvent::SecondaryActivate { x, y });
+    }
+
+    pub fn right_click(&self, x: i32, y: i32) {
+        self.push_event(BackendEvent::ContextMenu { x, y });
+    }
+
+    pub fn scroll(&self, delta: i32, orientation: ScrollOrientation) {
+        self.push_event(BackendEvent::Scroll { delta, orientation });
+    }
+
+    /// Choose the dropdown menu entry with this result id
+    pub fn choose_menu_item(&self, result: i32) {
+        self.push_event(BackendEvent::MenuItem(result));
+    }
+
+    fn next_event(&self) -> Option<TrayEvent> {
+        self.lock().events.pop_front()
+    }
+}
+
+impl SystemTrayIconComponentInterface for HeadlessBackend {
+    fn set_icon_image(&mut self, colour_image: &Image, _template_image: &Image) {
+        let mut recording = self.lock();
+        recording.icon = colour_image.clone();
+        recording.record(TrayChange::Icon(colour_image.clone()));
+    }
+
+    fn set_icon_tooltip(&mut self, tooltip: &str) {
+        let mut recording = self.lock();
+        recording.tooltip = tooltip.to_string();
+        recording.record(TrayChange::Tooltip(tooltip.to_string()));
+    }
+
+    fn set_highlighted(&mut self, should_highlight: bool) {
+        let mut recording = self.lock();
+        recording.highlighted = should_highlight;
+        recording.record(TrayChange::Highlighted(should_highlight));
+    }
+
+    fn show_info_bubble(&mut self, title: &str, content: &str) {
+        let mut recording = self.lock();
+        recording.bubble = Some((title.to_string(), content.to_string()));
+        recording.record(TrayChange::BubbleShown {
+            title: title.to_string(),
+            content: content.to_string(),
+        });
+    }
+
+    fn hide_info_bubble(&mut self) {
+        let mut recording = self.lock();
+        if recording.bubble.take().is_some() {
+            recording.record(TrayChange::BubbleHidden);
+        }
+    }
+
+    fn show_dropdown_menu(&mut self, menu: &PopupMenu) {
+        let mut recording = self.lock();
+        recording.menu = Some(menu.clone());
+        recording.record(TrayChange::MenuShown(menu.clone()));
+    }
+
+    fn get_bounds(&self) -> Rectangle {
+        self.lock().bounds
+    }
+
+    fn set_status(&mut self, status: ItemStatus) {
+        let mut recording = self.lock();
+        recording.status = status;
+        recording.record(TrayChange::Status(status));
+    }
+
+    fn poll_event(&mut self) -> Option<TrayEvent> {
+        self.next_event()
+    }
+}
+
+impl TrayBackend for HeadlessBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Headless
+    }
+
+    fn set_icon(&mut self, icon: &Image) -> Result<()> {
+        self.set_icon_image(icon, icon);
+        Ok(())
+    }
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<()> {
+        self.set_icon_tooltip(tooltip);
+        Ok(())
+    }
+
+    fn set_status(&mut self, status: ItemStatus) -> Result<()> {
+        SystemTrayIconComponentInterface::set_status(self, status);
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Result<Option<BackendEvent>> {
+        Ok(self.next_event())
+    }
+
+    fn bounds(&self) -> Option<Rectangle> {
+        Some(self.get_bounds())
+    }
+
+    /// Records the menu; choices come from [`choose_menu_item`](Self::choose_menu_item)
+    fn show_menu(&mut self, menu: &PopupMenu, _anchor: Rectangle) -> Result<Option<i32>> {
+        self.show_dropdown_menu(menu);
+        Ok(None)
+    }
+}
@@ -0,0 +1,326 @@
+//! One interface over the ways this crate can show an icon of its own.
+//!
+//! A [`TrayBackend`] is picked at runtime: [`detect`] walks the preference
+//! order from [`BackendConfig`](crate::config::BackendConfig), probes each
+//! backend (is there a StatusNotifierWatcher with a host? an X display whose
+//! tray selection is owned?) and starts the first one that works. The
+//! [`BackendReport`] it returns says which backend was chosen and why the
+//! ones before it were passed over.
+//!
+//! - [`BackendKind::Sni`] publishes a StatusNotifierItem, see [`SniItem`](crate::tray::SniItem)
+//! - [`BackendKind::Xembed`] docks an X window, see [`LinuxTrayIcon`](crate::tray::linux_impl::LinuxTrayIcon)
+//! - [`BackendKind::TrayIcon`] goes through the `tray-icon` crate, with the `tray-icon` feature
+//! - [`BackendKind::Headless`] shows nothing and always works
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+use x11rb::protocol::xproto::ConnectionExt as _;
+use x11rb::NONE;
+use zbus::fdo;
+use crate::config::{AppConfig, ItemStatus};
+use crate::error::{Result, TrayError};
+use crate::stray_impl::WATCHER_PATH;
+use crate::tray::{Image, PopupMenu, Rectangle, TrayEvent};
+use crate::watcher::WatcherFlavor;
+
+mod headless;
+mod sni;
+#[cfg(feature = "tray-icon")]
+mod tray_icon;
+mod xembed;
+
+#[cfg(test)]
+mod tests;
+
+pub use headless::{HeadlessBackend, TimelineEntry, TrayChange};
+pub use sni::SniBackend;
+#[cfg(feature = "tray-icon")]
+pub use self::tray_icon::TrayIconBackend;
+pub use xembed::XEmbedBackend;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    Sni,
+    Xembed,
+    TrayIcon,
+    Headless,
+}
+
+impl BackendKind {
+    /// Default preference order
+    pub const ALL: [BackendKind; 4] = [
+        BackendKind::Sni,
+        BackendKind::Xembed,
+        BackendKind::TrayIcon,
+        BackendKind::Headless,
+    ];
+
+    /// Name used in the config file
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Sni => "sni",
+            BackendKind::Xembed => "xembed",
+            BackendKind::TrayIcon => "tray-icon",
+            BackendKind::Headless => "headless",
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What the user did to the icon, or what happened to the tray showing it
+///
+/// The same events [`TrayIcon`](crate::tray::TrayIcon) reports, whichever
+/// backend shows it.
+pub type BackendEvent = TrayEvent;
+
+/// An icon in the tray, however it gets there
+pub trait TrayBackend {
+    fn kind(&self) -> BackendKind;
+    fn set_icon(&mut self, icon: &Image) -> Result<()>;
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<()>;
+    fn set_status(&mut self, status: ItemStatus) -> Result<()>;
+    /// Next event, `None` when nothing is pending
+    fn poll_event(&mut self) -> Result<Option<BackendEvent>>;
+    /// Where the icon is on screen, `None` when the backend cannot tell
+    fn bounds(&self) -> Option<Rectangle>;
+    /// Drop down `menu` next to `anchor` and wait for a choice, returning the
+    /// result id of the chosen entry
+    ///
+    /// Only backends without a menu host draw menus, the others return `None`.
+    fn show_menu(&mut self, _menu: &PopupMenu, _anchor: Rectangle) -> Result<Option<i32>> {
+        Ok(None)
+    }
+}
+
+/// Whether one backend can be used here
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Probe {
+    pub kind: BackendKind,
+    pub available: bool,
+    pub reason: String,
+}
+
+impl Probe {
+    fn available(kind: BackendKind, reason: impl Into<String>) -> Self {
+        Self { kind, available: true, reason: reason.into() }
+    }
+
+    fn unavailable(kind: BackendKind, reason: impl Into<String>) -> Self {
+        Self { kind, available: false, reason: reason.into() }
+    }
+}
+
+/// The backends tried, in order, and the one that was started
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BackendReport {
+    pub chosen: Option<BackendKind>,
+    pub probes: Vec<Probe>,
+}
+
+impl fmt::Display for BackendReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for probe in &self.probes {
+            let verdict = if Some(probe.kind) == self.chosen {
+                "chosen"
+            } else if probe.available {
+                "available"
+            } else {
+                "unavailable"
+            };
+            writeln!(f, "{:<10} {:<12} {}", probe.kind.name(), verdict, probe.reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Probes and starts backends in preference order
+pub struct Detector {
+    order: Vec<BackendKind>,
+    connection: Option<zbus::Connection>,
+    display: Option<String>,
+}
+
+impl Detector {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            order: config.backend.order(),
+            connection: None,
+            display: None,
+        }
+    }
+
+    /// Probe and publish on `connection` instead of the session bus
+    pub fn connection(mut self, connection: zbus::Connection) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Probe and dock on `display`, e.g. `:1`, instead of `$DISPLAY`
+    pub fn display(mut self, display: &str) -> Self {
+        self.display = Some(display.to_string());
+        self
+    }
+
+    /// Probe every backend in the preference order without starting any
+    pub async fn probe_all(&self) -> BackendReport {
+        let mut report = BackendReport::default();
+        for &kind in &self.order {
+            report.probes.push(self.probe(kind).await);
+        }
+        report.chosen = report.probes.iter().find(|probe| probe.available).map(|probe| probe.kind);
+        report
+    }
+
+    pub async fn probe(&self, kind: BackendKind) -> Probe {
+        match kind {
+            BackendKind::Sni => self.probe_sni().await,
+            BackendKind::Xembed => self.probe_xembed(),
+            BackendKind::TrayIcon => self.probe_tray_icon(),
+            BackendKind::Headless => Probe::available(kind, "always available"),
+        }
+    }
+
+    /// Start the first backend that probes as available and comes up
+    ///
+    /// A backend that probes fine but fails to start is reported with the
+    /// error and the next one is tried.
+    pub async fn detect(&self, config: &AppConfig) -> Result<(Box<dyn TrayBackend>, BackendReport)> {
+        let mut report = BackendReport::default();
+        for &kind in &self.order {
+            let mut probe = self.probe(kind).await;
+            if probe.available {
+                match self.start(kind, config).await {
+                    Ok(backend) => {
+                        info!("Using the {} tray backend: {}", kind, probe.reason);
+                        report.probes.push(probe);
+                        report.chosen = Some(kind);
+                        return Ok((backend, report));
+                    }
+                    Err(e) => {
+                        probe.available = false;
+                        probe.reason = format!("{}, but starting failed: {}", probe.reason, e);
+                    }
+                }
+            }
+            debug!("Not using the {} tray backend: {}", kind, probe.reason);
+            report.probes.push(probe);
+        }
+
+        Err(TrayError::InitializationError(format!("No usable tray backend\n{}", report)))
+    }
+
+    async fn start(&self, kind: BackendKind, config: &AppConfig) -> Result<Box<dyn TrayBackend>> {
+        Ok(match kind {
+            BackendKind::Sni => Box::new(match &self.connection {
+                Some(connection) => SniBackend::publish_on(connection.clone(), config).await?,
+                None => SniBackend::publish(config).await?,
+            }),
+            BackendKind::Xembed => Box::new(XEmbedBackend::connect(self.display.as_deref(), config)?),
+            #[cfg(feature = "tray-icon")]
+            BackendKind::TrayIcon => Box::new(TrayIconBackend::new(config)?),
+            #[cfg(not(feature = "tray-icon"))]
+            BackendKind::TrayIcon => {
+                return Err(TrayError::InitializationError("Built without the tray-icon feature".into()))
+            }
+            BackendKind::Headless => Box::new(HeadlessBackend::new(config)),
+        })
+    }
+
+    /// A watcher alone is not enough: without a host nobody draws the item
+    async fn probe_sni(&self) -> Probe {
+        let kind = BackendKind::Sni;
+        let connection = match &self.connection {
+            Some(connection) => connection.clone(),
+            None => match zbus::Connection::session().await {
+                Ok(connection) => connection,
+                Err(e) => return Probe::unavailable(kind, format!("no session bus: {}", e)),
+            },
+        };
+
+        // Either name will do; report the first one found when neither has a host
+        let mut reason = None;
+        for flavor in WatcherFlavor::ALL {
+            let watcher = flavor.name();
+            let problem = match host_registered(&connection, watcher).await {
+                Ok(Some(true)) => return Probe::available(kind, format!("{} has a host registered", watcher)),
+                Ok(Some(false)) => format!("{} runs, but no host shows its items", watcher),
+                Ok(None) => continue,
+                Err(e) => format!("cannot ask {}: {}", watcher, e),
+            };
+            reason.get_or_insert(problem);
+        }
+
+        let reason = reason.unwrap_or_else(|| {
+            format!(
+                "no {} or {} on the bus",
+                WatcherFlavor::Kde.name(),
+                WatcherFlavor::Freedesktop.name()
+            )
+        });
+        Probe::unavailable(kind, reason)
+    }
+
+    fn probe_xembed(&self) -> Probe {
+        let kind = BackendKind::Xembed;
+        if self.display.is_none() && std::env::var_os("DISPLAY").is_none() {
+            return Probe::unavailable(kind, "DISPLAY is not set");
+        }
+
+        match tray_manager(self.display.as_deref()) {
+            Ok((selection, Some(manager))) => {
+                Probe::available(kind, format!("tray manager {:#x} owns {}", manager, selection))
+            }
+            Ok((selection, None)) => Probe::unavailable(kind, format!("no tray manager owns {}", selection)),
+            Err(e) => Probe::unavailable(kind, e.to_string()),
+        }
+    }
+
+    fn probe_tray_icon(&self) -> Probe {
+        let kind = BackendKind::TrayIcon;
+        if !cfg!(feature = "tray-icon") {
+            return Probe::unavailable(kind, "built without the tray-icon feature");
+        }
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return Probe::unavailable(kind, "neither DISPLAY nor WAYLAND_DISPLAY is set");
+        }
+        Probe::available(kind, "built with the tray-icon feature and a display is set")
+    }
+}
+
+/// Start the preferred backend that works here, see [`Detector::detect`]
+pub async fn detect(config: &AppConfig) -> Result<(Box<dyn TrayBackend>, BackendReport)> {
+    Detector::new(config).detect(config).await
+}
+
+/// `IsStatusNotifierHostRegistered` of the watcher, `None` without a watcher
+async fn host_registered(connection: &zbus::Connection, watcher: &str) -> zbus::Result<Option<bool>> {
+    let bus = fdo::DBusProxy::new(connection).await?;
+    if !bus.name_has_owner(zbus::names::BusName::try_from(watcher)?).await? {
+        return Ok(None);
+    }
+
+    let properties = fdo::PropertiesProxy::builder(connection)
+        .destination(watcher.to_string())?
+        .path(WATCHER_PATH)?
+        .build()
+        .await?;
+    let interface = zbus::names::InterfaceName::try_from(watcher)?;
+    let registered = properties.get(interface, "IsStatusNotifierHostRegistered").await?;
+    Ok(Some(bool::try_from(registered).unwrap_or(false)))
+}
+
+/// Name of the tray selection on `display` and the window owning it
+pub(crate) fn tray_manager(display: Option<&str>) -> Result<(String, Option<u32>)> {
+    let (conn, screen) = x11rb::connect(display)?;
+    let selection = format!("_NET_SYSTEM_TRAY_S{}", screen);
+    let atom = conn.intern_atom(false, selection.as_bytes())?.reply()?.atom;
+    let owner = conn.get_selection_owner(atom)?.reply()?.owner;
+    Ok((selection, (owner != NONE).then_some(owner)))
+}
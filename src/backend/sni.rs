@@ -0,0 +1,138 @@
+use tokio::sync::{broadcast, mpsc};
+use tracing::warn;
+use crate::config::{AppConfig, ItemStatus};
+use crate::error::{Result, TrayError};
+use crate::stray_impl::{IconPixmap, ToolTip};
+use crate::tray::{Image, ItemProperties, Rectangle, SniEvent, SniItem};
+use super::{BackendEvent, BackendKind, TrayBackend};
+
+/// Changes queued for the task that owns the item, applied in order
+enum Update {
+    Icon(Vec<IconPixmap>),
+    Tooltip(String),
+    Status(ItemStatus),
+}
+
+/// The icon as a StatusNotifierItem
+///
+/// Setters return at once; the bus traffic happens on a task, so this must
+/// be created inside a Tokio runtime.
+pub struct SniBackend {
+    address: String,
+    updates: mpsc::UnboundedSender<Update>,
+    events: broadcast::Receiver<SniEvent>,
+    /// Where the host last said the icon was clicked
+    clicked_at: Option<(i32, i32)>,
+}
+
+impl SniBackend {
+    /// Publish on a new session bus connection
+    pub async fn publish(config: &AppConfig) -> Result<Self> {
+        Ok(Self::start(SniItem::publish(properties(config)).await?))
+    }
+
+    pub async fn publish_on(connection: zbus::Connection, config: &AppConfig) -> Result<Self> {
+        Ok(Self::start(SniItem::publish_on(connection, properties(config)).await?))
+    }
+
+    fn start(item: SniItem) -> Self {
+        let address = item.address().to_string();
+        let events = item.subscribe();
+        let (updates, mut queue) = mpsc::unbounded_channel();
+
+        // The item goes away with the task, once the backend is dropped
+        tokio::spawn(async move {
+            while let Some(update) = queue.recv().await {
+                let result = match update {
+                    Update::Icon(pixmaps) => item.set_icon("", pixmaps).await,
+                    Update::Tooltip(title) => item.set_tooltip(ToolTip { title, ..Default::default() }).await,
+                    Update::Status(status) => item.set_status(status).await,
+                };
+                if let Err(e) = result {
+                    warn!("Could not update {}: {}", item.address(), e);
+                }
+            }
+        });
+
+        Self { address, updates, events, clicked_at: None }
+    }
+
+    /// Bus name the item is published under
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn send(&self, update: Update) -> Result<()> {
+        self.updates
+            .send(update)
+            .map_err(|_| TrayError::EventError(format!("{} is no longer published", self.address)))
+    }
+}
+
+fn properties(config: &AppConfig) -> ItemProperties {
+    ItemProperties {
+        id: config.app_name.clone(),
+        title: config.app_name.clone(),
+        icon_name: config.icon_name.clone().unwrap_or_default(),
+        tooltip: ToolTip { title: config.tooltip.clone(), ..Default::default() },
+        ..Default::default()
+    }
+}
+
+impl TrayBackend for SniBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Sni
+    }
+
+    fn set_icon(&mut self, icon: &Image) -> Result<()> {
+        self.send(Update::Icon(icon.pixmaps().iter().map(IconPixmap::from_image).collect()))
+    }
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<()> {
+        self.send(Update::Tooltip(tooltip.to_string()))
+    }
+
+    fn set_status(&mut self, status: ItemStatus) -> Result<()> {
+        self.send(Update::Status(status))
+    }
+
+    fn poll_event(&mut self) -> Result<Option<BackendEvent>> {
+        loop {
+            let event = match self.events.try_recv() {
+                Ok(event) => event,
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                    warn!("Dropped {} events of {}", missed, self.address);
+                    continue;
+                }
+                Err(_) => return Ok(None),
+            };
+
+            return Ok(Some(match event {
+                SniEvent::WatcherLost => BackendEvent::TrayLost,
+                SniEvent::WatcherRestored => BackendEvent::TrayRestored,
+                SniEvent::Activate { x, y } => {
+                    self.clicked_at = Some((x, y));
+                    BackendEvent::Activate { x, y }
+                }
+                SniEvent::SecondaryActivate { x, y } => {
+                    self.clicked_at = Some((x, y));
+                    BackendEvent::SecondaryActivate { x, y }
+                }
+                SniEvent::ContextMenu { x, y } => {
+                    self.clicked_at = Some((x, y));
+                    BackendEvent::ContextMenu { x, y }
+                }
+                SniEvent::Scroll { delta, orientation } => BackendEvent::Scroll { delta, orientation },
+            }));
+        }
+    }
+
+    /// The point of the last click polled, as SNI hosts only pass that
+    ///
+    /// Hosts differ in whether it is the pointer or a corner of the icon,
+    /// so the rectangle is empty; [`Rectangle::place_popup`] still puts a
+    /// window next to it. `None` until the first click.
+    fn bounds(&self) -> Option<Rectangle> {
+        self.clicked_at.map(|(x, y)| Rectangle::new(x, y, 0, 0))
+    }
+}
@@ -0,0 +1,137 @@
+use super::*;
+use crate::config::BackendConfig;
+use crate::stray_impl::ScrollOrientation;
+
+fn config(prefer: Vec<BackendKind>) -> AppConfig {
+    AppConfig {
+        backend: BackendConfig { prefer },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_kind_names_match_config() {
+    for kind in BackendKind::ALL {
+        let parsed: BackendConfig = toml::from_str(&format!("prefer = [\"{}\"]", kind.name())).unwrap();
+        assert_eq!(parsed.prefer, vec![kind]);
+    }
+}
+
+#[test]
+fn test_preference_order() {
+    assert_eq!(BackendConfig::default().order(), BackendKind::ALL.to_vec());
+    assert_eq!(BackendConfig { prefer: vec![] }.order(), BackendKind::ALL.to_vec());
+
+    let prefer = vec![BackendKind::Xembed, BackendKind::Headless, BackendKind::Xembed];
+    assert_eq!(BackendConfig { prefer }.order(), vec![BackendKind::Xembed, BackendKind::Headless]);
+}
+
+#[test]
+fn test_report_display() {
+    let report = BackendReport {
+        chosen: Some(BackendKind::Headless),
+        probes: vec![
+            Probe::unavailable(BackendKind::Xembed, "DISPLAY is not set"),
+            Probe::available(BackendKind::Headless, "always available"),
+        ],
+    };
+    assert_eq!(
+        report.to_string(),
+        "xembed     unavailable  DISPLAY is not set\nheadless   chosen       always available\n"
+    );
+}
+
+#[tokio::test]
+async fn test_detect_falls_back_to_headless() {
+    let config = config(vec![BackendKind::Xembed, BackendKind::Headless]);
+    let detector = Detector::new(&config).display(":1000");
+
+    let (backend, report) = detector.detect(&config).await.unwrap();
+    assert_eq!(backend.kind(), BackendKind::Headless);
+    assert_eq!(report.chosen, Some(BackendKind::Headless));
+    assert_eq!(report.probes.len(), 2);
+    assert!(!report.probes[0].available);
+}
+
+#[tokio::test]
+async fn test_detect_without_usable_backend() {
+    let config = config(vec![BackendKind::Xembed]);
+    let result = Detector::new(&config).display(":1000").detect(&config).await;
+    assert!(matches!(result, Err(TrayError::InitializationError(message)) if message.contains("xembed")));
+}
+
+#[cfg(not(feature = "tray-icon"))]
+#[tokio::test]
+async fn test_tray_icon_needs_feature() {
+    let probe = Detector::new(&AppConfig::default()).probe(BackendKind::TrayIcon).await;
+    assert_eq!(probe, Probe::unavailable(BackendKind::TrayIcon, "built without the tray-icon feature"));
+}
+
+#[test]
+fn test_headless_backend() {
+    let mut backend = HeadlessBackend::new(&AppConfig::default());
+    assert_eq!(backend.tooltip(), "Click to open menu");
+    assert!(backend.timeline().is_empty());
+
+    backend.set_tooltip("Syncing").unwrap();
+    TrayBackend::set_status(&mut backend, ItemStatus::NeedsAttention).unwrap();
+    assert_eq!(backend.tooltip(), "Syncing");
+    assert_eq!(backend.status(), ItemStatus::NeedsAttention);
+    assert_eq!(
+        backend.changes(),
+        vec![TrayChange::Tooltip("Syncing".into()), TrayChange::Status(ItemStatus::NeedsAttention)]
+    );
+
+    backend.click(4, 2);
+    backend.push_event(BackendEvent::TrayLost);
+    assert_eq!(TrayBackend::poll_event(&mut backend).unwrap(), Some(BackendEvent::Activate { x: 4, y: 2 }));
+    assert_eq!(TrayBackend::poll_event(&mut backend).unwrap(), Some(BackendEvent::TrayLost));
+    assert_eq!(backend.bounds(), Some(crate::tray::Rectangle::new(0, 0, 32, 32)));
+    assert_eq!(TrayBackend::poll_event(&mut backend).unwrap(), None);
+}
+
+#[test]
+fn test_headless_records_component_calls() {
+    use crate::tray::{PopupMenu, Rectangle, SystemTrayIconComponentInterface};
+
+    let recorder = HeadlessBackend::default();
+    let mut component = recorder.clone();
+    let mut menu = PopupMenu::new();
+    menu.add_item(1, "Quit", true, false);
+
+    component.show_info_bubble("Sync", "Done");
+    component.hide_info_bubble();
+    component.hide_info_bubble();
+    component.show_dropdown_menu(&menu);
+    component.set_highlighted(true);
+
+    assert_eq!(
+        recorder.changes(),
+        vec![
+            TrayChange::BubbleShown { title: "Sync".into(), content: "Done".into() },
+            TrayChange::BubbleHidden,
+            TrayChange::MenuShown(menu.clone()),
+            TrayChange::Highlighted(true),
+        ]
+    );
+    assert!(recorder.bubble().is_none());
+    assert!(recorder.is_highlighted());
+
+    let timeline = recorder.timeline();
+    assert!(timeline.windows(2).all(|pair| pair[0].at <= pair[1].at));
+    recorder.clear();
+    assert!(recorder.timeline().is_empty());
+    assert!(recorder.is_highlighted());
+
+    assert_eq!(component.get_bounds(), Rectangle::new(0, 0, 32, 32));
+    recorder.set_bounds(Rectangle::new(600, 0, 24, 24));
+    assert_eq!(component.get_bounds(), Rectangle::new(600, 0, 24, 24));
+
+    recorder.choose_menu_item(3);
+    recorder.scroll(-120, ScrollOrientation::Vertical);
+    assert_eq!(SystemTrayIconComponentInterface::poll_event(&mut component), Some(TrayEvent::MenuItem(3)));
+    assert_eq!(
+        SystemTrayIconComponentInterface::poll_event(&mut component),
+        Some(TrayEvent::Scroll { delta: -120, orientation: ScrollOrientation::Vertical })
+    );
+}
@@ -0,0 +1,95 @@
+use tray_icon::{Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+use crate::config::{AppConfig, ItemStatus};
+use crate::error::{Result, TrayError};
+use crate::tray::{Image, Rectangle};
+use super::{BackendEvent, BackendKind, TrayBackend};
+
+/// Side of the pixmap handed to tray-icon
+const ICON_SIZE: u32 = 32;
+
+/// The icon through the `tray-icon` crate
+///
+/// On Linux tray-icon draws with GTK, so the backend initializes GTK and
+/// must stay on the thread that created it; [`poll_event`](TrayBackend::poll_event)
+/// runs the pending GTK iterations. tray-icon does not report clicks on
+/// Linux, only its own menu is shown there.
+pub struct TrayIconBackend {
+    tray: TrayIcon,
+}
+
+impl TrayIconBackend {
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        #[cfg(target_os = "linux")]
+        gtk::init().map_err(|e| TrayError::InitializationError(format!("GTK: {}", e)))?;
+
+        let tray = TrayIconBuilder::new()
+            .with_id(config.app_name.as_str())
+            .with_tooltip(&config.tooltip)
+            .with_title(&config.app_name)
+            .build()
+            .map_err(|e| TrayError::InitializationError(e.to_string()))?;
+        Ok(Self { tray })
+    }
+}
+
+impl TrayBackend for TrayIconBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::TrayIcon
+    }
+
+    fn set_icon(&mut self, icon: &Image) -> Result<()> {
+        let Some(pixmap) = icon.pixmaps().iter().find(|pixmap| pixmap.width() >= ICON_SIZE).or(icon.pixmaps().last())
+        else {
+            return self.tray.set_icon(None).map_err(|e| TrayError::IconLoadError(e.to_string()));
+        };
+        let icon = Icon::from_rgba(pixmap.as_raw().clone(), pixmap.width(), pixmap.height())
+            .map_err(|e| TrayError::IconLoadError(e.to_string()))?;
+        self.tray.set_icon(Some(icon)).map_err(|e| TrayError::IconLoadError(e.to_string()))
+    }
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<()> {
+        self.tray
+            .set_tooltip(Some(tooltip))
+            .map_err(|e| TrayError::EventError(e.to_string()))
+    }
+
+    fn set_status(&mut self, status: ItemStatus) -> Result<()> {
+        self.tray
+            .set_visible(status != ItemStatus::Passive)
+            .map_err(|e| TrayError::EventError(e.to_string()))
+    }
+
+    fn poll_event(&mut self) -> Result<Option<BackendEvent>> {
+        #[cfg(target_os = "linux")]
+        while gtk::events_pending() {
+            gtk::main_iteration_do(false);
+        }
+
+        while let Ok(event) = TrayIconEvent::receiver().try_recv() {
+            if event.id() != self.tray.id() {
+                continue;
+            }
+            let TrayIconEvent::Click { position, button, button_state: MouseButtonState::Up, .. } = event else {
+                continue;
+            };
+            let (x, y) = (position.x as i32, position.y as i32);
+            return Ok(Some(match button {
+                MouseButton::Left => BackendEvent::Activate { x, y },
+                MouseButton::Middle => BackendEvent::SecondaryActivate { x, y },
+                MouseButton::Right => BackendEvent::ContextMenu { x, y },
+            }));
+        }
+        Ok(None)
+    }
+
+    fn bounds(&self) -> Option<Rectangle> {
+        // Only known on Windows and macOS
+        let rect = self.tray.rect()?;
+        Some(Rectangle::new(
+            rect.position.x as i32,
+            rect.position.y as i32,
+            rect.size.width as i32,
+            rect.size.height as i32,
+        ))
+    }
+}
@@ -0,0 +1,63 @@
+use crate::config::{AppConfig, ItemStatus};
+use crate::error::Result;
+use crate::tray::linux_impl::LinuxTrayIcon;
+use crate::tray::{Image, PopupMenu, Rectangle};
+use super::{BackendEvent, BackendKind, TrayBackend};
+
+/// The icon as a window docked with the XEmbed tray manager
+pub struct XEmbedBackend {
+    icon: LinuxTrayIcon,
+}
+
+impl XEmbedBackend {
+    /// Connect to `display`, `$DISPLAY` if `None`, and dock with its tray
+    pub fn connect(display: Option<&str>, config: &AppConfig) -> Result<Self> {
+        let mut icon = LinuxTrayIcon::connect(display, config.clone())?;
+        icon.dock()?;
+        icon.set_tooltip(&config.tooltip)?;
+        Ok(Self { icon })
+    }
+
+    pub fn icon(&self) -> &LinuxTrayIcon {
+        &self.icon
+    }
+}
+
+impl TrayBackend for XEmbedBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Xembed
+    }
+
+    fn set_icon(&mut self, icon: &Image) -> Result<()> {
+        self.icon.set_icon(icon)
+    }
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<()> {
+        self.icon.set_tooltip(tooltip)
+    }
+
+    fn set_status(&mut self, _status: ItemStatus) -> Result<()> {
+        // XEmbed has no status, the caller swaps the artwork instead
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Result<Option<BackendEvent>> {
+        while let Some(event) = self.icon.poll_event()? {
+            if let Some(event) = event.to_tray_event() {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    fn bounds(&self) -> Option<Rectangle> {
+        self.icon.bounds().ok()
+    }
+
+    /// Draw `menu` ourselves, as XEmbed trays have no menu host
+    ///
+    /// See [`LinuxTrayIcon::show_menu`].
+    fn show_menu(&mut self, menu: &PopupMenu, anchor: Rectangle) -> Result<Option<i32>> {
+        self.icon.show_menu(menu, anchor)
+    }
+}
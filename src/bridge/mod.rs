@@ -0,0 +1,21 @@
+//! Bridges between the two tray protocols, so icons of either kind show on
+//! panels that only speak the other.
+
+pub mod sni_to_xembed;
+pub mod xembed_to_sni;
+
+#[cfg(test)]
+mod tests;
+
+pub use sni_to_xembed::{MirroredItem, SniToXEmbed};
+pub use xembed_to_sni::{ProxiedIcon, XEmbedToSni};
+
+/// A new connection to the bus at `address`, the session bus if `None`
+///
+/// Every item needs its own connection, since they all use the same path.
+pub(crate) async fn connect(address: Option<&str>) -> crate::Result<zbus::Connection> {
+    Ok(match address {
+        Some(address) => zbus::ConnectionBuilder::address(address)?.build().await?,
+        None => zbus::Connection::session().await?,
+    })
+}
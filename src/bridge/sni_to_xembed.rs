@@ -0,0 +1,427 @@
+//! StatusNotifierItems mirrored as XEmbed icons, for panels that only dock
+//! the latter.
+//!
+//! Every item the host reports gets a [`LinuxTrayIcon`] of its own, docked
+//! with the XEmbed tray and kept in step with the item's icon and tooltip.
+//! The left and middle buttons and the wheel go back to the item as
+//! `Activate`, `SecondaryActivate` and `Scroll`; the right button, or the
+//! left one on items that are only a menu, opens the item's dbusmenu drawn
+//! by [`LinuxTrayIcon::show_menu`]. Each icon runs on a thread of its own,
+//! since a menu blocks it until it is closed.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use crate::config::{AppConfig, ItemStatus};
+use crate::error::{Result, TrayError};
+use crate::icon::IconThemeResolver;
+use crate::stray_impl::{pixmap_icon, AlphaMode, HostEvent, HostHandle, ItemField, MenuKind, MenuNode, NotifierInfo, ScrollOrientation};
+use crate::tray::linux_impl::{LinuxTrayIcon, MenuCloser, XEmbedEvent};
+use crate::tray::{Image, PopupMenu, Rectangle};
+
+/// Size item artwork is loaded at; icons scale it down to what the tray gives them
+pub const ARTWORK_SIZE: u32 = 48;
+
+/// How often an idle icon thread looks for X events
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// An item and the icon window it is mirrored as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirroredItem {
+    /// Bus name of the item
+    pub address: String,
+    /// The docked window, `None` until the tray embedded it
+    pub window: Option<u32>,
+}
+
+/// A running bridge, serving until [`stop`](Self::stop) is called
+pub struct SniToXEmbed {
+    mirrored: Arc<Mutex<Vec<MirroredItem>>>,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl SniToXEmbed {
+    /// Mirror the items `host` reports in the tray of `display`, `$DISPLAY` if `None`
+    ///
+    /// Fails if no XEmbed tray manager runs there.
+    pub async fn start(display: Option<&str>, host: HostHandle) -> Result<Self> {
+        let (selection, manager) = crate::backend::tray_manager(display)?;
+        if manager.is_none() {
+            return Err(TrayError::InitializationError(format!("No system tray manager owns {}", selection)));
+        }
+
+        let mirrored = Arc::new(Mutex::new(Vec::new()));
+        let (events, icon_events) = mpsc::unbounded_channel();
+        // Subscribed before the first listing, so no change falls in between
+        let host_events = host.subscribe();
+        let bridge = Bridge {
+            display: display.map(str::to_string),
+            host,
+            resolver: IconThemeResolver::system(),
+            mirrors: HashMap::new(),
+            mirrored: mirrored.clone(),
+            events,
+        };
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(bridge.run(host_events, icon_events, stopped));
+
+        info!("Mirroring StatusNotifierItems in the XEmbed tray of {}", selection);
+        Ok(Self { mirrored, stop, task })
+    }
+
+    /// Mirrored items, in the order they appeared
+    pub fn items(&self) -> Vec<MirroredItem> {
+        self.mirrored.lock().unwrap().clone()
+    }
+
+    /// Take every icon out of the tray
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        self.task
+            .await
+            .map_err(|e| TrayError::EventError(format!("SNI to XEmbed bridge failed: {}", e)))
+    }
+}
+
+/// What the bridge asks of an icon thread
+enum IconCommand {
+    Artwork(Image),
+    Tooltip(String),
+    Menu(PopupMenu),
+}
+
+/// What an icon thread reports back
+#[derive(Debug)]
+enum IconEvent {
+    Docked(u32),
+    Input(XEmbedEvent),
+    /// Result id of the menu entry chosen
+    Chosen(i32),
+}
+
+/// What a click or wheel turn on an icon does to its item
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Forward {
+    Activate { x: i32, y: i32 },
+    SecondaryActivate { x: i32, y: i32 },
+    ContextMenu { x: i32, y: i32 },
+    Scroll { delta: i32, orientation: ScrollOrientation },
+    ShowMenu(PopupMenu),
+}
+
+struct Mirror {
+    commands: std_mpsc::Sender<IconCommand>,
+    thread: std::thread::JoinHandle<()>,
+    /// Dismisses a menu the thread is blocked in
+    menu_closer: MenuCloser,
+}
+
+impl Mirror {
+    fn send(&self, command: IconCommand) {
+        // A thread that gave up has logged why already
+        let _ = self.commands.send(command);
+    }
+}
+
+struct Bridge {
+    display: Option<String>,
+    host: HostHandle,
+    resolver: IconThemeResolver,
+    mirrors: HashMap<String, Mirror>,
+    mirrored: Arc<Mutex<Vec<MirroredItem>>>,
+    events: mpsc::UnboundedSender<(String, IconEvent)>,
+}
+
+impl Bridge {
+    async fn run(
+        mut self,
+        mut host_events: broadcast::Receiver<HostEvent>,
+        mut icon_events: mpsc::UnboundedReceiver<(String, IconEvent)>,
+        mut stopped: oneshot::Receiver<()>,
+    ) {
+        self.resync().await;
+        loop {
+            tokio::select! {
+                _ = &mut stopped => break,
+                event = host_events.recv() => match event {
+                    Ok(event) => self.follow(event).await,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("Missed {} host events, listing the items again", missed);
+                        self.resync().await;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                Some((address, event)) = icon_events.recv() => {
+                    if let Err(e) = self.handle(&address, event).await {
+                        warn!("Could not forward to {}: {}", address, e);
+                    }
+                }
+            }
+        }
+
+        let addresses: Vec<String> = self.mirrors.keys().cloned().collect();
+        for address in addresses {
+            self.unmirror(&address).await;
+        }
+        info!("SNI to XEmbed bridge stopped");
+    }
+
+    /// Mirror exactly the items the host knows now
+    async fn resync(&mut self) {
+        let items = self.host.items().await;
+        let gone: Vec<String> = self
+            .mirrors
+            .keys()
+            .filter(|address| !items.iter().any(|info| &info.address == *address))
+            .cloned()
+            .collect();
+        for address in gone {
+            self.unmirror(&address).await;
+        }
+        for info in items {
+            self.follow(HostEvent::Added(info)).await;
+        }
+    }
+
+    async fn follow(&mut self, event: HostEvent) {
+        match event {
+            HostEvent::Added(info) => match self.mirrors.get(&info.address) {
+                Some(mirror) => {
+                    if let Some(artwork) = self.artwork(&info) {
+                        mirror.send(IconCommand::Artwork(artwork));
+                    }
+                    mirror.send(IconCommand::Tooltip(tooltip(&info)));
+                }
+                None => self.mirror(&info).await,
+            },
+            HostEvent::Changed { info, fields } => {
+                let Some(mirror) = self.mirrors.get(&info.address) else { return };
+                let redraw = [ItemField::Icon, ItemField::AttentionIcon, ItemField::Status, ItemField::IconThemePath];
+                if fields.iter().any(|field| redraw.contains(field)) {
+                    if let Some(artwork) = self.artwork(&info) {
+                        mirror.send(IconCommand::Artwork(artwork));
+                    }
+                }
+                if fields.iter().any(|field| [ItemField::ToolTip, ItemField::Title, ItemField::Id].contains(field)) {
+                    mirror.send(IconCommand::Tooltip(tooltip(&info)));
+                }
+            }
+            HostEvent::Removed(info) => {
+                if self.unmirror(&info.address).await {
+                    info!("Took {} out of the XEmbed tray", info.display_name());
+                }
+            }
+        }
+    }
+
+    async fn mirror(&mut self, info: &NotifierInfo) {
+        let config = AppConfig {
+            app_name: info.id.clone(),
+            tooltip: tooltip(info),
+            ..Default::default()
+        };
+        let (display, artwork) = (self.display.clone(), self.artwork(info));
+        // Connecting and docking wait on the X server
+        let docked = tokio::task::spawn_blocking(move || {
+            let mut icon = LinuxTrayIcon::connect(display.as_deref(), config)?;
+            if let Some(artwork) = artwork {
+                icon.set_icon(&artwork)?;
+            }
+            icon.dock()?;
+            let menu_closer = icon.menu_closer()?;
+            Ok((icon, menu_closer))
+        })
+        .await
+        .unwrap_or_else(|e| Err(TrayError::X11Error(format!("Docking failed: {}", e))));
+        let (icon, menu_closer) = match docked {
+            Ok(docked) => docked,
+            Err(e) => {
+                warn!("Could not mirror {}: {}", info.display_name(), e);
+                return;
+            }
+        };
+
+        let (commands, received) = std_mpsc::channel();
+        let (address, events) = (info.address.clone(), self.events.clone());
+        let thread = std::thread::spawn(move || run_icon(icon, address, received, events));
+        self.mirrors.insert(info.address.clone(), Mirror { commands, thread, menu_closer });
+        self.mirrored.lock().unwrap().push(MirroredItem { address: info.address.clone(), window: None });
+        info!("Mirroring {} in the XEmbed tray", info.display_name());
+    }
+
+    /// Close the icon and wait until its window is gone, `false` if there was none
+    ///
+    /// Waiting is short: an open menu is dismissed first, and an idle thread
+    /// notices within one poll.
+    async fn unmirror(&mut self, address: &str) -> bool {
+        let Some(Mirror { commands, thread, menu_closer }) = self.mirrors.remove(address) else { return false };
+        self.mirrored.lock().unwrap().retain(|item| item.address != address);
+        if let Err(e) = menu_closer.close() {
+            warn!("Could not close the menu of {}: {}", address, e);
+        }
+        drop(commands);
+        let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+        true
+    }
+
+    async fn handle(&mut self, address: &str, event: IconEvent) -> Result<()> {
+        let forwarded = match event {
+            IconEvent::Docked(window) => {
+                for item in self.mirrored.lock().unwrap().iter_mut().filter(|item| item.address == address) {
+                    item.window = Some(window);
+                }
+                Ok(())
+            }
+            IconEvent::Chosen(id) => self.host.click_menu_item(id, address).await,
+            IconEvent::Input(event) => {
+                let Some(info) = self.host.item(address).await else { return Ok(()) };
+                match forward_for(&event, &info) {
+                    Some(Forward::Activate { x, y }) => self.host.activate(address, x, y).await,
+                    Some(Forward::SecondaryActivate { x, y }) => self.host.secondary_activate(address, x, y).await,
+                    Some(Forward::ContextMenu { x, y }) => self.host.context_menu(address, x, y).await,
+                    Some(Forward::Scroll { delta, orientation }) => self.host.scroll(address, delta, orientation).await,
+                    Some(Forward::ShowMenu(menu)) => {
+                        if let Some(mirror) = self.mirrors.get(address) {
+                            mirror.send(IconCommand::Menu(menu));
+                        }
+                        Ok(())
+                    }
+                    None => Ok(()),
+                }
+            }
+        };
+        forwarded.map_err(|e| TrayError::EventError(e.to_string()))
+    }
+
+    /// The item's icon, or its attention icon while it needs attention
+    ///
+    /// Named icons are looked up in the theme first, as hosts do, and the
+    /// pixmaps are the fallback.
+    fn artwork(&self, info: &NotifierInfo) -> Option<Image> {
+        let wants_attention = info.status == ItemStatus::NeedsAttention
+            && (info.attention_icon_name.is_some() || !info.attention_icon_pixmaps.is_empty());
+        let (name, pixmaps) = if wants_attention {
+            (&info.attention_icon_name, &info.attention_icon_pixmaps)
+        } else {
+            (&info.icon_name, &info.icon_pixmaps)
+        };
+
+        let theme_path = info.icon_theme_path.as_deref().filter(|path| !path.is_empty()).map(Path::new);
+        let image = name
+            .as_deref()
+            .filter(|name| !name.is_empty())
+            .and_then(|name| self.resolver.load(name, ARTWORK_SIZE, 1, theme_path).ok())
+            .or_else(|| pixmap_icon(pixmaps, ARTWORK_SIZE, AlphaMode::Straight).ok());
+        if image.is_none() {
+            debug!("{} has no icon that can be drawn", info.display_name());
+        }
+        image.map(|image| Image::from_pixmaps(vec![image]))
+    }
+}
+
+/// Apply commands to `icon` and report its input until the bridge lets go
+fn run_icon(
+    mut icon: LinuxTrayIcon,
+    address: String,
+    commands: std_mpsc::Receiver<IconCommand>,
+    events: mpsc::UnboundedSender<(String, IconEvent)>,
+) {
+    let send = |event| {
+        let _ = events.send((address.clone(), event));
+    };
+
+    loop {
+        let result = match commands.recv_timeout(POLL_INTERVAL) {
+            Ok(IconCommand::Artwork(artwork)) => icon.set_icon(&artwork),
+            Ok(IconCommand::Tooltip(tooltip)) => icon.set_tooltip(&tooltip),
+            Ok(IconCommand::Menu(menu)) => {
+                let anchor = icon.bounds().unwrap_or_else(|_| {
+                    let (width, height) = icon.size();
+                    Rectangle::new(0, 0, width as i32, height as i32)
+                });
+                icon.show_menu(&menu, anchor).map(|chosen| {
+                    if let Some(id) = chosen {
+                        send(IconEvent::Chosen(id));
+                    }
+                })
+            }
+            Err(std_mpsc::RecvTimeoutError::Timeout) => Ok(()),
+            Err(std_mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+
+        let result = result.and_then(|()| {
+            while let Some(event) = icon.poll_event()? {
+                match event {
+                    XEmbedEvent::Embedded { .. } => send(IconEvent::Docked(icon.window())),
+                    XEmbedEvent::Click { .. } | XEmbedEvent::Scroll { .. } => send(IconEvent::Input(event)),
+                    event => debug!("Mirror of {}: {:?}", address, event),
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("Mirror of {} stopped: {}", address, e);
+            return;
+        }
+    }
+}
+
+/// Tooltip title of the item, or the name it is known by
+fn tooltip(info: &NotifierInfo) -> String {
+    info.tooltip
+        .as_ref()
+        .map(|tooltip| tooltip.title.as_str())
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| info.display_name())
+        .to_string()
+}
+
+/// What `event` on the icon of `info` should do
+pub(super) fn forward_for(event: &XEmbedEvent, info: &NotifierInfo) -> Option<Forward> {
+    let menu = info.menu.as_ref().map(popup_menu).filter(|menu| !menu.is_empty());
+    match *event {
+        XEmbedEvent::Click { button, x, y } => {
+            let (x, y) = (x as i32, y as i32);
+            match (button, menu) {
+                (1, Some(menu)) if info.item_is_menu => Some(Forward::ShowMenu(menu)),
+                (1, _) => Some(Forward::Activate { x, y }),
+                (2, _) => Some(Forward::SecondaryActivate { x, y }),
+                (3, Some(menu)) => Some(Forward::ShowMenu(menu)),
+                // Items without a dbusmenu draw their own
+                (3, None) => Some(Forward::ContextMenu { x, y }),
+                _ => None,
+            }
+        }
+        XEmbedEvent::Scroll { delta, orientation } => Some(Forward::Scroll { delta, orientation }),
+        _ => None,
+    }
+}
+
+/// A dbusmenu tree as a popup, reporting entries by their dbusmenu id
+///
+/// Hidden entries are left out, along with separators that would end up
+/// at the edges of the menu.
+pub(super) fn popup_menu(node: &MenuNode) -> PopupMenu {
+    let mut menu = PopupMenu::new();
+    let mut separator = false;
+    for entry in node.children.iter().filter(|entry| entry.visible) {
+        if entry.kind == MenuKind::Separator {
+            separator = true;
+            continue;
+        }
+        if std::mem::take(&mut separator) {
+            menu.add_separator();
+        }
+        if entry.children.is_empty() {
+            menu.add_item(entry.id, &entry.label, entry.enabled, entry.toggle_state == Some(true));
+        } else {
+            menu.add_sub_menu(&entry.label, popup_menu(entry), entry.enabled);
+        }
+    }
+    menu
+}
@@ -0,0 +1,106 @@
+use image::Rgba;
+use crate::stray_impl::{MenuKind, MenuNode, NotifierInfo, ScrollOrientation, ToggleKind};
+use crate::tray::linux_impl::XEmbedEvent;
+use crate::tray::{PopupMenu, SniEvent};
+use super::sni_to_xembed::{forward_for, popup_menu, Forward};
+use super::xembed_to_sni::{button_for, decode_capture, BACKGROUND_KEY};
+
+#[test]
+fn test_decode_capture_unpremultiplies() {
+    // Half transparent orange, premultiplied, in BGRA and ARGB order
+    let lsb = [0, 64, 128, 128];
+    let msb = [128, 128, 64, 0];
+
+    for (data, lsb_first) in [(lsb, true), (msb, false)] {
+        let image = decode_capture(&data, 1, 1, 32, lsb_first).unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 127, 0, 128]));
+    }
+    assert!(decode_capture(&lsb, 2, 1, 32, true).is_none());
+}
+
+#[test]
+fn test_decode_capture_keys_out_background() {
+    let [r, g, b] = BACKGROUND_KEY;
+    // Padding bytes of depth 24 pixels are whatever the server left there
+    let data = [b, g, r, 0x55, 0, 0, 255, 0x55];
+
+    let image = decode_capture(&data, 2, 1, 24, true).unwrap();
+    assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+    assert_eq!(image.get_pixel(1, 0), &Rgba([255, 0, 0, 255]));
+}
+
+#[test]
+fn test_buttons_for_item_events() {
+    assert_eq!(button_for(&SniEvent::Activate { x: 5, y: 6 }), Some((1, 5, 6)));
+    assert_eq!(button_for(&SniEvent::SecondaryActivate { x: 5, y: 6 }), Some((2, 5, 6)));
+    assert_eq!(button_for(&SniEvent::ContextMenu { x: 5, y: 6 }), Some((3, 5, 6)));
+
+    let scroll = |delta, orientation| button_for(&SniEvent::Scroll { delta, orientation }).map(|(button, ..)| button);
+    assert_eq!(scroll(120, ScrollOrientation::Vertical), Some(4));
+    assert_eq!(scroll(-120, ScrollOrientation::Vertical), Some(5));
+    assert_eq!(scroll(120, ScrollOrientation::Horizontal), Some(6));
+    assert_eq!(scroll(-120, ScrollOrientation::Horizontal), Some(7));
+    assert_eq!(scroll(0, ScrollOrientation::Vertical), None);
+
+    assert_eq!(button_for(&SniEvent::WatcherLost), None);
+}
+
+fn entry(id: i32, label: &str) -> MenuNode {
+    MenuNode { id, label: label.into(), ..Default::default() }
+}
+
+fn separator(id: i32) -> MenuNode {
+    MenuNode { id, kind: MenuKind::Separator, ..Default::default() }
+}
+
+fn network_menu() -> MenuNode {
+    let hidden = MenuNode { visible: false, ..entry(3, "Debug") };
+    let checked = MenuNode { toggle: ToggleKind::Checkmark, toggle_state: Some(true), ..entry(5, "Wi-Fi") };
+    let settings = MenuNode {
+        children: vec![entry(7, "Preferences"), MenuNode { enabled: false, ..entry(8, "Advanced") }],
+        ..entry(6, "Settings")
+    };
+    MenuNode {
+        children: vec![separator(1), entry(2, "Connect"), hidden, separator(4), checked, settings, separator(9)],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_popup_menu_from_dbusmenu() {
+    let mut settings = PopupMenu::new();
+    settings.add_item(7, "Preferences", true, false);
+    settings.add_item(8, "Advanced", false, false);
+    let mut expected = PopupMenu::new();
+    expected.add_item(2, "Connect", true, false);
+    expected.add_separator();
+    expected.add_item(5, "Wi-Fi", true, true);
+    expected.add_sub_menu("Settings", settings, true);
+
+    assert_eq!(popup_menu(&network_menu()), expected);
+    assert!(popup_menu(&MenuNode { children: vec![separator(1)], ..Default::default() }).is_empty());
+}
+
+#[test]
+fn test_clicks_forwarded_to_items() {
+    let click = |button| XEmbedEvent::Click { button, x: 30, y: 5 };
+    let plain = NotifierInfo::default();
+    let with_menu = NotifierInfo { menu: Some(network_menu()), ..Default::default() };
+    let only_menu = NotifierInfo { item_is_menu: true, ..with_menu.clone() };
+    let menu = popup_menu(&network_menu());
+
+    assert_eq!(forward_for(&click(1), &plain), Some(Forward::Activate { x: 30, y: 5 }));
+    assert_eq!(forward_for(&click(1), &with_menu), Some(Forward::Activate { x: 30, y: 5 }));
+    assert_eq!(forward_for(&click(1), &only_menu), Some(Forward::ShowMenu(menu.clone())));
+    assert_eq!(forward_for(&click(2), &with_menu), Some(Forward::SecondaryActivate { x: 30, y: 5 }));
+    assert_eq!(forward_for(&click(3), &with_menu), Some(Forward::ShowMenu(menu)));
+    assert_eq!(forward_for(&click(3), &plain), Some(Forward::ContextMenu { x: 30, y: 5 }));
+    assert_eq!(forward_for(&click(8), &plain), None);
+
+    let scroll = XEmbedEvent::Scroll { delta: -120, orientation: ScrollOrientation::Horizontal };
+    assert_eq!(
+        forward_for(&scroll, &plain),
+        Some(Forward::Scroll { delta: -120, orientation: ScrollOrientation::Horizontal })
+    );
+    assert_eq!(forward_for(&XEmbedEvent::Unembedded, &plain), None);
+}
@@ -0,0 +1,637 @@
+//! XEmbed icons republished as StatusNotifierItems, for panels that only
+//! show the latter.
+//!
+//! The proxy becomes the tray manager by owning `_NET_SYSTEM_TRAY_S{screen}`,
+//! so old applications dock with it as with any tray. Each docked window is
+//! moved into a container out of sight and redirected with the Composite
+//! extension, which keeps it drawing there; when the Damage extension
+//! reports changes the window is copied, at most once a frame, into the
+//! pixmap of an item of its own. Clicks and wheel turns on the item go back
+//! to the window as synthetic button events, which most toolkits accept.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use image::RgbaImage;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, info, warn};
+use x11rb::connection::{Connection, RequestConnection as _};
+use x11rb::protocol::composite::{self, ConnectionExt as _};
+use x11rb::protocol::damage::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{
+    AtomEnum, ButtonPressEvent, ChangeWindowAttributesAux, ClientMessageEvent, Colormap, ColormapAlloc,
+    ConfigureWindowAux, ConnectionExt as _, CreateWindowAux, EventMask, ImageFormat, ImageOrder, KeyButMask, PropMode,
+    Screen, SetMode, Window, WindowClass, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{CURRENT_TIME, NONE};
+use crate::config::ItemStatus;
+use crate::error::{Result, TrayError};
+use crate::stray_impl::{IconPixmap, ScrollOrientation, ToolTip};
+use crate::tray::linux_impl::{Atoms, SYSTEM_TRAY_REQUEST_DOCK, XEMBED_EMBEDDED_NOTIFY, XEMBED_MAPPED, XEMBED_VERSION};
+use crate::tray::{ItemProperties, SniEvent, SniItem};
+
+/// Side of the square docked windows are given to draw in
+pub const EMBED_SIZE: u16 = 24;
+
+/// Containers sit off screen; redirected windows draw there all the same
+const OFFSCREEN: i16 = -1000;
+
+/// Damage is gathered and icons copied at most once per frame
+const FRAME: Duration = Duration::from_millis(33);
+
+/// Background of containers for windows without alpha, keyed out as
+/// transparency when they are captured. No icon uses it on purpose.
+pub(super) const BACKGROUND_KEY: [u8; 3] = [0x01, 0x02, 0x03];
+
+/// A docked window and the item it is shown as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxiedIcon {
+    pub window: u32,
+    /// Bus name of the item
+    pub address: String,
+    pub title: String,
+}
+
+/// A running proxy, serving until [`stop`](Self::stop) is called
+pub struct XEmbedToSni {
+    icons: Arc<Mutex<Vec<ProxiedIcon>>>,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl XEmbedToSni {
+    /// Manage the tray of `display`, `$DISPLAY` if `None`, and publish on
+    /// the bus at `bus`, the session bus if `None`
+    ///
+    /// Fails if another tray manager is running, as it would be replaced.
+    pub async fn start(display: Option<&str>, bus: Option<&str>) -> Result<Self> {
+        let (conn, screen) = x11rb::connect(display)?;
+        let conn = Arc::new(conn);
+        for extension in [composite::X11_EXTENSION_NAME, damage::X11_EXTENSION_NAME] {
+            if conn.extension_information(extension)?.is_none() {
+                return Err(TrayError::InitializationError(format!(
+                    "The X server has no {} extension",
+                    extension
+                )));
+            }
+        }
+        conn.composite_query_version(0, 4)?.reply()?;
+        conn.damage_query_version(1, 1)?.reply()?;
+
+        let atoms = Atoms::new(&*conn)?.reply()?;
+        let manager = take_selection(&conn, screen, &atoms)?;
+
+        let (events, x_events) = mpsc::unbounded_channel();
+        {
+            let conn = conn.clone();
+            std::thread::spawn(move || pump(&conn, manager, events));
+        }
+
+        let icons = Arc::new(Mutex::new(Vec::new()));
+        let (clicks, click_events) = mpsc::unbounded_channel();
+        let proxy = Proxy {
+            conn,
+            screen,
+            atoms,
+            manager,
+            bus: bus.map(str::to_string),
+            docked: HashMap::new(),
+            damaged: HashSet::new(),
+            icons: icons.clone(),
+            clicks,
+        };
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(proxy.run(x_events, click_events, stopped));
+
+        info!("Proxying XEmbed icons on screen {} as StatusNotifierItems", screen);
+        Ok(Self { icons, stop, task })
+    }
+
+    /// Docked windows and their items, in docking order
+    pub fn icons(&self) -> Vec<ProxiedIcon> {
+        self.icons.lock().unwrap().clone()
+    }
+
+    /// Close every item, hand the windows back and give up the selection
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        self.task
+            .await
+            .map_err(|e| TrayError::EventError(format!("XEmbed proxy failed: {}", e)))
+    }
+}
+
+/// Become the tray manager of `screen`, returning the selection owner window
+fn take_selection(conn: &RustConnection, screen: usize, atoms: &Atoms) -> Result<Window> {
+    let root = conn.setup().roots[screen].root;
+    let selection = conn
+        .intern_atom(false, format!("_NET_SYSTEM_TRAY_S{}", screen).as_bytes())?
+        .reply()?
+        .atom;
+    let owner = conn.get_selection_owner(selection)?.reply()?.owner;
+    if owner != NONE {
+        return Err(TrayError::InitializationError(format!(
+            "Tray manager {:#x} already owns _NET_SYSTEM_TRAY_S{}",
+            owner, screen
+        )));
+    }
+
+    let manager = conn.generate_id()?;
+    conn.create_window(
+        x11rb::COPY_DEPTH_FROM_PARENT,
+        manager,
+        root,
+        OFFSCREEN,
+        OFFSCREEN,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        x11rb::COPY_FROM_PARENT,
+        &CreateWindowAux::new().override_redirect(1).event_mask(EventMask::STRUCTURE_NOTIFY),
+    )?;
+    // Horizontal, like the panels the items end up in
+    conn.change_property32(PropMode::REPLACE, manager, atoms._NET_SYSTEM_TRAY_ORIENTATION, AtomEnum::CARDINAL, &[0])?;
+    conn.set_selection_owner(manager, selection, CURRENT_TIME)?;
+    if conn.get_selection_owner(selection)?.reply()?.owner != manager {
+        conn.destroy_window(manager)?;
+        conn.flush()?;
+        return Err(TrayError::InitializationError(format!(
+            "Could not take _NET_SYSTEM_TRAY_S{}",
+            screen
+        )));
+    }
+
+    let announce = ClientMessageEvent::new(32, root, atoms.MANAGER, [CURRENT_TIME, selection, manager, 0, 0]);
+    conn.send_event(false, root, EventMask::STRUCTURE_NOTIFY, announce)?;
+    conn.flush()?;
+    Ok(manager)
+}
+
+/// Hand X events to the proxy task until the manager window is destroyed
+fn pump(conn: &RustConnection, manager: Window, events: mpsc::UnboundedSender<Event>) {
+    loop {
+        let event = match conn.wait_for_event() {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Lost the X connection: {}", e);
+                return;
+            }
+        };
+        if matches!(event, Event::DestroyNotify(ref e) if e.window == manager) || events.send(event).is_err() {
+            return;
+        }
+    }
+}
+
+struct Docked {
+    container: Window,
+    /// Colormap made for the container, `NONE` if it uses the default one
+    colormap: Colormap,
+    damage: damage::Damage,
+    item: SniItem,
+    forwarder: JoinHandle<()>,
+}
+
+struct Proxy {
+    conn: Arc<RustConnection>,
+    screen: usize,
+    atoms: Atoms,
+    manager: Window,
+    bus: Option<String>,
+    docked: HashMap<Window, Docked>,
+    /// Windows that drew since their icon was last copied
+    damaged: HashSet<Window>,
+    icons: Arc<Mutex<Vec<ProxiedIcon>>>,
+    clicks: mpsc::UnboundedSender<(Window, SniEvent)>,
+}
+
+impl Proxy {
+    async fn run(
+        mut self,
+        mut x_events: mpsc::UnboundedReceiver<Event>,
+        mut clicks: mpsc::UnboundedReceiver<(Window, SniEvent)>,
+        mut stopped: oneshot::Receiver<()>,
+    ) {
+        let mut frame = time::interval(FRAME);
+        frame.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let result = tokio::select! {
+                _ = &mut stopped => break,
+                event = x_events.recv() => match event {
+                    Some(event) => self.handle(event).await,
+                    None => break,
+                },
+                Some((window, event)) = clicks.recv() => self.forward(window, &event),
+                _ = frame.tick(), if !self.damaged.is_empty() => self.refresh_damaged().await,
+            };
+            if let Err(e) = result {
+                warn!("XEmbed proxy: {}", e);
+            }
+        }
+
+        if let Err(e) = self.shut_down().await {
+            warn!("Could not shut the XEmbed proxy down cleanly: {}", e);
+        }
+    }
+
+    fn screen(&self) -> &Screen {
+        &self.conn.setup().roots[self.screen]
+    }
+
+    async fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::ClientMessage(e)
+                if e.window == self.manager
+                    && e.type_ == self.atoms._NET_SYSTEM_TRAY_OPCODE
+                    && e.data.as_data32()[1] == SYSTEM_TRAY_REQUEST_DOCK =>
+            {
+                let window = e.data.as_data32()[2];
+                if !self.docked.contains_key(&window) {
+                    self.dock(window).await?;
+                }
+            }
+            Event::DamageNotify(e) if self.docked.contains_key(&e.drawable) => {
+                self.conn.damage_subtract(e.damage, NONE, NONE)?;
+                self.damaged.insert(e.drawable);
+            }
+            Event::PropertyNotify(e) if self.docked.contains_key(&e.window) => {
+                if e.atom == self.atoms._NET_WM_NAME || e.atom == u32::from(AtomEnum::WM_NAME) {
+                    self.refresh_title(e.window).await?;
+                } else if e.atom == self.atoms._XEMBED_INFO {
+                    self.refresh_mapping(e.window).await?;
+                }
+            }
+            Event::DestroyNotify(e) if self.docked.contains_key(&e.window) => {
+                self.undock(e.window, false).await?;
+            }
+            Event::ReparentNotify(e) if self.docked.get(&e.window).is_some_and(|d| d.container != e.parent) => {
+                self.undock(e.window, false).await?;
+            }
+            Event::SelectionClear(e) if e.owner == self.manager => {
+                warn!("Another tray manager took over, no new icons will be proxied");
+            }
+            Event::Error(e) => debug!("X11 error: {:?}", e),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn dock(&mut self, window: Window) -> Result<()> {
+        let mapped = self.wants_mapping(window)?;
+        let title = self.title(window)?;
+        let properties = ItemProperties {
+            id: self.class(window)?.unwrap_or_else(|| format!("xembed-{:#x}", window)),
+            title: title.clone(),
+            status: if mapped { ItemStatus::Active } else { ItemStatus::Passive },
+            tooltip: ToolTip { title: title.clone(), ..Default::default() },
+            ..Default::default()
+        };
+        // Publish before touching the window, so a bus that refuses the item
+        // leaves nothing on the X side to undo
+        let item = SniItem::publish_on(super::connect(self.bus.as_deref()).await?, properties).await?;
+        let (container, colormap, damage) = match self.embed(window, mapped) {
+            Ok(embedded) => embedded,
+            Err(e) => {
+                let address = item.address().to_string();
+                if let Err(close) = item.close().await {
+                    warn!("Could not close {}: {}", address, close);
+                }
+                return Err(e);
+            }
+        };
+
+        let forwarder = tokio::spawn(forward_events(window, item.subscribe(), self.clicks.clone()));
+        info!("Proxying docked window {:#x} as {}", window, item.address());
+
+        self.icons.lock().unwrap().push(ProxiedIcon {
+            window,
+            address: item.address().to_string(),
+            title,
+        });
+        self.docked.insert(window, Docked { container, colormap, damage, item, forwarder });
+        // Windows that drew before being redirected report no damage
+        if mapped {
+            self.damaged.insert(window);
+        }
+        Ok(())
+    }
+
+    /// Move `window` into a container out of sight and redirect it, returning
+    /// the container, its colormap and the Damage object watching the window
+    fn embed(&self, window: Window, mapped: bool) -> Result<(Window, Colormap, damage::Damage)> {
+        let attributes = self.conn.get_window_attributes(window)?.reply()?;
+        let depth = self.conn.get_geometry(window)?.reply()?.depth;
+        let screen = self.screen();
+        let root = screen.root;
+
+        // The container shares the window's visual, so ParentRelative
+        // backgrounds keep working
+        let mut aux = CreateWindowAux::new().override_redirect(1);
+        let mut colormap = NONE;
+        if attributes.visual != screen.root_visual {
+            colormap = self.conn.generate_id()?;
+            self.conn.create_colormap(ColormapAlloc::NONE, colormap, root, attributes.visual)?;
+            aux = aux.colormap(colormap).border_pixel(0);
+        }
+        aux = aux.background_pixel(if depth == 32 { 0 } else { key_pixel() });
+
+        let container = self.conn.generate_id()?;
+        self.conn.create_window(
+            depth,
+            container,
+            root,
+            OFFSCREEN,
+            OFFSCREEN,
+            EMBED_SIZE,
+            EMBED_SIZE,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            attributes.visual,
+            &aux,
+        )?;
+
+        let events = EventMask::STRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE;
+        self.conn.change_window_attributes(window, &ChangeWindowAttributesAux::new().event_mask(events))?;
+        // Keep the window alive if the proxy goes away
+        self.conn.change_save_set(SetMode::INSERT, window)?;
+        self.conn.reparent_window(window, container, 0, 0)?;
+        self.conn.configure_window(
+            window,
+            &ConfigureWindowAux::new().width(EMBED_SIZE as u32).height(EMBED_SIZE as u32),
+        )?;
+        self.conn.composite_redirect_window(window, composite::Redirect::MANUAL)?;
+        let damage = self.conn.generate_id()?;
+        self.conn.damage_create(damage, window, damage::ReportLevel::NON_EMPTY)?;
+        self.conn.map_window(container)?;
+        if mapped {
+            self.conn.map_window(window)?;
+        }
+        let notify = ClientMessageEvent::new(
+            32,
+            window,
+            self.atoms._XEMBED,
+            [CURRENT_TIME, XEMBED_EMBEDDED_NOTIFY, 0, container, XEMBED_VERSION],
+        );
+        self.conn.send_event(false, window, EventMask::NO_EVENT, notify)?;
+        self.conn.flush()?;
+        Ok((container, colormap, damage))
+    }
+
+    /// Let go of `window`, handing it back to the root window unless it is gone
+    async fn undock(&mut self, window: Window, hand_back: bool) -> Result<()> {
+        let Some(docked) = self.docked.remove(&window) else { return Ok(()) };
+        self.damaged.remove(&window);
+        self.icons.lock().unwrap().retain(|icon| icon.window != window);
+        docked.forwarder.abort();
+        info!("Docked window {:#x} left, closing {}", window, docked.item.address());
+
+        if hand_back {
+            self.conn.damage_destroy(docked.damage)?;
+            self.conn.composite_unredirect_window(window, composite::Redirect::MANUAL)?;
+            self.conn.unmap_window(window)?;
+            self.conn.reparent_window(window, self.screen().root, 0, 0)?;
+        }
+        self.conn.destroy_window(docked.container)?;
+        if docked.colormap != NONE {
+            self.conn.free_colormap(docked.colormap)?;
+        }
+        self.conn.flush()?;
+        docked.item.close().await
+    }
+
+    async fn shut_down(mut self) -> Result<()> {
+        let windows: Vec<Window> = self.docked.keys().copied().collect();
+        for window in windows {
+            if let Err(e) = self.undock(window, true).await {
+                warn!("Could not hand back {:#x}: {}", window, e);
+            }
+        }
+        // Also ends the pump and gives up the selection
+        self.conn.destroy_window(self.manager)?;
+        self.conn.flush()?;
+        info!("XEmbed proxy stopped");
+        Ok(())
+    }
+
+    /// Copy every window that drew since the last frame
+    async fn refresh_damaged(&mut self) -> Result<()> {
+        for window in std::mem::take(&mut self.damaged) {
+            if let Err(e) = self.refresh_icon(window).await {
+                warn!("Could not copy the icon of {:#x}: {}", window, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn refresh_icon(&self, window: Window) -> Result<()> {
+        let Some(docked) = self.docked.get(&window) else { return Ok(()) };
+        let image = self.capture(window)?;
+        docked.item.set_icon("", vec![IconPixmap::from_image(&image)]).await
+    }
+
+    async fn refresh_title(&mut self, window: Window) -> Result<()> {
+        let title = self.title(window)?;
+        let Some(docked) = self.docked.get(&window) else { return Ok(()) };
+        docked.item.set_title(&title).await?;
+        docked.item.set_tooltip(ToolTip { title: title.clone(), ..Default::default() }).await?;
+        for icon in self.icons.lock().unwrap().iter_mut().filter(|icon| icon.window == window) {
+            icon.title = title.clone();
+        }
+        Ok(())
+    }
+
+    /// Follow the window's `_XEMBED_INFO` flags, hiding the item with it
+    async fn refresh_mapping(&self, window: Window) -> Result<()> {
+        let Some(docked) = self.docked.get(&window) else { return Ok(()) };
+        let mapped = self.wants_mapping(window)?;
+        if mapped {
+            self.conn.map_window(window)?;
+        } else {
+            self.conn.unmap_window(window)?;
+        }
+        self.conn.flush()?;
+        let status = if mapped { ItemStatus::Active } else { ItemStatus::Passive };
+        docked.item.set_status(status).await
+    }
+
+    /// Copy what the window drew, through the pixmap Composite keeps for it
+    fn capture(&self, window: Window) -> Result<RgbaImage> {
+        let geometry = self.conn.get_geometry(window)?.reply()?;
+        let pixmap = self.conn.generate_id()?;
+        self.conn.composite_name_window_pixmap(window, pixmap)?;
+        let image = self
+            .conn
+            .get_image(ImageFormat::Z_PIXMAP, pixmap, 0, 0, geometry.width, geometry.height, !0)?
+            .reply();
+        self.conn.free_pixmap(pixmap)?;
+        let image = image?;
+
+        let setup = self.conn.setup();
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == geometry.depth)
+            .map(|format| format.bits_per_pixel);
+        if bits_per_pixel != Some(32) {
+            return Err(TrayError::X11Error(format!(
+                "Cannot capture depth {} at {:?} bits per pixel",
+                geometry.depth, bits_per_pixel
+            )));
+        }
+        decode_capture(
+            &image.data,
+            geometry.width as u32,
+            geometry.height as u32,
+            geometry.depth,
+            setup.image_byte_order == ImageOrder::LSB_FIRST,
+        )
+        .ok_or_else(|| TrayError::X11Error(format!("Short image of {:#x}", window)))
+    }
+
+    /// Whether the window's `_XEMBED_INFO` asks to be shown, as it does without one
+    fn wants_mapping(&self, window: Window) -> Result<bool> {
+        let reply = self
+            .conn
+            .get_property(false, window, self.atoms._XEMBED_INFO, self.atoms._XEMBED_INFO, 0, 2)?
+            .reply()?;
+        let flags = reply.value32().and_then(|mut values| values.nth(1));
+        Ok(flags.is_none_or(|flags| flags & XEMBED_MAPPED != 0))
+    }
+
+    fn title(&self, window: Window) -> Result<String> {
+        let names = [
+            (self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING),
+            (AtomEnum::WM_NAME.into(), AtomEnum::STRING.into()),
+        ];
+        for (property, kind) in names {
+            let value = self.conn.get_property(false, window, property, kind, 0, 1024)?.reply()?.value;
+            if !value.is_empty() {
+                return Ok(String::from_utf8_lossy(&value).into_owned());
+            }
+        }
+        Ok(String::new())
+    }
+
+    /// Class from `WM_CLASS`, which old applications set even when they have no title
+    fn class(&self, window: Window) -> Result<Option<String>> {
+        let value = self
+            .conn
+            .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 1024)?
+            .reply()?
+            .value;
+        Ok(value
+            .split(|&byte| byte == 0)
+            .rfind(|part| !part.is_empty())
+            .map(|class| String::from_utf8_lossy(class).into_owned()))
+    }
+
+    /// Press and release a button over the window, for a click on its item
+    fn forward(&self, window: Window, event: &SniEvent) -> Result<()> {
+        let Some((button, x, y)) = button_for(event) else { return Ok(()) };
+        let center = (EMBED_SIZE / 2) as i16;
+        for (response_type, mask) in [
+            (BUTTON_PRESS_EVENT, EventMask::BUTTON_PRESS),
+            (BUTTON_RELEASE_EVENT, EventMask::BUTTON_RELEASE),
+        ] {
+            let event = ButtonPressEvent {
+                response_type,
+                detail: button,
+                sequence: 0,
+                time: CURRENT_TIME,
+                root: self.screen().root,
+                event: window,
+                child: NONE,
+                root_x: x as i16,
+                root_y: y as i16,
+                event_x: center,
+                event_y: center,
+                state: KeyButMask::default(),
+                same_screen: true,
+            };
+            self.conn.send_event(false, window, mask, event)?;
+        }
+        self.conn.flush()?;
+        debug!("Forwarded button {} to {:#x}", button, window);
+        Ok(())
+    }
+}
+
+async fn forward_events(
+    window: Window,
+    mut events: broadcast::Receiver<SniEvent>,
+    clicks: mpsc::UnboundedSender<(Window, SniEvent)>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if clicks.send((window, event)).is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// X button and root position for a click or wheel turn on an item
+pub(super) fn button_for(event: &SniEvent) -> Option<(u8, i32, i32)> {
+    match *event {
+        SniEvent::Activate { x, y } => Some((1, x, y)),
+        SniEvent::SecondaryActivate { x, y } => Some((2, x, y)),
+        SniEvent::ContextMenu { x, y } => Some((3, x, y)),
+        SniEvent::Scroll { delta, orientation } if delta != 0 => {
+            let button = match (orientation, delta > 0) {
+                (ScrollOrientation::Vertical, true) => 4,
+                (ScrollOrientation::Vertical, false) => 5,
+                (ScrollOrientation::Horizontal, true) => 6,
+                (ScrollOrientation::Horizontal, false) => 7,
+            };
+            Some((button, 0, 0))
+        }
+        _ => None,
+    }
+}
+
+/// [`BACKGROUND_KEY`] as a pixel of a 24 bit TrueColor visual
+fn key_pixel() -> u32 {
+    let [r, g, b] = BACKGROUND_KEY;
+    u32::from_be_bytes([0, r, g, b])
+}
+
+/// RGBA pixels from ZPixmap data at 32 bits per pixel
+///
+/// Depth 32 data is premultiplied; at depth 24 the container background
+/// shows where the window drew nothing and becomes transparent. `None` if
+/// `data` is too short.
+pub(super) fn decode_capture(data: &[u8], width: u32, height: u32, depth: u8, lsb_first: bool) -> Option<RgbaImage> {
+    let pixels = data
+        .get(..(width * height * 4) as usize)?
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let [a, r, g, b] = if lsb_first {
+                [pixel[3], pixel[2], pixel[1], pixel[0]]
+            } else {
+                [pixel[0], pixel[1], pixel[2], pixel[3]]
+            };
+            if depth == 32 {
+                let unpremultiply = |channel: u8| match a {
+                    0 => 0,
+                    a => (channel as u32 * 255 / a as u32).min(255) as u8,
+                };
+                [unpremultiply(r), unpremultiply(g), unpremultiply(b), a]
+            } else if [r, g, b] == BACKGROUND_KEY {
+                [0, 0, 0, 0]
+            } else {
+                [r, g, b, 0xff]
+            }
+        })
+        .collect();
+    RgbaImage::from_raw(width, height, pixels)
+}
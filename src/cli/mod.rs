@@ -0,0 +1,543 @@
+//! Command line front end for inspecting and driving the desktop tray.
+//!
+//! Every command except `run`, `replay`, `watcher`, `proxy-watcher`, `xembed-proxy`, `xembed-mirror` and
+//! `backend` starts a host in the background, waits for the tray to report its items and then acts on them,
+//! so it can be used from shell scripts and CI jobs.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Result};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+use crate::backend::Detector;
+use crate::bridge::{SniToXEmbed, XEmbedToSni};
+use crate::config::AppConfig;
+use crate::icon::IconThemeResolver;
+use crate::stray_impl::{AlphaMode, HostEvent, HostHandle, MenuNode, NotifierInfo, ProcessInfo, Replay, StrayTrayApp, ToggleKind};
+use crate::watcher::{ProxyWatcher, StatusNotifierWatcher};
+
+#[cfg(test)]
+mod tests;
+
+/// How long the tray must stay quiet before the item list counts as complete
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Longest wait for an item or its menu to show up
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time given to stray to deliver a queued command before the host stops
+const COMMAND_GRACE: Duration = Duration::from_millis(250);
+
+const DEFAULT_ICON_SIZE: u32 = 22;
+
+pub const USAGE: &str = "\
+Usage: system_tray_linux_aio [COMMAND]
+
+Commands:
+  run [--record <file.jsonl>]   Run the tray host until Ctrl+C (default),
+                                recording events and commands if asked
+  list [--json]                 List the items in the tray
+  watch [--json]                Print tray events as they happen
+  watcher                       Serve a StatusNotifierWatcher until Ctrl+C,
+                                for desktops that do not have one
+  proxy-watcher                 Serve a StatusNotifierWatcher until Ctrl+C that
+                                shows panels the items as [items] configures
+  xembed-proxy                  Take over the XEmbed tray until Ctrl+C and
+                                show its icons as StatusNotifierItems
+  xembed-mirror                 Show every StatusNotifierItem in the XEmbed
+                                tray until Ctrl+C, menus included
+  backend [--json]              Show which tray backend would be used for our
+                                own icon, and why the others would not
+  replay <file.jsonl> [--speed <factor>|--speed max] [--json]
+                                Print the events of a recording, played back
+                                at its original pace times <factor>
+  menu <item> [--json]          Print the menu tree of an item
+  click <item> <path>           Click a menu entry, e.g. \"Settings/Preferences\"
+  activate <item> [--secondary] Activate an item like a left (or middle) click
+  process <item> [--json]       Show the process owning an item
+  terminate <item> [--force]    Stop the application owning an item
+  restart <item>                Stop the application owning an item and start it again
+  icon <item> --out <file.png> [--size <px>] [--premultiplied]
+                                Save the icon of an item as PNG, reading
+                                pixmaps as premultiplied alpha if asked
+  help                          Show this message
+
+<item> is a bus name, an item Id or a title.
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run { record: Option<PathBuf> },
+    List { json: bool },
+    Watch { json: bool },
+    Watcher,
+    ProxyWatcher,
+    XEmbedProxy,
+    XEmbedMirror,
+    Backend { json: bool },
+    Replay { file: PathBuf, speed: f64, json: bool },
+    Menu { item: String, json: bool },
+    Click { item: String, path: Vec<String> },
+    Activate { item: String, secondary: bool },
+    Process { item: String, json: bool },
+    Terminate { item: String, force: bool },
+    Restart { item: String },
+    Icon { item: String, out: PathBuf, size: u32, alpha: AlphaMode },
+    Help,
+}
+
+impl Command {
+    /// Parse the arguments following the program name
+    pub fn parse<I, S>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut args = Arguments::parse(args.into_iter().map(Into::into))?;
+        if args.help {
+            return Ok(Command::Help);
+        }
+
+        let command = match args.next_positional().as_deref() {
+            None | Some("run") => Command::Run { record: args.option("record").map(PathBuf::from) },
+            Some("help") => Command::Help,
+            Some("list") => Command::List { json: args.flag("json") },
+            Some("watch") => Command::Watch { json: args.flag("json") },
+            Some("watcher") => Command::Watcher,
+            Some("proxy-watcher") => Command::ProxyWatcher,
+            Some("xembed-proxy") => Command::XEmbedProxy,
+            Some("xembed-mirror") => Command::XEmbedMirror,
+            Some("backend") => Command::Backend { json: args.flag("json") },
+            Some("replay") => Command::Replay {
+                file: PathBuf::from(args.required("file")?),
+                speed: match args.option("speed").as_deref() {
+                    None => 1.0,
+                    Some("max") => f64::INFINITY,
+                    Some(speed) => speed
+                        .parse()
+                        .ok()
+                        .filter(|speed: &f64| *speed > 0.0)
+                        .ok_or_else(|| anyhow!("Invalid replay speed: {}", speed))?,
+                },
+                json: args.flag("json"),
+            },
+            Some("menu") => Command::Menu {
+                item: args.required("item")?,
+                json: args.flag("json"),
+            },
+            Some("click") => {
+                let item = args.required("item")?;
+                let path = split_menu_path(&args.required("path")?);
+                if path.is_empty() {
+                    bail!("Menu path is empty");
+                }
+                Command::Click { item, path }
+            }
+            Some("activate") => Command::Activate {
+                item: args.required("item")?,
+                secondary: args.flag("secondary"),
+            },
+            Some("process") => Command::Process {
+                item: args.required("item")?,
+                json: args.flag("json"),
+            },
+            Some("terminate") => Command::Terminate {
+                item: args.required("item")?,
+                force: args.flag("force"),
+            },
+            Some("restart") => Command::Restart { item: args.required("item")? },
+            Some("icon") => Command::Icon {
+                item: args.required("item")?,
+                out: args
+                    .option("out")
+                    .map(PathBuf::from)
+                    .ok_or_else(|| anyhow!("icon needs --out <file.png>"))?,
+                size: match args.option("size") {
+                    Some(size) => size.parse().map_err(|_| anyhow!("Invalid icon size: {}", size))?,
+                    None => DEFAULT_ICON_SIZE,
+                },
+                alpha: if args.flag("premultiplied") {
+                    AlphaMode::Premultiplied
+                } else {
+                    AlphaMode::Straight
+                },
+            },
+            Some(other) => bail!("Unknown command: {}", other),
+        };
+
+        args.finish()?;
+        Ok(command)
+    }
+}
+
+/// Split `Settings/Preferences` into labels
+pub fn split_menu_path(path: &str) -> Vec<String> {
+    path.split('/')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Arguments sorted into positionals and `--name [value]` options
+struct Arguments {
+    positionals: std::collections::VecDeque<String>,
+    options: Vec<(String, Option<String>)>,
+    help: bool,
+}
+
+/// Options that take a value
+const VALUE_OPTIONS: [&str; 4] = ["out", "size", "record", "speed"];
+
+impl Arguments {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self {
+            positionals: Default::default(),
+            options: vec![],
+            help: false,
+        };
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                parsed.help = true;
+            } else if let Some(name) = arg.strip_prefix("--") {
+                let (name, value) = match name.split_once('=') {
+                    Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                    None if VALUE_OPTIONS.contains(&name) => {
+                        let value = args.next().ok_or_else(|| anyhow!("--{} needs a value", name))?;
+                        (name.to_string(), Some(value))
+                    }
+                    None => (name.to_string(), None),
+                };
+                parsed.options.push((name, value));
+            } else {
+                parsed.positionals.push_back(arg);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn next_positional(&mut self) -> Option<String> {
+        self.positionals.pop_front()
+    }
+
+    fn required(&mut self, name: &str) -> Result<String> {
+        self.next_positional().ok_or_else(|| anyhow!("Missing <{}>", name))
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        let before = self.options.len();
+        self.options.retain(|(option, value)| !(option == name && value.is_none()));
+        self.options.len() != before
+    }
+
+    fn option(&mut self, name: &str) -> Option<String> {
+        let index = self.options.iter().position(|(option, value)| option == name && value.is_some())?;
+        self.options.remove(index).1
+    }
+
+    /// Fail on anything the command did not consume
+    fn finish(self) -> Result<()> {
+        if let Some(extra) = self.positionals.front() {
+            bail!("Unexpected argument: {}", extra);
+        }
+        if let Some((option, _)) = self.options.first() {
+            bail!("Unknown option: --{}", option);
+        }
+        Ok(())
+    }
+}
+
+/// Execute a parsed command
+pub async fn run(command: Command, config: AppConfig) -> Result<()> {
+    if let Command::Backend { json } = command {
+        return backend(&config, json).await;
+    }
+    if command == Command::ProxyWatcher {
+        let proxy = ProxyWatcher::start(config).await?;
+        tokio::signal::ctrl_c().await?;
+        proxy.stop().await?;
+        return Ok(());
+    }
+    let app = StrayTrayApp::new(config);
+
+    match command {
+        Command::Run { record: Some(path) } => app.record_to(path).run().await,
+        Command::Run { record: None } => app.run().await,
+        Command::Replay { file, speed, json } => replay(app, &file, speed, json).await,
+        Command::Watcher => {
+            let watcher = StatusNotifierWatcher::start_session().await?;
+            tokio::signal::ctrl_c().await?;
+            watcher.stop().await?;
+            Ok(())
+        }
+        Command::XEmbedProxy => {
+            let proxy = XEmbedToSni::start(None, None).await?;
+            tokio::signal::ctrl_c().await?;
+            proxy.stop().await?;
+            Ok(())
+        }
+        Command::XEmbedMirror => {
+            let host = app.spawn();
+            let mirror = SniToXEmbed::start(None, host.handle()).await?;
+            tokio::signal::ctrl_c().await?;
+            mirror.stop().await?;
+            host.stop().await?;
+            Ok(())
+        }
+        Command::Help => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        command => {
+            let host = app.spawn();
+            let result = execute(&host, command).await;
+            host.stop().await?;
+            result
+        }
+    }
+}
+
+/// Probe every tray backend for our own icon, in the configured order
+async fn backend(config: &AppConfig, json: bool) -> Result<()> {
+    let report = Detector::new(config).probe_all().await;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+    Ok(())
+}
+
+async fn execute(handle: &HostHandle, command: Command) -> Result<()> {
+    match command {
+        Command::List { json } => {
+            settle(handle).await;
+            let items = handle.items().await;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&items)?);
+            } else {
+                for item in &items {
+                    println!("{}", format_item(item));
+                }
+            }
+        }
+        Command::Watch { json } => watch(handle, json).await?,
+        Command::Menu { item, json } => {
+            let info = wait_for_item(handle, &item, true).await?;
+            let menu = info.menu.as_ref().ok_or_else(|| anyhow!("{} has no menu", info.display_name()))?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(menu)?);
+            } else {
+                print!("{}", format_menu(menu));
+            }
+        }
+        Command::Click { item, path } => {
+            let info = wait_for_item(handle, &item, true).await?;
+            handle.click_by_path(&info.address, &path).await?;
+            tokio::time::sleep(COMMAND_GRACE).await;
+        }
+        Command::Activate { item, secondary } => {
+            let info = wait_for_item(handle, &item, false).await?;
+            if secondary {
+                handle.secondary_activate(&info.address, 0, 0).await?;
+            } else {
+                handle.activate(&info.address, 0, 0).await?;
+            }
+        }
+        Command::Process { item, json } => {
+            let info = wait_for_item(handle, &item, false).await?;
+            let process = handle.item_process(&info.address).await?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&process)?);
+            } else {
+                print!("{}", format_process(&process));
+            }
+        }
+        Command::Terminate { item, force } => {
+            let info = wait_for_item(handle, &item, false).await?;
+            let process = handle.terminate(&info.address, force).await?;
+            println!("Terminated {} ({})", process.name, process.pid);
+        }
+        Command::Restart { item } => {
+            let info = wait_for_item(handle, &item, false).await?;
+            let pid = handle.restart(&info.address).await?;
+            println!("Restarted {} as {}", info.display_name(), pid);
+        }
+        Command::Icon { item, out, size, alpha } => {
+            let info = wait_for_item(handle, &item, false).await?;
+            let image = handle.item_icon(&IconThemeResolver::system(), &info.address, size, alpha).await?;
+            image.save(&out)?;
+            println!("Saved {}", out.display());
+        }
+        Command::Run { .. }
+        | Command::Replay { .. }
+        | Command::Watcher
+        | Command::ProxyWatcher
+        | Command::XEmbedProxy
+        | Command::XEmbedMirror
+        | Command::Backend { .. }
+        | Command::Help => unreachable!("handled by run"),
+    }
+
+    Ok(())
+}
+
+async fn watch(handle: &HostHandle, json: bool) -> Result<()> {
+    let mut events = handle.subscribe();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => print_event(&event, json)?,
+                Err(RecvError::Lagged(missed)) => eprintln!("Missed {} events", missed),
+                Err(RecvError::Closed) => break,
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn replay(app: StrayTrayApp, file: &Path, speed: f64, json: bool) -> Result<()> {
+    let replay = Replay::load(file).await?;
+
+    // Subscribe before playback starts so the first events are not missed
+    let mut events = app.handle().subscribe();
+    let host = app.spawn_replay(replay, speed);
+    let handle = host.handle();
+    let mut done = std::pin::pin!(host.join());
+
+    loop {
+        tokio::select! {
+            biased;
+            event = events.recv() => match event {
+                Ok(event) => print_event(&event, json)?,
+                Err(RecvError::Lagged(missed)) => eprintln!("Missed {} events", missed),
+                Err(RecvError::Closed) => break,
+            },
+            result = &mut done => {
+                while let Ok(event) = events.try_recv() {
+                    print_event(&event, json)?;
+                }
+                return result;
+            }
+            _ = tokio::signal::ctrl_c() => handle.shutdown(),
+        }
+    }
+
+    done.await
+}
+
+fn print_event(event: &HostEvent, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(event)?);
+    } else {
+        println!("{}", format_event(event));
+    }
+    Ok(())
+}
+
+/// Wait until no event arrived for [`SETTLE_TIME`], or [`WAIT_TIMEOUT`] passed
+async fn settle(handle: &HostHandle) {
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    let mut events = handle.subscribe();
+
+    while Instant::now() < deadline {
+        match tokio::time::timeout(SETTLE_TIME, events.recv()).await {
+            Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+            Ok(Err(RecvError::Closed)) | Err(_) => break,
+        }
+    }
+}
+
+/// Wait for an item matching `query`, and for its menu when `with_menu` is set
+async fn wait_for_item(handle: &HostHandle, query: &str, with_menu: bool) -> Result<NotifierInfo> {
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    let mut events = handle.subscribe();
+
+    loop {
+        if let Some(info) = handle.find_item(query).await {
+            if !with_menu || info.menu.is_some() || info.menu_path.is_empty() {
+                return Ok(info);
+            }
+        }
+
+        match tokio::time::timeout_at(deadline, events.recv()).await {
+            Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+            Ok(Err(RecvError::Closed)) | Err(_) => break,
+        }
+    }
+
+    handle
+        .find_item(query)
+        .await
+        .ok_or_else(|| anyhow!("No tray item matches \"{}\"", query))
+}
+
+/// One line per item: bus name, status, id and title
+pub fn format_item(info: &NotifierInfo) -> String {
+    format!("{:<12} {:<15} {:<24} {}", info.address, info.status.as_dbus(), info.id, info.title)
+        .trim_end()
+        .to_string()
+}
+
+/// Labelled fields of a process, unknown ones left out
+pub fn format_process(process: &ProcessInfo) -> String {
+    let mut out = format!("pid:     {}\nname:    {}\n", process.pid, process.name);
+    if let Some(exe) = &process.exe {
+        out.push_str(&format!("exe:     {}\n", exe.display()));
+    }
+    if !process.cmdline.is_empty() {
+        out.push_str(&format!("cmdline: {}\n", process.cmdline.join(" ")));
+    }
+    if let Some(desktop_file) = &process.desktop_file {
+        out.push_str(&format!("desktop: {}\n", desktop_file.display()));
+    }
+    out
+}
+
+pub fn format_event(event: &HostEvent) -> String {
+    match event {
+        HostEvent::Added(info) => format!("added   {} {}", info.address, info.display_name()),
+        HostEvent::Changed { info, fields } => {
+            let fields: Vec<String> = fields.iter().map(|field| format!("{:?}", field)).collect();
+            format!("changed {} {} [{}]", info.address, info.display_name(), fields.join(", "))
+        }
+        HostEvent::Removed(info) => format!("removed {} {}", info.address, info.display_name()),
+    }
+}
+
+/// Indented menu tree, hidden entries left out
+pub fn format_menu(menu: &MenuNode) -> String {
+    let mut out = String::new();
+    write_entries(menu, 0, &mut out);
+    out
+}
+
+fn write_entries(node: &MenuNode, depth: usize, out: &mut String) {
+    for child in node.children.iter().filter(|child| child.visible) {
+        let indent = "  ".repeat(depth);
+
+        if child.is_separator() {
+            out.push_str(&format!("{}----\n", indent));
+            continue;
+        }
+
+        let toggle = match (child.toggle, child.toggle_state) {
+            (ToggleKind::Checkmark, Some(true)) => "[x] ",
+            (ToggleKind::Checkmark, _) => "[ ] ",
+            (ToggleKind::Radio, Some(true)) => "(*) ",
+            (ToggleKind::Radio, _) => "( ) ",
+            (ToggleKind::None, _) => "",
+        };
+        let disabled = if child.enabled { "" } else { " (disabled)" };
+
+        out.push_str(&format!("{}{}{}{}\n", indent, toggle, child.label, disabled));
+        write_entries(child, depth + 1, out);
+    }
+}
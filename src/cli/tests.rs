@@ -0,0 +1,143 @@
+use super::*;
+use crate::config::ItemStatus;
+use crate::stray_impl::{ItemField, MenuKind};
+
+#[test]
+fn test_parse_commands() {
+    assert_eq!(Command::parse(Vec::<String>::new()).unwrap(), Command::Run { record: None });
+    assert_eq!(
+        Command::parse(["run", "--record", "tray.jsonl"]).unwrap(),
+        Command::Run { record: Some(PathBuf::from("tray.jsonl")) }
+    );
+    assert_eq!(
+        Command::parse(["replay", "tray.jsonl", "--speed=4"]).unwrap(),
+        Command::Replay { file: PathBuf::from("tray.jsonl"), speed: 4.0, json: false }
+    );
+    assert_eq!(
+        Command::parse(["replay", "tray.jsonl", "--speed", "max", "--json"]).unwrap(),
+        Command::Replay { file: PathBuf::from("tray.jsonl"), speed: f64::INFINITY, json: true }
+    );
+    assert_eq!(Command::parse(["list", "--json"]).unwrap(), Command::List { json: true });
+    assert_eq!(Command::parse(["watch"]).unwrap(), Command::Watch { json: false });
+    assert_eq!(Command::parse(["watcher"]).unwrap(), Command::Watcher);
+    assert_eq!(Command::parse(["proxy-watcher"]).unwrap(), Command::ProxyWatcher);
+    assert_eq!(Command::parse(["xembed-proxy"]).unwrap(), Command::XEmbedProxy);
+    assert_eq!(Command::parse(["xembed-mirror"]).unwrap(), Command::XEmbedMirror);
+    assert_eq!(Command::parse(["backend", "--json"]).unwrap(), Command::Backend { json: true });
+    assert_eq!(
+        Command::parse(["menu", "nm-applet"]).unwrap(),
+        Command::Menu { item: "nm-applet".into(), json: false }
+    );
+    assert_eq!(
+        Command::parse(["click", "nm-applet", "Settings/Preferences"]).unwrap(),
+        Command::Click { item: "nm-applet".into(), path: vec!["Settings".into(), "Preferences".into()] }
+    );
+    assert_eq!(
+        Command::parse(["activate", "--secondary", ":1.52"]).unwrap(),
+        Command::Activate { item: ":1.52".into(), secondary: true }
+    );
+    assert_eq!(
+        Command::parse(["icon", "nm-applet", "--out", "icon.png", "--size=48"]).unwrap(),
+        Command::Icon { item: "nm-applet".into(), out: PathBuf::from("icon.png"), size: 48, alpha: AlphaMode::Straight }
+    );
+    assert_eq!(
+        Command::parse(["icon", "nm-applet", "--premultiplied", "--out=icon.png"]).unwrap(),
+        Command::Icon { item: "nm-applet".into(), out: PathBuf::from("icon.png"), size: 22, alpha: AlphaMode::Premultiplied }
+    );
+    assert_eq!(
+        Command::parse(["process", "nm-applet", "--json"]).unwrap(),
+        Command::Process { item: "nm-applet".into(), json: true }
+    );
+    assert_eq!(
+        Command::parse(["terminate", "--force", "steam"]).unwrap(),
+        Command::Terminate { item: "steam".into(), force: true }
+    );
+    assert_eq!(Command::parse(["restart", "steam"]).unwrap(), Command::Restart { item: "steam".into() });
+    assert_eq!(Command::parse(["list", "--help"]).unwrap(), Command::Help);
+    assert_eq!(Command::parse(["menu", "-h"]).unwrap(), Command::Help);
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = |args: &[&str]| Command::parse(args.iter().copied()).unwrap_err().to_string();
+
+    assert_eq!(error(&["frobnicate"]), "Unknown command: frobnicate");
+    assert_eq!(error(&["menu"]), "Missing <item>");
+    assert_eq!(error(&["click", "nm-applet", "/"]), "Menu path is empty");
+    assert_eq!(error(&["icon", "nm-applet"]), "icon needs --out <file.png>");
+    assert_eq!(error(&["icon", "nm-applet", "--out"]), "--out needs a value");
+    assert_eq!(error(&["icon", "x", "--out", "x.png", "--size", "big"]), "Invalid icon size: big");
+    assert_eq!(error(&["replay", "tray.jsonl", "--speed", "-1"]), "Invalid replay speed: -1");
+    assert_eq!(error(&["list", "--yaml"]), "Unknown option: --yaml");
+    assert_eq!(error(&["list", "extra"]), "Unexpected argument: extra");
+}
+
+#[test]
+fn test_split_menu_path() {
+    assert_eq!(split_menu_path("Settings / Preferences/"), vec!["Settings", "Preferences"]);
+    assert_eq!(split_menu_path("Quit"), vec!["Quit"]);
+}
+
+#[test]
+fn test_format_item_and_event() {
+    let info = NotifierInfo {
+        address: ":1.52".into(),
+        id: "nm-applet".into(),
+        title: "Network".into(),
+        status: ItemStatus::NeedsAttention,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        format_item(&info),
+        ":1.52        NeedsAttention  nm-applet                Network"
+    );
+    assert_eq!(
+        format_event(&HostEvent::Changed { info: info.clone(), fields: vec![ItemField::Status, ItemField::Icon] }),
+        "changed :1.52 Network [Status, Icon]"
+    );
+    assert_eq!(format_event(&HostEvent::Removed(info)), "removed :1.52 Network");
+}
+
+#[test]
+fn test_format_menu() {
+    let entry = |id, label: &str| MenuNode { id, label: label.into(), ..Default::default() };
+
+    let menu = MenuNode {
+        children: vec![
+            MenuNode {
+                children: vec![
+                    entry(2, "Preferences"),
+                    MenuNode { kind: MenuKind::Separator, ..entry(3, "") },
+                    MenuNode { toggle: ToggleKind::Checkmark, toggle_state: Some(true), ..entry(4, "Notifications") },
+                    MenuNode { enabled: false, ..entry(5, "Advanced") },
+                ],
+                ..entry(1, "Settings")
+            },
+            MenuNode { visible: false, ..entry(6, "Hidden") },
+            MenuNode { toggle: ToggleKind::Radio, ..entry(7, "Offline") },
+        ],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        format_menu(&menu),
+        "Settings\n  Preferences\n  ----\n  [x] Notifications\n  Advanced (disabled)\n( ) Offline\n"
+    );
+}
+
+#[test]
+fn test_format_process() {
+    let process = ProcessInfo {
+        pid: 4242,
+        name: "nm-applet".into(),
+        cmdline: vec!["nm-applet".into(), "--indicator".into()],
+        desktop_file: Some(PathBuf::from("/usr/share/applications/nm-applet.desktop")),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        format_process(&process),
+        "pid:     4242\nname:    nm-applet\ncmdline: nm-applet --indicator\ndesktop: /usr/share/applications/nm-applet.desktop\n"
+    );
+}
@@ -1,5 +1,7 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use crate::backend::BackendKind;
 use crate::error::Result;
 
 #[cfg(test)]
@@ -10,10 +12,24 @@ pub struct AppConfig {
     pub app_name: String,
     pub tooltip: String,
     pub icon_path: PathBuf,
+    /// Themed icon name, used when `icon_path` cannot be loaded
+    #[serde(default)]
+    pub icon_name: Option<String>,
     pub dark_icon_path: Option<PathBuf>,
     pub start_minimized: bool,
     pub auto_start: bool,
     pub menu_config: MenuConfig,
+    #[serde(default)]
+    pub states: BTreeMap<String, IconState>,
+    /// Automation run by the host against remote tray items
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Which remote tray items the host shows, and in what order
+    #[serde(default)]
+    pub items: ItemsConfig,
+    /// Which ways of showing our own icon are tried, and in what order
+    #[serde(default)]
+    pub backend: BackendConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,16 +48,260 @@ pub struct MenuItem {
     pub separator_after: bool,
 }
 
+/// A named look for the tray icon, switched with `TrayIcon::set_state`
+///
+/// ```toml
+/// [states.busy]
+/// icon_text = "..."
+/// icon_color = "#f39c12"
+/// tooltip = "Syncing"
+/// status = "active"
+///
+/// [states.busy.animation]
+/// frames = ["busy-1.png", "busy-2.png"]
+/// interval_ms = 300
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IconState {
+    /// Icon file, takes precedence over `icon_name` and `icon_text`
+    #[serde(default)]
+    pub icon_path: Option<PathBuf>,
+    /// Icon name looked up in the current icon theme
+    #[serde(default)]
+    pub icon_name: Option<String>,
+    /// Text rendered with the icon generator when no file is given
+    #[serde(default)]
+    pub icon_text: Option<String>,
+    /// Background colour for generated icons, as `#rrggbb`
+    #[serde(default)]
+    pub icon_color: Option<String>,
+    /// Tooltip shown in this state, the app tooltip is used when unset
+    #[serde(default)]
+    pub tooltip: Option<String>,
+    #[serde(default)]
+    pub status: ItemStatus,
+    #[serde(default)]
+    pub animation: Option<AnimationConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    Passive,
+    #[default]
+    Active,
+    NeedsAttention,
+}
+
+impl ItemStatus {
+    /// Parse the StatusNotifierItem `Status` property value
+    pub fn from_dbus(value: &str) -> Self {
+        match value {
+            "Passive" => ItemStatus::Passive,
+            "NeedsAttention" => ItemStatus::NeedsAttention,
+            _ => ItemStatus::Active,
+        }
+    }
+
+    pub fn as_dbus(&self) -> &'static str {
+        match self {
+            ItemStatus::Passive => "Passive",
+            ItemStatus::Active => "Active",
+            ItemStatus::NeedsAttention => "NeedsAttention",
+        }
+    }
+}
+
+/// Actions to take when a remote tray item starts matching
+///
+/// Rules fire when an item goes from not matching to matching, so an item
+/// that stays in `NeedsAttention` triggers its rule once.
+///
+/// ```toml
+/// [[rules]]
+/// name = "vpn-reconnect"
+/// match = { id = "vpn-applet", tooltip_contains = "disconnected" }
+/// actions = [
+///     { type = "click", path = ["Reconnect"] },
+///     { type = "notify", summary = "VPN dropped", body = "{tooltip}" },
+/// ]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "match", default)]
+    pub matcher: ItemMatcher,
+    pub actions: Vec<RuleAction>,
+}
+
+/// Conditions on item properties, all of which must hold
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ItemMatcher {
+    /// Exact `Id` of the item
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Exact title
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Case-insensitive part of the title
+    #[serde(default)]
+    pub title_contains: Option<String>,
+    /// Case-insensitive part of the tooltip title or description
+    #[serde(default)]
+    pub tooltip_contains: Option<String>,
+    #[serde(default)]
+    pub status: Option<ItemStatus>,
+    /// Bus name, e.g. `:1.52` or `org.kde.StatusNotifierItem-1234-1`
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Name of the owning process, as in `/proc/<pid>/comm`
+    #[serde(default)]
+    pub process: Option<String>,
+}
+
+/// Filtering and ordering of remote tray items
+///
+/// ```toml
+/// [items]
+/// block = [{ process = "steam" }, { title_contains = "updates" }]
+/// order = ["nm-applet", "blueman", "pasystray"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ItemsConfig {
+    /// When not empty, only items matching one of these are shown
+    #[serde(default)]
+    pub allow: Vec<ItemMatcher>,
+    /// Items matching any of these are hidden, even when allowed
+    #[serde(default)]
+    pub block: Vec<ItemMatcher>,
+    /// Item ids (or titles, for items without an id) in display order;
+    /// other items follow in the order they first appeared
+    #[serde(default)]
+    pub order: Vec<String>,
+    /// How matching items are shown by a [`ProxyWatcher`](crate::watcher::ProxyWatcher),
+    /// the first override that matches applies
+    #[serde(default)]
+    pub overrides: Vec<ItemOverride>,
+}
+
+/// Title and icon to show for matching items instead of their own
+///
+/// ```toml
+/// [[items.overrides]]
+/// match = { id = "nm-applet" }
+/// title = "Network"
+/// icon = "network-wireless-symbolic"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ItemOverride {
+    #[serde(rename = "match", default)]
+    pub matcher: ItemMatcher,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Icon theme name, or the path of an image file when it contains a `/`
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+/// Order in which tray backends are tried
+///
+/// ```toml
+/// [backend]
+/// prefer = ["xembed", "sni", "headless"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// Backends left out are never used; an empty list means the default
+    /// order, [`BackendKind::ALL`]
+    #[serde(default)]
+    pub prefer: Vec<BackendKind>,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self { prefer: BackendKind::ALL.to_vec() }
+    }
+}
+
+impl BackendConfig {
+    /// Preference order with duplicates removed
+    pub fn order(&self) -> Vec<BackendKind> {
+        if self.prefer.is_empty() {
+            return BackendKind::ALL.to_vec();
+        }
+        let mut order = Vec::new();
+        for kind in &self.prefer {
+            if !order.contains(kind) {
+                order.push(*kind);
+            }
+        }
+        order
+    }
+}
+
+/// What a rule does when it fires
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Run a shell command with `sh -c`, the item is described by the
+    /// `TRAY_ITEM_ADDRESS`, `TRAY_ITEM_ID`, `TRAY_ITEM_TITLE` and
+    /// `TRAY_ITEM_STATUS` environment variables
+    Run { command: String },
+    /// Click the menu entry at this label path
+    Click { path: Vec<String> },
+    /// Show a desktop notification, `{id}`, `{title}`, `{address}`,
+    /// `{status}` and `{tooltip}` in the text are replaced with the item's values
+    Notify {
+        summary: String,
+        #[serde(default)]
+        body: String,
+    },
+    /// Stop the application owning the item, with SIGKILL if `force` is set
+    Terminate {
+        #[serde(default)]
+        force: bool,
+    },
+    /// Stop the application owning the item and start it again; the new
+    /// instance is a new item, so pair this with a matcher it will not meet
+    Restart,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnimationConfig {
+    #[serde(deserialize_with = "non_empty_frames")]
+    pub frames: Vec<PathBuf>,
+    #[serde(default = "default_frame_interval")]
+    pub interval_ms: u64,
+}
+
+fn default_frame_interval() -> u64 {
+    250
+}
+
+fn non_empty_frames<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<PathBuf>, D::Error> {
+    let frames = Vec::<PathBuf>::deserialize(deserializer)?;
+    if frames.is_empty() {
+        return Err(serde::de::Error::custom("an animation needs at least one frame"));
+    }
+    Ok(frames)
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             app_name: "System Tray App".to_string(),
             tooltip: "Click to open menu".to_string(),
             icon_path: PathBuf::from("assets/icons/default.png"),
+            icon_name: None,
             dark_icon_path: None,
             start_minimized: true,
             auto_start: false,
             menu_config: MenuConfig::default(),
+            states: BTreeMap::new(),
+            rules: vec![],
+            items: ItemsConfig::default(),
+            backend: BackendConfig::default(),
         }
     }
 }
@@ -1,3 +1,5 @@
+use super::*;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +56,132 @@ mod tests {
         // Note: This test would need mock filesystem or integration test setup
         // as it relies on dirs::config_dir() which uses system paths
     }
+
+    #[test]
+    fn test_states_deserialization() {
+        let content = r##"
+            app_name = "States App"
+            tooltip = "Idle"
+            icon_path = "icon.png"
+            start_minimized = true
+            auto_start = false
+
+            [menu_config]
+            show_about = true
+            show_settings = true
+            show_quit = true
+            custom_items = []
+
+            [states.busy]
+            icon_text = "..."
+            icon_color = "#f39c12"
+            tooltip = "Syncing"
+
+            [states.busy.animation]
+            frames = ["busy-1.png", "busy-2.png"]
+
+            [states.error]
+            icon_path = "error.png"
+            status = "needs_attention"
+        "##;
+
+        let config: AppConfig = toml::from_str(content).unwrap();
+        assert_eq!(config.states.len(), 2);
+
+        let busy = &config.states["busy"];
+        assert_eq!(busy.icon_text.as_deref(), Some("..."));
+        assert_eq!(busy.status, ItemStatus::Active);
+        assert_eq!(busy.animation.as_ref().unwrap().interval_ms, 250);
+
+        let error = &config.states["error"];
+        assert_eq!(error.status, ItemStatus::NeedsAttention);
+        assert!(error.tooltip.is_none());
+    }
+
+    #[test]
+    fn test_animation_without_frames_is_rejected() {
+        let result = toml::from_str::<AnimationConfig>("frames = []");
+        assert!(result.is_err());
+
+        let animation: AnimationConfig = toml::from_str(r#"frames = ["busy.png"]"#).unwrap();
+        assert_eq!(animation.interval_ms, 250);
+    }
+
+    #[test]
+    fn test_states_default_to_empty() {
+        // Configs written before states existed must still load
+        let mut value = toml::Value::try_from(AppConfig::default()).unwrap();
+        value.as_table_mut().unwrap().remove("states");
+        let config: AppConfig = value.try_into().unwrap();
+        assert!(config.states.is_empty());
+    }
+
+    #[test]
+    fn test_rules_deserialization() {
+        let mut value = toml::Value::try_from(AppConfig::default()).unwrap();
+        let rules: toml::Value = toml::from_str(r#"
+            [[rules]]
+            name = "vpn-reconnect"
+            match = { id = "vpn-applet", tooltip_contains = "disconnected" }
+            actions = [
+                { type = "click", path = ["Connection", "Reconnect"] },
+                { type = "notify", summary = "VPN dropped" },
+            ]
+
+            [[rules]]
+            match = { status = "needs_attention" }
+            actions = [{ type = "run", command = "notify-send attention" }, { type = "restart" }]
+        "#).unwrap();
+        value.as_table_mut().unwrap().extend(rules.as_table().unwrap().clone());
+
+        let config: AppConfig = value.try_into().unwrap();
+        assert_eq!(config.rules.len(), 2);
+
+        let vpn = &config.rules[0];
+        assert_eq!(vpn.matcher.id.as_deref(), Some("vpn-applet"));
+        assert!(vpn.matcher.status.is_none());
+        assert_eq!(vpn.actions[0], RuleAction::Click { path: vec!["Connection".into(), "Reconnect".into()] });
+        assert_eq!(vpn.actions[1], RuleAction::Notify { summary: "VPN dropped".into(), body: String::new() });
+
+        let attention = &config.rules[1];
+        assert!(attention.name.is_empty());
+        assert_eq!(attention.matcher.status, Some(ItemStatus::NeedsAttention));
+        assert_eq!(attention.actions[1], RuleAction::Restart);
+
+        let terminate: RuleAction = toml::from_str(r#"type = "terminate""#).unwrap();
+        assert_eq!(terminate, RuleAction::Terminate { force: false });
+    }
+
+    #[test]
+    fn test_items_deserialization() {
+        let items: ItemsConfig = toml::from_str(r#"
+            block = [{ process = "steam" }]
+            order = ["nm-applet", "blueman"]
+        "#).unwrap();
+
+        assert!(items.allow.is_empty());
+        assert_eq!(items.block[0].process.as_deref(), Some("steam"));
+        assert_eq!(items.order, vec!["nm-applet", "blueman"]);
+        assert_eq!(AppConfig::default().items, ItemsConfig::default());
+    }
+
+    #[test]
+    fn test_item_overrides_deserialization() {
+        let items: ItemsConfig = toml::from_str(r#"
+            [[overrides]]
+            match = { id = "nm-applet" }
+            title = "Network"
+            icon = "network-wireless-symbolic"
+
+            [[overrides]]
+            match = { process = "steam" }
+            icon = "/usr/share/pixmaps/steam.png"
+        "#).unwrap();
+
+        assert_eq!(items.overrides.len(), 2);
+        assert_eq!(items.overrides[0].matcher.id.as_deref(), Some("nm-applet"));
+        assert_eq!(items.overrides[0].title.as_deref(), Some("Network"));
+        assert_eq!(items.overrides[1].title, None);
+        assert_eq!(items.overrides[1].icon.as_deref(), Some("/usr/share/pixmaps/steam.png"));
+    }
 }
\ No newline at end of file
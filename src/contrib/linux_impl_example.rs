@@ -1,156 +1,121 @@
 // Example implementation for contributing to aloe-system-tray
-// This shows how the Linux implementation could work
+// This shows how the Linux implementation plugs into aloe's component trait,
+// using the XEmbed backend in `tray::linux_impl`
 
-#![allow(dead_code)]
+use std::collections::VecDeque;
+use crate::config::AppConfig;
+use crate::error::Result;
+use crate::tray::linux_impl::{LinuxTrayIcon, XEmbedEvent};
+use crate::tray::{Image, PopupMenu, Rectangle, SystemTrayIconComponentInterface, TrayEvent};
 
-use std::ffi::CString;
-
-// These would come from aloe-x11 and aloe-xembed
-#[allow(non_snake_case)]
-mod x11_bindings {
-    pub type Display = *mut std::ffi::c_void;
-    pub type Window = u64;
-    pub type Atom = u64;
-    pub type Pixmap = u64;
-    
-    pub const XA_ATOM: Atom = 4;
-    pub const CURRENT_TIME: u64 = 0;
-    
-    // Simulated X11 functions (keeping original names for X11 compatibility)
-    pub unsafe fn XOpenDisplay(_name: *const i8) -> Display { std::ptr::null_mut() }
-    pub unsafe fn XCloseDisplay(_display: Display) -> i32 { 0 }
-    pub unsafe fn XInternAtom(_display: Display, _name: *const i8, _only_if_exists: i32) -> Atom { 0 }
-    pub unsafe fn XGetSelectionOwner(_display: Display, _selection: Atom) -> Window { 0 }
-    pub unsafe fn XCreateSimpleWindow(_display: Display, _parent: Window, _x: i32, _y: i32, 
-                                     _width: u32, _height: u32, _border_width: u32, 
-                                     _border: u64, _background: u64) -> Window { 0 }
-}
-
-use x11_bindings::*;
+/// How long an info bubble stays up, in milliseconds
+const BUBBLE_TIMEOUT_MS: u32 = 10_000;
 
 /// Example Linux implementation for SystemTrayIconComponent
 pub struct LinuxSystemTrayIconComponent {
-    display: Display,
-    tray_window: Window,
-    tray_atom: Atom,
-    manager_window: Window,
-    tooltip: String,
+    icon: LinuxTrayIcon,
     is_highlighted: bool,
+    bubble: Option<u32>,
+    /// Menu choices not yet picked up by `poll_event`
+    events: VecDeque<TrayEvent>,
 }
 
 impl LinuxSystemTrayIconComponent {
-    pub fn new() -> Result<Self, String> {
-        unsafe {
-            // Open X11 display
-            let display = XOpenDisplay(std::ptr::null());
-            if display.is_null() {
-                return Err("Failed to open X11 display".into());
-            }
-            
-            // Get system tray atom
-            let tray_atom_name = CString::new("_NET_SYSTEM_TRAY_S0").unwrap();
-            let tray_atom = XInternAtom(display, tray_atom_name.as_ptr(), 0);
-            
-            // Find system tray manager
-            let manager_window = XGetSelectionOwner(display, tray_atom);
-            if manager_window == 0 {
-                XCloseDisplay(display);
-                return Err("No system tray manager found".into());
-            }
-            
-            // Create tray window
-            let tray_window = XCreateSimpleWindow(
-                display,
-                manager_window,
-                0, 0, 24, 24,
-                0, 0, 0
-            );
-            
-            Ok(Self {
-                display,
-                tray_window,
-                tray_atom,
-                manager_window,
-                tooltip: String::new(),
-                is_highlighted: false,
-            })
+    /// Connect to `$DISPLAY` and dock with the running tray
+    pub fn new(config: AppConfig) -> Result<Self> {
+        let mut icon = LinuxTrayIcon::new(config)?;
+        icon.dock()?;
+
+        Ok(Self {
+            icon,
+            is_highlighted: false,
+            bubble: None,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Pump X11 events, call regularly from the application's loop
+    pub fn handle_x11_events(&mut self) -> Result<Vec<XEmbedEvent>> {
+        let mut events = Vec::new();
+        while let Some(event) = self.icon.poll_event()? {
+            tracing::debug!("Tray icon event: {:?}", event);
+            events.push(event);
         }
+        Ok(events)
     }
-    
-    pub fn set_icon_from_rgba(&mut self, _rgba_data: &[u8], width: u32, height: u32) -> Result<(), String> {
-        // Implementation would:
-        // 1. Create X11 Pixmap from RGBA data
-        // 2. Set _NET_WM_ICON property
-        // 3. Send update notification
-        
-        tracing::debug!("Setting icon: {}x{}", width, height);
-        Ok(())
+
+    pub fn is_highlighted(&self) -> bool {
+        self.is_highlighted
     }
-    
-    pub fn set_tooltip(&mut self, tooltip: &str) -> Result<(), String> {
-        self.tooltip = tooltip.to_string();
-        
-        // Implementation would:
-        // 1. Set _NET_WM_NAME property
-        // 2. Update tooltip window if visible
-        
-        tracing::debug!("Setting tooltip: {}", tooltip);
-        Ok(())
+}
+
+impl SystemTrayIconComponentInterface for LinuxSystemTrayIconComponent {
+    fn set_icon_image(&mut self, colour_image: &Image, _template_image: &Image) {
+        if let Err(e) = self.icon.set_icon(colour_image) {
+            tracing::warn!("Could not paint tray icon: {}", e);
+        }
+    }
+
+    fn set_icon_tooltip(&mut self, tooltip: &str) {
+        if let Err(e) = self.icon.set_tooltip(tooltip) {
+            tracing::warn!("Could not set tooltip: {}", e);
+        }
     }
-    
-    pub fn show_menu_at(&mut self, x: i32, y: i32) -> Result<(), String> {
-        // Implementation would:
-        // 1. Create menu window
-        // 2. Position at x, y
-        // 3. Map window and grab pointer
-        
-        tracing::debug!("Showing menu at: {}, {}", x, y);
-        Ok(())
+
+    fn set_highlighted(&mut self, should_highlight: bool) {
+        // XEmbed has no attention state, the caller swaps the artwork instead
+        self.is_highlighted = should_highlight;
     }
-    
-    pub fn handle_x11_event(&mut self, event_type: i32) {
-        match event_type {
-            4 => { // ButtonPress
-                tracing::debug!("Button pressed on tray icon");
-                // Emit click event
-            },
-            5 => { // ButtonRelease
-                tracing::debug!("Button released on tray icon");
-            },
-            _ => {}
+
+    fn show_info_bubble(&mut self, title: &str, content: &str) {
+        self.hide_info_bubble();
+        match self.icon.show_message(&format!("{}\n{}", title, content), BUBBLE_TIMEOUT_MS) {
+            Ok(id) => self.bubble = Some(id),
+            Err(e) => tracing::warn!("Could not show info bubble: {}", e),
         }
     }
-}
 
-impl Drop for LinuxSystemTrayIconComponent {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.display.is_null() {
-                XCloseDisplay(self.display);
+    fn hide_info_bubble(&mut self) {
+        if let Some(id) = self.bubble.take() {
+            if let Err(e) = self.icon.cancel_message(id) {
+                tracing::warn!("Could not hide info bubble: {}", e);
             }
         }
     }
-}
 
-/// Example of how to integrate with aloe's trait system
-pub trait AloeSystemTrayInterface {
-    fn set_icon_image(&mut self, colour_image: &[u8], template_image: &[u8]);
-    fn set_icon_tooltip(&mut self, tooltip: &str);
-    fn set_highlighted(&mut self, should_highlight: bool);
-}
-
-impl AloeSystemTrayInterface for LinuxSystemTrayIconComponent {
-    fn set_icon_image(&mut self, colour_image: &[u8], _template_image: &[u8]) {
-        // Assume colour_image is RGBA data
-        let _ = self.set_icon_from_rgba(colour_image, 32, 32);
+    fn show_dropdown_menu(&mut self, menu: &PopupMenu) {
+        match self.icon.show_menu(menu, self.get_bounds()) {
+            Ok(Some(result)) => self.events.push_back(TrayEvent::MenuItem(result)),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Could not show menu: {}", e),
+        }
     }
-    
-    fn set_icon_tooltip(&mut self, tooltip: &str) {
-        let _ = self.set_tooltip(tooltip);
+
+    fn get_bounds(&self) -> Rectangle {
+        self.icon.bounds().unwrap_or_else(|e| {
+            tracing::warn!("Could not locate tray icon: {}", e);
+            let (width, height) = self.icon.size();
+            Rectangle::new(0, 0, width as i32, height as i32)
+        })
     }
-    
-    fn set_highlighted(&mut self, should_highlight: bool) {
-        self.is_highlighted = should_highlight;
-        // Implementation would update visual state
+
+    fn poll_event(&mut self) -> Option<TrayEvent> {
+        if let Some(event) = self.events.pop_front() {
+            return Some(event);
+        }
+        loop {
+            match self.icon.poll_event() {
+                Ok(Some(event)) => {
+                    if let Some(event) = event.to_tray_event() {
+                        return Some(event);
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    tracing::warn!("Could not read tray events: {}", e);
+                    return None;
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+}
@@ -17,11 +17,39 @@ pub enum TrayError {
     #[error("Configuration error: {0}")]
     ConfigError(String),
     
+    #[error("Unknown icon state: {0}")]
+    UnknownState(String),
+    
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
     
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
+    
+    #[error("D-Bus error: {0}")]
+    DBusError(#[from] zbus::Error),
+    
+    #[error("X11 error: {0}")]
+    X11Error(String),
 }
 
+macro_rules! x11_error {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for TrayError {
+                fn from(e: $error) -> Self {
+                    TrayError::X11Error(e.to_string())
+                }
+            }
+        )*
+    };
+}
+
+x11_error!(
+    x11rb::errors::ConnectError,
+    x11rb::errors::ConnectionError,
+    x11rb::errors::ReplyError,
+    x11rb::errors::ReplyOrIdError
+);
+
 pub type Result<T> = std::result::Result<T, TrayError>;
\ No newline at end of file
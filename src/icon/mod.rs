@@ -0,0 +1,292 @@
+//! Icon generator that renders short text, bundled font glyphs or simple
+//! shapes into tray icons at any size, so no PNG assets need to be shipped.
+
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tray-icon")]
+use crate::aloe_compat::Image;
+use crate::error::{Result, TrayError};
+
+pub use theme::IconThemeResolver;
+
+pub mod font;
+pub mod theme;
+
+#[cfg(test)]
+mod tests;
+
+/// Samples per pixel along each axis, used for anti-aliasing
+const SUPERSAMPLE: u32 = 4;
+
+/// Largest padding allowed on each side, as a fraction of the icon size
+const MAX_PADDING: f32 = 0.45;
+
+/// A filled shape, drawn inside the unit square of the area it covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    Square,
+    RoundedSquare,
+    Circle,
+    Diamond,
+    Triangle,
+}
+
+impl Shape {
+    /// Whether the point (`u`, `v`), both in `0.0..=1.0`, lies inside the shape
+    pub fn contains(&self, u: f32, v: f32) -> bool {
+        match self {
+            Shape::Square => true,
+            Shape::RoundedSquare => {
+                let radius = 0.2;
+                let cx = u.clamp(radius, 1.0 - radius);
+                let cy = v.clamp(radius, 1.0 - radius);
+                (u - cx).powi(2) + (v - cy).powi(2) <= radius * radius
+            }
+            Shape::Circle => (u - 0.5).powi(2) + (v - 0.5).powi(2) <= 0.25,
+            Shape::Diamond => (u - 0.5).abs() + (v - 0.5).abs() <= 0.5,
+            Shape::Triangle => (u - 0.5).abs() <= v / 2.0,
+        }
+    }
+}
+
+/// What gets drawn in the foreground colour
+#[derive(Debug, Clone, PartialEq)]
+pub enum IconContent {
+    Text(String),
+    Shape(Shape),
+}
+
+/// Renders an [`IconContent`] over an optional background shape
+///
+/// ```ignore
+/// let icon = IconGenerator::text("OK")
+///     .foreground(Rgba([255, 255, 255, 255]))
+///     .background(Rgba([39, 174, 96, 255]))
+///     .render(22);
+/// ```
+#[derive(Debug, Clone)]
+pub struct IconGenerator {
+    content: IconContent,
+    foreground: Rgba<u8>,
+    background: Option<Rgba<u8>>,
+    background_shape: Shape,
+    padding: f32,
+}
+
+impl IconGenerator {
+    /// Render short text with the bundled font, e.g. "12" or "OK"
+    pub fn text(text: &str) -> Self {
+        Self::new(IconContent::Text(text.to_string()))
+    }
+
+    /// Render a single filled shape
+    pub fn shape(shape: Shape) -> Self {
+        Self::new(IconContent::Shape(shape))
+    }
+
+    fn new(content: IconContent) -> Self {
+        Self {
+            content,
+            foreground: Rgba([255, 255, 255, 255]),
+            background: None,
+            background_shape: Shape::RoundedSquare,
+            padding: 0.15,
+        }
+    }
+
+    pub fn foreground(mut self, colour: Rgba<u8>) -> Self {
+        self.foreground = colour;
+        self
+    }
+
+    pub fn background(mut self, colour: Rgba<u8>) -> Self {
+        self.background = Some(colour);
+        self
+    }
+
+    /// Leave the background transparent
+    pub fn transparent(mut self) -> Self {
+        self.background = None;
+        self
+    }
+
+    pub fn background_shape(mut self, shape: Shape) -> Self {
+        self.background_shape = shape;
+        self
+    }
+
+    /// Space kept free on each side, as a fraction of the icon size
+    pub fn padding(mut self, padding: f32) -> Self {
+        self.padding = padding.clamp(0.0, MAX_PADDING);
+        self
+    }
+
+    /// Render a square RGBA icon of `size` pixels
+    pub fn render(&self, size: u32) -> RgbaImage {
+        let mut img = RgbaImage::new(size, size);
+        if size == 0 {
+            return img;
+        }
+
+        let layout = self.layout();
+        let samples = (SUPERSAMPLE * SUPERSAMPLE) as f32;
+
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let mut background_hits = 0;
+            let mut foreground_hits = 0;
+
+            for sy in 0..SUPERSAMPLE {
+                for sx in 0..SUPERSAMPLE {
+                    let u = (x as f32 + (sx as f32 + 0.5) / SUPERSAMPLE as f32) / size as f32;
+                    let v = (y as f32 + (sy as f32 + 0.5) / SUPERSAMPLE as f32) / size as f32;
+
+                    if self.background.is_some() && self.background_shape.contains(u, v) {
+                        background_hits += 1;
+                    }
+                    if layout.contains(u, v) {
+                        foreground_hits += 1;
+                    }
+                }
+            }
+
+            if let Some(background) = self.background {
+                blend(pixel, background, background_hits as f32 / samples);
+            }
+            blend(pixel, self.foreground, foreground_hits as f32 / samples);
+        }
+
+        img
+    }
+
+    /// Render straight into the aloe-compatible [`Image`]
+    #[cfg(feature = "tray-icon")]
+    pub fn render_image(&self, size: u32) -> Image {
+        Image::from_rgba(self.render(size).into_raw(), size, size)
+    }
+
+    fn layout(&self) -> Layout {
+        let inner = 1.0 - 2.0 * self.padding;
+
+        match &self.content {
+            IconContent::Shape(shape) => Layout::Shape {
+                shape: *shape,
+                origin: (self.padding, self.padding),
+                extent: inner,
+            },
+            IconContent::Text(text) => {
+                let glyphs: Vec<font::Glyph> = text.chars().map(font::glyph).collect();
+                if glyphs.is_empty() {
+                    return Layout::Empty;
+                }
+
+                // One blank column between glyphs
+                let columns = glyphs.len() as u32 * (font::GLYPH_WIDTH + 1) - 1;
+                let rows = font::GLYPH_HEIGHT;
+                let cell = (inner / columns as f32).min(inner / rows as f32);
+
+                let origin = (
+                    0.5 - cell * columns as f32 / 2.0,
+                    0.5 - cell * rows as f32 / 2.0,
+                );
+
+                Layout::Text { glyphs, origin, cell }
+            }
+        }
+    }
+}
+
+enum Layout {
+    Empty,
+    Shape {
+        shape: Shape,
+        origin: (f32, f32),
+        extent: f32,
+    },
+    Text {
+        glyphs: Vec<font::Glyph>,
+        origin: (f32, f32),
+        cell: f32,
+    },
+}
+
+impl Layout {
+    fn contains(&self, u: f32, v: f32) -> bool {
+        match self {
+            Layout::Empty => false,
+            Layout::Shape { shape, origin, extent } => {
+                let lu = (u - origin.0) / extent;
+                let lv = (v - origin.1) / extent;
+                (0.0..=1.0).contains(&lu) && (0.0..=1.0).contains(&lv) && shape.contains(lu, lv)
+            }
+            Layout::Text { glyphs, origin, cell } => {
+                let gx = (u - origin.0) / cell;
+                let gy = (v - origin.1) / cell;
+                if gx < 0.0 || gy < 0.0 {
+                    return false;
+                }
+
+                let column = gx as u32;
+                let row = gy as u32;
+                let index = (column / (font::GLYPH_WIDTH + 1)) as usize;
+
+                glyphs
+                    .get(index)
+                    .map(|glyph| font::is_set(glyph, column % (font::GLYPH_WIDTH + 1), row))
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
+
+/// Source-over blend of `colour` onto `dst` with the given coverage
+pub(crate) fn blend(dst: &mut Rgba<u8>, colour: Rgba<u8>, coverage: f32) {
+    if coverage <= 0.0 {
+        return;
+    }
+
+    let src_a = colour[3] as f32 / 255.0 * coverage;
+    let dst_a = dst[3] as f32 / 255.0;
+    let out_a = src_a + dst_a * (1.0 - src_a);
+    if out_a <= 0.0 {
+        return;
+    }
+
+    for c in 0..3 {
+        let value = (colour[c] as f32 * src_a + dst[c] as f32 * dst_a * (1.0 - src_a)) / out_a;
+        dst[c] = value.round().clamp(0.0, 255.0) as u8;
+    }
+    dst[3] = (out_a * 255.0).round() as u8;
+}
+
+/// Parse `#rgb`, `#rrggbb` or `#rrggbbaa` into a colour
+pub fn parse_color(value: &str) -> Result<Rgba<u8>> {
+    let hex = value.trim().trim_start_matches('#');
+    let invalid = || TrayError::ConfigError(format!("Invalid colour: {}", value));
+
+    // from_str_radix would take a sign, as in "+f"
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    let channel = |s: &str| u8::from_str_radix(s, 16).map_err(|_| invalid());
+
+    match hex.len() {
+        3 => {
+            let mut rgba = [255u8; 4];
+            for (i, c) in hex.chars().enumerate() {
+                let v = channel(&c.to_string())?;
+                rgba[i] = v * 17;
+            }
+            Ok(Rgba(rgba))
+        }
+        6 | 8 => {
+            let mut rgba = [255u8; 4];
+            for (i, value) in rgba.iter_mut().take(hex.len() / 2).enumerate() {
+                *value = channel(hex.get(i * 2..i * 2 + 2).ok_or_else(invalid)?)?;
+            }
+            Ok(Rgba(rgba))
+        }
+        _ => Err(invalid()),
+    }
+}
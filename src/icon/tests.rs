@@ -0,0 +1,181 @@
+use super::*;
+
+#[test]
+fn test_render_size() {
+    for size in [16, 22, 24, 32, 48, 64] {
+        let img = IconGenerator::text("12").render(size);
+        assert_eq!(img.dimensions(), (size, size));
+    }
+}
+
+#[test]
+fn test_padding_stays_transparent() {
+    let img = IconGenerator::shape(Shape::Square)
+        .padding(0.25)
+        .render(32);
+
+    assert_eq!(img.get_pixel(0, 0)[3], 0);
+    assert_eq!(img.get_pixel(31, 31)[3], 0);
+    assert_eq!(*img.get_pixel(16, 16), Rgba([255, 255, 255, 255]));
+}
+
+#[test]
+fn test_background_shape() {
+    let img = IconGenerator::text("")
+        .background(Rgba([255, 0, 0, 255]))
+        .background_shape(Shape::Circle)
+        .render(32);
+
+    // Corners lie outside the circle, the centre is fully covered
+    assert_eq!(img.get_pixel(0, 0)[3], 0);
+    assert_eq!(*img.get_pixel(16, 16), Rgba([255, 0, 0, 255]));
+}
+
+#[test]
+fn test_text_uses_foreground() {
+    let img = IconGenerator::text("OK")
+        .foreground(Rgba([0, 0, 0, 255]))
+        .background(Rgba([255, 255, 255, 255]))
+        .background_shape(Shape::Square)
+        .render(32);
+
+    assert!(img.pixels().any(|p| *p == Rgba([0, 0, 0, 255])));
+    assert!(img.pixels().any(|p| *p == Rgba([255, 255, 255, 255])));
+}
+
+#[test]
+fn test_edges_are_antialiased() {
+    let img = IconGenerator::shape(Shape::Circle).padding(0.0).render(32);
+    assert!(img.pixels().any(|p| p[3] > 0 && p[3] < 255));
+}
+
+#[test]
+fn test_unknown_glyph_falls_back() {
+    assert!(font::has_glyph('a'));
+    assert!(!font::has_glyph('€'));
+    assert_eq!(font::glyph('€'), font::glyph('?'));
+}
+
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("#fff").unwrap(), Rgba([255, 255, 255, 255]));
+    assert_eq!(parse_color("#3498db").unwrap(), Rgba([52, 152, 219, 255]));
+    assert_eq!(parse_color("3498db80").unwrap(), Rgba([52, 152, 219, 128]));
+    assert!(parse_color("#12345").is_err());
+    assert!(parse_color("#zzzzzz").is_err());
+    assert!(parse_color("#+f+f+f").is_err());
+    assert!(parse_color("+ff").is_err());
+}
+
+fn write_icon(path: &std::path::Path, size: u32) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    IconGenerator::shape(Shape::Circle).render(size).save(path).unwrap();
+}
+
+fn write_theme(base: &std::path::Path, name: &str, index: &str) {
+    std::fs::create_dir_all(base.join(name)).unwrap();
+    std::fs::write(base.join(name).join("index.theme"), index).unwrap();
+}
+
+fn theme_fixture() -> tempfile::TempDir {
+    let dir = tempfile::TempDir::new().unwrap();
+    let base = dir.path().join("icons");
+
+    write_theme(&base, "test-theme", "\
+[Icon Theme]
+Name=Test
+Inherits=parent-theme
+Directories=16x16/apps,48x48/apps,scalable/apps
+
+[16x16/apps]
+Size=16
+Type=Fixed
+
+[48x48/apps]
+Size=48
+Type=Fixed
+
+[scalable/apps]
+Size=64
+MinSize=8
+MaxSize=512
+Type=Scalable
+");
+    // Inheriting back must not loop forever
+    write_theme(&base, "parent-theme", "\
+[Icon Theme]
+Inherits=test-theme
+Directories=22x22/apps
+
+[22x22/apps]
+Size=22
+");
+    write_theme(&base, "hicolor", "\
+[Icon Theme]
+Directories=32x32/apps
+
+[32x32/apps]
+Size=32
+Type=Threshold
+");
+
+    write_icon(&base.join("test-theme/16x16/apps/small.png"), 16);
+    write_icon(&base.join("test-theme/48x48/apps/small.png"), 48);
+    std::fs::create_dir_all(base.join("test-theme/scalable/apps")).unwrap();
+    std::fs::write(base.join("test-theme/scalable/apps/vector.svg"), "<svg/>").unwrap();
+    write_icon(&base.join("parent-theme/22x22/apps/inherited.png"), 22);
+    write_icon(&base.join("hicolor/32x32/apps/fallback.png"), 32);
+    write_icon(&base.join("unthemed.png"), 24);
+    write_icon(&dir.path().join("item-theme/hicolor/32x32/apps/private.png"), 32);
+    write_icon(&dir.path().join("item-flat/flat.png"), 32);
+
+    dir
+}
+
+#[test]
+fn test_theme_size_matching() {
+    let dir = theme_fixture();
+    let resolver = IconThemeResolver::with_base_dirs("test-theme", vec![dir.path().join("icons")]);
+
+    let path = |size| resolver.lookup("small", size, 1).unwrap();
+    assert!(path(16).ends_with("16x16/apps/small.png"));
+    assert!(path(48).ends_with("48x48/apps/small.png"));
+    // No exact match, so the closest directory wins
+    assert!(path(40).ends_with("48x48/apps/small.png"));
+    assert!(path(20).ends_with("16x16/apps/small.png"));
+
+    assert!(resolver.lookup("vector", 22, 1).unwrap().ends_with("scalable/apps/vector.svg"));
+}
+
+#[test]
+fn test_theme_inheritance_and_fallbacks() {
+    let dir = theme_fixture();
+    let resolver = IconThemeResolver::with_base_dirs("test-theme", vec![dir.path().join("icons")]);
+
+    assert!(resolver.lookup("inherited", 22, 1).unwrap().ends_with("parent-theme/22x22/apps/inherited.png"));
+    assert!(resolver.lookup("fallback", 22, 1).unwrap().ends_with("hicolor/32x32/apps/fallback.png"));
+    assert!(resolver.lookup("unthemed", 22, 1).unwrap().ends_with("icons/unthemed.png"));
+    assert!(resolver.lookup("missing", 22, 1).is_none());
+}
+
+#[test]
+fn test_theme_item_theme_path() {
+    let dir = theme_fixture();
+    let resolver = IconThemeResolver::with_base_dirs("test-theme", vec![dir.path().join("icons")]);
+
+    assert!(resolver.lookup("private", 32, 1).is_none());
+    let item_theme = dir.path().join("item-theme");
+    assert!(resolver.lookup_in("private", 32, 1, Some(&item_theme)).is_some());
+    let item_flat = dir.path().join("item-flat");
+    assert!(resolver.lookup_in("flat", 32, 1, Some(&item_flat)).is_some());
+}
+
+#[test]
+fn test_theme_load_scales_and_skips_svg() {
+    let dir = theme_fixture();
+    let resolver = IconThemeResolver::with_base_dirs("test-theme", vec![dir.path().join("icons")]);
+
+    let image = resolver.load("small", 22, 2, None).unwrap();
+    assert_eq!(image.dimensions(), (44, 44));
+    assert!(resolver.load("vector", 22, 1, None).is_err());
+}
@@ -0,0 +1,385 @@
+//! Icon name lookup following the freedesktop Icon Theme Specification.
+//!
+//! See <https://specifications.freedesktop.org/icon-theme-spec/latest/> for
+//! the lookup algorithm implemented by [`IconThemeResolver::lookup`].
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use image::RgbaImage;
+use crate::error::{Result, TrayError};
+
+/// Theme every other theme implicitly inherits from
+pub const FALLBACK_THEME: &str = "hicolor";
+
+/// File extensions in the order the spec prefers them
+const EXTENSIONS: [&str; 3] = ["png", "svg", "xpm"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DirectoryType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+#[derive(Debug, Clone)]
+struct ThemeDirectory {
+    path: String,
+    size: u32,
+    scale: u32,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+    kind: DirectoryType,
+}
+
+impl ThemeDirectory {
+    fn matches_size(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+
+        match self.kind {
+            DirectoryType::Fixed => self.size == size,
+            DirectoryType::Scalable => self.min_size <= size && size <= self.max_size,
+            DirectoryType::Threshold => {
+                self.size.saturating_sub(self.threshold) <= size && size <= self.size + self.threshold
+            }
+        }
+    }
+
+    fn size_distance(&self, size: u32, scale: u32) -> u32 {
+        let wanted = size * scale;
+
+        let (min, max) = match self.kind {
+            DirectoryType::Fixed => {
+                let actual = self.size * self.scale;
+                return actual.abs_diff(wanted);
+            }
+            DirectoryType::Scalable => (self.min_size, self.max_size),
+            DirectoryType::Threshold => (self.size.saturating_sub(self.threshold), self.size + self.threshold),
+        };
+
+        (min * self.scale).saturating_sub(wanted) + wanted.saturating_sub(max * self.scale)
+    }
+}
+
+/// Parsed `index.theme` of one theme
+#[derive(Debug, Clone)]
+struct ThemeIndex {
+    parents: Vec<String>,
+    directories: Vec<ThemeDirectory>,
+}
+
+impl ThemeIndex {
+    fn parse(content: &str) -> Self {
+        let sections = parse_ini(content);
+        let main = sections.get("Icon Theme").cloned().unwrap_or_default();
+
+        let list = |key: &str| -> Vec<String> {
+            main.get(key)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|entry| !entry.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let mut names = list("Directories");
+        for scaled in list("ScaledDirectories") {
+            if !names.contains(&scaled) {
+                names.push(scaled);
+            }
+        }
+
+        let directories = names
+            .into_iter()
+            .filter_map(|name| {
+                let section = sections.get(&name)?;
+                let number = |key: &str| section.get(key).and_then(|value| value.trim().parse::<u32>().ok());
+
+                let size = number("Size")?;
+                let kind = match section.get("Type").map(|value| value.trim()) {
+                    Some("Fixed") => DirectoryType::Fixed,
+                    Some("Scalable") => DirectoryType::Scalable,
+                    _ => DirectoryType::Threshold,
+                };
+
+                Some(ThemeDirectory {
+                    path: name,
+                    size,
+                    scale: number("Scale").unwrap_or(1).max(1),
+                    min_size: number("MinSize").unwrap_or(size),
+                    max_size: number("MaxSize").unwrap_or(size),
+                    threshold: number("Threshold").unwrap_or(2),
+                    kind,
+                })
+            })
+            .collect();
+
+        Self {
+            parents: list("Inherits"),
+            directories,
+        }
+    }
+}
+
+/// Resolves icon names to files through a theme and everything it inherits
+#[derive(Debug, Clone)]
+pub struct IconThemeResolver {
+    theme: String,
+    base_dirs: Vec<PathBuf>,
+    themes: HashMap<String, ThemeIndex>,
+}
+
+impl IconThemeResolver {
+    /// Resolver for `theme` using the standard icon base directories
+    pub fn new(theme: &str) -> Self {
+        Self::with_base_dirs(theme, default_base_dirs())
+    }
+
+    /// Resolver for the icon theme the desktop is currently using
+    pub fn system() -> Self {
+        Self::new(&current_theme_name())
+    }
+
+    /// Resolver searching only `base_dirs`, in order
+    pub fn with_base_dirs(theme: &str, base_dirs: Vec<PathBuf>) -> Self {
+        let mut resolver = Self {
+            theme: theme.to_string(),
+            base_dirs,
+            themes: HashMap::new(),
+        };
+
+        resolver.load_theme(theme, &mut HashSet::new());
+        resolver.load_theme(FALLBACK_THEME, &mut HashSet::new());
+        resolver
+    }
+
+    pub fn theme_name(&self) -> &str {
+        &self.theme
+    }
+
+    pub fn base_dirs(&self) -> &[PathBuf] {
+        &self.base_dirs
+    }
+
+    fn load_theme(&mut self, name: &str, visited: &mut HashSet<String>) {
+        if !visited.insert(name.to_string()) || self.themes.contains_key(name) {
+            return;
+        }
+
+        // The first base directory holding an index.theme defines the theme
+        let index = self
+            .base_dirs
+            .iter()
+            .map(|dir| dir.join(name).join("index.theme"))
+            .find_map(|path| std::fs::read_to_string(path).ok())
+            .map(|content| ThemeIndex::parse(&content));
+
+        if let Some(index) = index {
+            let parents = index.parents.clone();
+            self.themes.insert(name.to_string(), index);
+            for parent in parents {
+                self.load_theme(&parent, visited);
+            }
+        } else {
+            tracing::debug!("Icon theme {} not found", name);
+        }
+    }
+
+    /// Find the file for `name` closest to `size` at `scale`
+    pub fn lookup(&self, name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        self.lookup_in(name, size, scale, None)
+    }
+
+    /// Like [`lookup`](Self::lookup), also searching an item's `IconThemePath`
+    pub fn lookup_in(&self, name: &str, size: u32, scale: u32, theme_path: Option<&Path>) -> Option<PathBuf> {
+        self.find_icon(name, size, scale.max(1), theme_path, &EXTENSIONS)
+    }
+
+    /// Resolve `name` and decode it into a `size * scale` square image
+    ///
+    /// Only raster formats are decoded, so SVG-only icons are reported as
+    /// not found.
+    pub fn load(&self, name: &str, size: u32, scale: u32, theme_path: Option<&Path>) -> Result<RgbaImage> {
+        let path = self
+            .find_icon(name, size, scale.max(1), theme_path, &["png"])
+            .ok_or_else(|| TrayError::IconLoadError(format!("Icon {} not found in theme {}", name, self.theme)))?;
+
+        let pixels = size * scale.max(1);
+        let image = image::open(&path)
+            .map_err(|e| TrayError::IconLoadError(format!("{}: {}", path.display(), e)))?
+            .into_rgba8();
+
+        if image.dimensions() == (pixels, pixels) {
+            Ok(image)
+        } else {
+            Ok(image::imageops::resize(&image, pixels, pixels, image::imageops::FilterType::Lanczos3))
+        }
+    }
+
+    fn find_icon(
+        &self,
+        name: &str,
+        size: u32,
+        scale: u32,
+        theme_path: Option<&Path>,
+        extensions: &[&str],
+    ) -> Option<PathBuf> {
+        // Some items publish a path instead of a name
+        if name.starts_with('/') {
+            let path = PathBuf::from(name);
+            return path.is_file().then_some(path);
+        }
+
+        let base_dirs: Vec<&Path> = theme_path
+            .into_iter()
+            .chain(self.base_dirs.iter().map(PathBuf::as_path))
+            .collect();
+
+        let mut visited = HashSet::new();
+        self.find_in_theme(&self.theme, name, size, scale, &base_dirs, extensions, &mut visited)
+            .or_else(|| self.find_in_theme(FALLBACK_THEME, name, size, scale, &base_dirs, extensions, &mut visited))
+            .or_else(|| lookup_fallback(name, &base_dirs, extensions))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn find_in_theme(
+        &self,
+        theme: &str,
+        name: &str,
+        size: u32,
+        scale: u32,
+        base_dirs: &[&Path],
+        extensions: &[&str],
+        visited: &mut HashSet<String>,
+    ) -> Option<PathBuf> {
+        if !visited.insert(theme.to_string()) {
+            return None;
+        }
+
+        let index = self.themes.get(theme)?;
+        if let Some(path) = lookup_icon(index, theme, name, size, scale, base_dirs, extensions) {
+            return Some(path);
+        }
+
+        index
+            .parents
+            .iter()
+            .find_map(|parent| self.find_in_theme(parent, name, size, scale, base_dirs, extensions, visited))
+    }
+}
+
+fn lookup_icon(
+    index: &ThemeIndex,
+    theme: &str,
+    name: &str,
+    size: u32,
+    scale: u32,
+    base_dirs: &[&Path],
+    extensions: &[&str],
+) -> Option<PathBuf> {
+    let candidates = || {
+        index.directories.iter().flat_map(move |directory| {
+            base_dirs.iter().flat_map(move |base| {
+                extensions.iter().map(move |extension| {
+                    let file = base.join(theme).join(&directory.path).join(format!("{}.{}", name, extension));
+                    (directory, file)
+                })
+            })
+        })
+    };
+
+    if let Some((_, path)) = candidates().find(|(directory, path)| directory.matches_size(size, scale) && path.is_file()) {
+        return Some(path);
+    }
+
+    candidates()
+        .filter(|(_, path)| path.is_file())
+        .min_by_key(|(directory, _)| directory.size_distance(size, scale))
+        .map(|(_, path)| path)
+}
+
+fn lookup_fallback(name: &str, base_dirs: &[&Path], extensions: &[&str]) -> Option<PathBuf> {
+    base_dirs
+        .iter()
+        .flat_map(|base| extensions.iter().map(move |extension| base.join(format!("{}.{}", name, extension))))
+        .find(|path| path.is_file())
+}
+
+/// `$HOME/.icons`, `$XDG_DATA_HOME/icons`, `$XDG_DATA_DIRS/icons` and `/usr/share/pixmaps`
+pub fn default_base_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join(".icons"));
+    }
+    if let Some(data) = dirs::data_dir() {
+        dirs.push(data.join("icons"));
+    }
+
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    for dir in data_dirs.split(':').filter(|dir| !dir.is_empty()) {
+        dirs.push(PathBuf::from(dir).join("icons"));
+    }
+
+    dirs.push(PathBuf::from("/usr/share/pixmaps"));
+    dirs
+}
+
+/// Best guess at the icon theme selected by the user
+///
+/// Reads the GTK and KDE settings files, falling back to [`FALLBACK_THEME`].
+pub fn current_theme_name() -> String {
+    let Some(config_dir) = dirs::config_dir() else {
+        return FALLBACK_THEME.to_string();
+    };
+
+    let candidates = [
+        (config_dir.join("gtk-3.0").join("settings.ini"), "Settings", "gtk-icon-theme-name"),
+        (config_dir.join("gtk-4.0").join("settings.ini"), "Settings", "gtk-icon-theme-name"),
+        (config_dir.join("kdeglobals"), "Icons", "Theme"),
+    ];
+
+    candidates
+        .iter()
+        .find_map(|(path, section, key)| {
+            let content = std::fs::read_to_string(path).ok()?;
+            let value = parse_ini(&content).get(*section)?.get(*key)?.trim().to_string();
+            (!value.is_empty()).then_some(value)
+        })
+        .unwrap_or_else(|| FALLBACK_THEME.to_string())
+}
+
+/// Minimal desktop-entry style parser: `[Section]` headers and `key=value` lines
+fn parse_ini(content: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = String::new();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            current = name.to_string();
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            sections
+                .entry(current.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    sections
+}
@@ -1,12 +1,19 @@
+pub mod backend;
+pub mod bridge;
+pub mod cli;
 pub mod config;
 pub mod error;
+pub mod icon;
 pub mod menu;
 pub mod tray;
 pub mod contrib;
+#[cfg(feature = "tray-icon")]
 pub mod aloe_compat;
 pub mod stray_impl;
+pub mod watcher;
 
 pub use config::AppConfig;
 pub use error::{TrayError, Result};
+pub use icon::IconGenerator;
 pub use menu::MenuAction;
 pub use tray::TrayIcon;
\ No newline at end of file
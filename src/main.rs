@@ -1,19 +1,43 @@
 use anyhow::Result;
-use system_tray_linux_aio::{AppConfig, stray_impl::StrayTrayApp};
+use system_tray_linux_aio::AppConfig;
+use system_tray_linux_aio::cli::{self, Command};
 use tracing::{info, error};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let command = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    
+    // Keep stdout clean for the output of one-shot commands
+    let default_filter = match command {
+        Command::Run { .. }
+        | Command::Watcher
+        | Command::ProxyWatcher
+        | Command::XEmbedProxy
+        | Command::XEmbedMirror => {
+            "system_tray_linux_aio=info,stray=info"
+        }
+        _ => "warn",
+    };
+    
     // Initialize logging
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "system_tray_linux_aio=info,stray=info".into()),
+                .unwrap_or_else(|_| default_filter.into()),
         )
+        .with_writer(std::io::stderr)
         .init();
     
-    info!("Starting System Tray Application");
-    info!("Using stray crate for system tray functionality");
+    if matches!(command, Command::Run { .. }) {
+        info!("Starting System Tray Application");
+        info!("Using stray crate for system tray functionality");
+    }
     
     // Load configuration
     let config = match AppConfig::load() {
@@ -36,8 +60,7 @@ async fn main() -> Result<()> {
     
     #[cfg(target_os = "linux")]
     {
-        let app = StrayTrayApp::new(config);
-        app.run().await?;
+        cli::run(command, config).await?;
     }
     
     #[cfg(not(target_os = "linux"))]
@@ -48,4 +71,4 @@ async fn main() -> Result<()> {
     
     info!("Application shutdown complete");
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,18 +1,20 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use crate::config::AppConfig;
+use crate::config::{AppConfig, MenuConfig};
 use crate::error::Result;
-use crate::tray::{SystemTrayIconComponent, PopupMenu};
+use crate::tray::{PopupMenu, SystemTrayIconComponentInterface};
+
+#[cfg(test)]
+mod tests;
 
 pub struct TrayMenu {
     config: Arc<RwLock<AppConfig>>,
-    #[allow(dead_code)]
     menu: PopupMenu,
 }
 
 impl TrayMenu {
     pub async fn new(config: Arc<RwLock<AppConfig>>) -> Result<Self> {
-        let menu = PopupMenu;
+        let menu = build_popup(&config.read().await.menu_config);
         
         Ok(Self {
             config,
@@ -20,36 +22,23 @@ impl TrayMenu {
         })
     }
     
-    pub async fn setup_menu(&mut self, _tray: &mut SystemTrayIconComponent) -> Result<()> {
+    /// Rebuild the menu from the current config
+    pub async fn setup_menu(&mut self, _tray: &mut impl SystemTrayIconComponentInterface) -> Result<()> {
         let config = self.config.read().await;
+        self.menu = build_popup(&config.menu_config);
         
         tracing::info!("Setting up menu:");
-        
-        // Log custom menu items
         for item in &config.menu_config.custom_items {
             tracing::info!("  - {} (action: {})", item.label, item.action);
-            if item.separator_after {
-                tracing::info!("  ---separator---");
-            }
-        }
-        
-        // Log default items
-        if config.menu_config.show_about {
-            tracing::info!("  - About");
         }
-        if config.menu_config.show_settings {
-            tracing::info!("  - Settings");
-        }
-        if config.menu_config.show_quit {
-            tracing::info!("  - Quit");
-        }
-        
-        // When aloe-menus is properly implemented, we'll use:
-        // tray.show_dropdown_menu(&self.menu);
         
         Ok(())
     }
     
+    pub fn popup(&self) -> &PopupMenu {
+        &self.menu
+    }
+    
     pub async fn handle_menu_result(&self, result: i32) -> Result<Option<MenuAction>> {
         let config = self.config.read().await;
         
@@ -87,7 +76,45 @@ impl TrayMenu {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The dropdown menu for `config`, numbered the way
+/// [`TrayMenu::handle_menu_result`] reads results: custom items from 1, then
+/// About, Settings and Quit as far as they are shown
+pub fn build_popup(config: &MenuConfig) -> PopupMenu {
+    let mut menu = PopupMenu::new();
+    let mut id = 0;
+    let mut next_id = || {
+        id += 1;
+        id
+    };
+    
+    // A separator is only added once an entry follows it
+    let mut separator = false;
+    for item in &config.custom_items {
+        if std::mem::take(&mut separator) {
+            menu.add_separator();
+        }
+        menu.add_item(next_id(), &item.label, item.enabled, false);
+        separator = item.separator_after;
+    }
+    
+    separator = true;
+    let defaults = [
+        (config.show_about, "About"),
+        (config.show_settings, "Settings"),
+        (config.show_quit, "Quit"),
+    ];
+    for (shown, label) in defaults {
+        if shown {
+            if std::mem::take(&mut separator) {
+                menu.add_separator();
+            }
+            menu.add_item(next_id(), label, true, false);
+        }
+    }
+    menu
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MenuAction {
     About,
     Settings,
@@ -0,0 +1,62 @@
+use super::*;
+use crate::config::MenuItem;
+use crate::tray::PopupMenuItem;
+
+fn custom(label: &str, separator_after: bool) -> MenuItem {
+    MenuItem {
+        label: label.to_string(),
+        action: label.to_lowercase(),
+        enabled: true,
+        separator_after,
+    }
+}
+
+fn labels(menu: &PopupMenu) -> Vec<(i32, String)> {
+    menu.items()
+        .iter()
+        .map(|item| match item {
+            PopupMenuItem::Item { id, text, .. } => (*id, text.clone()),
+            PopupMenuItem::Separator => (0, "-".to_string()),
+            PopupMenuItem::SubMenu { text, .. } => (0, text.clone()),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_popup_ids_match_menu_results() {
+    let mut config = AppConfig::default();
+    config.menu_config.custom_items = vec![custom("Sync", true), custom("Pause", false)];
+    config.menu_config.show_settings = false;
+    let menu = TrayMenu::new(Arc::new(RwLock::new(config))).await.unwrap();
+
+    let expected = [(1, "Sync"), (0, "-"), (2, "Pause"), (0, "-"), (3, "About"), (4, "Quit")];
+    assert_eq!(labels(menu.popup()), expected.map(|(id, text)| (id, text.to_string())));
+
+    assert_eq!(menu.handle_menu_result(2).await.unwrap(), Some(MenuAction::Custom("pause".into())));
+    assert_eq!(menu.handle_menu_result(3).await.unwrap(), Some(MenuAction::About));
+    assert_eq!(menu.handle_menu_result(4).await.unwrap(), Some(MenuAction::Quit));
+}
+
+#[test]
+fn test_popup_without_defaults_has_no_trailing_separator() {
+    let config = MenuConfig {
+        show_about: false,
+        show_settings: false,
+        show_quit: false,
+        custom_items: vec![custom("Sync", true), custom("Pause", true)],
+    };
+    let expected = [(1, "Sync"), (0, "-"), (2, "Pause")];
+    assert_eq!(labels(&build_popup(&config)), expected.map(|(id, text)| (id, text.to_string())));
+}
+
+#[test]
+fn test_popup_separators_are_not_doubled() {
+    let config = MenuConfig {
+        show_about: false,
+        show_settings: false,
+        show_quit: true,
+        custom_items: vec![custom("Sync", true)],
+    };
+    let expected = [(1, "Sync"), (0, "-"), (2, "Quit")];
+    assert_eq!(labels(&build_popup(&config)), expected.map(|(id, text)| (id, text.to_string())));
+}
@@ -0,0 +1,100 @@
+//! StatusNotifierItem methods a host calls on behalf of the user.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use super::event::HostCommand;
+use super::handle::HostHandle;
+use super::item::ITEM_INTERFACE;
+
+/// Direction of a scroll over a tray icon
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollOrientation {
+    #[default]
+    Vertical,
+    Horizontal,
+}
+
+impl ScrollOrientation {
+    pub fn as_dbus(&self) -> &'static str {
+        match self {
+            ScrollOrientation::Vertical => "vertical",
+            ScrollOrientation::Horizontal => "horizontal",
+        }
+    }
+}
+
+impl std::str::FromStr for ScrollOrientation {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "vertical" => Ok(ScrollOrientation::Vertical),
+            "horizontal" => Ok(ScrollOrientation::Horizontal),
+            _ => Err(anyhow!("Unknown scroll orientation: {}", value)),
+        }
+    }
+}
+
+impl HostHandle {
+    /// Primary activation, what a left click on the icon does
+    ///
+    /// `x` and `y` are screen coordinates the item may use to place a window.
+    /// Items with `item_is_menu` set usually ignore this and expect their
+    /// menu to be shown instead.
+    pub async fn activate(&self, notifier_address: &str, x: i32, y: i32) -> Result<()> {
+        self.call_item(notifier_address, "Activate", &(x, y)).await?;
+        self.publish_command(HostCommand::Activate { address: notifier_address.to_string(), x, y });
+        Ok(())
+    }
+
+    /// Secondary activation, usually a middle click
+    pub async fn secondary_activate(&self, notifier_address: &str, x: i32, y: i32) -> Result<()> {
+        self.call_item(notifier_address, "SecondaryActivate", &(x, y)).await?;
+        self.publish_command(HostCommand::SecondaryActivate { address: notifier_address.to_string(), x, y });
+        Ok(())
+    }
+
+    /// Ask the item to show its own context menu at `x`, `y`
+    ///
+    /// Only items without a dbusmenu implement this; for the others use
+    /// [`click_by_path`](Self::click_by_path).
+    pub async fn context_menu(&self, notifier_address: &str, x: i32, y: i32) -> Result<()> {
+        self.call_item(notifier_address, "ContextMenu", &(x, y)).await?;
+        self.publish_command(HostCommand::ContextMenu { address: notifier_address.to_string(), x, y });
+        Ok(())
+    }
+
+    /// Scroll over the icon, `delta` is in wheel steps (120 per notch on most mice)
+    pub async fn scroll(&self, notifier_address: &str, delta: i32, orientation: ScrollOrientation) -> Result<()> {
+        self.call_item(notifier_address, "Scroll", &(delta, orientation.as_dbus())).await?;
+        self.publish_command(HostCommand::Scroll {
+            address: notifier_address.to_string(),
+            delta,
+            orientation,
+        });
+        Ok(())
+    }
+
+    async fn call_item<B>(&self, notifier_address: &str, method: &str, body: &B) -> Result<()>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        let info = self
+            .item(notifier_address)
+            .await
+            .ok_or_else(|| anyhow!("Notifier address {} not found", notifier_address))?;
+
+        let connection = self.connection().await?;
+        let proxy = zbus::Proxy::new(connection, info.address.as_str(), info.object_path.as_str(), ITEM_INTERFACE).await?;
+
+        proxy
+            .call_method(method, body)
+            .await
+            .with_context(|| format!("{} on {} failed", method, info.display_name()))?;
+
+        info!("Sent {} to {}", method, notifier_address);
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! Events published by the host whenever the set of tray items changes.
+
+use serde::{Deserialize, Serialize};
+use super::command::ScrollOrientation;
+use super::NotifierInfo;
+
+/// A change in the tray, delivered to every [`subscribe`](super::StrayTrayApp::subscribe)r
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HostEvent {
+    /// A new item appeared
+    Added(NotifierInfo),
+    /// Properties of a known item changed, `fields` lists which ones
+    Changed {
+        info: NotifierInfo,
+        fields: Vec<ItemField>,
+    },
+    /// An item went away, `info` is its last known state
+    Removed(NotifierInfo),
+}
+
+impl HostEvent {
+    /// Bus name of the item the event is about
+    pub fn address(&self) -> &str {
+        &self.info().address
+    }
+
+    pub fn info(&self) -> &NotifierInfo {
+        match self {
+            HostEvent::Added(info) | HostEvent::Removed(info) => info,
+            HostEvent::Changed { info, .. } => info,
+        }
+    }
+}
+
+/// A command the host sent to an item, published once it went out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostCommand {
+    MenuClick { address: String, submenu_id: i32 },
+    Activate { address: String, x: i32, y: i32 },
+    SecondaryActivate { address: String, x: i32, y: i32 },
+    ContextMenu { address: String, x: i32, y: i32 },
+    Scroll { address: String, delta: i32, orientation: ScrollOrientation },
+    Terminate { address: String, force: bool },
+    Restart { address: String },
+}
+
+impl HostCommand {
+    /// Bus name of the item the command went to
+    pub fn address(&self) -> &str {
+        match self {
+            HostCommand::MenuClick { address, .. }
+            | HostCommand::Activate { address, .. }
+            | HostCommand::SecondaryActivate { address, .. }
+            | HostCommand::ContextMenu { address, .. }
+            | HostCommand::Scroll { address, .. }
+            | HostCommand::Terminate { address, .. }
+            | HostCommand::Restart { address } => address,
+        }
+    }
+}
+
+/// Property of a [`NotifierInfo`] that can change between updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ItemField {
+    Id,
+    Category,
+    Status,
+    Title,
+    WindowId,
+    Icon,
+    OverlayIcon,
+    AttentionIcon,
+    IconThemePath,
+    ToolTip,
+    Menu,
+    ItemIsMenu,
+}
+
+impl NotifierInfo {
+    /// Fields that differ between `self` and a newer version of the item
+    pub fn diff(&self, newer: &NotifierInfo) -> Vec<ItemField> {
+        let mut fields = Vec::new();
+        let mut check = |changed: bool, field: ItemField| {
+            if changed {
+                fields.push(field);
+            }
+        };
+
+        check(self.id != newer.id, ItemField::Id);
+        check(self.category != newer.category, ItemField::Category);
+        check(self.status != newer.status, ItemField::Status);
+        check(self.title != newer.title, ItemField::Title);
+        check(self.window_id != newer.window_id, ItemField::WindowId);
+        check(
+            self.icon_name != newer.icon_name || self.icon_pixmaps != newer.icon_pixmaps,
+            ItemField::Icon,
+        );
+        check(
+            self.overlay_icon_name != newer.overlay_icon_name
+                || self.overlay_icon_pixmaps != newer.overlay_icon_pixmaps,
+            ItemField::OverlayIcon,
+        );
+        check(
+            self.attention_icon_name != newer.attention_icon_name
+                || self.attention_icon_pixmaps != newer.attention_icon_pixmaps
+                || self.attention_movie_name != newer.attention_movie_name,
+            ItemField::AttentionIcon,
+        );
+        check(self.icon_theme_path != newer.icon_theme_path, ItemField::IconThemePath);
+        check(self.tooltip != newer.tooltip, ItemField::ToolTip);
+        check(self.menu_path != newer.menu_path || self.menu != newer.menu, ItemField::Menu);
+        check(self.item_is_menu != newer.item_is_menu, ItemField::ItemIsMenu);
+
+        fields
+    }
+}
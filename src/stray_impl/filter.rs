@@ -0,0 +1,75 @@
+//! Allow/block filtering and stable ordering of remote items.
+
+use crate::config::{ItemMatcher, ItemsConfig};
+use super::item::NotifierInfo;
+
+/// Decides which items the host keeps, from `[items]` in the config
+#[derive(Debug, Clone, Default)]
+pub struct ItemFilter {
+    allow: Vec<ItemMatcher>,
+    block: Vec<ItemMatcher>,
+}
+
+impl ItemFilter {
+    pub fn new(config: &ItemsConfig) -> Self {
+        Self {
+            allow: config.allow.clone(),
+            block: config.block.clone(),
+        }
+    }
+
+    pub fn allows(&self, info: &NotifierInfo) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|matcher| matcher.matches(info)))
+            && !self.block.iter().any(|matcher| matcher.matches(info))
+    }
+}
+
+/// Display order of items, keyed by id so it survives items restarting
+///
+/// Configured keys come first; items not listed are appended the first time
+/// they appear and keep that slot when they go away and come back.
+#[derive(Debug, Clone, Default)]
+pub struct ItemOrder {
+    keys: Vec<String>,
+}
+
+impl ItemOrder {
+    pub fn new(order: &[String]) -> Self {
+        Self { keys: order.to_vec() }
+    }
+
+    /// Key an item is ordered by, its id or its title when it has none
+    pub fn key(info: &NotifierInfo) -> &str {
+        if info.id.is_empty() {
+            &info.title
+        } else {
+            &info.id
+        }
+    }
+
+    /// Slot of an item, matched on its key only so a title never takes
+    /// the slot of another item's id
+    fn position(&self, info: &NotifierInfo) -> Option<usize> {
+        let key = Self::key(info);
+        self.keys.iter().position(|slot| slot == key)
+    }
+
+    /// Give an item a slot if it does not have one yet
+    pub fn learn(&mut self, info: &NotifierInfo) {
+        if self.position(info).is_none() {
+            self.keys.push(Self::key(info).to_string());
+        }
+    }
+
+    /// Sort items by slot, unknown ones last by address
+    pub fn sort(&self, items: &mut [NotifierInfo]) {
+        items.sort_by(|a, b| {
+            let slot = |info| self.position(info).unwrap_or(usize::MAX);
+            slot(a).cmp(&slot(b)).then_with(|| a.address.cmp(&b.address))
+        });
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+}
@@ -0,0 +1,299 @@
+//! Cloneable handle for querying and driving a running host.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Result};
+use stray::message::NotifierItemCommand;
+use tokio::sync::{broadcast, mpsc, Notify, OnceCell};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use crate::config::ItemsConfig;
+use crate::icon::IconThemeResolver;
+use super::event::{HostCommand, HostEvent, ItemField};
+use super::filter::{ItemFilter, ItemOrder};
+use super::item::{ItemRegistry, NotifierInfo};
+use super::menu::MenuNode;
+use super::process::ProcessInfo;
+
+/// Events kept for slow subscribers before they start lagging
+const EVENT_CAPACITY: usize = 64;
+
+/// Shared access to the host's items, events and commands
+///
+/// Every clone talks to the same host, so it can be handed to other tasks
+/// while [`StrayTrayApp`](super::StrayTrayApp) runs.
+#[derive(Clone)]
+pub struct HostHandle {
+    registry: ItemRegistry,
+    events: broadcast::Sender<HostEvent>,
+    commands: broadcast::Sender<HostCommand>,
+    ui_tx: mpsc::Sender<NotifierItemCommand>,
+    connection: Arc<OnceCell<zbus::Connection>>,
+    filter: Arc<ItemFilter>,
+    order: Arc<Mutex<ItemOrder>>,
+    /// Processes of items the filter dropped, so updates need not look them up again
+    filtered: Arc<Mutex<HashMap<String, ProcessInfo>>>,
+    shutdown: Arc<Notify>,
+}
+
+impl HostHandle {
+    /// Handle that filters and orders items as `items` says
+    pub(crate) fn new(ui_tx: mpsc::Sender<NotifierItemCommand>, items: &ItemsConfig) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let (commands, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            registry: ItemRegistry::new(),
+            events,
+            commands,
+            ui_tx,
+            connection: Arc::new(OnceCell::new()),
+            filter: Arc::new(ItemFilter::new(items)),
+            order: Arc::new(Mutex::new(ItemOrder::new(&items.order))),
+            filtered: Arc::new(Mutex::new(HashMap::new())),
+            shutdown: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn get_sender(&self) -> mpsc::Sender<NotifierItemCommand> {
+        self.ui_tx.clone()
+    }
+
+    /// Session bus connection shared by every clone, opened on first use
+    pub(crate) async fn connection(&self) -> Result<&zbus::Connection> {
+        Ok(self.connection.get_or_try_init(zbus::Connection::session).await?)
+    }
+
+    /// Handle on the item registry that stays valid while the app runs
+    pub fn registry(&self) -> ItemRegistry {
+        self.registry.clone()
+    }
+
+    /// Receive every [`HostEvent`] from now on
+    ///
+    /// A subscriber that falls more than 64 events behind gets
+    /// `RecvError::Lagged` and should re-read [`items`](Self::items).
+    pub fn subscribe(&self) -> broadcast::Receiver<HostEvent> {
+        self.events.subscribe()
+    }
+
+    /// Receive every [`HostCommand`] sent to an item from now on
+    pub fn subscribe_commands(&self) -> broadcast::Receiver<HostCommand> {
+        self.commands.subscribe()
+    }
+
+    /// All remote items the filter lets through, in display order
+    pub async fn items(&self) -> Vec<NotifierInfo> {
+        let mut items = self.registry.list().await;
+        self.order.lock().unwrap().sort(&mut items);
+        items
+    }
+
+    /// Ordering keys, configured ones first and then as items appeared
+    pub fn item_order(&self) -> Vec<String> {
+        self.order.lock().unwrap().keys().to_vec()
+    }
+
+    pub async fn item(&self, notifier_address: &str) -> Option<NotifierInfo> {
+        self.registry.get(notifier_address).await
+    }
+
+    /// Look an item up by bus name, `Id` or title
+    pub async fn find_item(&self, query: &str) -> Option<NotifierInfo> {
+        self.registry.find(query).await
+    }
+
+    /// Ask a spawned host to stop
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+
+    pub(crate) async fn wait_for_shutdown(&self) {
+        self.shutdown.notified().await;
+    }
+
+    /// Store a new version of an item and tell subscribers what changed
+    ///
+    /// Items the filter rejects are dropped, and reported as removed if they
+    /// were shown before. Returns whether the item is kept.
+    pub(crate) async fn apply_update(&self, info: NotifierInfo) -> bool {
+        if !self.filter.allows(&info) {
+            self.apply_remove(&info.address).await;
+            if let Some(process) = info.process {
+                self.filtered.lock().unwrap().insert(info.address, process);
+            }
+            return false;
+        }
+
+        self.order.lock().unwrap().learn(&info);
+
+        let event = match self.registry.update(info).await {
+            (info, None) => HostEvent::Added(info),
+            (info, Some(previous)) => {
+                let fields = previous.diff(&info);
+                if fields.is_empty() {
+                    return true;
+                }
+                HostEvent::Changed { info, fields }
+            }
+        };
+
+        self.publish(event);
+        true
+    }
+
+    /// Process already looked up for an item, kept or filtered out
+    pub(crate) async fn known_process(&self, address: &str) -> Option<ProcessInfo> {
+        match self.registry.get(address).await {
+            Some(info) => info.process,
+            None => self.filtered.lock().unwrap().get(address).cloned(),
+        }
+    }
+
+    /// Replace the cached menu of an item
+    pub(crate) async fn apply_menu(&self, address: &str, menu: Option<MenuNode>) {
+        if let Some((previous, info)) = self.registry.set_menu(address, menu).await {
+            if previous.menu != info.menu {
+                self.publish(HostEvent::Changed { info, fields: vec![ItemField::Menu] });
+            }
+        }
+    }
+
+    pub(crate) async fn apply_remove(&self, address: &str) {
+        self.filtered.lock().unwrap().remove(address);
+        if let Some(info) = self.registry.remove(address).await {
+            self.publish(HostEvent::Removed(info));
+        }
+    }
+
+    /// Publish an event exactly as given, keeping the registry in step
+    ///
+    /// Used to replay recordings, where the events are already computed.
+    /// The filter still applies, so a replay can be narrowed down.
+    pub(crate) async fn apply_event(&self, event: HostEvent) {
+        match event {
+            HostEvent::Removed(info) => self.apply_remove(&info.address).await,
+            HostEvent::Added(ref info) | HostEvent::Changed { ref info, .. } => {
+                if !self.filter.allows(info) {
+                    self.apply_remove(&info.address).await;
+                    return;
+                }
+
+                self.order.lock().unwrap().learn(info);
+                self.registry.insert(info.clone()).await;
+                self.publish(event);
+            }
+        }
+    }
+
+    fn publish(&self, event: HostEvent) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    pub(crate) fn publish_command(&self, command: HostCommand) {
+        let _ = self.commands.send(command);
+    }
+
+    /// Resolve a remote item's icon name to a file, honouring its `IconThemePath`
+    pub async fn resolve_item_icon(&self, resolver: &IconThemeResolver, notifier_address: &str, size: u32) -> Option<PathBuf> {
+        let info = self.registry.get(notifier_address).await?;
+        let name = info.icon_name.as_deref().filter(|name| !name.is_empty())?;
+        let theme_path = info.icon_theme_path.as_deref().filter(|path| !path.is_empty()).map(Path::new);
+
+        resolver.lookup_in(name, size, 1, theme_path)
+    }
+
+    /// Send a menu item click command
+    pub async fn click_menu_item(&self, submenu_id: i32, notifier_address: &str) -> Result<()> {
+        if let Some(info) = self.registry.get(notifier_address).await {
+            let command = NotifierItemCommand::MenuItemClicked {
+                submenu_id,
+                menu_path: info.menu_path.clone(),
+                notifier_address: notifier_address.to_string(),
+            };
+
+            self.ui_tx.send(command).await?;
+            self.publish_command(HostCommand::MenuClick {
+                address: notifier_address.to_string(),
+                submenu_id,
+            });
+            info!("Sent menu click command for item {} on {}", submenu_id, notifier_address);
+        } else {
+            error!("Notifier address {} not found", notifier_address);
+        }
+
+        Ok(())
+    }
+
+    /// Cached menu tree of an item, `None` if the item has no menu or is unknown
+    pub async fn menu(&self, notifier_address: &str) -> Option<MenuNode> {
+        self.registry.get(notifier_address).await?.menu
+    }
+
+    /// Click the menu entry reached by following labels from the top,
+    /// e.g. `&["Settings", "Preferences"]`
+    pub async fn click_by_path<S: AsRef<str>>(&self, notifier_address: &str, path: &[S]) -> Result<()> {
+        let info = self
+            .registry
+            .get(notifier_address)
+            .await
+            .ok_or_else(|| anyhow!("Notifier address {} not found", notifier_address))?;
+        let menu = info
+            .menu
+            .as_ref()
+            .ok_or_else(|| anyhow!("{} has no menu", info.display_name()))?;
+
+        let entry = menu.clickable(path)?;
+        let command = NotifierItemCommand::MenuItemClicked {
+            submenu_id: entry.id,
+            menu_path: info.menu_path.clone(),
+            notifier_address: notifier_address.to_string(),
+        };
+
+        self.ui_tx.send(command).await?;
+        self.publish_command(HostCommand::MenuClick {
+            address: notifier_address.to_string(),
+            submenu_id: entry.id,
+        });
+        info!("Clicked \"{}\" (id {}) on {}", entry.label, entry.id, notifier_address);
+
+        Ok(())
+    }
+}
+
+/// A host running in the background, returned by [`StrayTrayApp::spawn`](super::StrayTrayApp::spawn)
+pub struct RunningHost {
+    handle: HostHandle,
+    task: JoinHandle<Result<()>>,
+}
+
+impl RunningHost {
+    pub(crate) fn new(handle: HostHandle, task: JoinHandle<Result<()>>) -> Self {
+        Self { handle, task }
+    }
+
+    pub fn handle(&self) -> HostHandle {
+        self.handle.clone()
+    }
+
+    /// Stop the host and wait for its event loop to finish
+    pub async fn stop(self) -> Result<()> {
+        self.handle.shutdown();
+        self.task.await?
+    }
+
+    /// Wait until the host stops on its own or through [`HostHandle::shutdown`]
+    pub async fn join(self) -> Result<()> {
+        self.task.await?
+    }
+}
+
+impl Deref for RunningHost {
+    type Target = HostHandle;
+
+    fn deref(&self) -> &HostHandle {
+        &self.handle
+    }
+}
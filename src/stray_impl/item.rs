@@ -0,0 +1,351 @@
+//! Typed model of a remote StatusNotifierItem and the registry holding them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use zbus::zvariant::{OwnedValue, Value};
+use crate::config::ItemStatus;
+use super::menu::MenuNode;
+use super::process::ProcessInfo;
+
+pub const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+pub const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+pub const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+
+/// Object path used by items that register with a bare bus name
+pub const DEFAULT_ITEM_PATH: &str = "/StatusNotifierItem";
+
+/// Everything known about one remote tray item
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotifierInfo {
+    /// Bus name of the item, e.g. `:1.52`
+    pub address: String,
+    /// Object path the item is exported at
+    pub object_path: String,
+    /// Object path of the item's com.canonical.dbusmenu, if any
+    pub menu_path: String,
+    pub id: String,
+    pub category: ItemCategory,
+    pub status: ItemStatus,
+    pub title: String,
+    pub window_id: i32,
+    pub icon_name: Option<String>,
+    pub icon_pixmaps: Vec<IconPixmap>,
+    pub overlay_icon_name: Option<String>,
+    pub overlay_icon_pixmaps: Vec<IconPixmap>,
+    pub attention_icon_name: Option<String>,
+    pub attention_icon_pixmaps: Vec<IconPixmap>,
+    pub attention_movie_name: Option<String>,
+    pub icon_theme_path: Option<String>,
+    pub tooltip: Option<ToolTip>,
+    /// The item only supports the context menu, Activate should open it
+    pub item_is_menu: bool,
+    /// Cached layout of the menu at `menu_path`, kept current by the host
+    pub menu: Option<MenuNode>,
+    /// Process owning the item's bus connection, when the bus tells
+    pub process: Option<ProcessInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemCategory {
+    #[default]
+    ApplicationStatus,
+    Communications,
+    SystemServices,
+    Hardware,
+}
+
+impl ItemCategory {
+    pub fn from_dbus(value: &str) -> Self {
+        match value {
+            "Communications" => ItemCategory::Communications,
+            "SystemServices" => ItemCategory::SystemServices,
+            "Hardware" => ItemCategory::Hardware,
+            _ => ItemCategory::ApplicationStatus,
+        }
+    }
+
+    pub fn as_dbus(&self) -> &'static str {
+        match self {
+            ItemCategory::ApplicationStatus => "ApplicationStatus",
+            ItemCategory::Communications => "Communications",
+            ItemCategory::SystemServices => "SystemServices",
+            ItemCategory::Hardware => "Hardware",
+        }
+    }
+}
+
+/// ARGB32 pixels in network byte order, as sent over the bus
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IconPixmap {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolTip {
+    pub icon_name: String,
+    pub icon_pixmaps: Vec<IconPixmap>,
+    pub title: String,
+    pub description: String,
+}
+
+impl NotifierInfo {
+    /// Build the model from the result of `Properties.GetAll`
+    pub fn from_properties(address: &str, object_path: &str, props: &HashMap<String, OwnedValue>) -> Self {
+        let string = |key: &str| props.get(key).and_then(|value| value_to_string(value));
+        let pixmaps = |key: &str| props.get(key).map(|value| pixmaps_from_value(value)).unwrap_or_default();
+
+        Self {
+            address: address.to_string(),
+            object_path: object_path.to_string(),
+            menu_path: string("Menu").unwrap_or_default(),
+            id: string("Id").unwrap_or_default(),
+            category: string("Category").map(|value| ItemCategory::from_dbus(&value)).unwrap_or_default(),
+            status: string("Status").map(|value| ItemStatus::from_dbus(&value)).unwrap_or_default(),
+            title: string("Title").unwrap_or_default(),
+            window_id: props.get("WindowId").and_then(|value| value_to_i32(value)).unwrap_or(0),
+            icon_name: string("IconName").filter(|name| !name.is_empty()),
+            icon_pixmaps: pixmaps("IconPixmap"),
+            overlay_icon_name: string("OverlayIconName").filter(|name| !name.is_empty()),
+            overlay_icon_pixmaps: pixmaps("OverlayIconPixmap"),
+            attention_icon_name: string("AttentionIconName").filter(|name| !name.is_empty()),
+            attention_icon_pixmaps: pixmaps("AttentionIconPixmap"),
+            attention_movie_name: string("AttentionMovieName").filter(|name| !name.is_empty()),
+            icon_theme_path: string("IconThemePath").filter(|path| !path.is_empty()),
+            tooltip: props.get("ToolTip").and_then(|value| tooltip_from_value(value)),
+            item_is_menu: props
+                .get("ItemIsMenu")
+                .and_then(|value| match &**value {
+                    Value::Bool(flag) => Some(*flag),
+                    _ => None,
+                })
+                .unwrap_or(false),
+            menu: None,
+            process: None,
+        }
+    }
+
+    /// Build the partial model stray reports when the full one is unavailable
+    pub fn from_stray(address: &str, item: &stray::message::tray::StatusNotifierItem) -> Self {
+        Self {
+            address: address.to_string(),
+            object_path: DEFAULT_ITEM_PATH.to_string(),
+            menu_path: item.menu.clone().unwrap_or_default(),
+            id: item.id.clone(),
+            title: item.title.clone().unwrap_or_else(|| "Unknown".to_string()),
+            icon_name: item.icon_name.clone(),
+            icon_pixmaps: item
+                .icon_pixmap
+                .iter()
+                .flatten()
+                .map(|pixmap| IconPixmap {
+                    width: pixmap.width,
+                    height: pixmap.height,
+                    pixels: pixmap.pixels.clone(),
+                })
+                .collect(),
+            attention_icon_name: item.attention_icon_name.clone(),
+            icon_theme_path: item.icon_theme_path.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Name to show for the item, falling back from title to id to address
+    pub fn display_name(&self) -> &str {
+        [&self.title, &self.id, &self.address]
+            .into_iter()
+            .find(|value| !value.is_empty() && value.as_str() != "Unknown")
+            .map(String::as_str)
+            .unwrap_or(&self.address)
+    }
+}
+
+fn value_to_string(value: &Value<'_>) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.to_string()),
+        Value::ObjectPath(path) => Some(path.to_string()),
+        Value::Value(inner) => value_to_string(inner),
+        _ => None,
+    }
+}
+
+fn value_to_i32(value: &Value<'_>) -> Option<i32> {
+    match value {
+        Value::I32(n) => Some(*n),
+        Value::U32(n) => i32::try_from(*n).ok(),
+        Value::Value(inner) => value_to_i32(inner),
+        _ => None,
+    }
+}
+
+/// Parse an `a(iiay)` pixmap list
+fn pixmaps_from_value(value: &Value<'_>) -> Vec<IconPixmap> {
+    let entries = match value {
+        Value::Array(array) => array.get(),
+        Value::Value(inner) => return pixmaps_from_value(inner),
+        _ => return vec![],
+    };
+
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Value::Structure(structure) => match structure.fields() {
+                [Value::I32(width), Value::I32(height), Value::Array(bytes)] => Some(IconPixmap {
+                    width: *width,
+                    height: *height,
+                    pixels: bytes
+                        .get()
+                        .iter()
+                        .filter_map(|byte| match byte {
+                            Value::U8(byte) => Some(*byte),
+                            _ => None,
+                        })
+                        .collect(),
+                }),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse a `(sa(iiay)ss)` tooltip
+fn tooltip_from_value(value: &Value<'_>) -> Option<ToolTip> {
+    match value {
+        Value::Structure(structure) => match structure.fields() {
+            [icon_name, pixmaps, title, description] => Some(ToolTip {
+                icon_name: value_to_string(icon_name).unwrap_or_default(),
+                icon_pixmaps: pixmaps_from_value(pixmaps),
+                title: value_to_string(title).unwrap_or_default(),
+                description: value_to_string(description).unwrap_or_default(),
+            }),
+            _ => None,
+        },
+        Value::Value(inner) => tooltip_from_value(inner),
+        _ => None,
+    }
+}
+
+/// Split a watcher registration such as `:1.52/org/ayatana/NotificationItem/x`
+/// into bus name and object path
+///
+/// stray's watcher joins the sender and a bus name without a separator, as in
+/// `:1.52:1.52`; those resolve to the sender at the default path.
+pub fn split_service(service: &str) -> (String, String) {
+    if let Some(index) = service.find('/') {
+        return (service[..index].to_string(), service[index..].to_string());
+    }
+
+    match service.strip_prefix(':').and_then(|rest| rest.find(':')) {
+        Some(index) => (service[..=index].to_string(), DEFAULT_ITEM_PATH.to_string()),
+        None => (service.to_string(), DEFAULT_ITEM_PATH.to_string()),
+    }
+}
+
+/// Find the object path an item registered with the watcher
+pub async fn item_path(connection: &zbus::Connection, address: &str) -> zbus::Result<String> {
+    let watcher = zbus::Proxy::new(connection, WATCHER_NAME, WATCHER_PATH, WATCHER_NAME).await?;
+    let services: Vec<String> = watcher.get_property("RegisteredStatusNotifierItems").await?;
+
+    Ok(services
+        .iter()
+        .map(|service| split_service(service))
+        .find(|(bus_name, _)| bus_name == address)
+        .map(|(_, path)| path)
+        .unwrap_or_else(|| DEFAULT_ITEM_PATH.to_string()))
+}
+
+/// Read every property of a remote item
+pub async fn fetch_item(connection: &zbus::Connection, address: &str, object_path: &str) -> zbus::Result<NotifierInfo> {
+    let properties = zbus::fdo::PropertiesProxy::builder(connection)
+        .destination(address.to_string())?
+        .path(object_path.to_string())?
+        .build()
+        .await?;
+
+    let interface = zbus::names::InterfaceName::try_from(ITEM_INTERFACE)?;
+    let props = properties.get_all(interface).await?;
+
+    Ok(NotifierInfo::from_properties(address, object_path, &props))
+}
+
+/// Shared, always current view of the items known to the host
+#[derive(Debug, Clone, Default)]
+pub struct ItemRegistry {
+    items: Arc<RwLock<HashMap<String, NotifierInfo>>>,
+}
+
+impl ItemRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(&self, address: &str) -> Option<NotifierInfo> {
+        self.items.read().await.get(address).cloned()
+    }
+
+    /// All items, sorted by address so the order is stable
+    pub async fn list(&self) -> Vec<NotifierInfo> {
+        let mut items: Vec<_> = self.items.read().await.values().cloned().collect();
+        items.sort_by(|a, b| a.address.cmp(&b.address));
+        items
+    }
+
+    /// First item whose `Id` property equals `id`
+    pub async fn find_by_id(&self, id: &str) -> Option<NotifierInfo> {
+        self.list().await.into_iter().find(|item| item.id == id)
+    }
+
+    /// Item matching `query` as bus name, then `Id`, then display name ignoring case
+    ///
+    /// Watcher registrations such as `:1.52/StatusNotifierItem` match by their bus name.
+    pub async fn find(&self, query: &str) -> Option<NotifierInfo> {
+        let items = self.list().await;
+        let (address, _) = split_service(query);
+
+        items
+            .iter()
+            .find(|item| item.address == address)
+            .or_else(|| items.iter().find(|item| item.id == query))
+            .or_else(|| items.iter().find(|item| item.display_name().eq_ignore_ascii_case(query)))
+            .cloned()
+    }
+
+    /// Store an item, returning the previous version
+    pub async fn insert(&self, info: NotifierInfo) -> Option<NotifierInfo> {
+        self.items.write().await.insert(info.address.clone(), info)
+    }
+
+    /// Store a new version of an item, keeping the cached menu when the
+    /// update carries none for the same menu path
+    ///
+    /// Returns the stored version and the previous one.
+    pub async fn update(&self, mut info: NotifierInfo) -> (NotifierInfo, Option<NotifierInfo>) {
+        let mut items = self.items.write().await;
+
+        if let Some(previous) = items.get(&info.address) {
+            if info.menu.is_none() && info.menu_path == previous.menu_path {
+                info.menu = previous.menu.clone();
+            }
+        }
+
+        let previous = items.insert(info.address.clone(), info.clone());
+        (info, previous)
+    }
+
+    /// Replace the cached menu of a known item, returning the old and new versions
+    pub async fn set_menu(&self, address: &str, menu: Option<MenuNode>) -> Option<(NotifierInfo, NotifierInfo)> {
+        let mut items = self.items.write().await;
+        let info = items.get_mut(address)?;
+        let previous = info.clone();
+        info.menu = menu;
+        Some((previous, info.clone()))
+    }
+
+    pub async fn remove(&self, address: &str) -> Option<NotifierInfo> {
+        self.items.write().await.remove(address)
+    }
+}
@@ -0,0 +1,329 @@
+//! Menu tree of a remote item, read from its com.canonical.dbusmenu object.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio_stream::StreamExt;
+use tracing::debug;
+use zbus::zvariant::{OwnedValue, Value};
+use super::handle::HostHandle;
+
+pub const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+
+/// Signals after which the cached layout is out of date
+const REFRESH_SIGNALS: [&str; 2] = ["LayoutUpdated", "ItemsPropertiesUpdated"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MenuKind {
+    #[default]
+    Standard,
+    Separator,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToggleKind {
+    #[default]
+    None,
+    Checkmark,
+    Radio,
+}
+
+/// One entry of a remote menu; the root has id 0 and no label
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MenuNode {
+    pub id: i32,
+    /// Label with the `_` mnemonic markers removed
+    pub label: String,
+    pub kind: MenuKind,
+    pub enabled: bool,
+    pub visible: bool,
+    pub icon_name: Option<String>,
+    pub toggle: ToggleKind,
+    /// `Some(true)` when checked, `None` for indeterminate or untoggleable entries
+    pub toggle_state: Option<bool>,
+    pub children: Vec<MenuNode>,
+}
+
+impl Default for MenuNode {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            label: String::new(),
+            kind: MenuKind::Standard,
+            enabled: true,
+            visible: true,
+            icon_name: None,
+            toggle: ToggleKind::None,
+            toggle_state: None,
+            children: vec![],
+        }
+    }
+}
+
+/// Why a label path could not be resolved to a menu entry
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MenuPathError {
+    #[error("Menu path is empty")]
+    EmptyPath,
+
+    #[error("No entry \"{label}\" in {parent}, available: {}", available.join(", "))]
+    NotFound {
+        parent: String,
+        label: String,
+        available: Vec<String>,
+    },
+
+    #[error("Menu entry \"{0}\" has no submenu")]
+    NotASubmenu(String),
+
+    #[error("Menu entry \"{0}\" is disabled")]
+    Disabled(String),
+}
+
+impl MenuNode {
+    /// Build a node from the `(ia{sv}av)` layout structure
+    pub fn from_value(value: &Value<'_>) -> Option<Self> {
+        match value {
+            Value::Structure(structure) => match structure.fields() {
+                [Value::I32(id), Value::Dict(props), Value::Array(children)] => {
+                    let props = HashMap::<String, OwnedValue>::try_from(props.clone()).ok()?;
+                    let children = children.get().iter().filter_map(Self::from_value).collect();
+                    Some(Self::from_parts(*id, &props, children))
+                }
+                _ => None,
+            },
+            Value::Value(inner) => Self::from_value(inner),
+            _ => None,
+        }
+    }
+
+    /// Build a node from its id, properties and already parsed children
+    pub fn from_parts(id: i32, props: &HashMap<String, OwnedValue>, children: Vec<MenuNode>) -> Self {
+        let string = |key: &str| props.get(key).and_then(|value| value_str(value));
+        let flag = |key: &str| props.get(key).and_then(|value| value_bool(value));
+
+        let toggle = match string("toggle-type").as_deref() {
+            Some("checkmark") => ToggleKind::Checkmark,
+            Some("radio") => ToggleKind::Radio,
+            _ => ToggleKind::None,
+        };
+
+        // 1 is on, 0 is off, anything else is indeterminate
+        let toggle_state = props.get("toggle-state").and_then(|value| match &**value {
+            Value::I32(1) => Some(true),
+            Value::I32(0) => Some(false),
+            _ => None,
+        });
+
+        Self {
+            id,
+            label: string("label").map(|label| strip_mnemonic(&label)).unwrap_or_default(),
+            kind: match string("type").as_deref() {
+                Some("separator") => MenuKind::Separator,
+                _ => MenuKind::Standard,
+            },
+            enabled: flag("enabled").unwrap_or(true),
+            visible: flag("visible").unwrap_or(true),
+            icon_name: string("icon-name").filter(|name| !name.is_empty()),
+            toggle,
+            toggle_state: if toggle == ToggleKind::None { None } else { toggle_state },
+            children,
+        }
+    }
+
+    /// Convert the layout stray reports, used when we cannot read it ourselves
+    pub fn from_stray(menu: &stray::message::menu::TrayMenu) -> Self {
+        Self {
+            children: menu.submenus.iter().map(Self::from_stray_item).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn from_stray_item(item: &stray::message::menu::MenuItem) -> Self {
+        use stray::message::menu::{MenuType, ToggleState, ToggleType};
+
+        let toggle = match item.toggle_type {
+            ToggleType::Checkmark => ToggleKind::Checkmark,
+            ToggleType::Radio => ToggleKind::Radio,
+            ToggleType::CannotBeToggled => ToggleKind::None,
+        };
+
+        Self {
+            id: item.id,
+            label: item.label.clone(),
+            kind: match item.menu_type {
+                MenuType::Separator => MenuKind::Separator,
+                MenuType::Standard => MenuKind::Standard,
+            },
+            enabled: item.enabled,
+            visible: item.visible,
+            icon_name: item.icon_name.clone(),
+            toggle,
+            toggle_state: match item.toggle_state {
+                ToggleState::On => Some(true),
+                ToggleState::Off => Some(false),
+                ToggleState::Indeterminate => None,
+            },
+            children: item.submenu.iter().map(Self::from_stray_item).collect(),
+        }
+    }
+
+    pub fn is_separator(&self) -> bool {
+        self.kind == MenuKind::Separator
+    }
+
+    /// Visible entries a user could pick, without separators
+    pub fn entries(&self) -> impl Iterator<Item = &MenuNode> {
+        self.children.iter().filter(|child| child.visible && !child.is_separator())
+    }
+
+    /// Entry with the given id anywhere below this node
+    pub fn find_id(&self, id: i32) -> Option<&MenuNode> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find_id(id))
+    }
+
+    /// Walk the tree by labels, e.g. `["Settings", "Preferences"]`
+    pub fn find_path<S: AsRef<str>>(&self, path: &[S]) -> Result<&MenuNode, MenuPathError> {
+        if path.is_empty() {
+            return Err(MenuPathError::EmptyPath);
+        }
+
+        let mut node = self;
+        for (depth, label) in path.iter().map(AsRef::as_ref).enumerate() {
+            if depth > 0 && node.children.is_empty() {
+                return Err(MenuPathError::NotASubmenu(join_path(&path[..depth])));
+            }
+
+            node = node.entries().find(|entry| entry.label == label).ok_or_else(|| MenuPathError::NotFound {
+                parent: if depth == 0 {
+                    "the top level".to_string()
+                } else {
+                    format!("\"{}\"", join_path(&path[..depth]))
+                },
+                label: label.to_string(),
+                available: node.entries().map(|entry| entry.label.clone()).collect(),
+            })?;
+        }
+
+        Ok(node)
+    }
+
+    /// Like [`find_path`](Self::find_path), also requiring the entry to be enabled
+    pub fn clickable<S: AsRef<str>>(&self, path: &[S]) -> Result<&MenuNode, MenuPathError> {
+        let node = self.find_path(path)?;
+        if !node.enabled {
+            return Err(MenuPathError::Disabled(join_path(path)));
+        }
+        Ok(node)
+    }
+}
+
+fn join_path<S: AsRef<str>>(path: &[S]) -> String {
+    path.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(" > ")
+}
+
+/// Drop the `_` that marks an access key, `__` stands for a literal underscore
+pub fn strip_mnemonic(label: &str) -> String {
+    let mut result = String::with_capacity(label.len());
+    let mut chars = label.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            if chars.peek() == Some(&'_') {
+                chars.next();
+                result.push('_');
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+fn value_str(value: &Value<'_>) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.to_string()),
+        Value::Value(inner) => value_str(inner),
+        _ => None,
+    }
+}
+
+fn value_bool(value: &Value<'_>) -> Option<bool> {
+    match value {
+        Value::Bool(flag) => Some(*flag),
+        Value::Value(inner) => value_bool(inner),
+        _ => None,
+    }
+}
+
+async fn menu_proxy<'a>(connection: &zbus::Connection, address: &'a str, menu_path: &'a str) -> zbus::Result<zbus::Proxy<'a>> {
+    zbus::Proxy::new(connection, address, menu_path, MENU_INTERFACE).await
+}
+
+/// Read the whole layout of a remote menu
+pub async fn fetch_menu(connection: &zbus::Connection, address: &str, menu_path: &str) -> zbus::Result<MenuNode> {
+    let proxy = menu_proxy(connection, address, menu_path).await?;
+    fetch_layout(&proxy).await
+}
+
+async fn fetch_layout(proxy: &zbus::Proxy<'_>) -> zbus::Result<MenuNode> {
+    type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+    // Parent 0 with depth -1 is the full tree, no names means every property
+    let (_revision, (id, props, children)): (u32, Layout) =
+        proxy.call("GetLayout", &(0i32, -1i32, Vec::<&str>::new())).await?;
+
+    let children = children.iter().filter_map(|child| MenuNode::from_value(child)).collect();
+    Ok(MenuNode::from_parts(id, &props, children))
+}
+
+/// Tell a remote menu entry it was clicked, as stray does for menu clicks
+pub(crate) async fn click(connection: &zbus::Connection, address: &str, menu_path: &str, id: i32) -> zbus::Result<()> {
+    let proxy = menu_proxy(connection, address, menu_path).await?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as u32)
+        .unwrap_or_default();
+    proxy.call_method("Event", &(id, "clicked", Value::I32(0), timestamp)).await?;
+    Ok(())
+}
+
+/// Keep the cached menu of an item current until the item goes away
+pub(crate) async fn watch_menu(connection: zbus::Connection, handle: HostHandle, address: String, menu_path: String) {
+    let proxy = match menu_proxy(&connection, &address, &menu_path).await {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            debug!("No menu proxy for {}: {}", address, e);
+            return;
+        }
+    };
+
+    // Subscribe before the first read so no update falls in between
+    let signals = proxy.receive_all_signals().await;
+
+    match fetch_layout(&proxy).await {
+        Ok(menu) => handle.apply_menu(&address, Some(menu)).await,
+        Err(e) => debug!("Could not read menu of {}: {}", address, e),
+    }
+
+    let Ok(mut signals) = signals else {
+        return;
+    };
+
+    while let Some(message) = signals.next().await {
+        let member = message.member();
+        if !member.is_some_and(|member| REFRESH_SIGNALS.contains(&member.as_str())) {
+            continue;
+        }
+
+        match fetch_layout(&proxy).await {
+            Ok(menu) => handle.apply_menu(&address, Some(menu)).await,
+            Err(e) => debug!("Could not refresh menu of {}: {}", address, e),
+        }
+    }
+}
@@ -2,52 +2,211 @@
 use anyhow::Result;
 use stray::{SystemTray, message::{NotifierItemMessage, NotifierItemCommand}};
 use tokio_stream::StreamExt;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use crate::AppConfig;
-use tracing::{info, debug, error};
+use crate::icon::IconThemeResolver;
+use crate::watcher::{StatusNotifierWatcher, UpstreamEvent, UpstreamItems};
+use tracing::{info, debug, warn};
 use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+
+mod command;
+mod event;
+mod filter;
+mod handle;
+mod item;
+mod menu;
+mod pixmap;
+mod process;
+mod record;
+mod rules;
+mod upstream;
+
+#[cfg(test)]
+mod tests;
+
+pub use command::ScrollOrientation;
+pub use event::{HostCommand, HostEvent, ItemField};
+pub use filter::{ItemFilter, ItemOrder};
+pub use handle::{HostHandle, RunningHost};
+pub use item::{IconPixmap, ItemCategory, ItemRegistry, NotifierInfo, ToolTip, DEFAULT_ITEM_PATH, WATCHER_PATH};
+pub use menu::{fetch_menu, MenuKind, MenuNode, MenuPathError, ToggleKind};
+pub use pixmap::{best_pixmap, pixmap_icon, AlphaMode};
+pub use process::{application_dirs, ProcessInfo};
+pub use record::{RecordEntry, Recorder, Replay};
+pub use rules::{expand, RuleEngine};
+
+/// Menu watcher task per item address, with the menu path it follows
+type MenuWatchers = HashMap<String, (String, tokio::task::JoinHandle<()>)>;
+
+/// Tasks that follow the host's events while it runs
+struct Followers {
+    rules: Option<tokio::task::JoinHandle<()>>,
+    recorder: Option<(
+        tokio::sync::oneshot::Sender<()>,
+        tokio::task::JoinHandle<Result<tokio::fs::File>>,
+    )>,
+}
+
+impl Followers {
+    /// Stop the rules and let the recorder write what is still queued
+    async fn stop(self) -> Result<()> {
+        if let Some(rules) = self.rules {
+            rules.abort();
+        }
+        if let Some((stop, recorder)) = self.recorder {
+            let _ = stop.send(());
+            recorder.await??;
+        }
+        Ok(())
+    }
+}
 
 pub struct StrayTrayApp {
     config: AppConfig,
-    ui_tx: mpsc::Sender<NotifierItemCommand>,
     ui_rx: mpsc::Receiver<NotifierItemCommand>,
-    notifier_items: HashMap<String, NotifierInfo>,
-}
-
-#[derive(Debug, Clone)]
-struct NotifierInfo {
-    address: String,
-    menu_path: String,
-    title: String,
-    icon_name: Option<String>,
+    handle: HostHandle,
+    record: Option<PathBuf>,
 }
 
 impl StrayTrayApp {
     pub fn new(config: AppConfig) -> Self {
         let (ui_tx, ui_rx) = mpsc::channel(32);
+        let handle = HostHandle::new(ui_tx, &config.items);
         Self {
             config,
-            ui_tx,
             ui_rx,
-            notifier_items: HashMap::new(),
+            handle,
+            record: None,
         }
     }
     
-    pub fn get_sender(&self) -> mpsc::Sender<NotifierItemCommand> {
-        self.ui_tx.clone()
+    /// Write every event and command to a JSON Lines file while running
+    pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record = Some(path.into());
+        self
+    }
+    
+    /// Cloneable handle to query and drive the host once it runs
+    pub fn handle(&self) -> HostHandle {
+        self.handle.clone()
+    }
+    
+    /// Run until Ctrl+C
+    pub async fn run(self) -> Result<()> {
+        self.run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("Received Ctrl+C, shutting down");
+        })
+        .await
+    }
+    
+    /// Run in a background task, keeping control in the caller
+    pub fn spawn(self) -> RunningHost {
+        let handle = self.handle.clone();
+        let stop = self.handle.clone();
+        let task = tokio::spawn(self.run_until(async move { stop.wait_for_shutdown().await }));
+        
+        RunningHost::new(handle, task)
+    }
+    
+    /// Follow the items a curating watcher holds back, instead of the ones
+    /// stray finds on the bus
+    ///
+    /// See [`StatusNotifierWatcher::start_curated`]. Items are read over the
+    /// session bus, which has to be the one the watcher serves on.
+    pub fn spawn_upstream(self, watcher: &StatusNotifierWatcher) -> RunningHost {
+        let handle = self.handle.clone();
+        let stop = self.handle.clone();
+        // Subscribed before listing, so no registration falls in between
+        let registrations = watcher.subscribe_upstream();
+        let task = tokio::spawn(self.run_upstream(watcher.upstream(), registrations, async move {
+            stop.wait_for_shutdown().await
+        }));
+        
+        RunningHost::new(handle, task)
+    }
+    
+    /// Play a recording instead of watching the bus
+    ///
+    /// Subscribers and a recording set with [`record_to`](Self::record_to)
+    /// see the recorded events as if they were live. Rules are not evaluated:
+    /// their commands, clicks and signals would reach today's bus, where the
+    /// recorded names may belong to other processes. The host stops once the
+    /// recording ends, or earlier through [`HostHandle::shutdown`].
+    pub fn spawn_replay(self, replay: Replay, speed: f64) -> RunningHost {
+        let handle = self.handle.clone();
+        let task = tokio::spawn(async move {
+            let followers = self.start_followers(false).await?;
+            
+            let played = tokio::select! {
+                played = replay.play(&self.handle, speed) => played,
+                _ = self.handle.wait_for_shutdown() => Ok(()),
+            };
+            
+            followers.stop().await?;
+            played
+        });
+        
+        RunningHost::new(handle, task)
     }
     
-    pub async fn run(mut self) -> Result<()> {
+    /// Start the recorder, and the rule engine if `rules` is set
+    async fn start_followers(&self, rules: bool) -> Result<Followers> {
+        // Subscribe before the first event so the recording misses nothing
+        let recorder = match &self.record {
+            Some(path) => {
+                let recorder = record::Recorder::create(path).await?;
+                info!("Recording tray activity to {}", path.display());
+                
+                let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+                let task = tokio::spawn(record::run(
+                    recorder,
+                    self.handle.subscribe(),
+                    self.handle.subscribe_commands(),
+                    stop_rx,
+                ));
+                Some((stop_tx, task))
+            }
+            None => None,
+        };
+        
+        let engine = rules::RuleEngine::new(&self.config);
+        let rules = (rules && !engine.is_empty()).then(|| {
+            info!("Evaluating {} tray rules", self.config.rules.len());
+            tokio::spawn(rules::run(engine, self.handle.clone(), self.handle.subscribe()))
+        });
+        
+        Ok(Followers { rules, recorder })
+    }
+    
+    async fn run_until(self, stop: impl Future<Output = ()>) -> Result<()> {
         info!("Starting stray-based Linux system tray application");
         info!("App name: {}", self.config.app_name);
         info!("Tooltip: {}", self.config.tooltip);
         
+        let followers = self.start_followers(true).await?;
+        
         // Create the system tray
         let mut tray = SystemTray::new(self.ui_rx).await;
         
+        // stray drops most item properties, so read them ourselves
+        let connection = match self.handle.connection().await {
+            Ok(connection) => Some(connection.clone()),
+            Err(e) => {
+                warn!("No session bus connection, item details will be partial: {}", e);
+                None
+            }
+        };
+        
         info!("System tray created successfully!");
         info!("Listening for tray icon changes...");
         
+        let mut menu_watchers = MenuWatchers::new();
+        
+        tokio::pin!(stop);
+        
         // Event loop - listen for tray updates
         loop {
             tokio::select! {
@@ -62,55 +221,218 @@ impl StrayTrayApp {
                             
                             // Store notifier information
                             if let Some(item) = item {
-                                let info = NotifierInfo {
-                                    address: address.clone(),
-                                    menu_path: item.menu.clone().unwrap_or_default(),
-                                    title: item.title.clone().unwrap_or_else(|| "Unknown".to_string()),
-                                    icon_name: item.icon_name.clone(),
+                                let mut info = match &connection {
+                                    Some(connection) => Self::fetch_info(connection, &address)
+                                        .await
+                                        .unwrap_or_else(|| NotifierInfo::from_stray(&address, &item)),
+                                    None => NotifierInfo::from_stray(&address, &item),
                                 };
                                 
-                                info!("  Title: {}", info.title);
-                                if let Some(icon) = &info.icon_name {
-                                    info!("  Icon: {}", icon);
+                                // Without a connection of our own, stray's copy of the menu is all we get
+                                if connection.is_none() {
+                                    info.menu = menu.as_ref().map(MenuNode::from_stray);
                                 }
                                 
-                                self.notifier_items.insert(address, info);
+                                Self::store(&self.handle, connection.as_ref(), &mut menu_watchers, info).await;
                             }
                         }
                         NotifierItemMessage::Remove { address } => {
                             info!("NotifierItem removed: {}", address);
-                            self.notifier_items.remove(&address);
+                            if let Some((_, watcher)) = menu_watchers.remove(&address) {
+                                watcher.abort();
+                            }
+                            self.handle.apply_remove(&address).await;
+                        }
+                    }
+                }
+                
+                _ = &mut stop => break,
+            }
+        }
+        
+        for (_, watcher) in menu_watchers.into_values() {
+            watcher.abort();
+        }
+        followers.stop().await
+    }
+    
+    async fn run_upstream(
+        mut self,
+        upstream: UpstreamItems,
+        mut registrations: broadcast::Receiver<UpstreamEvent>,
+        stop: impl Future<Output = ()>,
+    ) -> Result<()> {
+        info!("Following the items held back by our StatusNotifierWatcher");
+        
+        let followers = self.start_followers(true).await?;
+        let connection = self.handle.connection().await?.clone();
+        
+        let (refresh_tx, mut refreshes) = mpsc::unbounded_channel();
+        let mut item_followers = upstream::ItemFollowers::new();
+        let mut menu_watchers = MenuWatchers::new();
+        for service in upstream.list() {
+            upstream::follow(&mut item_followers, &connection, service, &refresh_tx);
+        }
+        
+        tokio::pin!(stop);
+        
+        loop {
+            tokio::select! {
+                event = registrations.recv() => match event {
+                    Ok(UpstreamEvent::Registered(service)) => {
+                        upstream::follow(&mut item_followers, &connection, service, &refresh_tx);
+                    }
+                    Ok(UpstreamEvent::Unregistered(service)) => {
+                        Self::forget_upstream(&self.handle, &mut item_followers, &mut menu_watchers, &service).await;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Missed {} item registrations, listing the items again", missed);
+                        let listed = upstream.list();
+                        let gone: Vec<String> = item_followers
+                            .keys()
+                            .filter(|service| !listed.contains(service))
+                            .cloned()
+                            .collect();
+                        for service in gone {
+                            Self::forget_upstream(&self.handle, &mut item_followers, &mut menu_watchers, &service).await;
+                        }
+                        for service in listed {
+                            upstream::follow(&mut item_followers, &connection, service, &refresh_tx);
                         }
                     }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                
+                // The item announced a change, or was just registered
+                Some(service) = refreshes.recv() => {
+                    let (address, object_path) = item::split_service(&service);
+                    match item::fetch_item(&connection, &address, &object_path).await {
+                        Ok(info) => Self::store(&self.handle, Some(&connection), &mut menu_watchers, info).await,
+                        Err(e) => debug!("Could not read properties of {}: {}", address, e),
+                    }
                 }
                 
-                // Handle Ctrl+C
-                _ = tokio::signal::ctrl_c() => {
-                    info!("Received Ctrl+C, shutting down");
-                    break;
+                // Without stray, menu clicks are ours to send
+                Some(command) = self.ui_rx.recv() => {
+                    let NotifierItemCommand::MenuItemClicked { submenu_id, menu_path, notifier_address } = command;
+                    let (notifier_address, _) = item::split_service(&notifier_address);
+                    if let Err(e) = menu::click(&connection, &notifier_address, &menu_path, submenu_id).await {
+                        warn!("Could not click menu item {} of {}: {}", submenu_id, notifier_address, e);
+                    }
                 }
+                
+                _ = &mut stop => break,
             }
         }
         
-        Ok(())
+        for follower in item_followers.into_values() {
+            follower.abort();
+        }
+        for (_, watcher) in menu_watchers.into_values() {
+            watcher.abort();
+        }
+        followers.stop().await
     }
     
-    /// Send a menu item click command
-    pub async fn click_menu_item(&self, submenu_id: i32, notifier_address: &str) -> Result<()> {
-        if let Some(info) = self.notifier_items.get(notifier_address) {
-            let command = NotifierItemCommand::MenuItemClicked {
-                submenu_id,
-                menu_path: info.menu_path.clone(),
-                notifier_address: notifier_address.to_string(),
+    /// Stop following an item that left the curating watcher
+    async fn forget_upstream(
+        handle: &HostHandle,
+        item_followers: &mut upstream::ItemFollowers,
+        menu_watchers: &mut MenuWatchers,
+        service: &str,
+    ) {
+        if let Some(follower) = item_followers.remove(service) {
+            follower.abort();
+        }
+        let (address, _) = item::split_service(service);
+        info!("NotifierItem removed: {}", address);
+        if let Some((_, watcher)) = menu_watchers.remove(&address) {
+            watcher.abort();
+        }
+        handle.apply_remove(&address).await;
+    }
+    
+    /// Keep what was read of an item, and follow its menu while the filter keeps it
+    async fn store(
+        handle: &HostHandle,
+        connection: Option<&zbus::Connection>,
+        menu_watchers: &mut MenuWatchers,
+        mut info: NotifierInfo,
+    ) {
+        let address = info.address.clone();
+        
+        // Filters may match on the process, so look it up once per item
+        if let Some(connection) = connection {
+            info.process = match handle.known_process(&address).await {
+                Some(process) => Some(process),
+                None => process::fetch_process(connection, &address)
+                    .await
+                    .map_err(|e| debug!("No process for {}: {}", address, e))
+                    .ok(),
             };
-            
-            self.ui_tx.send(command).await?;
-            info!("Sent menu click command for item {} on {}", submenu_id, notifier_address);
-        } else {
-            error!("Notifier address {} not found", notifier_address);
         }
         
-        Ok(())
+        info!("  Title: {}", info.title);
+        if let Some(icon) = &info.icon_name {
+            info!("  Icon: {}", icon);
+        }
+        
+        let menu_path = info.menu_path.clone();
+        let kept = handle.apply_update(info).await;
+        
+        match connection {
+            Some(connection) if kept => {
+                Self::watch_menu(handle, menu_watchers, connection, &address, menu_path);
+            }
+            _ => {
+                if let Some((_, watcher)) = menu_watchers.remove(&address) {
+                    watcher.abort();
+                }
+            }
+        }
+    }
+    
+    /// Start following the menu of an item, restarting when its path changes
+    fn watch_menu(
+        handle: &HostHandle,
+        watchers: &mut MenuWatchers,
+        connection: &zbus::Connection,
+        address: &str,
+        menu_path: String,
+    ) {
+        if let Some((path, _)) = watchers.get(address) {
+            if *path == menu_path {
+                return;
+            }
+        }
+        
+        if let Some((_, watcher)) = watchers.remove(address) {
+            watcher.abort();
+        }
+        
+        if menu_path.is_empty() {
+            return;
+        }
+        
+        let watcher = tokio::spawn(menu::watch_menu(
+            connection.clone(),
+            handle.clone(),
+            address.to_string(),
+            menu_path.clone(),
+        ));
+        watchers.insert(address.to_string(), (menu_path, watcher));
+    }
+    
+    async fn fetch_info(connection: &zbus::Connection, address: &str) -> Option<NotifierInfo> {
+        let object_path = item::item_path(connection, address).await.ok()?;
+        
+        match item::fetch_item(connection, address, &object_path).await {
+            Ok(info) => Some(info),
+            Err(e) => {
+                debug!("Could not read properties of {}: {}", address, e);
+                None
+            }
+        }
     }
 }
 
@@ -143,10 +465,20 @@ pub mod aloe_compat {
         }
     }
     
-    /// Helper to create icon (note: stray uses system icons, not custom images)
-    pub fn create_system_icon_name() -> String {
-        // Return a standard system icon name
-        // You can customize this based on your app
-        "application-x-executable".to_string()
+    /// Icon name to publish (note: stray uses system icons, not custom images)
+    ///
+    /// Uses `config.icon_name` when the current theme can resolve it and
+    /// falls back to a standard system icon otherwise.
+    pub fn create_system_icon_name(config: &AppConfig) -> String {
+        let resolver = IconThemeResolver::system();
+        
+        match &config.icon_name {
+            Some(name) if resolver.lookup(name, 22, 1).is_some() => name.clone(),
+            Some(name) => {
+                debug!("Icon {} not found in theme {}", name, resolver.theme_name());
+                "application-x-executable".to_string()
+            }
+            None => "application-x-executable".to_string(),
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,285 @@
+//! The process behind a remote item's bus connection.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::info;
+use zbus::names::BusName;
+use super::event::HostCommand;
+use super::handle::HostHandle;
+
+/// How long a terminated application gets to exit before a restart gives up
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Short name from `/proc/<pid>/comm`
+    pub name: String,
+    /// Target of `/proc/<pid>/exe`, unreadable for other users' processes
+    #[serde(default)]
+    pub exe: Option<PathBuf>,
+    /// Arguments from `/proc/<pid>/cmdline`, the program first
+    #[serde(default)]
+    pub cmdline: Vec<String>,
+    /// Desktop entry whose `Exec` or `TryExec` runs this program
+    #[serde(default)]
+    pub desktop_file: Option<PathBuf>,
+    /// Clock ticks after boot the process started at, from `/proc/<pid>/stat`;
+    /// tells it apart from a later process given the same pid
+    #[serde(default)]
+    pub start_time: Option<u64>,
+}
+
+impl ProcessInfo {
+    /// Read what `/proc` knows about `pid` and look up its desktop entry
+    pub fn from_pid(pid: u32) -> Result<Self> {
+        let mut process = Self::from_proc(Path::new("/proc"), pid)?;
+        process.desktop_file = process.find_desktop_file(&application_dirs());
+        Ok(process)
+    }
+
+    /// Like [`from_pid`](Self::from_pid) with another procfs root and
+    /// without the desktop entry, for tests
+    pub fn from_proc(proc_root: &Path, pid: u32) -> Result<Self> {
+        let dir = proc_root.join(pid.to_string());
+        let name = std::fs::read_to_string(dir.join("comm"))?.trim_end().to_string();
+        let exe = std::fs::read_link(dir.join("exe")).ok();
+        let cmdline = std::fs::read(dir.join("cmdline"))
+            .map(|raw| {
+                raw.split(|byte| *byte == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            pid,
+            name,
+            exe,
+            cmdline,
+            desktop_file: None,
+            start_time: read_start_time(&dir),
+        })
+    }
+
+    /// Names the program may be known by in an `Exec` line
+    fn program_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .exe
+            .as_deref()
+            .and_then(file_name)
+            .into_iter()
+            .chain(self.cmdline.first().and_then(|program| file_name(Path::new(program))))
+            .collect();
+        if !self.name.is_empty() {
+            names.push(&self.name);
+        }
+        names
+    }
+
+    /// First desktop entry in `dirs` that launches this program
+    ///
+    /// Earlier directories win, as with `XDG_DATA_DIRS`, so a user's own
+    /// entry shadows the system one.
+    pub fn find_desktop_file(&self, dirs: &[PathBuf]) -> Option<PathBuf> {
+        let names = self.program_names();
+        if names.is_empty() {
+            return None;
+        }
+
+        dirs.iter().find_map(|dir| {
+            desktop_files(dir).into_iter().find(|path| {
+                std::fs::read_to_string(path)
+                    .map(|contents| desktop_programs(&contents).iter().any(|program| names.contains(&program.as_str())))
+                    .unwrap_or(false)
+            })
+        })
+    }
+}
+
+fn file_name(path: &Path) -> Option<&str> {
+    path.file_name().and_then(|name| name.to_str())
+}
+
+/// `applications` directories under the XDG data dirs, most important first
+pub fn application_dirs() -> Vec<PathBuf> {
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    dirs::data_dir()
+        .into_iter()
+        .chain(std::env::split_paths(&data_dirs))
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// `.desktop` files under `dir`, sorted so lookups are repeatable
+fn desktop_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return files;
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            files.extend(desktop_files(&path));
+        } else if path.extension().is_some_and(|ext| ext == "desktop") {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    files
+}
+
+/// File names of the programs the `[Desktop Entry]` group launches
+fn desktop_programs(contents: &str) -> Vec<String> {
+    let mut programs = Vec::new();
+    let mut in_entry = false;
+
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_entry {
+            continue;
+        }
+
+        let value = match line.split_once('=') {
+            Some((key, value)) if matches!(key.trim(), "Exec" | "TryExec") => value.trim(),
+            _ => continue,
+        };
+
+        // Skip `env VAR=value` prefixes to get at the real program
+        let program = value
+            .split_whitespace()
+            .map(|word| word.trim_matches('"'))
+            .find(|word| *word != "env" && !word.contains('='));
+        if let Some(name) = program.and_then(|program| file_name(Path::new(program))) {
+            programs.push(name.to_string());
+        }
+    }
+
+    programs
+}
+
+/// Ask the bus which process owns `address`
+pub async fn connection_pid(connection: &zbus::Connection, address: &str) -> Result<u32> {
+    let bus = zbus::fdo::DBusProxy::new(connection).await?;
+    Ok(bus.get_connection_unix_process_id(BusName::try_from(address)?).await?)
+}
+
+pub async fn fetch_process(connection: &zbus::Connection, address: &str) -> Result<ProcessInfo> {
+    ProcessInfo::from_pid(connection_pid(connection, address).await?)
+}
+
+/// Field 22 of `/proc/<pid>/stat`, counted after the name since that may
+/// contain spaces and parentheses itself
+fn read_start_time(dir: &Path) -> Option<u64> {
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Whether the pid still belongs to `process`, not to a process started
+/// after it exited
+pub(super) fn is_running(proc_root: &Path, process: &ProcessInfo) -> bool {
+    let dir = proc_root.join(process.pid.to_string());
+    match process.start_time {
+        Some(start_time) => read_start_time(&dir) == Some(start_time),
+        None => dir.exists(),
+    }
+}
+
+fn send_signal(process: &ProcessInfo, signal: Signal) -> Result<()> {
+    if !is_running(Path::new("/proc"), process) {
+        bail!("{} ({}) is no longer running", process.name, process.pid);
+    }
+
+    signal::kill(Pid::from_raw(process.pid as i32), signal)
+        .with_context(|| format!("Could not send {} to {} ({})", signal, process.name, process.pid))
+}
+
+impl HostHandle {
+    /// Process owning a remote item, asked of the bus again so it is current
+    pub async fn item_process(&self, notifier_address: &str) -> Result<ProcessInfo> {
+        let info = self
+            .item(notifier_address)
+            .await
+            .ok_or_else(|| anyhow!("Notifier address {} not found", notifier_address))?;
+
+        let connection = self.connection().await?;
+        fetch_process(connection, &info.address)
+            .await
+            .with_context(|| format!("No process found for {}", info.display_name()))
+    }
+
+    /// Send SIGTERM (SIGKILL with `force`) to the application behind an item
+    pub async fn terminate(&self, notifier_address: &str, force: bool) -> Result<ProcessInfo> {
+        let process = self.item_process(notifier_address).await?;
+        if process.pid == std::process::id() {
+            bail!("{} belongs to this host, not terminating it", notifier_address);
+        }
+
+        send_signal(&process, if force { Signal::SIGKILL } else { Signal::SIGTERM })?;
+        self.publish_command(HostCommand::Terminate {
+            address: notifier_address.to_string(),
+            force,
+        });
+        info!("Terminated {} ({})", process.name, process.pid);
+        Ok(process)
+    }
+
+    /// Terminate the application behind an item and start it again
+    ///
+    /// The new instance gets the same command line and working directory and
+    /// runs in its own process group, so it outlives the host. Returns its
+    /// pid; the item itself shows up again once the application registers.
+    pub async fn restart(&self, notifier_address: &str) -> Result<u32> {
+        let process = self.item_process(notifier_address).await?;
+        let (program, args) = process
+            .cmdline
+            .split_first()
+            .ok_or_else(|| anyhow!("Command line of {} ({}) is unknown", process.name, process.pid))?;
+        let cwd = std::fs::read_link(format!("/proc/{}/cwd", process.pid)).ok();
+
+        self.terminate(notifier_address, false).await?;
+
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        while is_running(Path::new("/proc"), &process) {
+            if Instant::now() >= deadline {
+                bail!("{} ({}) did not exit after SIGTERM", process.name, process.pid);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let mut command = tokio::process::Command::new(program);
+        command
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .process_group(0);
+        if let Some(cwd) = cwd {
+            command.current_dir(cwd);
+        }
+
+        let child = command
+            .spawn()
+            .with_context(|| format!("Could not start {}", program))?;
+        let pid = child.id().unwrap_or_default();
+        self.publish_command(HostCommand::Restart { address: notifier_address.to_string() });
+
+        info!("Restarted {} as {}", process.name, pid);
+        Ok(pid)
+    }
+}
@@ -0,0 +1,200 @@
+//! Recording host activity to JSON Lines and playing it back.
+//!
+//! A recording starts with a `start` entry holding the wall clock time,
+//! followed by one `event` or `command` entry per line, each stamped with
+//! the milliseconds since the start:
+//!
+//! ```text
+//! {"kind":"start","unix_ms":1760774400000}
+//! {"kind":"event","at_ms":12,"event":{"Added":{"address":":1.52",...}}}
+//! {"kind":"command","at_ms":3051,"command":{"type":"activate","address":":1.52","x":0,"y":0}}
+//! ```
+
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+use super::event::{HostCommand, HostEvent};
+use super::handle::HostHandle;
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordEntry {
+    /// When the recording began, in milliseconds since the Unix epoch
+    Start { unix_ms: u64 },
+    Event { at_ms: u64, event: Box<HostEvent> },
+    Command { at_ms: u64, command: HostCommand },
+}
+
+impl RecordEntry {
+    /// Offset from the start of the recording
+    pub fn at(&self) -> Duration {
+        match self {
+            RecordEntry::Start { .. } => Duration::ZERO,
+            RecordEntry::Event { at_ms, .. } | RecordEntry::Command { at_ms, .. } => Duration::from_millis(*at_ms),
+        }
+    }
+}
+
+/// Writes host events and commands as they happen
+pub struct Recorder<W> {
+    writer: BufWriter<W>,
+    start: Instant,
+}
+
+impl Recorder<tokio::fs::File> {
+    /// Record into `path`, replacing what was there
+    pub async fn create(path: &Path) -> Result<Self> {
+        let file = tokio::fs::File::create(path)
+            .await
+            .with_context(|| format!("Could not create recording {}", path.display()))?;
+        Self::new(file).await
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Recorder<W> {
+    pub async fn new(writer: W) -> Result<Self> {
+        let mut recorder = Self {
+            writer: BufWriter::new(writer),
+            start: Instant::now(),
+        };
+
+        let unix_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        recorder.write(&RecordEntry::Start { unix_ms }).await?;
+        Ok(recorder)
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    pub async fn event(&mut self, event: HostEvent) -> Result<()> {
+        let at_ms = self.elapsed_ms();
+        self.write(&RecordEntry::Event { at_ms, event: Box::new(event) }).await
+    }
+
+    pub async fn command(&mut self, command: HostCommand) -> Result<()> {
+        let at_ms = self.elapsed_ms();
+        self.write(&RecordEntry::Command { at_ms, command }).await
+    }
+
+    /// Append a line and flush it, so a crash loses nothing written so far
+    async fn write(&mut self, entry: &RecordEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}
+
+/// Record until `stop` fires, then write what is still queued and return
+pub(crate) async fn run<W: AsyncWrite + Unpin>(
+    mut recorder: Recorder<W>,
+    mut events: broadcast::Receiver<HostEvent>,
+    mut commands: broadcast::Receiver<HostCommand>,
+    mut stop: oneshot::Receiver<()>,
+) -> Result<W> {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => recorder.event(event).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => warn!("Recording missed {} tray events", missed),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            command = commands.recv() => match command {
+                Ok(command) => recorder.command(command).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => warn!("Recording missed {} commands", missed),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = &mut stop => {
+                while let Ok(event) = events.try_recv() {
+                    recorder.event(event).await?;
+                }
+                while let Ok(command) = commands.try_recv() {
+                    recorder.command(command).await?;
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(recorder.into_inner())
+}
+
+/// A recording loaded for playback
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Replay {
+    entries: Vec<RecordEntry>,
+}
+
+impl Replay {
+    pub async fn load(path: &Path) -> Result<Self> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Could not read recording {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid recording {}", path.display()))
+    }
+
+    /// Parse JSON Lines, skipping blank lines
+    pub fn parse(text: &str) -> Result<Self> {
+        let entries = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| serde_json::from_str(line).with_context(|| format!("Line {}", index + 1)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { entries })
+    }
+
+    pub fn entries(&self) -> &[RecordEntry] {
+        &self.entries
+    }
+
+    /// Length of the recording up to its last entry
+    pub fn duration(&self) -> Duration {
+        self.entries.iter().map(RecordEntry::at).max().unwrap_or_default()
+    }
+
+    /// Feed the recording into `handle` as if the items were live
+    ///
+    /// Events update the registry and reach subscribers unchanged; commands
+    /// are published to command subscribers but not sent anywhere. `speed`
+    /// scales the original timing, `2.0` plays twice as fast and
+    /// `f64::INFINITY` does not wait at all.
+    pub async fn play(&self, handle: &HostHandle, speed: f64) -> Result<()> {
+        if speed.is_nan() || speed <= 0.0 {
+            bail!("Replay speed must be positive, got {}", speed);
+        }
+
+        let start = Instant::now();
+        for entry in &self.entries {
+            let due = Duration::try_from_secs_f64(entry.at().as_secs_f64() / speed)
+                .ok()
+                .and_then(|due| start.checked_add(due))
+                .with_context(|| format!("Replay speed {} is too slow", speed))?;
+            tokio::time::sleep_until(due).await;
+
+            match entry {
+                RecordEntry::Start { unix_ms } => debug!("Replaying recording started at {} ms", unix_ms),
+                RecordEntry::Event { event, .. } => handle.apply_event(event.as_ref().clone()).await,
+                RecordEntry::Command { command, .. } => handle.publish_command(command.clone()),
+            }
+
+            // Let subscribers see each entry before the next one lands
+            tokio::task::yield_now().await;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Evaluation of `AppConfig::rules` against the items the host sees.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Instant;
+use tracing::{info, warn};
+use zbus::zvariant::Value;
+use crate::config::{AppConfig, ItemMatcher, Rule, RuleAction};
+use super::event::HostEvent;
+use super::handle::HostHandle;
+use super::item::NotifierInfo;
+
+/// Longest wait for the menu of a freshly added item before a click gives up
+const MENU_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl ItemMatcher {
+    pub fn matches(&self, info: &NotifierInfo) -> bool {
+        let contains = |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+
+        self.id.as_ref().is_none_or(|id| *id == info.id)
+            && self.title.as_ref().is_none_or(|title| *title == info.title)
+            && self.title_contains.as_ref().is_none_or(|part| contains(&info.title, part))
+            && self.tooltip_contains.as_ref().is_none_or(|part| {
+                info.tooltip
+                    .as_ref()
+                    .is_some_and(|tooltip| contains(&tooltip.title, part) || contains(&tooltip.description, part))
+            })
+            && self.status.is_none_or(|status| status == info.status)
+            && self.address.as_ref().is_none_or(|address| *address == info.address)
+            && self.process.as_ref().is_none_or(|name| {
+                info.process.as_ref().is_some_and(|process| process.name == *name)
+            })
+    }
+}
+
+/// Tracks which rules match which items, to fire only on the transition
+#[derive(Debug, Clone)]
+pub struct RuleEngine {
+    app_name: String,
+    rules: Vec<Rule>,
+    /// (rule index, item address) pairs currently matching
+    matched: HashSet<(usize, String)>,
+}
+
+impl RuleEngine {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            app_name: config.app_name.clone(),
+            rules: config.rules.clone(),
+            matched: HashSet::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rules that start matching because of `event`
+    pub fn evaluate(&mut self, event: &HostEvent) -> Vec<&Rule> {
+        let info = match event {
+            HostEvent::Removed(info) => {
+                self.matched.retain(|(_, address)| *address != info.address);
+                return vec![];
+            }
+            HostEvent::Added(info) | HostEvent::Changed { info, .. } => info,
+        };
+
+        let mut fired = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let key = (index, info.address.clone());
+            if !rule.matcher.matches(info) {
+                self.matched.remove(&key);
+            } else if self.matched.insert(key) {
+                fired.push(rule);
+            }
+        }
+
+        fired
+    }
+}
+
+/// Replace `{id}`, `{title}`, `{address}`, `{status}` and `{tooltip}`
+pub fn expand(template: &str, info: &NotifierInfo) -> String {
+    let tooltip = info
+        .tooltip
+        .as_ref()
+        .map(|tooltip| {
+            [tooltip.title.as_str(), tooltip.description.as_str()]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(": ")
+        })
+        .unwrap_or_default();
+
+    template
+        .replace("{id}", &info.id)
+        .replace("{title}", &info.title)
+        .replace("{address}", &info.address)
+        .replace("{status}", info.status.as_dbus())
+        .replace("{tooltip}", &tooltip)
+}
+
+/// Feed host events through the engine until the host stops
+pub(crate) async fn run(mut engine: RuleEngine, handle: HostHandle, mut events: broadcast::Receiver<HostEvent>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Rule engine missed {} tray events", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let app_name = engine.app_name.clone();
+        for rule in engine.evaluate(&event) {
+            info!("Rule \"{}\" matched {}", rule.name, event.info().display_name());
+
+            // Clicks may wait for a menu, so each firing runs on its own
+            tokio::spawn(run_actions(
+                handle.clone(),
+                app_name.clone(),
+                rule.clone(),
+                event.info().clone(),
+            ));
+        }
+    }
+}
+
+async fn run_actions(handle: HostHandle, app_name: String, rule: Rule, info: NotifierInfo) {
+    for action in &rule.actions {
+        if let Err(e) = execute(&handle, &app_name, action, &info).await {
+            warn!("Rule \"{}\" failed on {}: {:#}", rule.name, info.display_name(), e);
+        }
+    }
+}
+
+async fn execute(handle: &HostHandle, app_name: &str, action: &RuleAction, info: &NotifierInfo) -> Result<()> {
+    match action {
+        // Item values go through the environment, never into the command
+        // line, so a hostile title cannot inject shell code
+        RuleAction::Run { command } => {
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("TRAY_ITEM_ADDRESS", &info.address)
+                .env("TRAY_ITEM_ID", &info.id)
+                .env("TRAY_ITEM_TITLE", &info.title)
+                .env("TRAY_ITEM_STATUS", info.status.as_dbus())
+                .status()
+                .await?;
+
+            if !status.success() {
+                return Err(anyhow!("`{}` exited with {}", command, status));
+            }
+        }
+        RuleAction::Click { path } => {
+            wait_for_menu(handle, &info.address).await;
+            handle.click_by_path(&info.address, path).await?;
+        }
+        RuleAction::Notify { summary, body } => {
+            notify(handle, app_name, &expand(summary, info), &expand(body, info)).await?;
+        }
+        RuleAction::Terminate { force } => {
+            handle.terminate(&info.address, *force).await?;
+        }
+        RuleAction::Restart => {
+            handle.restart(&info.address).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn wait_for_menu(handle: &HostHandle, address: &str) {
+    let deadline = Instant::now() + MENU_TIMEOUT;
+    let mut events = handle.subscribe();
+
+    loop {
+        match handle.item(address).await {
+            Some(info) if info.menu.is_none() && !info.menu_path.is_empty() => {}
+            _ => return,
+        }
+
+        match tokio::time::timeout_at(deadline, events.recv()).await {
+            Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+            Ok(Err(RecvError::Closed)) | Err(_) => return,
+        }
+    }
+}
+
+/// Show a desktop notification through org.freedesktop.Notifications
+async fn notify(handle: &HostHandle, app_name: &str, summary: &str, body: &str) -> Result<()> {
+    let connection = handle.connection().await?;
+    let proxy = zbus::Proxy::new(
+        connection,
+        "org.freedesktop.Notifications",
+        "/org/freedesktop/Notifications",
+        "org.freedesktop.Notifications",
+    )
+    .await?;
+
+    let hints: HashMap<&str, Value<'_>> = HashMap::new();
+    let _id: u32 = proxy
+        .call("Notify", &(app_name, 0u32, "", summary, body, Vec::<&str>::new(), hints, -1i32))
+        .await?;
+
+    Ok(())
+}
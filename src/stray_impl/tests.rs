@@ -0,0 +1,670 @@
+use super::*;
+use std::collections::HashMap;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use crate::config::ItemStatus;
+
+type Pixmaps = Vec<(i32, i32, Vec<u8>)>;
+
+fn item_properties() -> HashMap<String, OwnedValue> {
+    let pixmaps: Pixmaps = vec![(1, 1, vec![255, 1, 2, 3])];
+    let tooltip = ("tooltip-icon".to_string(), pixmaps.clone(), "Title".to_string(), "Disconnected".to_string());
+
+    let mut props: HashMap<String, OwnedValue> = HashMap::new();
+    props.insert("Id".into(), Value::from("nm-applet").into());
+    props.insert("Title".into(), Value::from("Network").into());
+    props.insert("Status".into(), Value::from("NeedsAttention").into());
+    props.insert("Category".into(), Value::from("Hardware").into());
+    props.insert("WindowId".into(), Value::from(7i32).into());
+    props.insert("ItemIsMenu".into(), Value::from(true).into());
+    props.insert("Menu".into(), Value::from(ObjectPath::try_from("/MenuBar").unwrap()).into());
+    props.insert("IconName".into(), Value::from("").into());
+    props.insert("IconPixmap".into(), Value::from(pixmaps).into());
+    props.insert("ToolTip".into(), Value::from(tooltip).into());
+    props
+}
+
+#[test]
+fn test_item_from_properties() {
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+
+    assert_eq!(info.address, ":1.52");
+    assert_eq!(info.id, "nm-applet");
+    assert_eq!(info.display_name(), "Network");
+    assert_eq!(info.status, ItemStatus::NeedsAttention);
+    assert_eq!(info.category, ItemCategory::Hardware);
+    assert_eq!(info.window_id, 7);
+    assert_eq!(info.menu_path, "/MenuBar");
+    assert!(info.item_is_menu);
+    assert!(info.icon_name.is_none());
+    assert_eq!(info.icon_pixmaps, vec![IconPixmap { width: 1, height: 1, pixels: vec![255, 1, 2, 3] }]);
+
+    let tooltip = info.tooltip.unwrap();
+    assert_eq!(tooltip.icon_name, "tooltip-icon");
+    assert_eq!(tooltip.description, "Disconnected");
+    assert_eq!(tooltip.icon_pixmaps.len(), 1);
+}
+
+#[test]
+fn test_missing_properties_use_defaults() {
+    let info = NotifierInfo::from_properties(":1.9", "/StatusNotifierItem", &HashMap::new());
+
+    assert_eq!(info.status, ItemStatus::Active);
+    assert_eq!(info.category, ItemCategory::ApplicationStatus);
+    assert_eq!(info.display_name(), ":1.9");
+    assert!(info.tooltip.is_none());
+}
+
+#[test]
+fn test_split_service() {
+    assert_eq!(
+        item::split_service(":1.52/org/ayatana/NotificationItem/nm"),
+        (":1.52".to_string(), "/org/ayatana/NotificationItem/nm".to_string())
+    );
+    assert_eq!(item::split_service(":1.9"), (":1.9".to_string(), "/StatusNotifierItem".to_string()));
+
+    // As joined by stray's watcher for items that register their bus name
+    assert_eq!(item::split_service(":1.9:1.9"), (":1.9".to_string(), "/StatusNotifierItem".to_string()));
+}
+
+#[tokio::test]
+async fn test_registry() {
+    let registry = ItemRegistry::new();
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+
+    assert!(registry.insert(info.clone()).await.is_none());
+    assert_eq!(registry.find_by_id("nm-applet").await, Some(info.clone()));
+    assert_eq!(registry.find(":1.52").await, Some(info.clone()));
+    assert_eq!(registry.find(":1.52/StatusNotifierItem").await, Some(info.clone()));
+    assert_eq!(registry.find("network").await, Some(info.clone()));
+    assert!(registry.find("Bluetooth").await.is_none());
+    assert_eq!(registry.list().await.len(), 1);
+    assert_eq!(registry.remove(":1.52").await, Some(info));
+    assert!(registry.get(":1.52").await.is_none());
+}
+
+#[tokio::test]
+async fn test_host_events() {
+    let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &Default::default());
+    let mut events = handle.subscribe();
+
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    handle.apply_update(info.clone()).await;
+    assert_eq!(events.recv().await.unwrap(), HostEvent::Added(info.clone()));
+
+    // Identical updates are not reported
+    handle.apply_update(info.clone()).await;
+
+    let mut changed = info.clone();
+    changed.status = ItemStatus::Active;
+    changed.title = "Network (wired)".to_string();
+    handle.apply_update(changed.clone()).await;
+    assert_eq!(
+        events.recv().await.unwrap(),
+        HostEvent::Changed { info: changed.clone(), fields: vec![ItemField::Status, ItemField::Title] }
+    );
+
+    handle.apply_remove(":1.52").await;
+    handle.apply_remove(":1.52").await;
+    assert_eq!(events.recv().await.unwrap(), HostEvent::Removed(changed));
+    assert!(events.try_recv().is_err());
+    assert!(handle.items().await.is_empty());
+}
+
+fn menu_entry(id: i32, props: Vec<(&str, Value<'static>)>, children: Vec<Value<'static>>) -> Value<'static> {
+    let props: HashMap<String, Value<'static>> = props.into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+    let children: Vec<Value<'static>> = children.into_iter().map(|child| Value::Value(Box::new(child))).collect();
+
+    Value::from(zbus::zvariant::StructureBuilder::new()
+        .add_field(id)
+        .append_field(Value::Dict(props.into()))
+        .append_field(Value::Array(children.into()))
+        .build())
+}
+
+fn sample_menu() -> MenuNode {
+    let layout = menu_entry(0, vec![("children-display", Value::from("submenu"))], vec![
+        menu_entry(1, vec![("label", Value::from("_Settings"))], vec![
+            menu_entry(2, vec![("label", Value::from("_Preferences"))], vec![]),
+            menu_entry(3, vec![("type", Value::from("separator"))], vec![]),
+            menu_entry(4, vec![
+                ("label", Value::from("Show _Notifications")),
+                ("toggle-type", Value::from("checkmark")),
+                ("toggle-state", Value::from(0i32)),
+            ], vec![]),
+            menu_entry(5, vec![("label", Value::from("Advanced")), ("enabled", Value::from(false))], vec![]),
+        ]),
+        menu_entry(6, vec![("label", Value::from("Hidden")), ("visible", Value::from(false))], vec![]),
+        menu_entry(7, vec![("label", Value::from("_Quit")), ("icon-name", Value::from("application-exit"))], vec![]),
+    ]);
+
+    MenuNode::from_value(&layout).unwrap()
+}
+
+#[test]
+fn test_menu_from_layout() {
+    let menu = sample_menu();
+
+    assert_eq!(menu.id, 0);
+    let labels: Vec<_> = menu.entries().map(|entry| entry.label.as_str()).collect();
+    assert_eq!(labels, vec!["Settings", "Quit"]);
+
+    let settings = &menu.children[0];
+    assert_eq!(settings.children.len(), 4);
+    assert!(settings.children[1].is_separator());
+
+    let notifications = menu.find_id(4).unwrap();
+    assert_eq!(notifications.label, "Show Notifications");
+    assert_eq!(notifications.toggle, ToggleKind::Checkmark);
+    assert_eq!(notifications.toggle_state, Some(false));
+
+    assert!(!menu.find_id(5).unwrap().enabled);
+    assert_eq!(menu.find_id(7).unwrap().icon_name.as_deref(), Some("application-exit"));
+}
+
+#[test]
+fn test_strip_mnemonic() {
+    assert_eq!(menu::strip_mnemonic("_File"), "File");
+    assert_eq!(menu::strip_mnemonic("Save __as"), "Save _as");
+    assert_eq!(menu::strip_mnemonic("Plain"), "Plain");
+}
+
+#[test]
+fn test_menu_find_path() {
+    let menu = sample_menu();
+
+    assert_eq!(menu.find_path(&["Settings", "Preferences"]).unwrap().id, 2);
+    assert_eq!(menu.clickable(&["Quit"]).unwrap().id, 7);
+
+    assert_eq!(menu.find_path::<&str>(&[]), Err(MenuPathError::EmptyPath));
+    assert_eq!(
+        menu.find_path(&["Settings", "Colours"]),
+        Err(MenuPathError::NotFound {
+            parent: "\"Settings\"".to_string(),
+            label: "Colours".to_string(),
+            available: vec!["Preferences".to_string(), "Show Notifications".to_string(), "Advanced".to_string()],
+        })
+    );
+    // Hidden entries cannot be reached
+    assert!(matches!(menu.find_path(&["Hidden"]), Err(MenuPathError::NotFound { .. })));
+    assert_eq!(
+        menu.find_path(&["Quit", "Now"]),
+        Err(MenuPathError::NotASubmenu("Quit".to_string()))
+    );
+    assert_eq!(
+        menu.clickable(&["Settings", "Advanced"]),
+        Err(MenuPathError::Disabled("Settings > Advanced".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn test_menu_updates() {
+    let (ui_tx, mut ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &Default::default());
+
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    handle.apply_update(info.clone()).await;
+    assert!(handle.click_by_path(":1.52", &["Quit"]).await.is_err());
+
+    let mut events = handle.subscribe();
+    handle.apply_menu(":1.52", Some(sample_menu())).await;
+    match events.recv().await.unwrap() {
+        HostEvent::Changed { fields, .. } => assert_eq!(fields, vec![ItemField::Menu]),
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    // Property updates keep the cached menu
+    handle.apply_update(info).await;
+    assert_eq!(handle.menu(":1.52").await, Some(sample_menu()));
+    assert!(events.try_recv().is_err());
+
+    handle.click_by_path(":1.52", &["Settings", "Preferences"]).await.unwrap();
+    match ui_rx.recv().await.unwrap() {
+        stray::message::NotifierItemCommand::MenuItemClicked { submenu_id, menu_path, notifier_address } => {
+            assert_eq!(submenu_id, 2);
+            assert_eq!(menu_path, "/MenuBar");
+            assert_eq!(notifier_address, ":1.52");
+        }
+    }
+
+    let error = handle.click_by_path(":1.52", &["Settings", "Colours"]).await.unwrap_err();
+    assert!(error.to_string().contains("available: Preferences"));
+    assert!(handle.click_by_path(":1.99", &["Quit"]).await.is_err());
+}
+
+#[test]
+fn test_scroll_orientation() {
+    assert_eq!("Horizontal".parse::<ScrollOrientation>().unwrap(), ScrollOrientation::Horizontal);
+    assert_eq!("vertical".parse::<ScrollOrientation>().unwrap().as_dbus(), "vertical");
+    assert!("diagonal".parse::<ScrollOrientation>().is_err());
+}
+
+#[tokio::test]
+async fn test_commands_need_known_item() {
+    let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &Default::default());
+
+    let error = handle.activate(":1.99", 0, 0).await.unwrap_err();
+    assert_eq!(error.to_string(), "Notifier address :1.99 not found");
+    assert!(handle.scroll(":1.99", 120, ScrollOrientation::Vertical).await.is_err());
+}
+
+#[test]
+fn test_pixmap_to_image() {
+    // One opaque red pixel and one half transparent premultiplied grey
+    let pixmap = IconPixmap { width: 2, height: 1, pixels: vec![255, 255, 0, 0, 128, 64, 64, 64] };
+
+    let straight = pixmap.to_image(AlphaMode::Straight).unwrap();
+    assert_eq!(straight.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(straight.get_pixel(1, 0).0, [64, 64, 64, 128]);
+
+    let premultiplied = pixmap.to_image(AlphaMode::Premultiplied).unwrap();
+    assert_eq!(premultiplied.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(premultiplied.get_pixel(1, 0).0, [128, 128, 128, 128]);
+
+    // Fully transparent pixels carry no colour
+    let clear = IconPixmap { width: 1, height: 1, pixels: vec![0, 10, 20, 30] };
+    assert_eq!(clear.to_image(AlphaMode::Premultiplied).unwrap().get_pixel(0, 0).0, [0, 0, 0, 0]);
+
+    assert!(IconPixmap { width: 2, height: 2, pixels: vec![0; 12] }.to_image(AlphaMode::Straight).is_err());
+    assert!(IconPixmap { width: 0, height: 0, pixels: vec![] }.to_image(AlphaMode::Straight).is_err());
+}
+
+#[test]
+fn test_pixmap_round_trip() {
+    let image = image::RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8 * 80, y as u8 * 100, 7, 200]));
+    let pixmap = IconPixmap::from_image(&image);
+
+    assert_eq!(&pixmap.pixels[..4], &[200, 0, 0, 7]);
+    assert_eq!(pixmap.to_image(AlphaMode::Straight).unwrap(), image);
+}
+
+#[test]
+fn test_best_pixmap() {
+    let pixmap = |size: i32| IconPixmap { width: size, height: size, pixels: vec![255; (size * size * 4) as usize] };
+    let pixmaps = vec![pixmap(16), pixmap(48), pixmap(22), IconPixmap::default()];
+
+    assert_eq!(best_pixmap(&pixmaps, 22).unwrap().width, 22);
+    assert_eq!(best_pixmap(&pixmaps, 24).unwrap().width, 48);
+    assert_eq!(best_pixmap(&pixmaps, 64).unwrap().width, 48);
+    assert!(best_pixmap(&[IconPixmap::default()], 16).is_none());
+
+    let icon = pixmap_icon(&pixmaps, 24, AlphaMode::Straight).unwrap();
+    assert_eq!(icon.dimensions(), (24, 24));
+    assert_eq!(icon.get_pixel(12, 12).0, [255, 255, 255, 255]);
+}
+
+#[tokio::test]
+async fn test_item_icon_from_pixmap() {
+    let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &Default::default());
+    let resolver = crate::icon::IconThemeResolver::with_base_dirs("hicolor", vec![]);
+
+    let mut info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    info.icon_name = Some("missing-icon".into());
+    handle.apply_update(info.clone()).await;
+
+    let icon = handle.item_icon(&resolver, ":1.52", 4, AlphaMode::Straight).await.unwrap();
+    assert_eq!(icon.dimensions(), (4, 4));
+    assert_eq!(icon.get_pixel(0, 0).0, [1, 2, 3, 255]);
+
+    info.icon_pixmaps.clear();
+    handle.apply_update(info).await;
+    assert!(handle.item_icon(&resolver, ":1.52", 4, AlphaMode::Straight).await.is_err());
+}
+
+fn rule(matcher: crate::config::ItemMatcher) -> crate::config::Rule {
+    crate::config::Rule { name: "test".into(), matcher, actions: vec![] }
+}
+
+#[test]
+fn test_item_matcher() {
+    use crate::config::ItemMatcher;
+
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+
+    assert!(ItemMatcher::default().matches(&info));
+    assert!(ItemMatcher { id: Some("nm-applet".into()), ..Default::default() }.matches(&info));
+    assert!(!ItemMatcher { id: Some("nm".into()), ..Default::default() }.matches(&info));
+    assert!(ItemMatcher { title_contains: Some("netw".into()), ..Default::default() }.matches(&info));
+    assert!(ItemMatcher { tooltip_contains: Some("DISCONNECTED".into()), ..Default::default() }.matches(&info));
+    assert!(!ItemMatcher {
+        tooltip_contains: Some("disconnected".into()),
+        status: Some(ItemStatus::Passive),
+        ..Default::default()
+    }
+    .matches(&info));
+}
+
+#[test]
+fn test_rules_fire_on_transition() {
+    use crate::config::{AppConfig, ItemMatcher};
+
+    let config = AppConfig {
+        rules: vec![rule(ItemMatcher { status: Some(ItemStatus::NeedsAttention), ..Default::default() })],
+        ..Default::default()
+    };
+    let mut engine = RuleEngine::new(&config);
+
+    let attention = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    let calm = NotifierInfo { status: ItemStatus::Active, ..attention.clone() };
+    let changed = |info: &NotifierInfo| HostEvent::Changed { info: info.clone(), fields: vec![ItemField::Status] };
+
+    assert_eq!(engine.evaluate(&HostEvent::Added(attention.clone())).len(), 1);
+    assert!(engine.evaluate(&changed(&attention)).is_empty());
+    assert!(engine.evaluate(&changed(&calm)).is_empty());
+    assert_eq!(engine.evaluate(&changed(&attention)).len(), 1);
+
+    // A returning item fires again
+    assert!(engine.evaluate(&HostEvent::Removed(attention.clone())).is_empty());
+    assert_eq!(engine.evaluate(&HostEvent::Added(attention)).len(), 1);
+}
+
+#[test]
+fn test_expand_placeholders() {
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+
+    assert_eq!(
+        expand("{title} ({id} on {address}) is {status}: {tooltip}", &info),
+        "Network (nm-applet on :1.52) is NeedsAttention: Title: Disconnected"
+    );
+}
+
+#[tokio::test]
+async fn test_run_action() {
+    use crate::config::{AppConfig, ItemMatcher, RuleAction};
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let out = dir.path().join("out.txt");
+
+    let mut matching = rule(ItemMatcher { id: Some("nm-applet".into()), ..Default::default() });
+    matching.actions.push(RuleAction::Run {
+        command: format!("printf '%s %s' \"$TRAY_ITEM_ID\" \"$TRAY_ITEM_STATUS\" > '{}'", out.display()),
+    });
+    let config = AppConfig { rules: vec![matching], ..Default::default() };
+
+    let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &Default::default());
+    let task = tokio::spawn(rules::run(RuleEngine::new(&config), handle.clone(), handle.subscribe()));
+
+    handle
+        .apply_update(NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties()))
+        .await;
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while std::fs::read_to_string(&out).map_or(true, |content| content.is_empty()) {
+        assert!(std::time::Instant::now() < deadline, "rule command did not run");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "nm-applet NeedsAttention");
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_replay_runs_no_rules() {
+    use crate::config::{ItemMatcher, RuleAction};
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let out = dir.path().join("out.txt");
+    let mut matching = rule(ItemMatcher::default());
+    matching.actions.push(RuleAction::Run { command: format!("touch '{}'", out.display()) });
+    let config = AppConfig { rules: vec![matching], ..Default::default() };
+
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    let recording = [
+        RecordEntry::Start { unix_ms: 1_760_774_400_000 },
+        RecordEntry::Event { at_ms: 0, event: Box::new(HostEvent::Added(info)) },
+    ]
+    .iter()
+    .map(|entry| serde_json::to_string(entry).unwrap())
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    let app = StrayTrayApp::new(config);
+    let handle = app.handle();
+    app.spawn_replay(Replay::parse(&recording).unwrap(), 100.0).join().await.unwrap();
+    assert_eq!(handle.items().await.len(), 1, "the event was replayed");
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(!out.exists(), "a replayed event ran a rule action");
+}
+
+#[tokio::test]
+async fn test_filter_and_order() {
+    use crate::config::{ItemMatcher, ItemsConfig};
+
+    let config = ItemsConfig {
+        block: vec![ItemMatcher { process: Some("steam".into()), ..Default::default() }],
+        order: vec!["bluetooth".into(), "Volume".into()],
+        ..Default::default()
+    };
+    let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &config);
+    let mut events = handle.subscribe();
+
+    let item = |address: &str, id: &str, title: &str| NotifierInfo {
+        address: address.into(),
+        id: id.into(),
+        title: title.into(),
+        ..Default::default()
+    };
+    let network = item(":1.10", "nm-applet", "Network");
+    let volume = item(":1.11", "", "Volume");
+    let bluetooth = item(":1.12", "bluetooth", "Bluetooth");
+    let mut steam = item(":1.13", "steam", "Steam");
+    steam.process = Some(ProcessInfo { pid: 4242, name: "steam".into(), ..Default::default() });
+
+    assert!(handle.apply_update(network.clone()).await);
+    assert!(handle.apply_update(volume.clone()).await);
+    assert!(handle.apply_update(bluetooth.clone()).await);
+    assert!(!handle.apply_update(steam).await);
+
+    // The blocked item's process is remembered until the item goes away
+    assert_eq!(handle.known_process(":1.13").await.map(|process| process.pid), Some(4242));
+    handle.apply_remove(":1.13").await;
+    assert!(handle.known_process(":1.13").await.is_none());
+
+    let order = |items: Vec<NotifierInfo>| items.into_iter().map(|info| info.address).collect::<Vec<_>>();
+    assert_eq!(order(handle.items().await), vec![":1.12", ":1.11", ":1.10"]);
+    assert_eq!(handle.item_order(), vec!["bluetooth", "Volume", "nm-applet"]);
+
+    // A restarted item gets a new bus name but keeps its slot
+    handle.apply_remove(":1.10").await;
+    handle.apply_update(item(":1.20", "late", "Late")).await;
+    handle.apply_update(NotifierInfo { address: ":1.30".into(), ..network }).await;
+    assert_eq!(order(handle.items().await), vec![":1.12", ":1.11", ":1.30", ":1.20"]);
+
+    // Titles only count for items without an id, so neither takes a listed slot
+    handle.apply_update(item(":1.40", "tray-volume", "bluetooth")).await;
+    handle.apply_update(item(":1.41", "pasystray", "Volume")).await;
+    assert_eq!(order(handle.items().await), vec![":1.12", ":1.11", ":1.30", ":1.20", ":1.40", ":1.41"]);
+
+    // Blocked items never reach subscribers
+    let mut seen = vec![];
+    while let Ok(event) = events.try_recv() {
+        seen.push(event.address().to_string());
+    }
+    assert!(!seen.contains(&":1.13".to_string()));
+}
+
+#[test]
+fn test_allow_list() {
+    use crate::config::{ItemMatcher, ItemsConfig};
+
+    let filter = ItemFilter::new(&ItemsConfig {
+        allow: vec![ItemMatcher { id: Some("nm-applet".into()), ..Default::default() }],
+        block: vec![ItemMatcher { title_contains: Some("vpn".into()), ..Default::default() }],
+        ..Default::default()
+    });
+
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    assert!(filter.allows(&info));
+    assert!(!filter.allows(&NotifierInfo { id: "other".into(), ..info.clone() }));
+    assert!(!filter.allows(&NotifierInfo { title: "Network VPN".into(), ..info }));
+}
+
+#[test]
+fn test_process_from_proc() {
+    let proc_root = tempfile::TempDir::new().unwrap();
+    let dir = proc_root.path().join("4242");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("comm"), "nm-applet\n").unwrap();
+    std::fs::write(dir.join("cmdline"), "/usr/bin/nm-applet\0--indicator\0").unwrap();
+    std::os::unix::fs::symlink("/usr/bin/nm-applet", dir.join("exe")).unwrap();
+    std::fs::write(dir.join("stat"), "4242 (nm (applet)) S 1 4242 4242 0 -1 4194304 0 0 0 0 1 2 0 0 20 0 3 0 98765 1000 200").unwrap();
+
+    let process = ProcessInfo::from_proc(proc_root.path(), 4242).unwrap();
+    assert_eq!(
+        process,
+        ProcessInfo {
+            pid: 4242,
+            name: "nm-applet".into(),
+            exe: Some("/usr/bin/nm-applet".into()),
+            cmdline: vec!["/usr/bin/nm-applet".into(), "--indicator".into()],
+            desktop_file: None,
+            start_time: Some(98765),
+        }
+    );
+    assert!(ProcessInfo::from_proc(proc_root.path(), 1).is_err());
+}
+
+#[test]
+fn test_process_identity() {
+    let proc_root = tempfile::TempDir::new().unwrap();
+    let dir = proc_root.path().join("4242");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("comm"), "steam\n").unwrap();
+    let stat = |start_time: u64| format!("4242 (steam) S 1 4242 4242 0 -1 0 0 0 0 0 1 2 0 0 20 0 3 0 {} 1000 200", start_time);
+    std::fs::write(dir.join("stat"), stat(500)).unwrap();
+
+    let process = ProcessInfo::from_proc(proc_root.path(), 4242).unwrap();
+    assert!(process::is_running(proc_root.path(), &process));
+
+    // Same pid handed to a later process
+    std::fs::write(dir.join("stat"), stat(900)).unwrap();
+    assert!(!process::is_running(proc_root.path(), &process));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!process::is_running(proc_root.path(), &process));
+}
+
+#[test]
+fn test_find_desktop_file() {
+    let user = tempfile::TempDir::new().unwrap();
+    let system = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(system.path().join("kde")).unwrap();
+    std::fs::write(
+        system.path().join("nm-applet.desktop"),
+        "[Desktop Entry]\nName=Network\nExec=nm-applet\n",
+    )
+    .unwrap();
+    std::fs::write(
+        system.path().join("kde/steam.desktop"),
+        "[Desktop Entry]\nExec=env STEAM_RUNTIME=1 /usr/bin/steam %U\n[Desktop Action Quit]\nExec=nm-applet\n",
+    )
+    .unwrap();
+    let dirs = vec![user.path().to_path_buf(), system.path().to_path_buf()];
+
+    let process = |name: &str, exe: Option<&str>| ProcessInfo {
+        pid: 1,
+        name: name.into(),
+        exe: exe.map(Into::into),
+        ..Default::default()
+    };
+    assert_eq!(
+        process("nm-applet", Some("/usr/bin/nm-applet")).find_desktop_file(&dirs),
+        Some(system.path().join("nm-applet.desktop"))
+    );
+    assert_eq!(
+        process("steamwebhelper", Some("/usr/lib/steam/steam")).find_desktop_file(&dirs),
+        Some(system.path().join("kde/steam.desktop"))
+    );
+    assert_eq!(process("blueman-tray", None).find_desktop_file(&dirs), None);
+
+    // The user's own entry shadows the system one
+    std::fs::write(user.path().join("my-network.desktop"), "[Desktop Entry]\nExec=/opt/nm-applet\n").unwrap();
+    assert_eq!(
+        process("nm-applet", None).find_desktop_file(&dirs),
+        Some(user.path().join("my-network.desktop"))
+    );
+}
+
+#[tokio::test]
+async fn test_process_actions_need_known_item() {
+    let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &Default::default());
+
+    assert!(handle.item_process(":1.99").await.is_err());
+    assert!(handle.terminate(":1.99", false).await.is_err());
+    assert!(handle.restart(":1.99").await.is_err());
+}
+
+#[tokio::test]
+async fn test_record_events_and_commands() {
+    let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(1);
+    let handle = HostHandle::new(ui_tx, &Default::default());
+    let recorder = Recorder::new(Vec::new()).await.unwrap();
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+    let task = tokio::spawn(record::run(recorder, handle.subscribe(), handle.subscribe_commands(), stop_rx));
+
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    handle.apply_update(info.clone()).await;
+    handle.publish_command(HostCommand::Activate { address: ":1.52".into(), x: 0, y: 0 });
+    handle.apply_remove(":1.52").await;
+    stop_tx.send(()).unwrap();
+
+    let written = String::from_utf8(task.await.unwrap().unwrap()).unwrap();
+    assert!(written.lines().all(|line| serde_json::from_str::<serde_json::Value>(line).is_ok()));
+
+    let replay = Replay::parse(&written).unwrap();
+    let entries = replay.entries();
+    assert!(matches!(entries[0], RecordEntry::Start { .. }));
+    assert!(entries.iter().any(|entry| matches!(
+        entry,
+        RecordEntry::Event { event, .. } if **event == HostEvent::Added(info.clone())
+    )));
+    assert!(entries.iter().any(|entry| matches!(
+        entry,
+        RecordEntry::Command { command: HostCommand::Activate { .. }, .. }
+    )));
+    assert_eq!(entries.len(), 4);
+}
+
+#[tokio::test]
+async fn test_replay_through_subscribers() {
+    let info = NotifierInfo::from_properties(":1.52", "/StatusNotifierItem", &item_properties());
+    let changed = NotifierInfo { title: "Offline".into(), ..info.clone() };
+    let recording = [
+        RecordEntry::Start { unix_ms: 1_760_774_400_000 },
+        RecordEntry::Event { at_ms: 0, event: Box::new(HostEvent::Added(info.clone())) },
+        RecordEntry::Event {
+            at_ms: 10,
+            event: Box::new(HostEvent::Changed { info: changed.clone(), fields: vec![ItemField::Title] }),
+        },
+        RecordEntry::Command { at_ms: 20, command: HostCommand::MenuClick { address: ":1.52".into(), submenu_id: 3 } },
+        RecordEntry::Event { at_ms: 30, event: Box::new(HostEvent::Removed(changed.clone())) },
+    ]
+    .iter()
+    .map(|entry| serde_json::to_string(entry).unwrap())
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    let replay = Replay::parse(&recording).unwrap();
+    assert_eq!(replay.duration(), std::time::Duration::from_millis(30));
+
+    let app = StrayTrayApp::new(AppConfig::default());
+    let handle = app.handle();
+    let mut events = handle.subscribe();
+    let mut commands = handle.subscribe_commands();
+    app.spawn_replay(replay.clone(), 4.0).join().await.unwrap();
+
+    assert_eq!(events.try_recv().unwrap(), HostEvent::Added(info));
+    assert!(matches!(events.try_recv().unwrap(), HostEvent::Changed { info, .. } if info == changed));
+    assert_eq!(events.try_recv().unwrap(), HostEvent::Removed(changed));
+    assert_eq!(commands.try_recv().unwrap().address(), ":1.52");
+    assert!(handle.items().await.is_empty());
+
+    assert!(replay.play(&handle, 0.0).await.is_err());
+    assert!(Replay::parse("{\"kind\":\"start\",\"unix_ms\":0}\nnot json").is_err());
+}
@@ -0,0 +1,55 @@
+//! Items read straight from a curating watcher, without stray.
+
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::debug;
+use super::item::{split_service, ITEM_INTERFACE};
+
+/// Signal follower task per registered `bus_name/object/path`
+pub(super) type ItemFollowers = HashMap<String, JoinHandle<()>>;
+
+/// Start following an item unless it is followed already
+pub(super) fn follow(
+    followers: &mut ItemFollowers,
+    connection: &zbus::Connection,
+    service: String,
+    refreshes: &mpsc::UnboundedSender<String>,
+) {
+    if followers.contains_key(&service) {
+        return;
+    }
+    let follower = tokio::spawn(follow_item(connection.clone(), service.clone(), refreshes.clone()));
+    followers.insert(service, follower);
+}
+
+/// Ask for `service` to be read now, and again whenever it sends a signal
+///
+/// Every signal of the interface announces a change, so there is no need
+/// to tell them apart.
+async fn follow_item(connection: zbus::Connection, service: String, refreshes: mpsc::UnboundedSender<String>) {
+    let (address, object_path) = split_service(&service);
+    let proxy = match zbus::Proxy::new(&connection, address.as_str(), object_path.as_str(), ITEM_INTERFACE).await {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            debug!("No item proxy for {}: {}", service, e);
+            return;
+        }
+    };
+
+    // Subscribe before the first read so no change falls in between
+    let signals = proxy.receive_all_signals().await;
+    if refreshes.send(service.clone()).is_err() {
+        return;
+    }
+
+    let Ok(mut signals) = signals else {
+        return;
+    };
+    while signals.next().await.is_some() {
+        if refreshes.send(service.clone()).is_err() {
+            return;
+        }
+    }
+}
@@ -1,43 +1,730 @@
-// Linux-specific implementation placeholder
-// This would use aloe-x11 and aloe-xembed when the build issues are resolved
-
-#[cfg(target_os = "linux")]
-pub mod linux {
-    use crate::error::Result;
-    use crate::config::AppConfig;
-    
-    #[allow(dead_code)]
-    pub struct LinuxTrayIcon {
-        config: AppConfig,
-        // x11_connection: aloe_x11::Connection,
-        // xembed_window: aloe_xembed::Window,
-    }
-    
-    #[allow(dead_code)]
-    impl LinuxTrayIcon {
-        pub fn new(config: AppConfig) -> Result<Self> {
-            // TODO: Initialize X11 connection
-            // TODO: Create XEmbed window
-            // TODO: Register with system tray
-            
-            Ok(Self {
-                config,
-            })
-        }
-        
-        pub fn set_icon(&mut self, _icon_data: &[u8]) -> Result<()> {
-            // TODO: Update icon using X11
-            Ok(())
-        }
-        
-        pub fn set_tooltip(&mut self, _tooltip: &str) -> Result<()> {
-            // TODO: Set tooltip using X11 properties
-            Ok(())
-        }
-        
-        pub fn show_menu(&mut self) -> Result<()> {
-            // TODO: Show context menu
-            Ok(())
-        }
-    }
-}
\ No newline at end of file
+//! XEmbed tray icon for panels that only speak the freedesktop System Tray
+//! protocol.
+//!
+//! The icon is a small window handed to the tray manager, the owner of the
+//! `_NET_SYSTEM_TRAY_S{screen}` selection, with a `SYSTEM_TRAY_REQUEST_DOCK`
+//! message. The manager reparents it into the panel and confirms with
+//! `XEMBED_EMBEDDED_NOTIFY`. When the manager announces a 32 bit
+//! `_NET_SYSTEM_TRAY_VISUAL` the icon is painted with real alpha, otherwise
+//! it is blended over the panel background. A manager that takes over the
+//! selection later is docked with again.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use image::{imageops, RgbaImage};
+use tracing::{debug, info, warn};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, BackPixmap, ChangeWindowAttributesAux, ClientMessageEvent, ColormapAlloc, ConnectionExt as _,
+    CreateGCAux, CreateWindowAux, EventMask, ImageFormat, ImageOrder, PropMode, Screen, Visualid, Window,
+    WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{CURRENT_TIME, NONE};
+use crate::config::AppConfig;
+use crate::error::{Result, TrayError};
+use crate::stray_impl::ScrollOrientation;
+use super::{popup, Image, PopupMenu, Rectangle, TrayEvent};
+
+pub(crate) const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+const SYSTEM_TRAY_BEGIN_MESSAGE: u32 = 1;
+const SYSTEM_TRAY_CANCEL_MESSAGE: u32 = 2;
+
+pub(crate) const XEMBED_VERSION: u32 = 0;
+pub(crate) const XEMBED_MAPPED: u32 = 1;
+pub(crate) const XEMBED_EMBEDDED_NOTIFY: u32 = 0;
+
+/// Side of the icon until the tray gives it a size
+const DEFAULT_SIZE: u16 = 22;
+
+/// Wheel delta per notch, the same step SNI hosts use
+pub const SCROLL_STEP: i32 = 120;
+
+x11rb::atom_manager! {
+    pub(crate) Atoms: AtomsCookie {
+        MANAGER,
+        UTF8_STRING,
+        _NET_WM_NAME,
+        _NET_WM_WINDOW_TYPE,
+        _NET_WM_WINDOW_TYPE_POPUP_MENU,
+        _NET_SYSTEM_TRAY_OPCODE,
+        _NET_SYSTEM_TRAY_ORIENTATION,
+        _NET_SYSTEM_TRAY_MESSAGE_DATA,
+        _NET_SYSTEM_TRAY_VISUAL,
+        _XEMBED,
+        _XEMBED_INFO,
+        _TRAY_CLOSE_MENU,
+    }
+}
+
+/// Something the tray or the user did to the icon
+#[derive(Debug, Clone, PartialEq)]
+pub enum XEmbedEvent {
+    /// The manager took the icon into the panel
+    Embedded { embedder: u32 },
+    /// The icon was taken out of the panel again
+    Unembedded,
+    /// The tray manager went away
+    ManagerLost,
+    /// A new manager took over the selection and was asked to dock the icon
+    ManagerRestored,
+    /// A button pressed over the icon, in root window coordinates
+    Click { button: u8, x: i16, y: i16 },
+    Scroll { delta: i32, orientation: ScrollOrientation },
+    Resized { width: u16, height: u16 },
+}
+
+impl XEmbedEvent {
+    /// The event as the other backends report it, `None` for tray bookkeeping
+    pub fn to_tray_event(&self) -> Option<TrayEvent> {
+        match *self {
+            XEmbedEvent::Click { button, x, y } => {
+                let (x, y) = (x as i32, y as i32);
+                match button {
+                    1 => Some(TrayEvent::Activate { x, y }),
+                    2 => Some(TrayEvent::SecondaryActivate { x, y }),
+                    3 => Some(TrayEvent::ContextMenu { x, y }),
+                    _ => None,
+                }
+            }
+            XEmbedEvent::Scroll { delta, orientation } => Some(TrayEvent::Scroll { delta, orientation }),
+            XEmbedEvent::ManagerLost => Some(TrayEvent::TrayLost),
+            XEmbedEvent::ManagerRestored => Some(TrayEvent::TrayRestored),
+            XEmbedEvent::Embedded { .. } | XEmbedEvent::Unembedded | XEmbedEvent::Resized { .. } => None,
+        }
+    }
+}
+
+/// Visual the icon window is created with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IconVisual {
+    id: Visualid,
+    depth: u8,
+}
+
+impl IconVisual {
+    fn is_argb(&self) -> bool {
+        self.depth == 32
+    }
+}
+
+pub struct LinuxTrayIcon {
+    config: AppConfig,
+    conn: Arc<RustConnection>,
+    screen: usize,
+    atoms: Atoms,
+    /// `_NET_SYSTEM_TRAY_S{screen}`
+    selection: u32,
+    manager: Option<Window>,
+    embedder: Option<Window>,
+    window: Window,
+    /// The panel shows the window, so it can be painted
+    mapped: bool,
+    gc: u32,
+    /// Colormap of a 32 bit window, `NONE` otherwise
+    colormap: u32,
+    visual: IconVisual,
+    width: u16,
+    height: u16,
+    icon: Image,
+    tooltip: String,
+    next_message: u32,
+    /// Events that arrived while a menu was up
+    pending: VecDeque<Event>,
+    /// Set by a [`MenuCloser`], no menu is shown any more
+    menus_closed: Arc<AtomicBool>,
+}
+
+/// Dismisses the menu of a [`LinuxTrayIcon`] from another thread
+///
+/// [`LinuxTrayIcon::show_menu`] blocks the icon's thread until the menu
+/// closes. After [`close`](Self::close) the menu shown is dismissed and later
+/// calls return `None` at once.
+pub struct MenuCloser {
+    conn: Arc<RustConnection>,
+    /// Unmapped window of the icon's connection, messages to it reach the menu
+    window: Window,
+    atom: Atom,
+    closed: Arc<AtomicBool>,
+}
+
+impl MenuCloser {
+    pub fn close(&self) -> Result<()> {
+        // Set first: a menu about to open either sees the flag or gets the message
+        self.closed.store(true, Ordering::SeqCst);
+        let message = ClientMessageEvent::new(32, self.window, self.atom, [0; 5]);
+        self.conn.send_event(false, self.window, EventMask::NO_EVENT, message)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for MenuCloser {
+    fn drop(&mut self) {
+        let _ = self.conn.destroy_window(self.window);
+        let _ = self.conn.flush();
+    }
+}
+
+impl LinuxTrayIcon {
+    /// Connect to `$DISPLAY`
+    pub fn new(config: AppConfig) -> Result<Self> {
+        Self::connect(None, config)
+    }
+
+    /// Connect to `display`, e.g. `:1`, and create the icon window
+    ///
+    /// The icon is not shown until [`dock`](Self::dock) finds a tray manager.
+    pub fn connect(display: Option<&str>, config: AppConfig) -> Result<Self> {
+        let (conn, screen) = x11rb::connect(display)?;
+        let atoms = Atoms::new(&conn)?.reply()?;
+        let selection = conn
+            .intern_atom(false, format!("_NET_SYSTEM_TRAY_S{}", screen).as_bytes())?
+            .reply()?
+            .atom;
+
+        // New managers announce themselves with a MANAGER message to the root window
+        let root = conn.setup().roots[screen].root;
+        conn.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY),
+        )?;
+
+        let tooltip = config.tooltip.clone();
+        let visual = default_visual(&conn.setup().roots[screen]);
+        let mut icon = Self {
+            config,
+            conn: Arc::new(conn),
+            screen,
+            atoms,
+            selection,
+            manager: None,
+            embedder: None,
+            window: NONE,
+            mapped: false,
+            gc: NONE,
+            colormap: NONE,
+            visual,
+            width: DEFAULT_SIZE,
+            height: DEFAULT_SIZE,
+            icon: Image::new(),
+            tooltip,
+            next_message: 1,
+            pending: VecDeque::new(),
+            menus_closed: Arc::new(AtomicBool::new(false)),
+        };
+        icon.create_window(visual)?;
+        Ok(icon)
+    }
+
+    fn screen(&self) -> &Screen {
+        &self.conn.setup().roots[self.screen]
+    }
+
+    fn create_window(&mut self, visual: IconVisual) -> Result<()> {
+        let root = self.screen().root;
+        let window = self.conn.generate_id()?;
+        let events = EventMask::EXPOSURE | EventMask::STRUCTURE_NOTIFY | EventMask::BUTTON_PRESS;
+        let mut aux = CreateWindowAux::new().event_mask(events);
+        let mut colormap = NONE;
+
+        if visual.is_argb() {
+            // A 32 bit window needs its own colormap and an explicit border
+            colormap = self.conn.generate_id()?;
+            self.conn.create_colormap(ColormapAlloc::NONE, colormap, root, visual.id)?;
+            aux = aux.colormap(colormap).background_pixel(0).border_pixel(0);
+        } else {
+            aux = aux.background_pixmap(BackPixmap::PARENT_RELATIVE);
+        }
+
+        self.conn.create_window(
+            visual.depth,
+            window,
+            root,
+            0,
+            0,
+            self.width,
+            self.height,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            visual.id,
+            &aux,
+        )?;
+        self.conn.change_property32(
+            PropMode::REPLACE,
+            window,
+            self.atoms._XEMBED_INFO,
+            self.atoms._XEMBED_INFO,
+            &[XEMBED_VERSION, XEMBED_MAPPED],
+        )?;
+        self.conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_CLASS,
+            AtomEnum::STRING,
+            format!("{0}\0{0}\0", self.config.app_name).as_bytes(),
+        )?;
+
+        let gc = self.conn.generate_id()?;
+        self.conn.create_gc(gc, window, &CreateGCAux::new())?;
+
+        self.window = window;
+        self.gc = gc;
+        self.colormap = colormap;
+        self.visual = visual;
+        self.embedder = None;
+        self.mapped = false;
+        self.write_tooltip()?;
+        self.conn.flush()?;
+        debug!("Created tray icon window {:#x} with depth {}", window, visual.depth);
+        Ok(())
+    }
+
+    /// Replace the icon window, freeing what belonged to the old one
+    ///
+    /// `destroyed` is set when the server already destroyed the old window.
+    fn recreate_window(&mut self, visual: IconVisual, destroyed: bool) -> Result<()> {
+        let (window, gc, colormap) = (self.window, self.gc, self.colormap);
+        self.create_window(visual)?;
+
+        self.conn.free_gc(gc)?;
+        if !destroyed {
+            self.conn.destroy_window(window)?;
+        }
+        if colormap != NONE {
+            self.conn.free_colormap(colormap)?;
+        }
+        Ok(())
+    }
+
+    /// Ask the current tray manager to embed the icon
+    pub fn dock(&mut self) -> Result<()> {
+        let manager = self.conn.get_selection_owner(self.selection)?.reply()?.owner;
+        if manager == NONE {
+            return Err(TrayError::InitializationError(format!(
+                "No system tray manager owns _NET_SYSTEM_TRAY_S{}",
+                self.screen
+            )));
+        }
+
+        // Notice when the manager exits
+        self.conn.change_window_attributes(
+            manager,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY),
+        )?;
+
+        let visual = self.manager_visual(manager)?.unwrap_or_else(|| default_visual(self.screen()));
+        if visual != self.visual {
+            self.recreate_window(visual, false)?;
+        }
+
+        self.manager = Some(manager);
+        self.send_opcode(manager, [CURRENT_TIME, SYSTEM_TRAY_REQUEST_DOCK, self.window, 0, 0])?;
+        self.conn.flush()?;
+        info!("Asked tray manager {:#x} to dock {:#x}", manager, self.window);
+        Ok(())
+    }
+
+    /// Visual the manager wants icons in, if it says
+    fn manager_visual(&self, manager: Window) -> Result<Option<IconVisual>> {
+        let reply = self
+            .conn
+            .get_property(false, manager, self.atoms._NET_SYSTEM_TRAY_VISUAL, AtomEnum::VISUALID, 0, 1)?
+            .reply()?;
+        let Some(id) = reply.value32().and_then(|mut values| values.next()) else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .screen()
+            .allowed_depths
+            .iter()
+            .find(|depth| depth.visuals.iter().any(|visual| visual.visual_id == id))
+            .map(|depth| IconVisual { id, depth: depth.depth }))
+    }
+
+    fn send_opcode(&self, manager: Window, data: [u32; 5]) -> Result<()> {
+        let event = ClientMessageEvent::new(32, self.window, self.atoms._NET_SYSTEM_TRAY_OPCODE, data);
+        self.conn.send_event(false, manager, EventMask::NO_EVENT, event)?;
+        Ok(())
+    }
+
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+
+    /// Window of the tray manager the icon was docked with
+    pub fn manager(&self) -> Option<u32> {
+        self.manager
+    }
+
+    pub fn is_embedded(&self) -> bool {
+        self.embedder.is_some()
+    }
+
+    /// Whether the icon is painted with its own alpha channel
+    pub fn is_argb(&self) -> bool {
+        self.visual.is_argb()
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Where the tray put the icon, in root window coordinates
+    ///
+    /// Asks the server each time, since the panel moves the window it
+    /// embedded the icon in without telling the icon.
+    pub fn bounds(&self) -> Result<Rectangle> {
+        let position = self
+            .conn
+            .translate_coordinates(self.window, self.screen().root, 0, 0)?
+            .reply()?;
+        Ok(Rectangle::new(
+            position.dst_x as i32,
+            position.dst_y as i32,
+            self.width as i32,
+            self.height as i32,
+        ))
+    }
+
+    /// The whole screen the icon is on, for placing windows next to it
+    pub fn screen_bounds(&self) -> Rectangle {
+        let screen = self.screen();
+        Rectangle::new(0, 0, screen.width_in_pixels as i32, screen.height_in_pixels as i32)
+    }
+
+    /// Replace the artwork, scaled to whatever size the tray gives the icon
+    pub fn set_icon(&mut self, image: &Image) -> Result<()> {
+        self.icon = image.clone();
+        self.paint()
+    }
+
+    /// Name the icon window, which some trays show on hover
+    pub fn set_tooltip(&mut self, tooltip: &str) -> Result<()> {
+        self.tooltip = tooltip.to_string();
+        self.write_tooltip()?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn write_tooltip(&self) -> Result<()> {
+        let name = self.tooltip.as_bytes();
+        self.conn
+            .change_property8(PropMode::REPLACE, self.window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING, name)?;
+        self.conn
+            .change_property8(PropMode::REPLACE, self.window, AtomEnum::WM_NAME, AtomEnum::STRING, name)?;
+        Ok(())
+    }
+
+    /// Show a balloon message next to the icon, returning its id
+    ///
+    /// `timeout_ms` of zero leaves it up until the user closes it. Trays
+    /// without balloon support ignore the message.
+    pub fn show_message(&mut self, text: &str, timeout_ms: u32) -> Result<u32> {
+        let manager = self
+            .manager
+            .ok_or_else(|| TrayError::EventError("Not docked with a tray manager".to_string()))?;
+        let id = self.next_message;
+        self.next_message += 1;
+
+        let bytes = text.as_bytes();
+        self.send_opcode(manager, [CURRENT_TIME, SYSTEM_TRAY_BEGIN_MESSAGE, timeout_ms, bytes.len() as u32, id])?;
+        for chunk in bytes.chunks(20) {
+            let mut data = [0u8; 20];
+            data[..chunk.len()].copy_from_slice(chunk);
+            let event = ClientMessageEvent::new(8, self.window, self.atoms._NET_SYSTEM_TRAY_MESSAGE_DATA, data);
+            self.conn.send_event(false, manager, EventMask::NO_EVENT, event)?;
+        }
+        self.conn.flush()?;
+        Ok(id)
+    }
+
+    pub fn cancel_message(&mut self, id: u32) -> Result<()> {
+        if let Some(manager) = self.manager {
+            self.send_opcode(manager, [CURRENT_TIME, SYSTEM_TRAY_CANCEL_MESSAGE, id, 0, 0])?;
+            self.conn.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Drop down `menu` next to `anchor` and wait until it closes
+    ///
+    /// Returns the result id of the chosen entry, `None` when the menu was
+    /// dismissed. Other events that arrive meanwhile are kept for
+    /// [`poll_event`](Self::poll_event).
+    pub fn show_menu(&mut self, menu: &PopupMenu, anchor: Rectangle) -> Result<Option<i32>> {
+        if self.menus_closed.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        let screen = &self.conn.setup().roots[self.screen];
+        popup::run(&self.conn, screen, &self.atoms, menu, anchor, &mut self.pending)
+    }
+
+    /// A handle that closes the menu from another thread, see [`MenuCloser`]
+    pub fn menu_closer(&self) -> Result<MenuCloser> {
+        let window = self.conn.generate_id()?;
+        self.conn.create_window(
+            0,
+            window,
+            self.screen().root,
+            -1,
+            -1,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )?;
+        self.conn.flush()?;
+        Ok(MenuCloser {
+            conn: self.conn.clone(),
+            window,
+            atom: self.atoms._TRAY_CLOSE_MENU,
+            closed: self.menus_closed.clone(),
+        })
+    }
+
+    fn paint(&self) -> Result<()> {
+        if !self.mapped {
+            return Ok(());
+        }
+        let size = self.width.min(self.height);
+        let Some(pixmap) = scaled_pixmap(&self.icon, size as u32) else {
+            return Ok(());
+        };
+
+        let setup = self.conn.setup();
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == self.visual.depth)
+            .map(|format| format.bits_per_pixel);
+        if bits_per_pixel != Some(32) {
+            return Err(TrayError::X11Error(format!(
+                "Cannot paint depth {} at {:?} bits per pixel",
+                self.visual.depth, bits_per_pixel
+            )));
+        }
+        let lsb_first = setup.image_byte_order == ImageOrder::LSB_FIRST;
+
+        let x = ((self.width - size) / 2) as i16;
+        let y = ((self.height - size) / 2) as i16;
+        let data = if self.visual.is_argb() {
+            encode_argb(&pixmap, lsb_first)
+        } else {
+            // Let the panel draw its background, then blend over it
+            self.conn.clear_area(false, self.window, x, y, size, size)?;
+            let background = self
+                .conn
+                .get_image(ImageFormat::Z_PIXMAP, self.window, x, y, size, size, !0)?
+                .reply()?;
+            encode_over(&pixmap, &background.data, lsb_first)
+        };
+
+        self.conn
+            .put_image(ImageFormat::Z_PIXMAP, self.window, self.gc, size, size, x, y, 0, self.visual.depth, &data)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Paint in response to the server, where a failure is only logged
+    fn repaint(&self) {
+        if let Err(e) = self.paint() {
+            warn!("Could not paint tray icon: {}", e);
+        }
+    }
+
+    /// Next event without blocking, `None` when nothing is queued
+    pub fn poll_event(&mut self) -> Result<Option<XEmbedEvent>> {
+        loop {
+            let event = match self.pending.pop_front() {
+                Some(event) => event,
+                None => match self.conn.poll_for_event()? {
+                    Some(event) => event,
+                    None => return Ok(None),
+                },
+            };
+            if let Some(event) = self.handle(event)? {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Block until the next event
+    pub fn wait_event(&mut self) -> Result<XEmbedEvent> {
+        loop {
+            let event = match self.pending.pop_front() {
+                Some(event) => event,
+                None => self.conn.wait_for_event()?,
+            };
+            if let Some(event) = self.handle(event)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Forget the manager, so only a new one docks the icon again
+    fn manager_lost(&mut self) -> Option<XEmbedEvent> {
+        self.manager = None;
+        self.embedder = None;
+        Some(XEmbedEvent::ManagerLost)
+    }
+
+    fn handle(&mut self, event: Event) -> Result<Option<XEmbedEvent>> {
+        let root = self.screen().root;
+        let event = match event {
+            Event::Expose(e) if e.window == self.window && e.count == 0 => {
+                self.repaint();
+                None
+            }
+            Event::MapNotify(e) if e.window == self.window => {
+                self.mapped = true;
+                None
+            }
+            Event::UnmapNotify(e) if e.window == self.window => {
+                self.mapped = false;
+                None
+            }
+            Event::ConfigureNotify(e) if e.window == self.window => {
+                if (e.width, e.height) == (self.width, self.height) {
+                    None
+                } else {
+                    self.width = e.width;
+                    self.height = e.height;
+                    self.repaint();
+                    Some(XEmbedEvent::Resized { width: e.width, height: e.height })
+                }
+            }
+            Event::ReparentNotify(e) if e.window == self.window && e.parent == root => {
+                self.embedder.take().map(|_| XEmbedEvent::Unembedded)
+            }
+            Event::ClientMessage(e) if e.window == self.window && e.type_ == self.atoms._XEMBED => {
+                let data = e.data.as_data32();
+                if data[1] == XEMBED_EMBEDDED_NOTIFY {
+                    info!("Tray icon {:#x} embedded into {:#x}", self.window, data[3]);
+                    self.embedder = Some(data[3]);
+                    Some(XEmbedEvent::Embedded { embedder: data[3] })
+                } else {
+                    debug!("Ignoring XEmbed message {}", data[1]);
+                    None
+                }
+            }
+            Event::ClientMessage(e)
+                if e.window == root && e.type_ == self.atoms.MANAGER && e.data.as_data32()[1] == self.selection =>
+            {
+                info!("New system tray manager {:#x}", e.data.as_data32()[2]);
+                match self.dock() {
+                    Ok(()) => Some(XEmbedEvent::ManagerRestored),
+                    Err(e) => {
+                        warn!("Could not dock with the new tray manager: {}", e);
+                        self.manager_lost()
+                    }
+                }
+            }
+            Event::DestroyNotify(e) if Some(e.window) == self.manager => {
+                warn!("System tray manager {:#x} went away", e.window);
+                self.manager_lost()
+            }
+            Event::DestroyNotify(e) if e.window == self.window => {
+                // Managers that forget the save-set take the icon down with them
+                warn!("Tray icon window {:#x} was destroyed, creating it again", e.window);
+                self.recreate_window(self.visual, true)?;
+                match self.manager {
+                    Some(_) => match self.dock() {
+                        Ok(()) => None,
+                        Err(e) => {
+                            warn!("Could not dock the new tray icon window: {}", e);
+                            self.manager_lost()
+                        }
+                    },
+                    None => None,
+                }
+            }
+            Event::ButtonPress(e) if e.event == self.window => Some(match e.detail {
+                4 => XEmbedEvent::Scroll { delta: SCROLL_STEP, orientation: ScrollOrientation::Vertical },
+                5 => XEmbedEvent::Scroll { delta: -SCROLL_STEP, orientation: ScrollOrientation::Vertical },
+                6 => XEmbedEvent::Scroll { delta: SCROLL_STEP, orientation: ScrollOrientation::Horizontal },
+                7 => XEmbedEvent::Scroll { delta: -SCROLL_STEP, orientation: ScrollOrientation::Horizontal },
+                button => XEmbedEvent::Click { button, x: e.root_x, y: e.root_y },
+            }),
+            Event::Error(e) => {
+                warn!("X11 error: {:?}", e);
+                None
+            }
+            _ => None,
+        };
+        Ok(event)
+    }
+}
+
+fn default_visual(screen: &Screen) -> IconVisual {
+    IconVisual {
+        id: screen.root_visual,
+        depth: screen.root_depth,
+    }
+}
+
+/// Pixmap of `icon` at `size`, scaled down from the closest larger one
+pub(super) fn scaled_pixmap(icon: &Image, size: u32) -> Option<RgbaImage> {
+    if size == 0 {
+        return None;
+    }
+    let source = icon
+        .pixmaps()
+        .iter()
+        .find(|pixmap| pixmap.width() >= size && pixmap.height() >= size)
+        .or_else(|| icon.pixmaps().last())?;
+
+    if source.width() == size && source.height() == size {
+        Some(source.clone())
+    } else {
+        Some(imageops::resize(source, size, size, imageops::FilterType::Lanczos3))
+    }
+}
+
+/// One 32 bit pixel in the server's byte order
+fn pixel_bytes([r, g, b, a]: [u8; 4], lsb_first: bool) -> [u8; 4] {
+    if lsb_first {
+        [b, g, r, a]
+    } else {
+        [a, r, g, b]
+    }
+}
+
+fn premultiply(channel: u8, alpha: u8) -> u8 {
+    ((channel as u32 * alpha as u32 + 127) / 255) as u8
+}
+
+/// ZPixmap data for a 32 bit visual, which expects premultiplied alpha
+pub(super) fn encode_argb(image: &RgbaImage, lsb_first: bool) -> Vec<u8> {
+    image
+        .pixels()
+        .flat_map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            pixel_bytes([premultiply(r, a), premultiply(g, a), premultiply(b, a), a], lsb_first)
+        })
+        .collect()
+}
+
+/// ZPixmap data for `image` blended over `background`, both 32 bits per pixel
+pub(super) fn encode_over(image: &RgbaImage, background: &[u8], lsb_first: bool) -> Vec<u8> {
+    image
+        .pixels()
+        .zip(background.chunks_exact(4))
+        .flat_map(|(pixel, under)| {
+            let [r, g, b, a] = pixel.0;
+            let [under_r, under_g, under_b] = if lsb_first {
+                [under[2], under[1], under[0]]
+            } else {
+                [under[1], under[2], under[3]]
+            };
+            let blend = |over: u8, under: u8| premultiply(over, a) + premultiply(under, 255 - a);
+            pixel_bytes([blend(r, under_r), blend(g, under_g), blend(b, under_b), 0xff], lsb_first)
+        })
+        .collect()
+}
@@ -1,11 +1,32 @@
+use std::collections::VecDeque;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
+use image::RgbaImage;
 use tokio::sync::RwLock;
-use crate::config::AppConfig;
-use crate::error::Result;
-use crate::menu::TrayMenu;
+use crate::backend::TrayBackend;
+use crate::config::{AppConfig, ItemStatus};
+use crate::error::{Result, TrayError};
+use crate::icon::{IconGenerator, IconThemeResolver};
+use crate::menu::{MenuAction, TrayMenu};
+use crate::stray_impl::ScrollOrientation;
 
 #[cfg(target_os = "linux")]
-mod linux_impl;
+pub mod linux_impl;
+#[cfg(target_os = "linux")]
+mod popup;
+
+pub mod progress;
+pub mod sni;
+pub mod state;
+
+#[cfg(test)]
+mod tests;
+
+pub use progress::{ProgressColors, ProgressStyle};
+pub use sni::{ItemProperties, SniEvent, SniItem};
+use progress::ProgressThrottle;
+use state::Animation;
 
 // Working implementation module
 // mod working_impl;
@@ -22,11 +43,151 @@ pub trait SystemTrayIconComponentInterface {
     fn hide_info_bubble(&mut self);
     fn show_dropdown_menu(&mut self, menu: &PopupMenu);
     fn get_bounds(&self) -> Rectangle;
+
+    // Not part of aloe's API, components that cannot tell keep the defaults
+
+    /// Status of the icon, which SNI hosts use to show or hide it
+    fn set_status(&mut self, _status: ItemStatus) {}
+
+    /// Next click, scroll or menu choice, `None` when nothing is pending
+    fn poll_event(&mut self) -> Option<TrayEvent> {
+        None
+    }
+}
+
+/// What the user did to the icon, or what happened to the tray showing it
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrayEvent {
+    Activate { x: i32, y: i32 },
+    SecondaryActivate { x: i32, y: i32 },
+    ContextMenu { x: i32, y: i32 },
+    Scroll { delta: i32, orientation: ScrollOrientation },
+    /// An entry of the dropdown menu was chosen, by its result id
+    MenuItem(i32),
+    /// The tray went away; the icon is not shown until it returns
+    TrayLost,
+    /// A tray is back and shows the icon again
+    TrayRestored,
+}
+
+/// Sizes rendered when an icon is generated or loaded from a single file
+pub const ICON_SIZES: [u32; 5] = [16, 22, 24, 32, 48];
+
+/// Icon artwork, kept at every size the tray may ask for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Image {
+    pixmaps: Vec<RgbaImage>,
+}
+
+impl Image {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_pixmaps(mut pixmaps: Vec<RgbaImage>) -> Self {
+        pixmaps.sort_by_key(|pixmap| pixmap.width());
+        Self { pixmaps }
+    }
+
+    /// Render a generated icon at each of [`ICON_SIZES`]
+    pub fn from_generator(generator: &IconGenerator) -> Self {
+        Self::from_pixmaps(ICON_SIZES.iter().map(|&size| generator.render(size)).collect())
+    }
+
+    /// Load an image file and scale it to each of [`ICON_SIZES`]
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let source = image::open(path)
+            .map_err(|e| TrayError::IconLoadError(format!("{}: {}", path.display(), e)))?
+            .into_rgba8();
+
+        Ok(Self::from_pixmaps(
+            ICON_SIZES
+                .iter()
+                .map(|&size| image::imageops::resize(&source, size, size, image::imageops::FilterType::Lanczos3))
+                .collect(),
+        ))
+    }
+
+    /// Resolve a themed icon name and render it at each of [`ICON_SIZES`]
+    pub fn from_icon_name(resolver: &IconThemeResolver, name: &str) -> Result<Self> {
+        let pixmaps = ICON_SIZES
+            .iter()
+            .map(|&size| resolver.load(name, size, 1, None))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::from_pixmaps(pixmaps))
+    }
+    
+    pub fn pixmaps(&self) -> &[RgbaImage] {
+        &self.pixmaps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixmaps.is_empty()
+    }
+
+    /// Apply `f` to every pixmap, producing a new image
+    pub fn map(&self, f: impl Fn(&RgbaImage) -> RgbaImage) -> Self {
+        Self::from_pixmaps(self.pixmaps.iter().map(f).collect())
+    }
+}
+
+/// A dropdown menu, built like aloe's `PopupMenu`
+///
+/// Entries are reported by the result id given to [`add_item`](Self::add_item).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PopupMenu {
+    items: Vec<PopupMenuItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PopupMenuItem {
+    Item { id: i32, text: String, enabled: bool, checked: bool },
+    Separator,
+    SubMenu { text: String, menu: PopupMenu, enabled: bool },
+}
+
+impl PopupMenuItem {
+    /// Whether the entry can be highlighted and chosen
+    pub fn is_selectable(&self) -> bool {
+        match self {
+            PopupMenuItem::Item { enabled, .. } => *enabled,
+            PopupMenuItem::SubMenu { menu, enabled, .. } => *enabled && !menu.is_empty(),
+            PopupMenuItem::Separator => false,
+        }
+    }
+}
+
+impl PopupMenu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_item(&mut self, id: i32, text: &str, enabled: bool, checked: bool) {
+        self.items.push(PopupMenuItem::Item { id, text: text.to_string(), enabled, checked });
+    }
+
+    /// Add a separator, unless it would start the menu or follow another
+    pub fn add_separator(&mut self) {
+        if !matches!(self.items.last(), None | Some(PopupMenuItem::Separator)) {
+            self.items.push(PopupMenuItem::Separator);
+        }
+    }
+
+    pub fn add_sub_menu(&mut self, text: &str, menu: PopupMenu, enabled: bool) {
+        self.items.push(PopupMenuItem::SubMenu { text: text.to_string(), menu, enabled });
+    }
+
+    pub fn items(&self) -> &[PopupMenuItem] {
+        &self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
 }
 
 // Placeholder types that match aloe's structure
-pub struct Image;
-pub struct PopupMenu;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Rectangle {
     x: i32,
     y: i32,
@@ -35,7 +196,7 @@ pub struct Rectangle {
 }
 
 impl Rectangle {
-    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
         Self { x, y, width, height }
     }
     
@@ -43,25 +204,130 @@ impl Rectangle {
     pub fn get_y(&self) -> i32 { self.y }
     pub fn get_width(&self) -> i32 { self.width }
     pub fn get_height(&self) -> i32 { self.height }
+    pub fn get_right(&self) -> i32 { self.x + self.width }
+    pub fn get_bottom(&self) -> i32 { self.y + self.height }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        (self.x..self.get_right()).contains(&x) && (self.y..self.get_bottom()).contains(&y)
+    }
+
+    /// Edge of `screen` this rectangle is closest to, which for a tray icon
+    /// is the edge its panel sits on
+    pub fn nearest_edge(&self, screen: &Rectangle) -> ScreenEdge {
+        [
+            (self.y - screen.y, ScreenEdge::Top),
+            (screen.get_bottom() - self.get_bottom(), ScreenEdge::Bottom),
+            (self.x - screen.x, ScreenEdge::Left),
+            (screen.get_right() - self.get_right(), ScreenEdge::Right),
+        ]
+        .into_iter()
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, edge)| edge)
+        .unwrap_or(ScreenEdge::Top)
+    }
+
+    /// Where a popup of `width` by `height` goes next to this icon
+    ///
+    /// The popup opens away from the panel's screen edge, below an icon at
+    /// the top, above one at the bottom and beside one in a side panel. It
+    /// flips to the other side when there is no room and is then moved
+    /// fully onto `screen`.
+    pub fn place_popup(&self, width: i32, height: i32, screen: &Rectangle) -> Rectangle {
+        let below = Rectangle::new(self.x, self.get_bottom(), width, height);
+        let above = Rectangle::new(self.x, self.y - height, width, height);
+        let right = Rectangle::new(self.get_right(), self.y, width, height);
+        let left = Rectangle::new(self.x - width, self.y, width, height);
+
+        let (preferred, flipped) = match self.nearest_edge(screen) {
+            ScreenEdge::Top => (below, above),
+            ScreenEdge::Bottom => (above, below),
+            ScreenEdge::Left => (right, left),
+            ScreenEdge::Right => (left, right),
+        };
+        preferred.fit_or(flipped, screen)
+    }
+
+    /// Where a submenu of `width` by `height` goes next to this menu entry,
+    /// to its right unless only the left side has room
+    pub fn place_beside(&self, width: i32, height: i32, screen: &Rectangle) -> Rectangle {
+        let right = Rectangle::new(self.get_right(), self.y, width, height);
+        let left = Rectangle::new(self.x - width, self.y, width, height);
+        right.fit_or(left, screen)
+    }
+
+    /// This rectangle moved by as little as possible to lie within `screen`,
+    /// keeping its top left corner on screen if it is larger
+    pub fn constrained_within(&self, screen: &Rectangle) -> Rectangle {
+        let x = self.x.min(screen.get_right() - self.width).max(screen.x);
+        let y = self.y.min(screen.get_bottom() - self.height).max(screen.y);
+        Rectangle::new(x, y, self.width, self.height)
+    }
+
+    /// `self` if it fits on the side it was placed, else `other` if that does
+    fn fit_or(self, other: Rectangle, screen: &Rectangle) -> Rectangle {
+        // The two differ along one axis, the other is constrained either way
+        let fits = |candidate: &Rectangle| {
+            if self.x == other.x {
+                candidate.y >= screen.y && candidate.get_bottom() <= screen.get_bottom()
+            } else {
+                candidate.x >= screen.x && candidate.get_right() <= screen.get_right()
+            }
+        };
+        let chosen = if fits(&self) || !fits(&other) { self } else { other };
+        chosen.constrained_within(screen)
+    }
 }
 
+/// Side of the screen a panel is docked to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenEdge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// Reported by [`SystemTrayIconComponent::get_bounds`] when the backend cannot tell
+const FALLBACK_BOUNDS: Rectangle = Rectangle::new(0, 0, 32, 32);
+
+/// The component [`TrayIcon::new`] uses, showing the icon through a [`TrayBackend`]
+///
+/// Without a backend it only logs what it is asked to do.
 pub struct SystemTrayIconComponent {
-    // Internal implementation would go here
+    backend: Option<Box<dyn TrayBackend>>,
+    /// Menu choices, reported before the backend's own events
+    chosen: VecDeque<TrayEvent>,
 }
 
 impl SystemTrayIconComponent {
     pub fn new() -> Self {
-        Self {}
+        Self { backend: None, chosen: VecDeque::new() }
+    }
+
+    /// Show the icon through `backend`, e.g. the one [`backend::detect`](crate::backend::detect) started
+    pub fn with_backend(backend: Box<dyn TrayBackend>) -> Self {
+        Self { backend: Some(backend), chosen: VecDeque::new() }
+    }
+
+    /// Run `update` on the backend, logging instead of failing the caller
+    fn update(&mut self, what: &str, update: impl FnOnce(&mut dyn TrayBackend) -> Result<()>) {
+        if let Some(backend) = self.backend.as_deref_mut() {
+            if let Err(e) = update(backend) {
+                tracing::warn!("Could not update the {} of the tray icon: {}", what, e);
+            }
+        }
     }
 }
 
 impl SystemTrayIconComponentInterface for SystemTrayIconComponent {
-    fn set_icon_image(&mut self, _colour_image: &Image, _template_image: &Image) {
-        tracing::debug!("Setting icon image (not implemented)");
+    fn set_icon_image(&mut self, colour_image: &Image, _template_image: &Image) {
+        tracing::debug!("Setting icon image ({} pixmaps)", colour_image.pixmaps().len());
+        self.update("image", |backend| backend.set_icon(colour_image));
     }
 
     fn set_icon_tooltip(&mut self, tooltip: &str) {
         tracing::debug!("Setting tooltip: {}", tooltip);
+        self.update("tooltip", |backend| backend.set_tooltip(tooltip));
     }
 
     fn set_highlighted(&mut self, should_highlight: bool) {
@@ -76,31 +342,91 @@ impl SystemTrayIconComponentInterface for SystemTrayIconComponent {
         tracing::debug!("Hiding info bubble");
     }
 
-    fn show_dropdown_menu(&mut self, _menu: &PopupMenu) {
+    /// Blocks while a backend that draws menus itself shows it; the chosen
+    /// entry is reported by [`poll_event`](Self::poll_event) as [`TrayEvent::MenuItem`]
+    fn show_dropdown_menu(&mut self, menu: &PopupMenu) {
         tracing::debug!("Showing dropdown menu");
+        let anchor = self.get_bounds();
+        let Some(backend) = self.backend.as_deref_mut() else { return };
+        match backend.show_menu(menu, anchor) {
+            Ok(Some(result)) => self.chosen.push_back(TrayEvent::MenuItem(result)),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Could not show the tray menu: {}", e),
+        }
     }
 
+    /// Where the backend says the icon is, a 32 pixel square at the origin
+    /// when it cannot tell
     fn get_bounds(&self) -> Rectangle {
-        Rectangle::new(0, 0, 32, 32)
+        self.backend
+            .as_ref()
+            .and_then(|backend| backend.bounds())
+            .unwrap_or(FALLBACK_BOUNDS)
+    }
+
+    fn set_status(&mut self, status: ItemStatus) {
+        self.update("status", |backend| backend.set_status(status));
+    }
+
+    fn poll_event(&mut self) -> Option<TrayEvent> {
+        if let Some(event) = self.chosen.pop_front() {
+            return Some(event);
+        }
+        let backend = self.backend.as_mut()?;
+        backend.poll_event().unwrap_or_else(|e| {
+            tracing::warn!("Could not read tray icon events: {}", e);
+            None
+        })
     }
 }
 
-pub struct TrayIcon {
-    component: SystemTrayIconComponent,
+/// The application's icon, drawn by component `C`
+///
+/// Tests can hand in a [`HeadlessBackend`](crate::backend::HeadlessBackend)
+/// with [`with_component`](Self::with_component) and assert on what it recorded.
+pub struct TrayIcon<C = SystemTrayIconComponent> {
+    component: C,
     config: Arc<RwLock<AppConfig>>,
     menu: TrayMenu,
+    icon: Image,
+    default_icon: Image,
+    state: Option<String>,
+    status: ItemStatus,
+    animation: Option<Animation>,
+    progress: Option<f32>,
+    progress_style: ProgressStyle,
+    progress_colors: ProgressColors,
+    progress_throttle: ProgressThrottle,
 }
 
 impl TrayIcon {
+    /// Show the icon through the first backend that works here, see
+    /// [`backend::detect`](crate::backend::detect)
     pub async fn new(config: AppConfig) -> Result<Self> {
+        let (backend, report) = crate::backend::detect(&config).await?;
+        tracing::debug!("Tray backends:\n{}", report);
+        Self::with_component(config, SystemTrayIconComponent::with_backend(backend)).await
+    }
+}
+
+impl<C: SystemTrayIconComponentInterface> TrayIcon<C> {
+    pub async fn with_component(config: AppConfig, component: C) -> Result<Self> {
         let config = Arc::new(RwLock::new(config));
         let menu = TrayMenu::new(config.clone()).await?;
-        let component = SystemTrayIconComponent::new();
         
         Ok(Self {
             component,
             config,
             menu,
+            icon: Image::new(),
+            default_icon: Image::new(),
+            state: None,
+            status: ItemStatus::Active,
+            animation: None,
+            progress: None,
+            progress_style: ProgressStyle::Ring,
+            progress_colors: ProgressColors::default(),
+            progress_throttle: ProgressThrottle::default(),
         })
     }
     
@@ -111,12 +437,22 @@ impl TrayIcon {
         tracing::info!("App: {}", config.app_name);
         tracing::info!("Tooltip: {}", config.tooltip);
         
-        // Create placeholder images
-        let colour_image = Image;
-        let template_image = Image;
+        // Fall back to the themed icon, then a generated one, when the
+        // configured file is unusable
+        let themed = |e: TrayError| match &config.icon_name {
+            Some(name) => Image::from_icon_name(&IconThemeResolver::system(), name),
+            None => Err(e),
+        };
+        self.icon = Image::from_file(&config.icon_path).or_else(themed).unwrap_or_else(|e| {
+            tracing::warn!("Using generated icon: {}", e);
+            let initial = config.app_name.chars().next().unwrap_or('?').to_string();
+            Image::from_generator(&IconGenerator::text(&initial).background(image::Rgba([52, 152, 219, 255])))
+        });
+        self.default_icon = self.icon.clone();
         
         // Set up the icon using aloe API
-        self.component.set_icon_image(&colour_image, &template_image);
+        let image = self.composited_icon();
+        self.component.set_icon_image(&image, &image);
         self.component.set_icon_tooltip(&config.tooltip);
         
         // Set up menu
@@ -150,11 +486,133 @@ impl TrayIcon {
         self.component.hide_info_bubble();
     }
     
+    /// Replace the icon artwork, keeping any progress overlay on top
+    pub async fn set_icon(&mut self, image: Image) {
+        self.icon = image;
+        self.animation = None;
+        self.refresh_icon();
+    }
+    
+    /// Switch icon, tooltip, status and animation to a state from the config
+    ///
+    /// Every resource of the state is loaded before anything changes, so a
+    /// failing switch leaves the current state in place.
+    pub async fn set_state(&mut self, name: &str) -> Result<()> {
+        let config = self.config.read().await;
+        let state = config
+            .states
+            .get(name)
+            .ok_or_else(|| TrayError::UnknownState(name.to_string()))?;
+        
+        let resolved = state::resolve(state)?;
+        let tooltip = resolved.tooltip.unwrap_or_else(|| config.tooltip.clone());
+        drop(config);
+        
+        self.icon = resolved.icon.unwrap_or_else(|| self.default_icon.clone());
+        self.animation = resolved.animation;
+        self.status = resolved.status;
+        self.state = Some(name.to_string());
+        
+        self.component.set_icon_tooltip(&tooltip);
+        self.component.set_status(self.status);
+        self.component.set_highlighted(self.status == ItemStatus::NeedsAttention);
+        self.refresh_icon();
+        
+        tracing::info!("Switched to state: {}", name);
+        Ok(())
+    }
+    
+    pub fn get_state(&self) -> Option<&str> {
+        self.state.as_deref()
+    }
+    
+    pub fn get_status(&self) -> ItemStatus {
+        self.status
+    }
+    
+    /// Show progress over the icon, or clear the overlay with `None`
+    ///
+    /// Updates are throttled; values held back are shown on a later call
+    /// or by [`TrayIcon::handle_events`].
+    pub async fn set_progress(&mut self, progress: Option<f32>) {
+        if let Some(progress) = self.progress_throttle.offer(progress, Instant::now()) {
+            self.progress = progress;
+            self.refresh_icon();
+        }
+    }
+    
+    pub fn set_progress_style(&mut self, style: ProgressStyle, colors: ProgressColors) {
+        self.progress_style = style;
+        self.progress_colors = colors;
+        if self.progress.is_some() {
+            self.refresh_icon();
+        }
+    }
+    
+    pub fn get_progress(&self) -> Option<f32> {
+        self.progress
+    }
+    
+    fn composited_icon(&self) -> Image {
+        let icon = match &self.animation {
+            Some(animation) => animation.frame(),
+            None => &self.icon,
+        };
+        
+        match self.progress {
+            Some(progress) => icon.map(|pixmap| {
+                progress::composite(pixmap, progress, self.progress_style, self.progress_colors)
+            }),
+            None => icon.clone(),
+        }
+    }
+    
+    fn refresh_icon(&mut self) {
+        let image = self.composited_icon();
+        self.component.set_icon_image(&image, &image);
+    }
+    
     pub async fn handle_events(&mut self) {
-        // Event handling would be implemented here
+        let now = Instant::now();
+        let mut changed = false;
+        
+        if let Some(progress) = self.progress_throttle.flush(now) {
+            self.progress = progress;
+            changed = true;
+        }
+        if let Some(animation) = &mut self.animation {
+            changed |= animation.advance(now);
+        }
+        if changed {
+            self.refresh_icon();
+        }
+        
         tracing::debug!("Handling events");
     }
     
+    /// Next click, scroll or menu choice reported by the component
+    pub fn poll_event(&mut self) -> Option<TrayEvent> {
+        self.component.poll_event()
+    }
+    
+    /// What a [`TrayEvent::MenuItem`] result id stands for
+    pub async fn menu_action(&self, result: i32) -> Result<Option<MenuAction>> {
+        self.menu.handle_menu_result(result).await
+    }
+    
+    /// Drop down the menu built from the config
+    ///
+    /// On XEmbed trays the menu is drawn by the crate and this blocks until
+    /// it closes; the chosen entry then comes from [`poll_event`](Self::poll_event).
+    pub fn show_menu(&mut self) {
+        self.component.show_dropdown_menu(self.menu.popup());
+    }
+    
+    /// Where the icon is on screen, to anchor windows next to it
+    pub fn get_bounds(&self) -> Rectangle {
+        self.component.get_bounds()
+    }
+    
     pub fn show(&mut self) {
         tracing::info!("Showing system tray icon");
     }
@@ -163,7 +621,7 @@ impl TrayIcon {
         tracing::info!("Hiding system tray icon");
     }
     
-    pub fn get_component(&mut self) -> &mut SystemTrayIconComponent {
+    pub fn get_component(&mut self) -> &mut C {
         &mut self.component
     }
 }
\ No newline at end of file
@@ -0,0 +1,603 @@
+//! Popup menus drawn by the crate, for XEmbed trays where no host draws a
+//! [`PopupMenu`].
+//!
+//! Every open level of the menu is an override-redirect window painted in
+//! software with the bundled bitmap font, so entries show in capitals. The
+//! pointer and keyboard are grabbed while the menu is up: the arrow keys move
+//! through entries and submenus, Return chooses, and Escape or a click
+//! outside the menu closes it.
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+use image::{Rgba, RgbaImage};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    AtomEnum, ConnectionExt as _, CreateGCAux, CreateWindowAux, EventMask, Gcontext, GrabMode, GrabStatus,
+    ImageFormat, ImageOrder, Keycode, Keysym, PropMode, Screen, Window, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{CURRENT_TIME, NONE};
+use crate::error::{Result, TrayError};
+use crate::icon::font::{self, Glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::linux_impl::{encode_argb, Atoms};
+use super::{PopupMenu, PopupMenuItem, Rectangle};
+
+/// Font pixels per glyph pixel
+const SCALE: u32 = 2;
+const ADVANCE: u32 = (GLYPH_WIDTH + 1) * SCALE;
+const ITEM_HEIGHT: u32 = GLYPH_HEIGHT * SCALE + 10;
+const SEPARATOR_HEIGHT: u32 = 9;
+/// Space between the text and the submenu arrow
+const PADDING: u32 = 8;
+/// Columns for check marks on the left and submenu arrows on the right
+const GUTTER: u32 = 20;
+const BORDER: u32 = 1;
+const MIN_WIDTH: u32 = 120;
+
+const BACKGROUND: Rgba<u8> = Rgba([0xf5, 0xf5, 0xf5, 0xff]);
+const TEXT: Rgba<u8> = Rgba([0x20, 0x20, 0x20, 0xff]);
+const DISABLED: Rgba<u8> = Rgba([0xa0, 0xa0, 0xa0, 0xff]);
+const HIGHLIGHT: Rgba<u8> = Rgba([0x35, 0x84, 0xe4, 0xff]);
+const HIGHLIGHT_TEXT: Rgba<u8> = Rgba([0xff, 0xff, 0xff, 0xff]);
+const SEPARATOR: Rgba<u8> = Rgba([0xd0, 0xd0, 0xd0, 0xff]);
+const FRAME: Rgba<u8> = Rgba([0x80, 0x80, 0x80, 0xff]);
+
+const CHECK: Glyph = [0x00, 0x01, 0x02, 0x14, 0x08, 0x00, 0x00];
+const ARROW: Glyph = [0x08, 0x0C, 0x0E, 0x0F, 0x0E, 0x0C, 0x08];
+
+/// The tray may still hold the grab from the click that opened the menu.
+/// Retrying blocks the calling thread for at most half a second; events that
+/// arrive meanwhile stay queued on the connection.
+const GRAB_ATTEMPTS: u32 = 50;
+const GRAB_RETRY: Duration = Duration::from_millis(10);
+
+const XK_LEFT: Keysym = 0xff51;
+const XK_UP: Keysym = 0xff52;
+const XK_RIGHT: Keysym = 0xff53;
+const XK_DOWN: Keysym = 0xff54;
+const XK_RETURN: Keysym = 0xff0d;
+const XK_KP_ENTER: Keysym = 0xff8d;
+const XK_ESCAPE: Keysym = 0xff1b;
+
+/// Vertical extent of one entry, in window coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Row {
+    pub top: u32,
+    pub height: u32,
+}
+
+/// Size of a menu window and where its entries are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub rows: Vec<Row>,
+}
+
+impl Layout {
+    pub fn of(menu: &PopupMenu) -> Self {
+        let mut rows = Vec::with_capacity(menu.items().len());
+        let mut text_width = 0;
+        let mut top = BORDER;
+        for item in menu.items() {
+            let height = match item {
+                PopupMenuItem::Separator => SEPARATOR_HEIGHT,
+                PopupMenuItem::Item { text, .. } | PopupMenuItem::SubMenu { text, .. } => {
+                    text_width = text_width.max(measure(text));
+                    ITEM_HEIGHT
+                }
+            };
+            rows.push(Row { top, height });
+            top += height;
+        }
+
+        Self {
+            width: (2 * BORDER + 2 * GUTTER + PADDING + text_width).max(MIN_WIDTH),
+            height: top + BORDER,
+            rows,
+        }
+    }
+
+    /// Entry at `y` pixels from the top of the window
+    pub fn row_at(&self, y: i32) -> Option<usize> {
+        let y = u32::try_from(y).ok()?;
+        self.rows.iter().position(|row| (row.top..row.top + row.height).contains(&y))
+    }
+}
+
+fn measure(text: &str) -> u32 {
+    (text.chars().count() as u32 * ADVANCE).saturating_sub(SCALE)
+}
+
+/// Paint `menu` with entry `selected` highlighted
+pub(super) fn render(menu: &PopupMenu, layout: &Layout, selected: Option<usize>) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(layout.width, layout.height, FRAME);
+    fill(&mut image, BORDER, BORDER, layout.width - 2 * BORDER, layout.height - 2 * BORDER, BACKGROUND);
+
+    for (index, (item, row)) in menu.items().iter().zip(&layout.rows).enumerate() {
+        if let PopupMenuItem::Separator = item {
+            let inset = BORDER + PADDING / 2;
+            fill(&mut image, inset, row.top + row.height / 2, layout.width - 2 * inset, 1, SEPARATOR);
+            continue;
+        }
+
+        let highlighted = selected == Some(index) && item.is_selectable();
+        let colour = if highlighted {
+            fill(&mut image, BORDER, row.top, layout.width - 2 * BORDER, row.height, HIGHLIGHT);
+            HIGHLIGHT_TEXT
+        } else if item.is_selectable() {
+            TEXT
+        } else {
+            DISABLED
+        };
+
+        let y = row.top + (row.height - GLYPH_HEIGHT * SCALE) / 2;
+        let gutter_inset = (GUTTER - GLYPH_WIDTH * SCALE) / 2;
+        match item {
+            PopupMenuItem::Item { text, checked, .. } => {
+                if *checked {
+                    draw_glyph(&mut image, &CHECK, BORDER + gutter_inset, y, colour);
+                }
+                draw_text(&mut image, text, BORDER + GUTTER, y, colour);
+            }
+            PopupMenuItem::SubMenu { text, .. } => {
+                draw_text(&mut image, text, BORDER + GUTTER, y, colour);
+                draw_glyph(&mut image, &ARROW, layout.width - BORDER - GUTTER + gutter_inset, y, colour);
+            }
+            PopupMenuItem::Separator => unreachable!(),
+        }
+    }
+    image
+}
+
+fn fill(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, colour: Rgba<u8>) {
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, colour);
+        }
+    }
+}
+
+fn draw_text(image: &mut RgbaImage, text: &str, x: u32, y: u32, colour: Rgba<u8>) {
+    for (index, c) in text.chars().enumerate() {
+        draw_glyph(image, &font::glyph(c), x + index as u32 * ADVANCE, y, colour);
+    }
+}
+
+fn draw_glyph(image: &mut RgbaImage, glyph: &Glyph, x: u32, y: u32, colour: Rgba<u8>) {
+    for row in 0..GLYPH_HEIGHT {
+        for col in 0..GLYPH_WIDTH {
+            if font::is_set(glyph, col, row) {
+                fill(image, x + col * SCALE, y + row * SCALE, SCALE, SCALE, colour);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Escape,
+}
+
+impl Key {
+    fn from_keysym(keysym: Keysym) -> Option<Self> {
+        Some(match keysym {
+            XK_UP => Key::Up,
+            XK_DOWN => Key::Down,
+            XK_LEFT => Key::Left,
+            XK_RIGHT => Key::Right,
+            XK_RETURN | XK_KP_ENTER => Key::Enter,
+            XK_ESCAPE => Key::Escape,
+            _ => return None,
+        })
+    }
+}
+
+/// What the menu does after some input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Outcome {
+    /// It stays up, possibly with other entries or submenus shown
+    Open,
+    /// An entry was chosen, by its result id
+    Chosen(i32),
+    Dismissed,
+}
+
+#[derive(Debug)]
+struct Level<'a> {
+    menu: &'a PopupMenu,
+    selected: Option<usize>,
+}
+
+/// Which submenus are open and which entry of each is selected
+///
+/// The root menu is level 0, each open submenu adds one.
+#[derive(Debug)]
+pub(super) struct Navigator<'a> {
+    levels: Vec<Level<'a>>,
+}
+
+impl<'a> Navigator<'a> {
+    pub fn new(menu: &'a PopupMenu) -> Self {
+        Self { levels: vec![Level { menu, selected: None }] }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn menu(&self, level: usize) -> &'a PopupMenu {
+        self.levels[level].menu
+    }
+
+    pub fn selected(&self, level: usize) -> Option<usize> {
+        self.levels[level].selected
+    }
+
+    pub fn key(&mut self, key: Key) -> Outcome {
+        let top = self.levels.len() - 1;
+        match key {
+            Key::Up | Key::Down => {
+                let level = &mut self.levels[top];
+                level.selected = step(level.menu, level.selected, key == Key::Down);
+                Outcome::Open
+            }
+            Key::Enter => match self.selected_item(top) {
+                Some(PopupMenuItem::Item { id, .. }) => Outcome::Chosen(*id),
+                _ => {
+                    self.open_selected();
+                    Outcome::Open
+                }
+            },
+            Key::Right => {
+                self.open_selected();
+                Outcome::Open
+            }
+            Key::Left | Key::Escape if top > 0 => {
+                self.levels.pop();
+                Outcome::Open
+            }
+            Key::Left => Outcome::Open,
+            Key::Escape => Outcome::Dismissed,
+        }
+    }
+
+    /// The pointer moved onto `row` of `level`, closing deeper submenus
+    pub fn hover(&mut self, level: usize, row: usize) {
+        self.levels.truncate(level + 1);
+        let menu = self.levels[level].menu;
+        let selected = Some(row).filter(|&row| menu.items()[row].is_selectable());
+        self.levels[level].selected = selected;
+        if let Some(PopupMenuItem::SubMenu { menu, .. }) = selected.map(|row| &menu.items()[row]) {
+            self.levels.push(Level { menu, selected: None });
+        }
+    }
+
+    pub fn press(&mut self, level: usize, row: usize) -> Outcome {
+        self.hover(level, row);
+        match &self.levels[level].menu.items()[row] {
+            PopupMenuItem::Item { id, enabled: true, .. } => Outcome::Chosen(*id),
+            _ => Outcome::Open,
+        }
+    }
+
+    fn selected_item(&self, level: usize) -> Option<&'a PopupMenuItem> {
+        let menu = self.levels[level].menu;
+        self.levels[level].selected.map(|row| &menu.items()[row])
+    }
+
+    /// Open the selected submenu of the deepest level, selecting its first entry
+    fn open_selected(&mut self) {
+        if let Some(PopupMenuItem::SubMenu { menu, .. }) = self.selected_item(self.levels.len() - 1) {
+            self.levels.push(Level { menu, selected: step(menu, None, true) });
+        }
+    }
+}
+
+/// Next selectable entry after `from`, wrapping around
+fn step(menu: &PopupMenu, from: Option<usize>, forward: bool) -> Option<usize> {
+    let count = menu.items().len();
+    if count == 0 {
+        return None;
+    }
+    let start = match (from, forward) {
+        (Some(from), _) => from,
+        (None, true) => count - 1,
+        (None, false) => 0,
+    };
+    (1..=count)
+        .map(|offset| if forward { (start + offset) % count } else { (start + count - offset) % count })
+        .find(|&index| menu.items()[index].is_selectable())
+        .or(from)
+}
+
+/// One open level on screen
+struct MenuWindow {
+    window: Window,
+    gc: Gcontext,
+    layout: Layout,
+    bounds: Rectangle,
+}
+
+impl MenuWindow {
+    /// Row `index` in root window coordinates
+    fn row_bounds(&self, index: usize) -> Rectangle {
+        let row = self.layout.rows[index];
+        Rectangle::new(self.bounds.get_x(), self.bounds.get_y() + row.top as i32, self.bounds.get_width(), row.height as i32)
+    }
+}
+
+struct Popup<'a> {
+    conn: &'a RustConnection,
+    screen: &'a Screen,
+    atoms: &'a Atoms,
+    anchor: Rectangle,
+    screen_bounds: Rectangle,
+    windows: Vec<MenuWindow>,
+}
+
+impl Popup<'_> {
+    /// Bring the windows in line with the levels of `navigator` and paint them
+    fn sync(&mut self, navigator: &Navigator) -> Result<()> {
+        while self.windows.len() > navigator.depth() {
+            let shown = self.windows.pop().expect("more windows than levels");
+            self.destroy(&shown)?;
+        }
+        while self.windows.len() < navigator.depth() {
+            let level = self.windows.len();
+            let layout = Layout::of(navigator.menu(level));
+            let (width, height) = (layout.width as i32, layout.height as i32);
+            let bounds = match self.windows.last() {
+                Some(parent) => {
+                    let row = navigator.selected(level - 1).expect("a submenu is opened from its entry");
+                    parent.row_bounds(row).place_beside(width, height, &self.screen_bounds)
+                }
+                None => self.anchor.place_popup(width, height, &self.screen_bounds),
+            };
+            let shown = self.create(layout, bounds)?;
+            self.windows.push(shown);
+        }
+        for (level, shown) in self.windows.iter().enumerate() {
+            self.paint(shown, navigator.menu(level), navigator.selected(level))?;
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn create(&self, layout: Layout, bounds: Rectangle) -> Result<MenuWindow> {
+        let window = self.conn.generate_id()?;
+        let events = EventMask::EXPOSURE
+            | EventMask::BUTTON_PRESS
+            | EventMask::BUTTON_RELEASE
+            | EventMask::POINTER_MOTION
+            | EventMask::KEY_PRESS;
+        self.conn.create_window(
+            self.screen.root_depth,
+            window,
+            self.screen.root,
+            bounds.get_x() as i16,
+            bounds.get_y() as i16,
+            layout.width as u16,
+            layout.height as u16,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            self.screen.root_visual,
+            &CreateWindowAux::new()
+                .background_pixel(self.screen.white_pixel)
+                .override_redirect(1)
+                .save_under(1)
+                .event_mask(events),
+        )?;
+        self.conn.change_property32(
+            PropMode::REPLACE,
+            window,
+            self.atoms._NET_WM_WINDOW_TYPE,
+            AtomEnum::ATOM,
+            &[self.atoms._NET_WM_WINDOW_TYPE_POPUP_MENU],
+        )?;
+        let gc = self.conn.generate_id()?;
+        self.conn.create_gc(gc, window, &CreateGCAux::new())?;
+        self.conn.map_window(window)?;
+        Ok(MenuWindow { window, gc, layout, bounds })
+    }
+
+    fn paint(&self, shown: &MenuWindow, menu: &PopupMenu, selected: Option<usize>) -> Result<()> {
+        let setup = self.conn.setup();
+        let depth = self.screen.root_depth;
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == depth)
+            .map(|format| format.bits_per_pixel);
+        if bits_per_pixel != Some(32) {
+            return Err(TrayError::X11Error(format!(
+                "Cannot paint a menu at depth {} and {:?} bits per pixel",
+                depth, bits_per_pixel
+            )));
+        }
+
+        // Menus are opaque, which the root visual shows without an alpha channel
+        let image = render(menu, &shown.layout, selected);
+        let data = encode_argb(&image, setup.image_byte_order == ImageOrder::LSB_FIRST);
+        self.conn.put_image(
+            ImageFormat::Z_PIXMAP,
+            shown.window,
+            shown.gc,
+            shown.layout.width as u16,
+            shown.layout.height as u16,
+            0,
+            0,
+            0,
+            depth,
+            &data,
+        )?;
+        Ok(())
+    }
+
+    fn destroy(&self, shown: &MenuWindow) -> Result<()> {
+        self.conn.free_gc(shown.gc)?;
+        self.conn.destroy_window(shown.window)?;
+        Ok(())
+    }
+
+    /// Menu level shown in `window`, if it is one of ours
+    fn level_of(&self, window: Window) -> Option<usize> {
+        self.windows.iter().position(|shown| shown.window == window)
+    }
+
+    /// Deepest level and row under the pointer, in root coordinates
+    fn hit(&self, x: i16, y: i16) -> Option<(usize, Option<usize>)> {
+        let (x, y) = (x as i32, y as i32);
+        self.windows
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, shown)| shown.bounds.contains(x, y))
+            .map(|(level, shown)| (level, shown.layout.row_at(y - shown.bounds.get_y())))
+    }
+
+    fn grab(&self, window: Window) -> Result<()> {
+        let pointer_events = EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE | EventMask::POINTER_MOTION;
+        for _ in 0..GRAB_ATTEMPTS {
+            let pointer = self
+                .conn
+                .grab_pointer(true, window, pointer_events, GrabMode::ASYNC, GrabMode::ASYNC, NONE, NONE, CURRENT_TIME)?
+                .reply()?
+                .status;
+            if pointer == GrabStatus::SUCCESS {
+                let keyboard = self
+                    .conn
+                    .grab_keyboard(true, window, CURRENT_TIME, GrabMode::ASYNC, GrabMode::ASYNC)?
+                    .reply()?
+                    .status;
+                if keyboard == GrabStatus::SUCCESS {
+                    return Ok(());
+                }
+                self.conn.ungrab_pointer(CURRENT_TIME)?;
+            }
+            thread::sleep(GRAB_RETRY);
+        }
+        Err(TrayError::X11Error("Could not grab the pointer and keyboard for the menu".to_string()))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.conn.ungrab_pointer(CURRENT_TIME)?;
+        self.conn.ungrab_keyboard(CURRENT_TIME)?;
+        for shown in std::mem::take(&mut self.windows) {
+            self.destroy(&shown)?;
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+/// First keysym of every keycode, to map key presses without Xkb
+struct Keymap {
+    min_keycode: Keycode,
+    per_keycode: usize,
+    keysyms: Vec<Keysym>,
+}
+
+impl Keymap {
+    fn load(conn: &RustConnection) -> Result<Self> {
+        let setup = conn.setup();
+        let (min_keycode, max_keycode) = (setup.min_keycode, setup.max_keycode);
+        let reply = conn.get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?.reply()?;
+        Ok(Self {
+            min_keycode,
+            per_keycode: reply.keysyms_per_keycode as usize,
+            keysyms: reply.keysyms,
+        })
+    }
+
+    fn keysym(&self, keycode: Keycode) -> Option<Keysym> {
+        let index = (keycode.checked_sub(self.min_keycode)? as usize) * self.per_keycode;
+        self.keysyms.get(index).copied()
+    }
+}
+
+/// Show `menu` next to `anchor` until an entry is chosen or the menu is dismissed
+///
+/// Blocks while the menu is up, plus up to half a second before it takes
+/// input if another client still holds the pointer. Events that are not for
+/// the menu, exposures of other windows included, are pushed to `deferred`
+/// for the caller to handle afterwards.
+pub(super) fn run(
+    conn: &RustConnection,
+    screen: &Screen,
+    atoms: &Atoms,
+    menu: &PopupMenu,
+    anchor: Rectangle,
+    deferred: &mut VecDeque<Event>,
+) -> Result<Option<i32>> {
+    if menu.is_empty() {
+        return Ok(None);
+    }
+    let keymap = Keymap::load(conn)?;
+    let mut navigator = Navigator::new(menu);
+    let screen_bounds = Rectangle::new(0, 0, screen.width_in_pixels as i32, screen.height_in_pixels as i32);
+    let mut popup = Popup { conn, screen, atoms, anchor, screen_bounds, windows: Vec::new() };
+    popup.sync(&navigator)?;
+
+    let result = popup.grab(popup.windows[0].window).and_then(|()| {
+        let mut hovered = None;
+        loop {
+            let event = conn.wait_for_event()?;
+            if let Event::Expose(e) = &event {
+                if let Some(level) = popup.level_of(e.window) {
+                    if e.count == 0 {
+                        popup.paint(&popup.windows[level], navigator.menu(level), navigator.selected(level))?;
+                        conn.flush()?;
+                    }
+                    continue;
+                }
+            }
+            let outcome = match event {
+                Event::MotionNotify(e) => {
+                    let Some((level, Some(row))) = popup.hit(e.root_x, e.root_y) else { continue };
+                    if hovered == Some((level, row)) {
+                        continue;
+                    }
+                    hovered = Some((level, row));
+                    navigator.hover(level, row);
+                    Outcome::Open
+                }
+                // The wheel neither chooses nor dismisses
+                Event::ButtonPress(e) if (4..=7).contains(&e.detail) => continue,
+                Event::ButtonPress(e) => match popup.hit(e.root_x, e.root_y) {
+                    Some((level, Some(row))) => navigator.press(level, row),
+                    Some((_, None)) => continue,
+                    None => Outcome::Dismissed,
+                },
+                Event::ButtonRelease(_) => continue,
+                Event::KeyPress(e) => match keymap.keysym(e.detail).and_then(Key::from_keysym) {
+                    Some(key) => navigator.key(key),
+                    None => continue,
+                },
+                // Sent by a MenuCloser from another thread
+                Event::ClientMessage(e) if e.type_ == atoms._TRAY_CLOSE_MENU => Outcome::Dismissed,
+                event => {
+                    deferred.push_back(event);
+                    continue;
+                }
+            };
+
+            match outcome {
+                Outcome::Open => popup.sync(&navigator)?,
+                Outcome::Chosen(id) => return Ok(Some(id)),
+                Outcome::Dismissed => return Ok(None),
+            }
+        }
+    });
+
+    popup.close()?;
+    result
+}
@@ -0,0 +1,188 @@
+//! Progress overlays composited on top of the tray icon.
+
+use image::{Rgba, RgbaImage};
+use std::time::{Duration, Instant};
+use crate::icon::blend;
+
+/// Samples per pixel along each axis, used for anti-aliasing
+const SUPERSAMPLE: u32 = 4;
+
+/// Shortest delay between two overlay updates pushed to the tray
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How progress is drawn over the icon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressStyle {
+    /// Arc running clockwise from twelve o'clock around the icon edge
+    Ring,
+    /// Horizontal bar along the bottom edge
+    Bar,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressColors {
+    pub fill: Rgba<u8>,
+    pub track: Rgba<u8>,
+}
+
+impl Default for ProgressColors {
+    fn default() -> Self {
+        Self {
+            fill: Rgba([46, 204, 113, 255]),
+            track: Rgba([0, 0, 0, 96]),
+        }
+    }
+}
+
+/// Draw `progress` (clamped to `0.0..=1.0`) over a copy of `base`
+pub fn composite(base: &RgbaImage, progress: f32, style: ProgressStyle, colors: ProgressColors) -> RgbaImage {
+    let progress = if progress.is_nan() { 0.0 } else { progress.clamp(0.0, 1.0) };
+    let mut img = base.clone();
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return img;
+    }
+
+    let samples = (SUPERSAMPLE * SUPERSAMPLE) as f32;
+
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let mut fill_hits = 0;
+        let mut track_hits = 0;
+
+        for sy in 0..SUPERSAMPLE {
+            for sx in 0..SUPERSAMPLE {
+                let u = (x as f32 + (sx as f32 + 0.5) / SUPERSAMPLE as f32) / width as f32;
+                let v = (y as f32 + (sy as f32 + 0.5) / SUPERSAMPLE as f32) / height as f32;
+
+                match sample(style, progress, u, v) {
+                    Sample::Fill => fill_hits += 1,
+                    Sample::Track => track_hits += 1,
+                    Sample::Outside => {}
+                }
+            }
+        }
+
+        blend(pixel, colors.track, track_hits as f32 / samples);
+        blend(pixel, colors.fill, fill_hits as f32 / samples);
+    }
+
+    img
+}
+
+enum Sample {
+    Fill,
+    Track,
+    Outside,
+}
+
+fn sample(style: ProgressStyle, progress: f32, u: f32, v: f32) -> Sample {
+    match style {
+        ProgressStyle::Ring => {
+            let (dx, dy) = (u - 0.5, v - 0.5);
+            let distance = (dx * dx + dy * dy).sqrt();
+            if !(0.36..=0.5).contains(&distance) {
+                return Sample::Outside;
+            }
+
+            // Angle measured clockwise from twelve o'clock, in turns
+            let turns = (dx.atan2(-dy) / std::f32::consts::TAU).rem_euclid(1.0);
+            if turns <= progress {
+                Sample::Fill
+            } else {
+                Sample::Track
+            }
+        }
+        ProgressStyle::Bar => {
+            if !(0.82..=0.98).contains(&v) || !(0.04..=0.96).contains(&u) {
+                return Sample::Outside;
+            }
+
+            if (u - 0.04) / 0.92 <= progress {
+                Sample::Fill
+            } else {
+                Sample::Track
+            }
+        }
+    }
+}
+
+/// Rate limiter so frequent progress reports do not flood the bus
+///
+/// Values are only released once `interval` has passed since the previous
+/// release and the visible percentage changed. Clearing and completion are
+/// always released straight away.
+#[derive(Debug)]
+pub struct ProgressThrottle {
+    interval: Duration,
+    last_sent: Option<Instant>,
+    sent: Option<u8>,
+    pending: Option<Option<f32>>,
+}
+
+impl ProgressThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: None,
+            sent: None,
+            pending: None,
+        }
+    }
+
+    /// Offer a new value, returning it if it should be displayed now
+    pub fn offer(&mut self, progress: Option<f32>, now: Instant) -> Option<Option<f32>> {
+        let percent = progress.map(to_percent);
+        if percent == self.sent {
+            self.pending = None;
+            return None;
+        }
+
+        let due = self
+            .last_sent
+            .map(|last| now.duration_since(last) >= self.interval)
+            .unwrap_or(true);
+
+        if due || progress.is_none() || percent == Some(100) {
+            self.mark_sent(percent, now);
+            Some(progress)
+        } else {
+            self.pending = Some(progress);
+            None
+        }
+    }
+
+    /// Release a held back value once the interval has passed
+    pub fn flush(&mut self, now: Instant) -> Option<Option<f32>> {
+        let due = self
+            .last_sent
+            .map(|last| now.duration_since(last) >= self.interval)
+            .unwrap_or(true);
+
+        if !due {
+            return None;
+        }
+
+        let progress = self.pending.take()?;
+        self.mark_sent(progress.map(to_percent), now);
+        Some(progress)
+    }
+
+    fn mark_sent(&mut self, percent: Option<u8>, now: Instant) {
+        self.sent = percent;
+        self.last_sent = Some(now);
+        self.pending = None;
+    }
+}
+
+impl Default for ProgressThrottle {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROGRESS_INTERVAL)
+    }
+}
+
+fn to_percent(progress: f32) -> u8 {
+    if progress.is_nan() {
+        return 0;
+    }
+    (progress.clamp(0.0, 1.0) * 100.0).round() as u8
+}
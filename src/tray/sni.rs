@@ -0,0 +1,472 @@
+//! Publishing our own icon as a StatusNotifierItem.
+//!
+//! [`SniItem`] exports `org.kde.StatusNotifierItem` under a KDE style bus
+//! name and registers it with the watcher. It keeps following the watcher's
+//! name afterwards: when the watcher (usually the panel) goes away the item
+//! reports [`SniEvent::WatcherLost`], and as soon as a watcher owns the name
+//! again it registers anew, announces every property and reports
+//! [`SniEvent::WatcherRestored`], so the icon survives a panel restart.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::{debug, info, warn};
+use zbus::zvariant::ObjectPath;
+use zbus::{dbus_interface, fdo, InterfaceRef, SignalContext};
+use crate::config::ItemStatus;
+use crate::error::Result;
+use crate::stray_impl::{IconPixmap, ItemCategory, ScrollOrientation, ToolTip, DEFAULT_ITEM_PATH, WATCHER_PATH};
+use crate::watcher::WatcherFlavor;
+
+/// `Menu` value of items without a dbusmenu
+const NO_MENU: &str = "/NO_DBUSMENU";
+
+/// Numbers the bus names of items published by this process
+static NEXT_ITEM: AtomicUsize = AtomicUsize::new(1);
+
+/// Everything a host can read from a published item
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemProperties {
+    pub id: String,
+    pub title: String,
+    pub category: ItemCategory,
+    pub status: ItemStatus,
+    pub icon_name: String,
+    pub icon_pixmaps: Vec<IconPixmap>,
+    pub overlay_icon_name: String,
+    pub overlay_icon_pixmaps: Vec<IconPixmap>,
+    pub attention_icon_name: String,
+    pub attention_icon_pixmaps: Vec<IconPixmap>,
+    pub tooltip: ToolTip,
+    /// Object path of a com.canonical.dbusmenu exported on the same connection
+    pub menu: Option<String>,
+    pub item_is_menu: bool,
+}
+
+/// What happened to a published item
+#[derive(Debug, Clone, PartialEq)]
+pub enum SniEvent {
+    /// The watcher left the bus; the item is not shown until it returns
+    WatcherLost,
+    /// A watcher is back and the item is registered with it again
+    WatcherRestored,
+    Activate { x: i32, y: i32 },
+    SecondaryActivate { x: i32, y: i32 },
+    ContextMenu { x: i32, y: i32 },
+    Scroll { delta: i32, orientation: ScrollOrientation },
+}
+
+type Pixmaps = Vec<(i32, i32, Vec<u8>)>;
+
+fn wire_pixmaps(pixmaps: &[IconPixmap]) -> Pixmaps {
+    pixmaps
+        .iter()
+        .map(|pixmap| (pixmap.width, pixmap.height, pixmap.pixels.clone()))
+        .collect()
+}
+
+struct ItemInterface {
+    properties: Arc<Mutex<ItemProperties>>,
+    events: broadcast::Sender<SniEvent>,
+}
+
+impl ItemInterface {
+    fn read<T>(&self, f: impl FnOnce(&ItemProperties) -> T) -> T {
+        f(&self.properties.lock().unwrap())
+    }
+
+    fn send(&self, event: SniEvent) {
+        // Nobody listening is fine, the item still answers the host
+        let _ = self.events.send(event);
+    }
+}
+
+#[dbus_interface(name = "org.kde.StatusNotifierItem")]
+impl ItemInterface {
+    fn activate(&self, x: i32, y: i32) {
+        self.send(SniEvent::Activate { x, y });
+    }
+
+    fn secondary_activate(&self, x: i32, y: i32) {
+        self.send(SniEvent::SecondaryActivate { x, y });
+    }
+
+    fn context_menu(&self, x: i32, y: i32) {
+        self.send(SniEvent::ContextMenu { x, y });
+    }
+
+    fn scroll(&self, delta: i32, orientation: &str) {
+        let orientation = orientation.parse().unwrap_or_default();
+        self.send(SniEvent::Scroll { delta, orientation });
+    }
+
+    #[dbus_interface(property)]
+    fn category(&self) -> String {
+        self.read(|props| props.category.as_dbus().to_string())
+    }
+
+    #[dbus_interface(property)]
+    fn id(&self) -> String {
+        self.read(|props| props.id.clone())
+    }
+
+    #[dbus_interface(property)]
+    fn title(&self) -> String {
+        self.read(|props| props.title.clone())
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        self.read(|props| props.status.as_dbus().to_string())
+    }
+
+    #[dbus_interface(property)]
+    fn window_id(&self) -> i32 {
+        0
+    }
+
+    #[dbus_interface(property)]
+    fn icon_theme_path(&self) -> String {
+        String::new()
+    }
+
+    #[dbus_interface(property)]
+    fn icon_name(&self) -> String {
+        self.read(|props| props.icon_name.clone())
+    }
+
+    #[dbus_interface(property)]
+    fn icon_pixmap(&self) -> Pixmaps {
+        self.read(|props| wire_pixmaps(&props.icon_pixmaps))
+    }
+
+    #[dbus_interface(property)]
+    fn overlay_icon_name(&self) -> String {
+        self.read(|props| props.overlay_icon_name.clone())
+    }
+
+    #[dbus_interface(property)]
+    fn overlay_icon_pixmap(&self) -> Pixmaps {
+        self.read(|props| wire_pixmaps(&props.overlay_icon_pixmaps))
+    }
+
+    #[dbus_interface(property)]
+    fn attention_icon_name(&self) -> String {
+        self.read(|props| props.attention_icon_name.clone())
+    }
+
+    #[dbus_interface(property)]
+    fn attention_icon_pixmap(&self) -> Pixmaps {
+        self.read(|props| wire_pixmaps(&props.attention_icon_pixmaps))
+    }
+
+    #[dbus_interface(property)]
+    fn attention_movie_name(&self) -> String {
+        String::new()
+    }
+
+    #[dbus_interface(property)]
+    fn tool_tip(&self) -> (String, Pixmaps, String, String) {
+        self.read(|props| {
+            let tooltip = &props.tooltip;
+            (
+                tooltip.icon_name.clone(),
+                wire_pixmaps(&tooltip.icon_pixmaps),
+                tooltip.title.clone(),
+                tooltip.description.clone(),
+            )
+        })
+    }
+
+    #[dbus_interface(property)]
+    fn item_is_menu(&self) -> bool {
+        self.read(|props| props.item_is_menu)
+    }
+
+    #[dbus_interface(property)]
+    fn menu(&self) -> ObjectPath<'static> {
+        let path = self.read(|props| props.menu.clone()).unwrap_or_else(|| NO_MENU.to_string());
+        ObjectPath::try_from(path).unwrap_or_else(|_| ObjectPath::from_static_str_unchecked(NO_MENU))
+    }
+
+    #[dbus_interface(signal)]
+    async fn new_title(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_attention_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_overlay_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_tool_tip(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_status(ctxt: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+}
+
+/// State shared by the item and the task following the watcher
+#[derive(Clone)]
+struct Published {
+    connection: zbus::Connection,
+    name: String,
+    properties: Arc<Mutex<ItemProperties>>,
+    events: broadcast::Sender<SniEvent>,
+    /// Watcher the item is registered with, if any
+    registered: Arc<Mutex<Option<WatcherFlavor>>>,
+}
+
+impl Published {
+    /// Register with the first watcher flavor that accepts the item
+    async fn register(&self) -> zbus::Result<()> {
+        let mut error = None;
+        for flavor in WatcherFlavor::ALL {
+            match self.register_with(flavor).await {
+                Ok(()) => {
+                    *self.registered.lock().unwrap() = Some(flavor);
+                    return Ok(());
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.expect("at least one watcher flavor"))
+    }
+
+    async fn register_with(&self, flavor: WatcherFlavor) -> zbus::Result<()> {
+        let watcher = flavor.name();
+        let proxy = zbus::Proxy::new(&self.connection, watcher, WATCHER_PATH, watcher).await?;
+        proxy.call_method("RegisterStatusNotifierItem", &(self.name.as_str(),)).await?;
+        Ok(())
+    }
+
+    /// Tell hosts to read every property again
+    async fn announce_all(&self) -> zbus::Result<()> {
+        let ctxt = SignalContext::new(&self.connection, DEFAULT_ITEM_PATH)?;
+        let status = self.properties.lock().unwrap().status;
+        ItemInterface::new_title(&ctxt).await?;
+        ItemInterface::new_icon(&ctxt).await?;
+        ItemInterface::new_attention_icon(&ctxt).await?;
+        ItemInterface::new_overlay_icon(&ctxt).await?;
+        ItemInterface::new_tool_tip(&ctxt).await?;
+        ItemInterface::new_status(&ctxt, status.as_dbus()).await
+    }
+
+    fn lost(&self) {
+        let was_registered = self.registered.lock().unwrap().take().is_some();
+        if was_registered {
+            warn!("StatusNotifierWatcher left the bus, {} is hidden until it returns", self.name);
+            let _ = self.events.send(SniEvent::WatcherLost);
+        }
+    }
+
+    async fn restore(&self) {
+        match self.register().await {
+            Ok(()) => {
+                if let Err(e) = self.announce_all().await {
+                    warn!("Could not announce properties of {}: {}", self.name, e);
+                }
+                info!("Registered {} with the new StatusNotifierWatcher", self.name);
+                let _ = self.events.send(SniEvent::WatcherRestored);
+            }
+            Err(e) => warn!("Could not register {} with the new watcher: {}", self.name, e),
+        }
+    }
+}
+
+/// Follow both watcher names and register again whenever one gets a new owner
+async fn follow_watcher(published: Published, mut owners: fdo::NameOwnerChangedStream<'static>) {
+    while let Some(change) = owners.next().await {
+        let Ok(args) = change.args() else { continue };
+        let Some(flavor) = WatcherFlavor::ALL
+            .into_iter()
+            .find(|flavor| flavor.name() == args.name().as_str())
+        else {
+            continue;
+        };
+
+        // A watcher replaced by another one shows up as a change of owner
+        if args.old_owner().is_some() && *published.registered.lock().unwrap() == Some(flavor) {
+            published.lost();
+        }
+        // One watcher usually owns both names, so register once for the pair
+        if args.new_owner().is_some() && published.registered.lock().unwrap().is_none() {
+            published.restore().await;
+        }
+    }
+}
+
+/// An item on the bus, removed by [`close`](Self::close)
+pub struct SniItem {
+    published: Published,
+    follower: JoinHandle<()>,
+}
+
+impl SniItem {
+    /// Publish on a new session bus connection
+    pub async fn publish(properties: ItemProperties) -> Result<Self> {
+        let connection = zbus::Connection::session().await?;
+        Self::publish_on(connection, properties).await
+    }
+
+    /// Publish on `connection`, which must not carry another item
+    ///
+    /// Succeeds without a watcher on the bus; the item then registers as
+    /// soon as one shows up.
+    pub async fn publish_on(connection: zbus::Connection, properties: ItemProperties) -> Result<Self> {
+        let (events, _) = broadcast::channel(64);
+        let properties = Arc::new(Mutex::new(properties));
+        let name = format!(
+            "org.kde.StatusNotifierItem-{}-{}",
+            std::process::id(),
+            NEXT_ITEM.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let item = ItemInterface {
+            properties: properties.clone(),
+            events: events.clone(),
+        };
+        connection.object_server().at(DEFAULT_ITEM_PATH, item).await?;
+        connection.request_name(name.as_str()).await?;
+
+        let published = Published {
+            connection: connection.clone(),
+            name,
+            properties,
+            events,
+            registered: Arc::new(Mutex::new(None)),
+        };
+
+        // Follow the watcher before registering, so a restart in between is not missed
+        let bus = fdo::DBusProxy::new(&connection).await?;
+        let owners = bus.receive_name_owner_changed().await?;
+        let follower = tokio::spawn(follow_watcher(published.clone(), owners));
+
+        match published.register().await {
+            Ok(()) => info!("Published {}", published.name),
+            Err(e) => debug!("No StatusNotifierWatcher yet for {}: {}", published.name, e),
+        }
+
+        Ok(Self { published, follower })
+    }
+
+    /// Bus name the item is published under
+    pub fn address(&self) -> &str {
+        &self.published.name
+    }
+
+    /// Whether a watcher currently lists the item
+    pub fn is_registered(&self) -> bool {
+        self.published.registered.lock().unwrap().is_some()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SniEvent> {
+        self.published.events.subscribe()
+    }
+
+    pub fn properties(&self) -> ItemProperties {
+        self.published.properties.lock().unwrap().clone()
+    }
+
+    pub async fn set_title(&self, title: &str) -> Result<()> {
+        self.published.properties.lock().unwrap().title = title.to_string();
+        ItemInterface::new_title(&self.signal_context()?).await?;
+        Ok(())
+    }
+
+    pub async fn set_status(&self, status: ItemStatus) -> Result<()> {
+        self.published.properties.lock().unwrap().status = status;
+        ItemInterface::new_status(&self.signal_context()?, status.as_dbus()).await?;
+        Ok(())
+    }
+
+    /// Replace the icon, by theme name, pixmaps or both
+    pub async fn set_icon(&self, icon_name: &str, icon_pixmaps: Vec<IconPixmap>) -> Result<()> {
+        {
+            let mut properties = self.published.properties.lock().unwrap();
+            properties.icon_name = icon_name.to_string();
+            properties.icon_pixmaps = icon_pixmaps;
+        }
+        ItemInterface::new_icon(&self.signal_context()?).await?;
+        Ok(())
+    }
+
+    pub async fn set_attention_icon(&self, icon_name: &str, icon_pixmaps: Vec<IconPixmap>) -> Result<()> {
+        {
+            let mut properties = self.published.properties.lock().unwrap();
+            properties.attention_icon_name = icon_name.to_string();
+            properties.attention_icon_pixmaps = icon_pixmaps;
+        }
+        ItemInterface::new_attention_icon(&self.signal_context()?).await?;
+        Ok(())
+    }
+
+    pub async fn set_overlay_icon(&self, icon_name: &str, icon_pixmaps: Vec<IconPixmap>) -> Result<()> {
+        {
+            let mut properties = self.published.properties.lock().unwrap();
+            properties.overlay_icon_name = icon_name.to_string();
+            properties.overlay_icon_pixmaps = icon_pixmaps;
+        }
+        ItemInterface::new_overlay_icon(&self.signal_context()?).await?;
+        Ok(())
+    }
+
+    pub async fn set_tooltip(&self, tooltip: ToolTip) -> Result<()> {
+        self.published.properties.lock().unwrap().tooltip = tooltip;
+        ItemInterface::new_tool_tip(&self.signal_context()?).await?;
+        Ok(())
+    }
+
+    // The specification has no signals for the properties below, so their
+    // changes go out as PropertiesChanged
+
+    pub async fn set_category(&self, category: ItemCategory) -> Result<()> {
+        self.published.properties.lock().unwrap().category = category;
+        let interface = self.interface().await?;
+        interface.get().await.category_changed(interface.signal_context()).await?;
+        Ok(())
+    }
+
+    /// Point the item at another dbusmenu path, or at none
+    pub async fn set_menu(&self, menu: Option<String>) -> Result<()> {
+        self.published.properties.lock().unwrap().menu = menu;
+        let interface = self.interface().await?;
+        interface.get().await.menu_changed(interface.signal_context()).await?;
+        Ok(())
+    }
+
+    pub async fn set_item_is_menu(&self, item_is_menu: bool) -> Result<()> {
+        self.published.properties.lock().unwrap().item_is_menu = item_is_menu;
+        let interface = self.interface().await?;
+        interface.get().await.item_is_menu_changed(interface.signal_context()).await?;
+        Ok(())
+    }
+
+    async fn interface(&self) -> Result<InterfaceRef<ItemInterface>> {
+        Ok(self.published.connection.object_server().interface(DEFAULT_ITEM_PATH).await?)
+    }
+
+    fn signal_context(&self) -> Result<SignalContext<'static>> {
+        Ok(SignalContext::new(&self.published.connection, DEFAULT_ITEM_PATH)?.into_owned())
+    }
+
+    /// Leave the tray: release the bus name, which the watcher takes as the item quitting
+    pub async fn close(self) -> Result<()> {
+        self.follower.abort();
+        let connection = &self.published.connection;
+        connection.release_name(self.published.name.as_str()).await?;
+        connection.object_server().remove::<ItemInterface, _>(DEFAULT_ITEM_PATH).await?;
+        info!("Closed {}", self.published.name);
+        Ok(())
+    }
+}
+
+impl Drop for SniItem {
+    fn drop(&mut self) {
+        self.follower.abort();
+    }
+}
@@ -0,0 +1,92 @@
+//! Named icon states resolved from the configuration.
+
+use std::time::{Duration, Instant};
+use image::Rgba;
+use crate::config::{IconState, ItemStatus};
+use crate::error::{Result, TrayError};
+use crate::icon::{self, IconGenerator, IconThemeResolver};
+use super::Image;
+
+/// Everything a state needs, loaded up front so switching cannot fail halfway
+pub(crate) struct ResolvedState {
+    pub icon: Option<Image>,
+    pub tooltip: Option<String>,
+    pub status: ItemStatus,
+    pub animation: Option<Animation>,
+}
+
+pub(crate) fn resolve(state: &IconState) -> Result<ResolvedState> {
+    let icon = match (&state.icon_path, &state.icon_name, &state.icon_text) {
+        (Some(path), _, _) => Some(Image::from_file(path)?),
+        (None, Some(name), _) => Some(Image::from_icon_name(&IconThemeResolver::system(), name)?),
+        (None, None, Some(text)) => {
+            let background = match &state.icon_color {
+                Some(colour) => icon::parse_color(colour)?,
+                None => Rgba([52, 152, 219, 255]),
+            };
+            Some(Image::from_generator(&IconGenerator::text(text).background(background)))
+        }
+        (None, None, None) => None,
+    };
+
+    let animation = match &state.animation {
+        Some(animation) => {
+            let frames = animation
+                .frames
+                .iter()
+                .map(|path| Image::from_file(path))
+                .collect::<Result<Vec<_>>>()?;
+            let interval = Duration::from_millis(animation.interval_ms.max(1));
+            let animation = Animation::new(frames, interval, Instant::now())
+                .ok_or_else(|| TrayError::ConfigError("an animation needs at least one frame".into()))?;
+            Some(animation)
+        }
+        None => None,
+    };
+
+    Ok(ResolvedState {
+        icon,
+        tooltip: state.tooltip.clone(),
+        status: state.status,
+        animation,
+    })
+}
+
+/// Looping sequence of icon frames, advanced from the event loop
+pub struct Animation {
+    frames: Vec<Image>,
+    interval: Duration,
+    current: usize,
+    last_switch: Instant,
+}
+
+impl Animation {
+    /// Returns `None` for an empty list of frames
+    pub fn new(frames: Vec<Image>, interval: Duration, now: Instant) -> Option<Self> {
+        if frames.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            frames,
+            interval,
+            current: 0,
+            last_switch: now,
+        })
+    }
+
+    pub fn frame(&self) -> &Image {
+        &self.frames[self.current]
+    }
+
+    /// Move to the next frame if it is due, returning whether it changed
+    pub fn advance(&mut self, now: Instant) -> bool {
+        if self.frames.len() < 2 || now.duration_since(self.last_switch) < self.interval {
+            return false;
+        }
+
+        self.current = (self.current + 1) % self.frames.len();
+        self.last_switch = now;
+        true
+    }
+}
@@ -0,0 +1,415 @@
+use super::*;
+use image::Rgba;
+use std::time::Duration;
+
+#[test]
+fn test_scaled_pixmap() {
+    let icon = Image::from_pixmaps(vec![
+        RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255])),
+        RgbaImage::from_pixel(48, 48, Rgba([0, 255, 0, 255])),
+    ]);
+
+    let exact = linux_impl::scaled_pixmap(&icon, 16).unwrap();
+    assert_eq!(exact.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+
+    // Scaled down from the larger pixmap, never up from the smaller one
+    let scaled = linux_impl::scaled_pixmap(&icon, 24).unwrap();
+    assert_eq!(scaled.dimensions(), (24, 24));
+    assert_eq!(scaled.get_pixel(12, 12), &Rgba([0, 255, 0, 255]));
+
+    assert_eq!(linux_impl::scaled_pixmap(&icon, 64).unwrap().dimensions(), (64, 64));
+    assert!(linux_impl::scaled_pixmap(&Image::new(), 24).is_none());
+}
+
+#[test]
+fn test_encode_argb_premultiplies() {
+    let image = RgbaImage::from_pixel(1, 1, Rgba([255, 128, 0, 128]));
+
+    assert_eq!(linux_impl::encode_argb(&image, true), vec![0, 64, 128, 128]);
+    assert_eq!(linux_impl::encode_argb(&image, false), vec![128, 128, 64, 0]);
+}
+
+#[test]
+fn test_encode_over_background() {
+    let image = RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 255, 0, 0, 0]).unwrap();
+    // Blue background in BGRX order
+    let background = [255, 0, 0, 0, 255, 0, 0, 0];
+
+    let data = linux_impl::encode_over(&image, &background, true);
+    assert_eq!(&data[..4], &[0, 0, 255, 255], "opaque pixels cover the panel");
+    assert_eq!(&data[4..], &[255, 0, 0, 255], "transparent pixels show the panel");
+
+    let half = RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 128]));
+    assert_eq!(linux_impl::encode_over(&half, &[0, 0, 0, 0], true), vec![128, 128, 128, 255]);
+}
+
+fn sample_menu() -> PopupMenu {
+    let mut more = PopupMenu::new();
+    more.add_item(10, "Logs", true, false);
+    more.add_item(11, "Debug", true, true);
+
+    let mut menu = PopupMenu::new();
+    menu.add_item(1, "Pause", true, true);
+    menu.add_item(2, "Resync", false, false);
+    menu.add_separator();
+    menu.add_sub_menu("More", more, true);
+    menu.add_item(3, "Quit", true, false);
+    menu
+}
+
+#[test]
+fn test_popup_menu_separators() {
+    let mut menu = PopupMenu::new();
+    menu.add_separator();
+    menu.add_item(1, "A", true, false);
+    menu.add_separator();
+    menu.add_separator();
+    assert_eq!(menu.items().len(), 2);
+
+    menu.add_sub_menu("Empty", PopupMenu::new(), true);
+    assert!(!menu.items()[2].is_selectable(), "empty submenus cannot be opened");
+}
+
+#[test]
+fn test_popup_layout() {
+    let menu = sample_menu();
+    let layout = popup::Layout::of(&menu);
+
+    let heights: Vec<u32> = layout.rows.iter().map(|row| row.height).collect();
+    assert_eq!(heights, vec![24, 24, 9, 24, 24]);
+    assert_eq!(layout.rows[0].top, 1);
+    assert_eq!(layout.height, 1 + 24 * 4 + 9 + 1);
+    assert_eq!(layout.width, 120, "short entries keep the minimum width");
+
+    assert_eq!(layout.row_at(0), None);
+    assert_eq!(layout.row_at(1), Some(0));
+    assert_eq!(layout.row_at(25), Some(1));
+    assert_eq!(layout.row_at(50), Some(2));
+    assert_eq!(layout.row_at(layout.height as i32 - 1), None);
+
+    let mut wide = PopupMenu::new();
+    wide.add_item(1, "Open the settings window", true, false);
+    assert_eq!(popup::Layout::of(&wide).width, 2 + 40 + 8 + 24 * 12 - 2);
+}
+
+#[test]
+fn test_popup_render() {
+    let menu = sample_menu();
+    let layout = popup::Layout::of(&menu);
+    let plain = popup::render(&menu, &layout, None);
+    assert_eq!(plain.dimensions(), (layout.width, layout.height));
+    assert_eq!(plain.get_pixel(0, 0), &Rgba([0x80, 0x80, 0x80, 0xff]), "frame");
+    assert_eq!(plain.get_pixel(2, 2), &Rgba([0xf5, 0xf5, 0xf5, 0xff]), "background");
+
+    // Separator line halfway down its row
+    let separator = layout.rows[2];
+    assert_eq!(plain.get_pixel(60, separator.top + 4), &Rgba([0xd0, 0xd0, 0xd0, 0xff]));
+
+    let highlighted = popup::render(&menu, &layout, Some(4));
+    let quit = layout.rows[4];
+    assert_eq!(highlighted.get_pixel(2, quit.top + 1), &Rgba([0x35, 0x84, 0xe4, 0xff]));
+    assert_eq!(plain.get_pixel(2, quit.top + 1), &Rgba([0xf5, 0xf5, 0xf5, 0xff]));
+
+    // Disabled entries are never highlighted
+    assert_eq!(popup::render(&menu, &layout, Some(1)), plain);
+
+    // Check mark in the left gutter of the first entry only
+    let gutter = |image: &RgbaImage, row: popup::Row| {
+        (row.top..row.top + row.height).any(|y| (1..21).any(|x| image.get_pixel(x, y).0 == [0x20, 0x20, 0x20, 0xff]))
+    };
+    assert!(gutter(&plain, layout.rows[0]));
+    assert!(!gutter(&plain, layout.rows[4]));
+}
+
+#[test]
+fn test_popup_keyboard_navigation() {
+    use popup::{Key, Navigator, Outcome};
+
+    let menu = sample_menu();
+    let mut navigator = Navigator::new(&menu);
+    assert_eq!(navigator.selected(0), None);
+
+    // Disabled entries and separators are skipped, and the selection wraps
+    navigator.key(Key::Down);
+    assert_eq!(navigator.selected(0), Some(0));
+    navigator.key(Key::Down);
+    assert_eq!(navigator.selected(0), Some(3));
+    navigator.key(Key::Down);
+    navigator.key(Key::Down);
+    assert_eq!(navigator.selected(0), Some(0));
+    navigator.key(Key::Up);
+    assert_eq!(navigator.selected(0), Some(4));
+    navigator.key(Key::Up);
+
+    assert_eq!(navigator.key(Key::Right), Outcome::Open);
+    assert_eq!(navigator.depth(), 2);
+    assert_eq!(navigator.selected(1), Some(0));
+    navigator.key(Key::Down);
+    assert_eq!(navigator.key(Key::Enter), Outcome::Chosen(11));
+
+    assert_eq!(navigator.key(Key::Left), Outcome::Open);
+    assert_eq!(navigator.depth(), 1);
+    navigator.key(Key::Enter);
+    assert_eq!(navigator.key(Key::Escape), Outcome::Open);
+    assert_eq!(navigator.depth(), 1);
+    assert_eq!(navigator.key(Key::Escape), Outcome::Dismissed);
+}
+
+#[test]
+fn test_popup_pointer_navigation() {
+    use popup::{Navigator, Outcome};
+
+    let menu = sample_menu();
+    let mut navigator = Navigator::new(&menu);
+
+    navigator.hover(0, 3);
+    assert_eq!(navigator.depth(), 2, "hovering a submenu opens it");
+    assert_eq!(navigator.selected(1), None);
+    navigator.hover(1, 0);
+    assert_eq!(navigator.selected(1), Some(0));
+
+    navigator.hover(0, 1);
+    assert_eq!(navigator.depth(), 1);
+    assert_eq!(navigator.selected(0), None, "disabled entries are not selected");
+
+    assert_eq!(navigator.press(0, 1), Outcome::Open);
+    assert_eq!(navigator.press(0, 2), Outcome::Open);
+    assert_eq!(navigator.press(0, 3), Outcome::Open);
+    assert_eq!(navigator.press(1, 0), Outcome::Chosen(10));
+    assert_eq!(navigator.press(0, 4), Outcome::Chosen(3));
+}
+
+const SCREEN: Rectangle = Rectangle::new(0, 0, 1920, 1080);
+
+#[test]
+fn test_nearest_edge() {
+    assert_eq!(Rectangle::new(1890, 0, 24, 24).nearest_edge(&SCREEN), ScreenEdge::Top);
+    assert_eq!(Rectangle::new(10, 1056, 24, 24).nearest_edge(&SCREEN), ScreenEdge::Bottom);
+    assert_eq!(Rectangle::new(0, 500, 24, 24).nearest_edge(&SCREEN), ScreenEdge::Left);
+    assert_eq!(Rectangle::new(1896, 500, 24, 24).nearest_edge(&SCREEN), ScreenEdge::Right);
+}
+
+#[test]
+fn test_place_popup_away_from_the_panel() {
+    // Below an icon in a top panel, pushed left off the screen edge
+    let icon = Rectangle::new(1890, 0, 24, 24);
+    assert_eq!(icon.place_popup(120, 200, &SCREEN), Rectangle::new(1800, 24, 120, 200));
+
+    // Above an icon in a bottom panel
+    let icon = Rectangle::new(10, 1056, 24, 24);
+    assert_eq!(icon.place_popup(120, 200, &SCREEN), Rectangle::new(10, 856, 120, 200));
+
+    // Beside icons in side panels, kept on screen vertically
+    let icon = Rectangle::new(0, 1000, 24, 24);
+    assert_eq!(icon.place_popup(120, 200, &SCREEN), Rectangle::new(24, 880, 120, 200));
+    let icon = Rectangle::new(1896, 10, 24, 24);
+    assert_eq!(icon.place_popup(120, 200, &SCREEN), Rectangle::new(1776, 10, 120, 200));
+}
+
+#[test]
+fn test_place_popup_on_small_screens() {
+    // Beside a side panel icon, moved up onto a short screen
+    let screen = Rectangle::new(0, 0, 800, 300);
+    let icon = Rectangle::new(0, 200, 24, 24);
+    assert_eq!(icon.nearest_edge(&screen), ScreenEdge::Left);
+    assert_eq!(icon.place_popup(120, 250, &screen), Rectangle::new(24, 50, 120, 250));
+
+    let icon = Rectangle::new(400, 150, 24, 24);
+    let top_half = Rectangle::new(0, 0, 800, 180);
+    assert_eq!(icon.nearest_edge(&top_half), ScreenEdge::Bottom);
+    assert_eq!(icon.place_popup(120, 100, &top_half), Rectangle::new(400, 50, 120, 100));
+    // No side has room, so it stays above and is pinned to the top
+    assert_eq!(icon.place_popup(120, 160, &top_half), Rectangle::new(400, 0, 120, 160));
+
+    // Screens need not start at the origin
+    let second = Rectangle::new(1920, 0, 1280, 1024);
+    let icon = Rectangle::new(3180, 0, 20, 20);
+    assert_eq!(icon.place_popup(120, 200, &second), Rectangle::new(3080, 20, 120, 200));
+}
+
+#[test]
+fn test_place_beside() {
+    let row = Rectangle::new(100, 500, 120, 24);
+    assert_eq!(row.place_beside(120, 100, &SCREEN), Rectangle::new(220, 500, 120, 100));
+
+    // Flipped left of a row at the right edge, and moved up off the bottom
+    let row = Rectangle::new(1800, 1000, 120, 24);
+    assert_eq!(row.place_beside(120, 100, &SCREEN), Rectangle::new(1680, 980, 120, 100));
+}
+
+#[test]
+fn test_rectangle_edges() {
+    let rectangle = Rectangle::new(10, 20, 30, 40);
+    assert_eq!((rectangle.get_right(), rectangle.get_bottom()), (40, 60));
+    assert!(rectangle.contains(10, 20));
+    assert!(!rectangle.contains(40, 20));
+    assert!(!rectangle.contains(10, 60));
+
+    let huge = Rectangle::new(100, 100, 4000, 30);
+    assert_eq!(huge.constrained_within(&SCREEN), Rectangle::new(0, 100, 4000, 30));
+}
+
+const FILL: Rgba<u8> = Rgba([255, 0, 0, 255]);
+const TRACK: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+fn overlay(progress: f32, style: ProgressStyle) -> RgbaImage {
+    let base = RgbaImage::from_pixel(32, 32, Rgba([255, 255, 255, 255]));
+    let colors = ProgressColors { fill: FILL, track: TRACK };
+    progress::composite(&base, progress, style, colors)
+}
+
+#[test]
+fn test_progress_overlay_ring() {
+    // (30, 16) sits on the ring at three o'clock, (16, 16) is the centre
+    let empty = overlay(0.0, ProgressStyle::Ring);
+    assert_eq!(empty.get_pixel(30, 16), &TRACK);
+    assert_eq!(empty.get_pixel(16, 16), &Rgba([255, 255, 255, 255]));
+
+    let full = overlay(1.0, ProgressStyle::Ring);
+    assert_eq!(full.get_pixel(30, 16), &FILL);
+    assert_eq!(full.get_pixel(1, 16), &FILL);
+    assert_eq!(full.get_pixel(16, 16), &Rgba([255, 255, 255, 255]));
+
+    // A third of the way round covers three o'clock but not nine
+    let third = overlay(1.0 / 3.0, ProgressStyle::Ring);
+    assert_eq!(third.get_pixel(30, 16), &FILL);
+    assert_eq!(third.get_pixel(1, 16), &TRACK);
+}
+
+#[test]
+fn test_progress_overlay_bar() {
+    let empty = overlay(0.0, ProgressStyle::Bar);
+    assert_eq!(empty.get_pixel(4, 29), &TRACK);
+    assert_eq!(empty.get_pixel(28, 29), &TRACK);
+    assert_eq!(empty.get_pixel(16, 8), &Rgba([255, 255, 255, 255]));
+
+    let full = overlay(1.0, ProgressStyle::Bar);
+    assert_eq!(full.get_pixel(4, 29), &FILL);
+    assert_eq!(full.get_pixel(28, 29), &FILL);
+
+    let half = overlay(0.5, ProgressStyle::Bar);
+    assert_eq!(half.get_pixel(4, 29), &FILL);
+    assert_eq!(half.get_pixel(28, 29), &TRACK);
+}
+
+#[test]
+fn test_progress_overlay_clamped() {
+    for style in [ProgressStyle::Ring, ProgressStyle::Bar] {
+        assert_eq!(overlay(-0.5, style), overlay(0.0, style));
+        assert_eq!(overlay(f32::NAN, style), overlay(0.0, style));
+        assert_eq!(overlay(1.5, style), overlay(1.0, style));
+    }
+
+    let empty = RgbaImage::new(0, 0);
+    let composited = progress::composite(&empty, 0.5, ProgressStyle::Ring, ProgressColors::default());
+    assert_eq!(composited.dimensions(), (0, 0));
+}
+
+#[test]
+fn test_progress_throttle() {
+    let interval = Duration::from_millis(250);
+    let start = Instant::now();
+    let mut throttle = ProgressThrottle::new(interval);
+
+    assert_eq!(throttle.offer(Some(0.1), start), Some(Some(0.1)));
+
+    // Same visible percentage is dropped, new ones are held back
+    assert_eq!(throttle.offer(Some(0.101), start + Duration::from_millis(10)), None);
+    assert_eq!(throttle.offer(Some(0.2), start + Duration::from_millis(50)), None);
+    assert_eq!(throttle.offer(Some(0.3), start + Duration::from_millis(100)), None);
+    assert_eq!(throttle.flush(start + Duration::from_millis(200)), None);
+
+    // The latest held back value goes out once the interval has passed
+    assert_eq!(throttle.flush(start + interval), Some(Some(0.3)));
+    assert_eq!(throttle.flush(start + interval * 2), None);
+
+    // Offers are released directly when due
+    assert_eq!(throttle.offer(Some(0.4), start + interval * 2), Some(Some(0.4)));
+}
+
+#[test]
+fn test_progress_throttle_releases_clear_and_completion() {
+    let start = Instant::now();
+    let mut throttle = ProgressThrottle::new(Duration::from_millis(250));
+
+    assert_eq!(throttle.offer(Some(0.5), start), Some(Some(0.5)));
+    assert_eq!(throttle.offer(Some(1.0), start + Duration::from_millis(1)), Some(Some(1.0)));
+    assert_eq!(throttle.offer(None, start + Duration::from_millis(2)), Some(None));
+    assert_eq!(throttle.offer(None, start + Duration::from_millis(3)), None);
+
+    // A held back value is dropped when the displayed one comes back
+    assert_eq!(throttle.offer(Some(0.2), start + Duration::from_millis(4)), None);
+    assert_eq!(throttle.offer(None, start + Duration::from_millis(5)), None);
+    assert_eq!(throttle.flush(start + Duration::from_secs(1)), None);
+}
+
+#[test]
+fn test_animation_frames() {
+    let start = Instant::now();
+    let interval = Duration::from_millis(100);
+    assert!(state::Animation::new(Vec::new(), interval, start).is_none());
+
+    let frames = vec![
+        Image::from_pixmaps(vec![RgbaImage::from_pixel(1, 1, FILL)]),
+        Image::from_pixmaps(vec![RgbaImage::from_pixel(1, 1, TRACK)]),
+    ];
+    let mut animation = state::Animation::new(frames, interval, start).unwrap();
+    assert!(!animation.advance(start + Duration::from_millis(50)));
+    assert!(animation.advance(start + interval));
+    assert_eq!(animation.frame().pixmaps()[0].get_pixel(0, 0), &TRACK);
+
+    // Loops back round to the first frame
+    assert!(animation.advance(start + interval * 2));
+    assert_eq!(animation.frame().pixmaps()[0].get_pixel(0, 0), &FILL);
+}
+
+/// A backend that draws menus itself and always chooses `result`
+struct MenuDrawer {
+    result: i32,
+    anchor: Arc<std::sync::Mutex<Option<Rectangle>>>,
+}
+
+impl TrayBackend for MenuDrawer {
+    fn kind(&self) -> crate::backend::BackendKind {
+        crate::backend::BackendKind::Xembed
+    }
+
+    fn set_icon(&mut self, _icon: &Image) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_tooltip(&mut self, _tooltip: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_status(&mut self, _status: ItemStatus) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Result<Option<TrayEvent>> {
+        Ok(None)
+    }
+
+    fn bounds(&self) -> Option<Rectangle> {
+        Some(Rectangle::new(600, 0, 24, 24))
+    }
+
+    fn show_menu(&mut self, _menu: &PopupMenu, anchor: Rectangle) -> Result<Option<i32>> {
+        *self.anchor.lock().unwrap() = Some(anchor);
+        Ok(Some(self.result))
+    }
+}
+
+#[tokio::test]
+async fn test_show_menu_reports_the_chosen_entry() {
+    let anchor = Arc::new(std::sync::Mutex::new(None));
+    let drawer = MenuDrawer { result: 3, anchor: anchor.clone() };
+    let component = SystemTrayIconComponent::with_backend(Box::new(drawer));
+    let mut icon = TrayIcon::with_component(AppConfig::default(), component).await.unwrap();
+
+    icon.show_menu();
+    assert_eq!(*anchor.lock().unwrap(), Some(Rectangle::new(600, 0, 24, 24)));
+    let Some(TrayEvent::MenuItem(result)) = icon.poll_event() else { panic!("no menu event") };
+    assert_eq!(icon.menu_action(result).await.unwrap(), Some(MenuAction::Quit));
+    assert_eq!(icon.poll_event(), None);
+}
@@ -0,0 +1,582 @@
+//! A StatusNotifierWatcher of our own.
+//!
+//! Desktops without a watcher (and tests on a private bus) can run this one
+//! instead of depending on whatever else happens to own the name. It answers
+//! under both the `org.kde` and the `org.freedesktop` names, keeps items and
+//! hosts in registration order and forgets them when their bus name goes
+//! away.
+//!
+//! A curating watcher ([`StatusNotifierWatcher::start_curated`]) advertises
+//! only the items of connections it was told to [`trust`](StatusNotifierWatcher::trust).
+//! Everything else is held back and reported in-process instead, so a proxy
+//! such as [`ProxyWatcher`] can read the real items and show hosts a
+//! curated set of its own.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::{debug, info, warn};
+use zbus::zvariant::Value;
+use zbus::{dbus_interface, fdo, MessageHeader};
+use crate::error::{Result, TrayError};
+use crate::stray_impl::{DEFAULT_ITEM_PATH, WATCHER_PATH};
+
+pub mod proxy;
+
+pub use proxy::ProxyWatcher;
+
+/// Version reported through the `ProtocolVersion` property
+pub const PROTOCOL_VERSION: i32 = 0;
+
+/// Bus name and interface a watcher can be reached under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatcherFlavor {
+    Kde,
+    Freedesktop,
+}
+
+impl WatcherFlavor {
+    pub const ALL: [WatcherFlavor; 2] = [WatcherFlavor::Kde, WatcherFlavor::Freedesktop];
+
+    /// Well-known name, which is also the interface name
+    pub fn name(&self) -> &'static str {
+        match self {
+            WatcherFlavor::Kde => "org.kde.StatusNotifierWatcher",
+            WatcherFlavor::Freedesktop => "org.freedesktop.StatusNotifierWatcher",
+        }
+    }
+}
+
+/// One registered item or host and the bus name whose loss removes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    service: String,
+    owner: String,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    items: Vec<Entry>,
+    hosts: Vec<Entry>,
+    /// Items a curating watcher holds back from hosts
+    upstream: Vec<Entry>,
+}
+
+/// An item registering with a curating watcher, or going away, as
+/// `bus_name/object/path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpstreamEvent {
+    Registered(String),
+    Unregistered(String),
+}
+
+/// Lists the items a curating watcher holds back, for tasks that cannot
+/// borrow the watcher
+#[derive(Clone)]
+pub struct UpstreamItems {
+    shared: Shared,
+}
+
+impl UpstreamItems {
+    /// See [`StatusNotifierWatcher::upstream_items`]
+    pub fn list(&self) -> Vec<String> {
+        self.shared.upstream_items()
+    }
+}
+
+/// Signals a watcher sends as items and hosts come and go
+#[derive(Debug, Clone, Copy)]
+enum WatcherSignal<'a> {
+    ItemRegistered(&'a str),
+    ItemUnregistered(&'a str),
+    HostRegistered,
+    HostUnregistered,
+}
+
+/// Turn the argument of `RegisterStatusNotifierItem` into the
+/// `bus_name/object/path` form listed in `RegisteredStatusNotifierItems`
+///
+/// KDE items pass their bus name, Ayatana items pass their object path and
+/// rely on the sender being used as the bus name.
+pub fn item_entry(service: &str, sender: &str) -> (String, String) {
+    if service.starts_with('/') {
+        (format!("{}{}", sender, service), sender.to_string())
+    } else {
+        match service.find('/') {
+            Some(index) => (service.to_string(), service[..index].to_string()),
+            None => (format!("{}{}", service, DEFAULT_ITEM_PATH), service.to_string()),
+        }
+    }
+}
+
+/// State shared by the exported interfaces and the cleanup task
+#[derive(Clone)]
+struct Shared {
+    connection: zbus::Connection,
+    flavors: Vec<WatcherFlavor>,
+    registry: Arc<Mutex<Registry>>,
+    /// Unique names whose items are advertised, `None` when not curating
+    trusted: Option<Arc<Mutex<HashSet<String>>>>,
+    upstream: broadcast::Sender<UpstreamEvent>,
+}
+
+impl Shared {
+    fn items(&self) -> Vec<String> {
+        self.registry.lock().unwrap().items.iter().map(|entry| entry.service.clone()).collect()
+    }
+
+    fn hosts(&self) -> Vec<String> {
+        self.registry.lock().unwrap().hosts.iter().map(|entry| entry.service.clone()).collect()
+    }
+
+    fn upstream_items(&self) -> Vec<String> {
+        self.registry.lock().unwrap().upstream.iter().map(|entry| entry.service.clone()).collect()
+    }
+
+    fn holds_back(&self, sender: &str) -> bool {
+        self.trusted.as_ref().is_some_and(|trusted| !trusted.lock().unwrap().contains(sender))
+    }
+
+    fn is_host_registered(&self) -> bool {
+        !self.registry.lock().unwrap().hosts.is_empty()
+    }
+
+    async fn register_item(&self, service: &str, sender: &str) -> fdo::Result<()> {
+        let (service, owner) = item_entry(service, sender);
+        self.check_owner(&owner).await?;
+
+        if self.holds_back(sender) {
+            let added = {
+                let mut registry = self.registry.lock().unwrap();
+                let known = registry.upstream.iter().any(|entry| entry.service == service);
+                if !known {
+                    registry.upstream.push(Entry { service: service.clone(), owner });
+                }
+                !known
+            };
+            if added {
+                info!("Holding back tray item {}", service);
+                let _ = self.upstream.send(UpstreamEvent::Registered(service));
+            }
+            return Ok(());
+        }
+
+        let added = {
+            let mut registry = self.registry.lock().unwrap();
+            let known = registry.items.iter().any(|entry| entry.service == service);
+            if !known {
+                registry.items.push(Entry { service: service.clone(), owner });
+            }
+            !known
+        };
+
+        if added {
+            info!("Registered tray item {}", service);
+            self.emit(WatcherSignal::ItemRegistered(&service)).await;
+            self.items_changed().await;
+        }
+        Ok(())
+    }
+
+    async fn register_host(&self, service: &str, sender: &str) -> fdo::Result<()> {
+        let owner = if service.is_empty() || service.starts_with('/') { sender } else { service };
+        self.check_owner(owner).await?;
+
+        let (added, first) = {
+            let mut registry = self.registry.lock().unwrap();
+            let first = registry.hosts.is_empty();
+            let known = registry.hosts.iter().any(|entry| entry.service == service);
+            if !known {
+                registry.hosts.push(Entry { service: service.to_string(), owner: owner.to_string() });
+            }
+            (!known, first && !known)
+        };
+
+        if added {
+            info!("Registered tray host {}", service);
+            self.emit(WatcherSignal::HostRegistered).await;
+        }
+        if first {
+            self.hosts_changed().await;
+        }
+        Ok(())
+    }
+
+    /// Refuse registrations for names nobody owns, they would never go away
+    async fn check_owner(&self, owner: &str) -> fdo::Result<()> {
+        let name = zbus::names::BusName::try_from(owner)
+            .map_err(|_| fdo::Error::InvalidArgs(format!("Invalid bus name {}", owner)))?;
+        let bus = fdo::DBusProxy::new(&self.connection).await?;
+        if bus.name_has_owner(name).await? {
+            Ok(())
+        } else {
+            Err(fdo::Error::InvalidArgs(format!("{} is not on the bus", owner)))
+        }
+    }
+
+    /// Drop everything `name` registered
+    async fn name_lost(&self, name: &str) {
+        let (items, hosts, had_hosts, upstream) = {
+            let mut registry = self.registry.lock().unwrap();
+            let (upstream, kept) = registry.upstream.drain(..).partition(|entry: &Entry| entry.owner == name);
+            registry.upstream = kept;
+            let had_hosts = !registry.hosts.is_empty();
+            let (items, kept) = registry.items.drain(..).partition(|entry: &Entry| entry.owner == name);
+            registry.items = kept;
+            let (hosts, kept) = registry.hosts.drain(..).partition(|entry: &Entry| entry.owner == name);
+            registry.hosts = kept;
+            (items, hosts, had_hosts && registry.hosts.is_empty(), upstream)
+        };
+
+        for entry in upstream {
+            info!("Held back tray item {} went away", entry.service);
+            let _ = self.upstream.send(UpstreamEvent::Unregistered(entry.service));
+        }
+        for entry in &items {
+            info!("Tray item {} went away", entry.service);
+            self.emit(WatcherSignal::ItemUnregistered(&entry.service)).await;
+        }
+        if !items.is_empty() {
+            self.items_changed().await;
+        }
+        for entry in &hosts {
+            info!("Tray host {} went away", entry.service);
+            self.emit(WatcherSignal::HostUnregistered).await;
+        }
+        if had_hosts {
+            self.hosts_changed().await;
+        }
+    }
+
+    /// Put advertised items in the order of `order`, others after them
+    async fn arrange(&self, order: &[String]) {
+        let changed = {
+            let mut registry = self.registry.lock().unwrap();
+            let before = registry.items.clone();
+            let slot = |entry: &Entry| order.iter().position(|service| *service == entry.service).unwrap_or(usize::MAX);
+            registry.items.sort_by_key(slot);
+            registry.items != before
+        };
+        if changed {
+            self.items_changed().await;
+        }
+    }
+
+    async fn items_changed(&self) {
+        let items = Value::from(self.items());
+        self.properties_changed("RegisteredStatusNotifierItems", &items).await;
+    }
+
+    async fn hosts_changed(&self) {
+        let registered = Value::from(self.is_host_registered());
+        self.properties_changed("IsStatusNotifierHostRegistered", &registered).await;
+    }
+
+    async fn properties_changed(&self, property: &str, value: &Value<'_>) {
+        let ctxt = match zbus::SignalContext::new(&self.connection, WATCHER_PATH) {
+            Ok(ctxt) => ctxt,
+            Err(e) => return warn!("Could not announce {}: {}", property, e),
+        };
+        let changed = HashMap::from([(property, value)]);
+        for flavor in &self.flavors {
+            let interface = zbus::names::InterfaceName::from_static_str_unchecked(flavor.name());
+            if let Err(e) = fdo::Properties::properties_changed(&ctxt, interface, &changed, &[]).await {
+                warn!("Could not announce {} on {}: {}", property, flavor.name(), e);
+            }
+        }
+    }
+
+    /// Emit a watcher signal on every interface we serve
+    async fn emit(&self, signal: WatcherSignal<'_>) {
+        let ctxt = match zbus::SignalContext::new(&self.connection, WATCHER_PATH) {
+            Ok(ctxt) => ctxt,
+            Err(e) => return warn!("Could not emit {:?}: {}", signal, e),
+        };
+        for flavor in &self.flavors {
+            let result = match flavor {
+                WatcherFlavor::Kde => KdeWatcher::emit(&ctxt, signal).await,
+                WatcherFlavor::Freedesktop => FreedesktopWatcher::emit(&ctxt, signal).await,
+            };
+            if let Err(e) = result {
+                warn!("Could not emit {:?} on {}: {}", signal, flavor.name(), e);
+            }
+        }
+    }
+}
+
+fn sender(header: &MessageHeader<'_>) -> fdo::Result<String> {
+    header
+        .sender()
+        .ok()
+        .flatten()
+        .map(|sender| sender.to_string())
+        .ok_or_else(|| fdo::Error::InvalidArgs("Message has no sender".to_string()))
+}
+
+/// The same interface is exported under each name
+macro_rules! watcher_interface {
+    ($ty:ident, $name:literal) => {
+        struct $ty(Shared);
+
+        impl $ty {
+            async fn emit(ctxt: &zbus::SignalContext<'_>, signal: WatcherSignal<'_>) -> zbus::Result<()> {
+                match signal {
+                    WatcherSignal::ItemRegistered(service) => Self::status_notifier_item_registered(ctxt, service).await,
+                    WatcherSignal::ItemUnregistered(service) => {
+                        Self::status_notifier_item_unregistered(ctxt, service).await
+                    }
+                    WatcherSignal::HostRegistered => Self::status_notifier_host_registered(ctxt).await,
+                    WatcherSignal::HostUnregistered => Self::status_notifier_host_unregistered(ctxt).await,
+                }
+            }
+        }
+
+        #[dbus_interface(name = $name)]
+        impl $ty {
+            async fn register_status_notifier_item(
+                &self,
+                service: &str,
+                #[zbus(header)] header: MessageHeader<'_>,
+            ) -> fdo::Result<()> {
+                self.0.register_item(service, &sender(&header)?).await
+            }
+
+            async fn register_status_notifier_host(
+                &self,
+                service: &str,
+                #[zbus(header)] header: MessageHeader<'_>,
+            ) -> fdo::Result<()> {
+                self.0.register_host(service, &sender(&header)?).await
+            }
+
+            #[dbus_interface(property)]
+            fn registered_status_notifier_items(&self) -> Vec<String> {
+                self.0.items()
+            }
+
+            #[dbus_interface(property)]
+            fn is_status_notifier_host_registered(&self) -> bool {
+                self.0.is_host_registered()
+            }
+
+            #[dbus_interface(property)]
+            fn protocol_version(&self) -> i32 {
+                PROTOCOL_VERSION
+            }
+
+            #[dbus_interface(signal)]
+            async fn status_notifier_item_registered(
+                ctxt: &zbus::SignalContext<'_>,
+                service: &str,
+            ) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn status_notifier_item_unregistered(
+                ctxt: &zbus::SignalContext<'_>,
+                service: &str,
+            ) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn status_notifier_host_registered(ctxt: &zbus::SignalContext<'_>) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn status_notifier_host_unregistered(ctxt: &zbus::SignalContext<'_>) -> zbus::Result<()>;
+        }
+    };
+}
+
+watcher_interface!(KdeWatcher, "org.kde.StatusNotifierWatcher");
+watcher_interface!(FreedesktopWatcher, "org.freedesktop.StatusNotifierWatcher");
+
+/// A running watcher, serving until [`stop`](Self::stop) is called
+pub struct StatusNotifierWatcher {
+    shared: Shared,
+    cleanup: JoinHandle<()>,
+}
+
+impl StatusNotifierWatcher {
+    /// Serve on the session bus under both names
+    pub async fn start_session() -> Result<Self> {
+        let connection = zbus::Connection::session().await?;
+        Self::start(&connection).await
+    }
+
+    /// Serve on `connection` under both names
+    pub async fn start(connection: &zbus::Connection) -> Result<Self> {
+        Self::start_with(connection, &WatcherFlavor::ALL).await
+    }
+
+    /// Serve on `connection` under the given names only
+    ///
+    /// Fails if any of the names already has an owner, so an existing
+    /// watcher is never replaced.
+    pub async fn start_with(connection: &zbus::Connection, flavors: &[WatcherFlavor]) -> Result<Self> {
+        Self::serve(connection, flavors, None).await
+    }
+
+    /// Serve on `connection` under both names, holding back every item
+    /// whose connection is not [`trust`](Self::trust)ed
+    ///
+    /// Held back items are listed by [`upstream_items`](Self::upstream_items)
+    /// and reported to [`subscribe_upstream`](Self::subscribe_upstream)rs.
+    pub async fn start_curated(connection: &zbus::Connection) -> Result<Self> {
+        Self::serve(connection, &WatcherFlavor::ALL, Some(Default::default())).await
+    }
+
+    async fn serve(
+        connection: &zbus::Connection,
+        flavors: &[WatcherFlavor],
+        trusted: Option<Arc<Mutex<HashSet<String>>>>,
+    ) -> Result<Self> {
+        let shared = Shared {
+            connection: connection.clone(),
+            flavors: flavors.to_vec(),
+            registry: Default::default(),
+            trusted,
+            upstream: broadcast::channel(64).0,
+        };
+
+        // Subscribe before anyone can register, so no owner loss is missed
+        let bus = fdo::DBusProxy::new(connection).await?;
+        let owners = bus.receive_name_owner_changed().await?;
+        let cleanup = tokio::spawn(follow_owners(shared.clone(), owners));
+        let watcher = Self { shared, cleanup };
+
+        for (done, flavor) in flavors.iter().enumerate() {
+            let exported = {
+                let server = connection.object_server();
+                match flavor {
+                    WatcherFlavor::Kde => server.at(WATCHER_PATH, KdeWatcher(watcher.shared.clone())).await,
+                    WatcherFlavor::Freedesktop => {
+                        server.at(WATCHER_PATH, FreedesktopWatcher(watcher.shared.clone())).await
+                    }
+                }
+            };
+            if let Err(e) = exported {
+                unwind(connection, &flavors[..done], &[]).await;
+                return Err(e.into());
+            }
+        }
+
+        for (done, flavor) in flavors.iter().enumerate() {
+            match connection.request_name(flavor.name()).await {
+                Ok(()) => debug!("Serving {}", flavor.name()),
+                Err(e) => {
+                    let error = match e {
+                        zbus::Error::NameTaken => {
+                            TrayError::InitializationError(format!("{} already has an owner", flavor.name()))
+                        }
+                        e => e.into(),
+                    };
+                    unwind(connection, flavors, &flavors[..done]).await;
+                    return Err(error);
+                }
+            }
+        }
+
+        info!("StatusNotifierWatcher running");
+        Ok(watcher)
+    }
+
+    /// Registered items as `bus_name/object/path`, in registration order
+    pub fn items(&self) -> Vec<String> {
+        self.shared.items()
+    }
+
+    /// Registered host names, in registration order
+    pub fn hosts(&self) -> Vec<String> {
+        self.shared.hosts()
+    }
+
+    pub fn is_host_registered(&self) -> bool {
+        self.shared.is_host_registered()
+    }
+
+    /// Advertise items registered from the connection with this unique name
+    ///
+    /// Only matters to a curating watcher, which advertises every item
+    /// registered after this call.
+    pub fn trust(&self, unique_name: &str) {
+        if let Some(trusted) = &self.shared.trusted {
+            trusted.lock().unwrap().insert(unique_name.to_string());
+        }
+    }
+
+    /// Items held back from hosts as `bus_name/object/path`, in registration order
+    pub fn upstream_items(&self) -> Vec<String> {
+        self.shared.upstream_items()
+    }
+
+    /// Items held back from now on, and the ones going away
+    pub fn subscribe_upstream(&self) -> broadcast::Receiver<UpstreamEvent> {
+        self.shared.upstream.subscribe()
+    }
+
+    /// A cloneable lister of [`upstream_items`](Self::upstream_items), to
+    /// catch up after lagging behind [`subscribe_upstream`](Self::subscribe_upstream)
+    pub fn upstream(&self) -> UpstreamItems {
+        UpstreamItems { shared: self.shared.clone() }
+    }
+
+    /// List advertised items in the order of `order`, unlisted ones last
+    pub async fn arrange(&self, order: &[String]) {
+        self.shared.arrange(order).await;
+    }
+
+    /// Give up the names and stop answering
+    pub async fn stop(self) -> Result<()> {
+        self.cleanup.abort();
+        let connection = &self.shared.connection;
+        for flavor in &self.shared.flavors {
+            connection.release_name(flavor.name()).await?;
+            let server = connection.object_server();
+            match flavor {
+                WatcherFlavor::Kde => server.remove::<KdeWatcher, _>(WATCHER_PATH).await?,
+                WatcherFlavor::Freedesktop => server.remove::<FreedesktopWatcher, _>(WATCHER_PATH).await?,
+            };
+        }
+        info!("StatusNotifierWatcher stopped");
+        Ok(())
+    }
+}
+
+/// Undo a `serve` that failed halfway: release the names it got and remove
+/// the interfaces it exported, logging anything that fails in turn
+async fn unwind(connection: &zbus::Connection, exported: &[WatcherFlavor], named: &[WatcherFlavor]) {
+    for flavor in named {
+        if let Err(e) = connection.release_name(flavor.name()).await {
+            warn!("Could not release {}: {}", flavor.name(), e);
+        }
+    }
+
+    let server = connection.object_server();
+    for flavor in exported {
+        let removed = match flavor {
+            WatcherFlavor::Kde => server.remove::<KdeWatcher, _>(WATCHER_PATH).await,
+            WatcherFlavor::Freedesktop => server.remove::<FreedesktopWatcher, _>(WATCHER_PATH).await,
+        };
+        if let Err(e) = removed {
+            warn!("Could not remove the {} interface: {}", flavor.name(), e);
+        }
+    }
+}
+
+impl Drop for StatusNotifierWatcher {
+    fn drop(&mut self) {
+        self.cleanup.abort();
+    }
+}
+
+async fn follow_owners(shared: Shared, mut owners: fdo::NameOwnerChangedStream<'static>) {
+    while let Some(change) = owners.next().await {
+        let Ok(args) = change.args() else { continue };
+        if args.new_owner().is_none() {
+            shared.name_lost(args.name().as_str()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,566 @@
+//! A watcher between the real items and the panel.
+//!
+//! [`ProxyWatcher`] serves the watcher names in curating mode, so items
+//! register with it as usual but hosts are not told about them. A host of
+//! our own reads the held back items, the `[items]` config filters, orders,
+//! renames and re-icons them, and every item kept is published again as an
+//! [`SniItem`] that the watcher does advertise. Clicks, wheel turns and menu
+//! entries chosen on a copy go back to the real item.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{dbus_interface, SignalContext};
+use crate::config::{AppConfig, ItemOverride};
+use crate::error::{Result, TrayError};
+use crate::icon::IconThemeResolver;
+use crate::stray_impl::{
+    HostEvent, HostHandle, IconPixmap, MenuKind, MenuNode, NotifierInfo, RunningHost, StrayTrayApp, ToggleKind,
+    DEFAULT_ITEM_PATH,
+};
+use crate::tray::{ItemProperties, SniEvent, SniItem};
+use super::StatusNotifierWatcher;
+
+/// Object path of the menu exported next to each copy
+pub const MENU_PATH: &str = "/MenuBar";
+
+/// Size icons from an item's own theme directory are loaded at, since
+/// hosts only look names up in the system theme
+const THEME_ICON_SIZE: u32 = 48;
+
+/// A real item and the copy hosts are shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopiedItem {
+    /// Bus name of the real item
+    pub source: String,
+    /// Bus name of the copy
+    pub address: String,
+}
+
+/// A running proxy, serving until [`stop`](Self::stop) is called
+pub struct ProxyWatcher {
+    host: RunningHost,
+    copied: Arc<Mutex<Vec<CopiedItem>>>,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl ProxyWatcher {
+    /// Take the watcher names on the session bus and show hosts the items
+    /// `config.items` keeps
+    ///
+    /// Fails if a watcher runs already, so the proxy has to start before the
+    /// panel, or in place of the panel's own watcher.
+    pub async fn start(config: AppConfig) -> Result<Self> {
+        let connection = zbus::Connection::session().await?;
+        let watcher = StatusNotifierWatcher::start_curated(&connection).await?;
+        let host = StrayTrayApp::new(config.clone()).spawn_upstream(&watcher);
+
+        let copied = Arc::new(Mutex::new(Vec::new()));
+        // Subscribed before the first listing, so no change falls in between
+        let host_events = host.subscribe();
+        let curator = Curator {
+            watcher,
+            host: host.handle(),
+            overrides: config.items.overrides,
+            resolver: Arc::new(IconThemeResolver::system()),
+            icons: Mutex::new(HashMap::new()),
+            copies: HashMap::new(),
+            copied: copied.clone(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(curator.run(host_events, stopped));
+
+        info!("Proxying the StatusNotifierWatcher");
+        Ok(Self { host, copied, stop, task })
+    }
+
+    /// Items shown to hosts, in display order
+    pub fn items(&self) -> Vec<CopiedItem> {
+        self.copied.lock().unwrap().clone()
+    }
+
+    /// Handle on the host reading the real items
+    pub fn host(&self) -> HostHandle {
+        self.host.handle()
+    }
+
+    /// Withdraw every copy and give up the watcher names
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        let stopped = self
+            .task
+            .await
+            .map_err(|e| TrayError::EventError(format!("Proxy watcher failed: {}", e)))?;
+        self.host
+            .stop()
+            .await
+            .map_err(|e| TrayError::EventError(format!("Proxy watcher host failed: {}", e)))?;
+        stopped
+    }
+}
+
+/// The copy of one item
+struct Replica {
+    item: SniItem,
+    connection: zbus::Connection,
+    menu: Arc<Mutex<MenuState>>,
+    forwarder: JoinHandle<()>,
+}
+
+struct Curator {
+    watcher: StatusNotifierWatcher,
+    host: HostHandle,
+    overrides: Vec<ItemOverride>,
+    resolver: Arc<IconThemeResolver>,
+    /// Icons loaded for copies, by bus name of the real item
+    icons: Mutex<HashMap<String, LoadedIcons>>,
+    /// Copies by bus name of the real item
+    copies: HashMap<String, Replica>,
+    copied: Arc<Mutex<Vec<CopiedItem>>>,
+}
+
+impl Curator {
+    async fn run(mut self, mut host_events: broadcast::Receiver<HostEvent>, mut stopped: oneshot::Receiver<()>) -> Result<()> {
+        self.resync().await;
+        loop {
+            tokio::select! {
+                _ = &mut stopped => break,
+                event = host_events.recv() => match event {
+                    Ok(event) => self.follow(event).await,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("Missed {} host events, listing the items again", missed);
+                        self.resync().await;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+
+        let sources: Vec<String> = self.copies.keys().cloned().collect();
+        for source in sources {
+            self.withdraw(&source).await;
+        }
+        self.watcher.stop().await?;
+        info!("Proxy watcher stopped");
+        Ok(())
+    }
+
+    /// Copy exactly the items the host keeps now
+    async fn resync(&mut self) {
+        let items = self.host.items().await;
+        let gone: Vec<String> = self
+            .copies
+            .keys()
+            .filter(|source| !items.iter().any(|info| &info.address == *source))
+            .cloned()
+            .collect();
+        for source in gone {
+            self.withdraw(&source).await;
+        }
+        for info in items {
+            self.follow(HostEvent::Added(info)).await;
+        }
+    }
+
+    async fn follow(&mut self, event: HostEvent) {
+        match event {
+            HostEvent::Added(info) | HostEvent::Changed { info, .. } => {
+                if self.copies.contains_key(&info.address) {
+                    self.update(&info).await;
+                } else if let Err(e) = self.publish(&info).await {
+                    warn!("Could not copy {}: {}", info.display_name(), e);
+                }
+            }
+            HostEvent::Removed(info) => {
+                if self.withdraw(&info.address).await {
+                    info!("Withdrew the copy of {}", info.display_name());
+                }
+            }
+        }
+    }
+
+    async fn publish(&mut self, info: &NotifierInfo) -> Result<()> {
+        // Every copy needs a connection of its own, since they share a path
+        let connection = crate::bridge::connect(None).await?;
+        if let Some(name) = connection.unique_name() {
+            self.watcher.trust(name.as_str());
+        }
+
+        let menu = Arc::new(Mutex::new(MenuState { revision: 1, root: info.menu.clone() }));
+        let exported = CopiedMenu {
+            host: self.host.clone(),
+            source: info.address.clone(),
+            state: menu.clone(),
+        };
+        connection.object_server().at(MENU_PATH, exported).await?;
+
+        let item = SniItem::publish_on(connection.clone(), self.properties(info).await?).await?;
+        let forwarder = tokio::spawn(forward(self.host.clone(), info.address.clone(), item.subscribe()));
+        info!("Copied {} as {}", info.display_name(), item.address());
+
+        self.copies.insert(info.address.clone(), Replica { item, connection, menu, forwarder });
+        self.arrange().await;
+        Ok(())
+    }
+
+    /// Bring a copy in line with its item, announcing only what changed
+    async fn update(&self, info: &NotifierInfo) {
+        let Some(copy) = self.copies.get(&info.address) else { return };
+        let new = match self.properties(info).await {
+            Ok(properties) => properties,
+            Err(e) => {
+                warn!("Could not update the copy of {}: {}", info.display_name(), e);
+                return;
+            }
+        };
+        let (old, item) = (copy.item.properties(), &copy.item);
+
+        let result = async {
+            if old.title != new.title {
+                item.set_title(&new.title).await?;
+            }
+            if old.status != new.status {
+                item.set_status(new.status).await?;
+            }
+            if (&old.icon_name, &old.icon_pixmaps) != (&new.icon_name, &new.icon_pixmaps) {
+                item.set_icon(&new.icon_name, new.icon_pixmaps).await?;
+            }
+            if (&old.overlay_icon_name, &old.overlay_icon_pixmaps)
+                != (&new.overlay_icon_name, &new.overlay_icon_pixmaps)
+            {
+                item.set_overlay_icon(&new.overlay_icon_name, new.overlay_icon_pixmaps).await?;
+            }
+            if (&old.attention_icon_name, &old.attention_icon_pixmaps)
+                != (&new.attention_icon_name, &new.attention_icon_pixmaps)
+            {
+                item.set_attention_icon(&new.attention_icon_name, new.attention_icon_pixmaps).await?;
+            }
+            if old.tooltip != new.tooltip {
+                item.set_tooltip(new.tooltip).await?;
+            }
+            if old.category != new.category {
+                item.set_category(new.category).await?;
+            }
+            if old.menu != new.menu {
+                item.set_menu(new.menu).await?;
+            }
+            if old.item_is_menu != new.item_is_menu {
+                item.set_item_is_menu(new.item_is_menu).await?;
+            }
+            Result::Ok(())
+        };
+        if let Err(e) = result.await {
+            warn!("Could not update the copy of {}: {}", info.display_name(), e);
+        }
+
+        let changed = {
+            let mut menu = copy.menu.lock().unwrap();
+            let changed = menu.root != info.menu;
+            if changed {
+                menu.root = info.menu.clone();
+                menu.revision += 1;
+            }
+            changed.then_some(menu.revision)
+        };
+        if let Some(revision) = changed {
+            if let Err(e) = copy.menu_updated(revision).await {
+                warn!("Could not announce the menu of {}: {}", info.display_name(), e);
+            }
+        }
+    }
+
+    /// Close the copy of an item, `false` if there was none
+    async fn withdraw(&mut self, source: &str) -> bool {
+        let Some(copy) = self.copies.remove(source) else { return false };
+        self.copied.lock().unwrap().retain(|copied| copied.source != source);
+        self.icons.lock().unwrap().remove(source);
+        copy.forwarder.abort();
+        if let Err(e) = copy.item.close().await {
+            warn!("Could not close the copy of {}: {}", source, e);
+        }
+        true
+    }
+
+    /// List the copies in the host's display order, to the watcher and to us
+    async fn arrange(&self) {
+        let copied: Vec<CopiedItem> = self
+            .host
+            .items()
+            .await
+            .iter()
+            .filter_map(|info| {
+                let copy = self.copies.get(&info.address)?;
+                Some(CopiedItem { source: info.address.clone(), address: copy.item.address().to_string() })
+            })
+            .collect();
+        let order: Vec<String> =
+            copied.iter().map(|copied| format!("{}{}", copied.address, DEFAULT_ITEM_PATH)).collect();
+        *self.copied.lock().unwrap() = copied;
+        self.watcher.arrange(&order).await;
+    }
+
+    /// What a copy of `info` shows, with the first matching override applied
+    ///
+    /// Icons are read from disk on the blocking pool, the copy's loaded
+    /// icons are taken out of `icons` meanwhile.
+    async fn properties(&self, info: &NotifierInfo) -> Result<ItemProperties> {
+        let mut icons = self.icons.lock().unwrap().remove(&info.address).unwrap_or_default();
+        let item_override = self.overrides.iter().find(|o| o.matcher.matches(info)).cloned();
+        let (resolver, source) = (self.resolver.clone(), info.clone());
+        let (properties, icons) = tokio::task::spawn_blocking(move || {
+            let properties = load_properties(&source, item_override.as_ref(), &resolver, &mut icons);
+            (properties, icons)
+        })
+        .await
+        .map_err(|e| TrayError::EventError(format!("Loading icons failed: {}", e)))?;
+        self.icons.lock().unwrap().insert(info.address.clone(), icons);
+        Ok(properties)
+    }
+}
+
+/// [`copy_properties`], with icons found in the item's own theme directory
+/// turned into pixmaps, since hosts cannot see its IconThemePath
+fn load_properties(
+    info: &NotifierInfo,
+    item_override: Option<&ItemOverride>,
+    resolver: &IconThemeResolver,
+    icons: &mut LoadedIcons,
+) -> ItemProperties {
+    let mut properties = copy_properties(info, item_override, icons);
+    let theme_path = info.icon_theme_path.as_deref().filter(|path| !path.is_empty()).map(Path::new);
+    let Some(theme_path) = theme_path else { return properties };
+    for (name, pixmaps) in [
+        (&mut properties.icon_name, &mut properties.icon_pixmaps),
+        (&mut properties.overlay_icon_name, &mut properties.overlay_icon_pixmaps),
+        (&mut properties.attention_icon_name, &mut properties.attention_icon_pixmaps),
+    ] {
+        if name.is_empty() {
+            continue;
+        }
+        if let Some(pixmap) = icons.themed(resolver, theme_path, name) {
+            name.clear();
+            *pixmaps = vec![pixmap];
+        }
+    }
+    properties
+}
+
+impl Replica {
+    async fn menu_updated(&self, revision: u32) -> zbus::Result<()> {
+        let ctxt = SignalContext::new(&self.connection, MENU_PATH)?;
+        CopiedMenu::layout_updated(&ctxt, revision, 0).await
+    }
+}
+
+/// Send what is done to a copy on to the real item
+async fn forward(host: HostHandle, source: String, mut events: broadcast::Receiver<SniEvent>) {
+    loop {
+        let result = match events.recv().await {
+            Ok(SniEvent::Activate { x, y }) => host.activate(&source, x, y).await,
+            Ok(SniEvent::SecondaryActivate { x, y }) => host.secondary_activate(&source, x, y).await,
+            Ok(SniEvent::ContextMenu { x, y }) => host.context_menu(&source, x, y).await,
+            Ok(SniEvent::Scroll { delta, orientation }) => host.scroll(&source, delta, orientation).await,
+            Ok(SniEvent::WatcherLost | SniEvent::WatcherRestored) => Ok(()),
+            Err(broadcast::error::RecvError::Lagged(_)) => Ok(()),
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if let Err(e) = result {
+            warn!("Could not forward to {}: {}", source, e);
+        }
+    }
+}
+
+/// Icons read from disk for one copy
+///
+/// Items change often, a Changed event for every tooltip or status, so each
+/// file is read once and kept while the copy lives.
+#[derive(Debug, Default)]
+pub(super) struct LoadedIcons {
+    /// Override icon files by path, `None` if the file could not be loaded
+    files: HashMap<String, Option<IconPixmap>>,
+    /// Icons from the item's theme directory, `None` where hosts find the
+    /// name themselves or the directory does not have it
+    themed: HashMap<(PathBuf, String), Option<IconPixmap>>,
+}
+
+impl LoadedIcons {
+    fn file(&mut self, path: &str, info: &NotifierInfo) -> Option<IconPixmap> {
+        self.files
+            .entry(path.to_string())
+            .or_insert_with(|| match image::open(path) {
+                Ok(image) => Some(IconPixmap::from_image(&image.into_rgba8())),
+                Err(e) => {
+                    warn!("Could not load icon {} for {}: {}", path, info.display_name(), e);
+                    None
+                }
+            })
+            .clone()
+    }
+
+    fn themed(&mut self, resolver: &IconThemeResolver, theme_path: &Path, name: &str) -> Option<IconPixmap> {
+        self.themed
+            .entry((theme_path.to_path_buf(), name.to_string()))
+            .or_insert_with(|| {
+                if resolver.lookup_in(name, THEME_ICON_SIZE, 1, None).is_some() {
+                    return None;
+                }
+                let image = resolver.load(name, THEME_ICON_SIZE, 1, Some(theme_path)).ok()?;
+                Some(IconPixmap::from_image(&image))
+            })
+            .clone()
+    }
+}
+
+/// Properties of a copy of `info`, as changed by `item_override`
+///
+/// An icon override replaces the normal icon only, so items still show
+/// their attention icon when they need attention.
+pub(super) fn copy_properties(
+    info: &NotifierInfo,
+    item_override: Option<&ItemOverride>,
+    icons: &mut LoadedIcons,
+) -> ItemProperties {
+    let mut properties = ItemProperties {
+        id: info.id.clone(),
+        title: info.title.clone(),
+        category: info.category,
+        status: info.status,
+        icon_name: info.icon_name.clone().unwrap_or_default(),
+        icon_pixmaps: info.icon_pixmaps.clone(),
+        overlay_icon_name: info.overlay_icon_name.clone().unwrap_or_default(),
+        overlay_icon_pixmaps: info.overlay_icon_pixmaps.clone(),
+        attention_icon_name: info.attention_icon_name.clone().unwrap_or_default(),
+        attention_icon_pixmaps: info.attention_icon_pixmaps.clone(),
+        tooltip: info.tooltip.clone().unwrap_or_default(),
+        menu: (!info.menu_path.is_empty()).then(|| MENU_PATH.to_string()),
+        item_is_menu: info.item_is_menu,
+    };
+
+    let Some(item_override) = item_override else { return properties };
+    if let Some(title) = &item_override.title {
+        properties.title = title.clone();
+        if properties.tooltip.title == info.title {
+            properties.tooltip.title = title.clone();
+        }
+    }
+    match item_override.icon.as_deref() {
+        Some(path) if path.contains('/') => {
+            if let Some(pixmap) = icons.file(path, info) {
+                properties.icon_name.clear();
+                properties.icon_pixmaps = vec![pixmap];
+            }
+        }
+        Some(name) => {
+            properties.icon_name = name.to_string();
+            properties.icon_pixmaps.clear();
+        }
+        None => {}
+    }
+    properties
+}
+
+/// The menu a copy serves, a snapshot of the real item's
+struct MenuState {
+    revision: u32,
+    root: Option<MenuNode>,
+}
+
+/// `com.canonical.dbusmenu` of a copy, answered from the host's cached
+/// menu of the real item
+struct CopiedMenu {
+    host: HostHandle,
+    source: String,
+    state: Arc<Mutex<MenuState>>,
+}
+
+/// A dbusmenu entry on the wire: id, properties and children
+pub(super) type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+/// The `(ia{sv}av)` layout of `node` and everything below it
+pub(super) fn layout(node: &MenuNode) -> Layout {
+    let children = node.children.iter().map(|child| Value::from(layout(child)).into()).collect();
+    (node.id, entry_properties(node), children)
+}
+
+fn entry_properties(node: &MenuNode) -> HashMap<String, OwnedValue> {
+    let mut props: HashMap<String, OwnedValue> = HashMap::new();
+    let mut set = |key: &str, value: Value<'_>| {
+        props.insert(key.to_string(), value.into());
+    };
+
+    if node.kind == MenuKind::Separator {
+        set("type", Value::from("separator"));
+    } else if !node.label.is_empty() {
+        // Labels were read without their mnemonics, so underscores are literal
+        set("label", Value::from(node.label.replace('_', "__")));
+    }
+    set("enabled", Value::from(node.enabled));
+    set("visible", Value::from(node.visible));
+    if let Some(icon_name) = &node.icon_name {
+        set("icon-name", Value::from(icon_name.as_str()));
+    }
+    let toggle = match node.toggle {
+        ToggleKind::None => None,
+        ToggleKind::Checkmark => Some("checkmark"),
+        ToggleKind::Radio => Some("radio"),
+    };
+    if let Some(toggle) = toggle {
+        set("toggle-type", Value::from(toggle));
+        set("toggle-state", Value::from(node.toggle_state.map_or(-1, i32::from)));
+    }
+    if !node.children.is_empty() {
+        set("children-display", Value::from("submenu"));
+    }
+    props
+}
+
+#[dbus_interface(name = "com.canonical.dbusmenu")]
+impl CopiedMenu {
+    /// Always the whole subtree, which hosts are allowed to get
+    fn get_layout(&self, parent_id: i32, _recursion_depth: i32, _property_names: Vec<String>) -> (u32, Layout) {
+        let state = self.state.lock().unwrap();
+        let root = state.root.clone().unwrap_or_default();
+        let parent = root.find_id(parent_id).unwrap_or(&root);
+        (state.revision, layout(parent))
+    }
+
+    fn get_group_properties(&self, ids: Vec<i32>, _property_names: Vec<String>) -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        let state = self.state.lock().unwrap();
+        let Some(root) = &state.root else { return vec![] };
+        ids.iter()
+            .filter_map(|id| root.find_id(*id))
+            .map(|node| (node.id, entry_properties(node)))
+            .collect()
+    }
+
+    async fn event(&self, id: i32, event_id: String, _data: OwnedValue, _timestamp: u32) {
+        if event_id != "clicked" {
+            return;
+        }
+        if let Err(e) = self.host.click_menu_item(id, &self.source).await {
+            warn!("Could not forward menu click to {}: {}", self.source, e);
+        }
+    }
+
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        "normal".to_string()
+    }
+
+    #[dbus_interface(signal)]
+    async fn layout_updated(ctxt: &SignalContext<'_>, revision: u32, parent: i32) -> zbus::Result<()>;
+}
@@ -0,0 +1,114 @@
+use super::*;
+use super::proxy::{copy_properties, layout, LoadedIcons, MENU_PATH};
+use crate::config::ItemOverride;
+use crate::stray_impl::{IconPixmap, MenuKind, MenuNode, NotifierInfo, ToggleKind, ToolTip};
+
+#[test]
+fn test_item_entry() {
+    assert_eq!(
+        item_entry("org.kde.StatusNotifierItem-42-1", ":1.7"),
+        ("org.kde.StatusNotifierItem-42-1/StatusNotifierItem".to_string(), "org.kde.StatusNotifierItem-42-1".to_string())
+    );
+    assert_eq!(
+        item_entry("/org/ayatana/NotificationItem/nm", ":1.7"),
+        (":1.7/org/ayatana/NotificationItem/nm".to_string(), ":1.7".to_string())
+    );
+    assert_eq!(
+        item_entry(":1.9/StatusNotifierItem", ":1.7"),
+        (":1.9/StatusNotifierItem".to_string(), ":1.9".to_string())
+    );
+}
+
+#[test]
+fn test_flavor_names() {
+    assert_eq!(WatcherFlavor::Kde.name(), "org.kde.StatusNotifierWatcher");
+    assert_eq!(WatcherFlavor::Freedesktop.name(), "org.freedesktop.StatusNotifierWatcher");
+}
+
+fn network_item() -> NotifierInfo {
+    NotifierInfo {
+        address: ":1.52".into(),
+        id: "nm-applet".into(),
+        title: "nm-applet".into(),
+        icon_name: Some("network-idle".into()),
+        overlay_icon_name: Some("emblem-important".into()),
+        attention_icon_name: Some("network-error".into()),
+        tooltip: Some(ToolTip { title: "nm-applet".into(), description: "Connected".into(), ..Default::default() }),
+        menu_path: "/org/ayatana/NotificationItem/nm/Menu".into(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_copy_properties_without_override() {
+    let properties = copy_properties(&network_item(), None, &mut LoadedIcons::default());
+    assert_eq!(properties.id, "nm-applet");
+    assert_eq!(properties.title, "nm-applet");
+    assert_eq!(properties.icon_name, "network-idle");
+    assert_eq!(properties.overlay_icon_name, "emblem-important");
+    assert_eq!(properties.attention_icon_name, "network-error");
+    assert_eq!(properties.tooltip.description, "Connected");
+    assert_eq!(properties.menu.as_deref(), Some(MENU_PATH));
+
+    let plain = NotifierInfo { menu_path: String::new(), ..network_item() };
+    assert_eq!(copy_properties(&plain, None, &mut LoadedIcons::default()).menu, None);
+}
+
+#[test]
+fn test_copy_properties_with_override() {
+    let rename = ItemOverride {
+        title: Some("Network".into()),
+        icon: Some("network-wireless-symbolic".into()),
+        ..Default::default()
+    };
+    let info = NotifierInfo {
+        icon_pixmaps: vec![IconPixmap { width: 1, height: 1, pixels: vec![255, 0, 0, 0] }],
+        ..network_item()
+    };
+
+    let properties = copy_properties(&info, Some(&rename), &mut LoadedIcons::default());
+    assert_eq!(properties.title, "Network");
+    assert_eq!(properties.tooltip.title, "Network", "a tooltip repeating the title follows it");
+    assert_eq!(properties.icon_name, "network-wireless-symbolic");
+    assert!(properties.icon_pixmaps.is_empty());
+    assert_eq!(properties.attention_icon_name, "network-error");
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("steam.png");
+    image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255])).save(&path).unwrap();
+    let file = ItemOverride { icon: Some(path.display().to_string()), ..Default::default() };
+
+    let mut icons = LoadedIcons::default();
+    let properties = copy_properties(&info, Some(&file), &mut icons);
+    assert_eq!(properties.title, "nm-applet");
+    assert_eq!(properties.icon_name, "");
+    let red = vec![IconPixmap { width: 2, height: 2, pixels: [255, 255, 0, 0].repeat(4) }];
+    assert_eq!(properties.icon_pixmaps, red);
+    // The file is read once per copy, not on every change
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(copy_properties(&info, Some(&file), &mut icons).icon_pixmaps, red);
+
+    let missing = ItemOverride { icon: Some("/nonexistent/icon.png".into()), ..Default::default() };
+    assert_eq!(copy_properties(&info, Some(&missing), &mut LoadedIcons::default()).icon_pixmaps, info.icon_pixmaps);
+}
+
+#[test]
+fn test_layout_round_trips() {
+    let menu = MenuNode {
+        children: vec![
+            MenuNode { id: 1, label: "Wi_Fi".into(), toggle: ToggleKind::Checkmark, toggle_state: Some(true), ..Default::default() },
+            MenuNode { id: 2, kind: MenuKind::Separator, ..Default::default() },
+            MenuNode {
+                id: 3,
+                label: "Settings".into(),
+                children: vec![MenuNode { id: 4, label: "Advanced".into(), enabled: false, ..Default::default() }],
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    let (id, props, children) = layout(&menu);
+    let children = children.iter().filter_map(|child| MenuNode::from_value(child)).collect();
+    assert_eq!(MenuNode::from_parts(id, &props, children), menu);
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use common::{eventually, TestBus};
+use system_tray_linux_aio::backend::{BackendEvent, BackendKind, Detector};
+use system_tray_linux_aio::config::{AppConfig, BackendConfig};
+use system_tray_linux_aio::tray::{Rectangle, SystemTrayIconComponent, TrayEvent};
+use system_tray_linux_aio::TrayIcon;
+use system_tray_linux_aio::stray_impl::WATCHER_PATH;
+use system_tray_linux_aio::watcher::{StatusNotifierWatcher, WatcherFlavor};
+
+fn sni_first() -> AppConfig {
+    AppConfig {
+        backend: BackendConfig { prefer: vec![BackendKind::Sni, BackendKind::Headless] },
+        ..Default::default()
+    }
+}
+
+async fn register_host(bus: &TestBus, flavor: WatcherFlavor) -> zbus::Connection {
+    let host = bus.connect().await;
+    let name = "org.kde.StatusNotifierHost-test";
+    host.request_name(name).await.unwrap();
+    let watcher = flavor.name();
+    zbus::Proxy::new(&host, watcher, WATCHER_PATH, watcher)
+        .await
+        .unwrap()
+        .call_method("RegisterStatusNotifierHost", &(name,))
+        .await
+        .unwrap();
+    host
+}
+
+#[tokio::test]
+async fn test_sni_needs_watcher_and_host() {
+    let Some(bus) = TestBus::start() else { return };
+    let config = sni_first();
+    let detector = Detector::new(&config).connection(bus.connect().await);
+
+    let probe = detector.probe(BackendKind::Sni).await;
+    assert!(!probe.available);
+    assert!(probe.reason.starts_with("no org.kde.StatusNotifierWatcher"), "{}", probe.reason);
+
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let probe = detector.probe(BackendKind::Sni).await;
+    assert!(!probe.available);
+    assert!(probe.reason.contains("no host"), "{}", probe.reason);
+
+    let (backend, report) = detector.detect(&config).await.unwrap();
+    assert_eq!(backend.kind(), BackendKind::Headless);
+    assert_eq!(report.chosen, Some(BackendKind::Headless));
+
+    watcher.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sni_accepts_a_freedesktop_watcher() {
+    let Some(bus) = TestBus::start() else { return };
+    let freedesktop = [WatcherFlavor::Freedesktop];
+    let watcher = StatusNotifierWatcher::start_with(&bus.connect().await, &freedesktop).await.unwrap();
+    let _host = register_host(&bus, WatcherFlavor::Freedesktop).await;
+
+    let probe = Detector::new(&sni_first())
+        .connection(bus.connect().await)
+        .probe(BackendKind::Sni)
+        .await;
+    assert!(probe.available, "{}", probe.reason);
+    assert!(probe.reason.starts_with(WatcherFlavor::Freedesktop.name()), "{}", probe.reason);
+
+    watcher.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sni_backend_chosen_and_published() {
+    let Some(bus) = TestBus::start() else { return };
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let _host = register_host(&bus, WatcherFlavor::Kde).await;
+    let config = sni_first();
+
+    let (mut backend, report) = Detector::new(&config)
+        .connection(bus.connect().await)
+        .detect(&config)
+        .await
+        .unwrap();
+    assert_eq!(backend.kind(), BackendKind::Sni);
+    assert_eq!(report.chosen, Some(BackendKind::Sni));
+    assert_eq!(report.probes.len(), 1);
+    eventually(|| async { watcher.items().len() == 1 }).await.expect("item registered");
+
+    let entry = watcher.items().remove(0);
+    let (address, path) = entry.split_once('/').unwrap();
+    let client = bus.connect().await;
+    let item = zbus::Proxy::new(&client, address.to_string(), format!("/{}", path), "org.kde.StatusNotifierItem")
+        .await
+        .unwrap();
+
+    assert_eq!(backend.bounds(), None, "no click, no position");
+    item.call_method("Activate", &(3i32, 4i32)).await.unwrap();
+    let mut event = None;
+    eventually(|| {
+        event = event.take().or_else(|| backend.poll_event().unwrap());
+        let found = event.is_some();
+        async move { found }
+    })
+    .await
+    .expect("activation");
+    assert_eq!(event, Some(BackendEvent::Activate { x: 3, y: 4 }));
+    assert_eq!(backend.bounds(), Some(Rectangle::new(3, 4, 0, 0)));
+
+    // Setters are queued and reach the bus in order
+    backend.set_tooltip("Busy").unwrap();
+    backend.set_tooltip("Syncing").unwrap();
+    type ToolTip = (String, Vec<(i32, i32, Vec<u8>)>, String, String);
+    eventually(|| async {
+        let tooltip: ToolTip = item.get_property("ToolTip").await.unwrap();
+        tooltip.2 == "Syncing"
+    })
+    .await
+    .expect("tooltip updated");
+
+    watcher.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tray_icon_bounds_come_from_the_backend() {
+    let Some(bus) = TestBus::start() else { return };
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let _host = register_host(&bus, WatcherFlavor::Kde).await;
+    let config = sni_first();
+
+    let (backend, _) = Detector::new(&config)
+        .connection(bus.connect().await)
+        .detect(&config)
+        .await
+        .unwrap();
+    let component = SystemTrayIconComponent::with_backend(backend);
+    let mut icon = TrayIcon::with_component(config, component).await.unwrap();
+    eventually(|| async { watcher.items().len() == 1 }).await.expect("item registered");
+    assert_eq!(icon.get_bounds(), Rectangle::new(0, 0, 32, 32), "no click, no position");
+
+    let entry = watcher.items().remove(0);
+    let (address, path) = entry.split_once('/').unwrap();
+    let client = bus.connect().await;
+    zbus::Proxy::new(&client, address.to_string(), format!("/{}", path), "org.kde.StatusNotifierItem")
+        .await
+        .unwrap()
+        .call_method("Activate", &(640i32, 12i32))
+        .await
+        .unwrap();
+    let mut event = None;
+    eventually(|| {
+        event = event.take().or_else(|| icon.poll_event());
+        let found = event.is_some();
+        async move { found }
+    })
+    .await
+    .expect("activation");
+
+    assert_eq!(event, Some(TrayEvent::Activate { x: 640, y: 12 }));
+    assert_eq!(icon.get_bounds(), Rectangle::new(640, 12, 0, 0));
+}
@@ -0,0 +1,579 @@
+//! Private session bus and fake StatusNotifierItems for integration tests.
+//!
+//! [`TestBus::start`] launches a `dbus-daemon` of its own and points
+//! `DBUS_SESSION_BUS_ADDRESS` at it, so a `StrayTrayApp` started afterwards
+//! watches that bus instead of the desktop's. [`FakeItem`] exports an item
+//! with a dbusmenu and records every call it receives. Tests sharing the
+//! environment variable run one at a time.
+//!
+//! Set `DBUS_DAEMON` to use a `dbus-daemon` that is not on `PATH`; without
+//! one the tests print a note and pass, unless `REQUIRE_TEST_BUS=1` makes
+//! them fail, as CI should.
+
+#![allow(dead_code)]
+
+pub mod x11;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use system_tray_linux_aio::stray_impl::{HostEvent, HostHandle, NotifierInfo, DEFAULT_ITEM_PATH};
+use tokio::sync::broadcast;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{dbus_interface, SignalContext};
+
+/// Longest wait for the host or an item to react
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const MENU_PATH: &str = "/MenuBar";
+
+/// Note that the current test did not run, naming it
+///
+/// Written to stderr directly, which the test harness does not capture, so
+/// the note shows up although the test passes.
+pub fn skip(reason: impl std::fmt::Display) {
+    let thread = std::thread::current();
+    let test = thread.name().unwrap_or("test");
+    let _ = writeln!(std::io::stderr(), "Skipping {}: {}", test, reason);
+}
+
+/// Serializes tests that point the session bus at their own daemon
+static BUS_LOCK: Mutex<()> = Mutex::new(());
+
+/// A `dbus-daemon` private to one test, stopped on drop
+pub struct TestBus {
+    daemon: Child,
+    address: String,
+    _dir: tempfile::TempDir,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestBus {
+    /// Start a daemon and make it the session bus, `None` if there is no daemon to run
+    /// and `REQUIRE_TEST_BUS=1` is not set
+    pub fn start() -> Option<Self> {
+        // A failed test must not keep the others from running
+        let lock = BUS_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let dir = tempfile::TempDir::new().expect("temporary directory");
+        let config = dir.path().join("session.conf");
+        std::fs::write(
+            &config,
+            format!(
+                r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:dir={}</listen>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#,
+                dir.path().display()
+            ),
+        )
+        .expect("bus config");
+
+        let program = std::env::var("DBUS_DAEMON").unwrap_or_else(|_| "dbus-daemon".to_string());
+        let mut daemon = match Command::new(&program)
+            .arg(format!("--config-file={}", config.display()))
+            .arg("--nofork")
+            .arg("--print-address")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                if std::env::var_os("REQUIRE_TEST_BUS").is_some_and(|value| value == "1") {
+                    panic!("REQUIRE_TEST_BUS=1 but {} cannot run: {}", program, e);
+                }
+                skip(format_args!("cannot run {} (set REQUIRE_TEST_BUS=1 to fail instead): {}", program, e));
+                return None;
+            }
+        };
+
+        let mut address = String::new();
+        let stdout = daemon.stdout.take().expect("daemon stdout");
+        BufReader::new(stdout).read_line(&mut address).expect("bus address");
+        let address = address.trim().to_string();
+        assert!(!address.is_empty(), "{} printed no address", program);
+
+        std::env::set_var("DBUS_SESSION_BUS_ADDRESS", &address);
+
+        Some(Self {
+            daemon,
+            address,
+            _dir: dir,
+            _lock: lock,
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// A new connection to the bus, as a separate client would have
+    pub async fn connect(&self) -> zbus::Connection {
+        zbus::ConnectionBuilder::address(self.address.as_str())
+            .expect("bus address")
+            .build()
+            .await
+            .expect("bus connection")
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        std::env::remove_var("DBUS_SESSION_BUS_ADDRESS");
+    }
+}
+
+/// Something a fake item was asked to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemCall {
+    Activate { x: i32, y: i32 },
+    SecondaryActivate { x: i32, y: i32 },
+    ContextMenu { x: i32, y: i32 },
+    Scroll { delta: i32, orientation: String },
+    /// A dbusmenu event such as `clicked` on entry `id`
+    MenuEvent { id: i32, event: String },
+    AboutToShow { id: i32 },
+}
+
+type CallLog = Arc<Mutex<Vec<ItemCall>>>;
+
+/// A dbusmenu entry on the wire: id, properties and children
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+/// Entry of a fake dbusmenu, the root (id 0) is implied
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeMenuEntry {
+    pub id: i32,
+    pub label: String,
+    pub enabled: bool,
+    pub visible: bool,
+    pub separator: bool,
+    pub children: Vec<FakeMenuEntry>,
+}
+
+impl FakeMenuEntry {
+    pub fn new(id: i32, label: &str) -> Self {
+        Self {
+            id,
+            label: label.to_string(),
+            enabled: true,
+            visible: true,
+            separator: false,
+            children: vec![],
+        }
+    }
+
+    pub fn separator(id: i32) -> Self {
+        Self {
+            separator: true,
+            ..Self::new(id, "")
+        }
+    }
+
+    pub fn disabled(mut self) -> Self {
+        self.enabled = false;
+        self
+    }
+
+    pub fn submenu(mut self, children: Vec<FakeMenuEntry>) -> Self {
+        self.children = children;
+        self
+    }
+
+    /// The `(ia{sv}av)` layout dbusmenu sends for this entry
+    fn layout(&self) -> Layout {
+        let mut props: HashMap<String, OwnedValue> = HashMap::new();
+        if self.separator {
+            props.insert("type".into(), Value::from("separator").into());
+        } else {
+            props.insert("label".into(), Value::from(self.label.as_str()).into());
+        }
+        props.insert("enabled".into(), Value::from(self.enabled).into());
+        props.insert("visible".into(), Value::from(self.visible).into());
+        if !self.children.is_empty() {
+            props.insert("children-display".into(), Value::from("submenu").into());
+        }
+
+        (self.id, props, layout_children(&self.children))
+    }
+}
+
+fn layout_children(entries: &[FakeMenuEntry]) -> Vec<OwnedValue> {
+    entries
+        .iter()
+        .map(|entry| Value::from(entry.layout()).into())
+        .collect()
+}
+
+/// A tray item to export on a [`TestBus`]
+#[derive(Debug, Clone)]
+pub struct FakeItem {
+    pub id: String,
+    pub title: String,
+    pub status: String,
+    pub category: String,
+    pub icon_name: String,
+    pub item_is_menu: bool,
+    pub menu: Option<Vec<FakeMenuEntry>>,
+}
+
+impl FakeItem {
+    /// An active application item called `id`, without a menu
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            title: id.to_string(),
+            status: "Active".to_string(),
+            category: "ApplicationStatus".to_string(),
+            icon_name: String::new(),
+            item_is_menu: false,
+            menu: None,
+        }
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn status(mut self, status: &str) -> Self {
+        self.status = status.to_string();
+        self
+    }
+
+    pub fn icon_name(mut self, icon_name: &str) -> Self {
+        self.icon_name = icon_name.to_string();
+        self
+    }
+
+    pub fn menu(mut self, entries: Vec<FakeMenuEntry>) -> Self {
+        self.menu = Some(entries);
+        self
+    }
+
+    /// Export the item on its own connection and register it with the
+    /// watcher, waiting for one to appear
+    pub async fn spawn(self, bus: &TestBus) -> RunningItem {
+        let calls = CallLog::default();
+        let connection = bus.connect().await;
+
+        let has_menu = self.menu.is_some();
+        let menu = FakeMenu {
+            revision: 1,
+            entries: self.menu.clone().unwrap_or_default(),
+            calls: calls.clone(),
+        };
+        let item = FakeSni {
+            item: self,
+            calls: calls.clone(),
+        };
+
+        {
+            let server = connection.object_server();
+            server.at(DEFAULT_ITEM_PATH, item).await.expect("export item");
+            if has_menu {
+                server.at(MENU_PATH, menu).await.expect("export menu");
+            }
+        }
+
+        // Named like KDE applications do, so the item can leave by releasing
+        // it, and apart from the items the crate itself publishes in this process
+        let address = format!(
+            "org.kde.StatusNotifierItem-{}-fake{}",
+            std::process::id(),
+            NEXT_ITEM.fetch_add(1, Ordering::Relaxed)
+        );
+        connection.request_name(address.as_str()).await.expect("item name");
+
+        let watcher = zbus::Proxy::new(
+            &connection,
+            "org.kde.StatusNotifierWatcher",
+            "/StatusNotifierWatcher",
+            "org.kde.StatusNotifierWatcher",
+        )
+        .await
+        .expect("watcher proxy");
+
+        let (watcher, service) = (&watcher, address.as_str());
+        eventually(|| async move {
+            watcher
+                .call_method("RegisterStatusNotifierItem", &(service,))
+                .await
+                .is_ok()
+        })
+        .await
+        .expect("no StatusNotifierWatcher on the test bus");
+
+        RunningItem {
+            connection,
+            address,
+            calls,
+        }
+    }
+}
+
+/// Numbers the bus names of fake items
+static NEXT_ITEM: AtomicUsize = AtomicUsize::new(1);
+
+/// A fake item being served
+pub struct RunningItem {
+    connection: zbus::Connection,
+    address: String,
+    calls: CallLog,
+}
+
+impl RunningItem {
+    /// Bus name the item registered with the watcher
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn calls(&self) -> Vec<ItemCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Wait until the item received a call like `expected`
+    pub async fn wait_for_call(&self, expected: ItemCall) {
+        let expected = &expected;
+        let received = eventually(|| async move { self.calls().contains(expected) }).await;
+        assert!(received.is_some(), "{:?} never arrived, got {:?}", expected, self.calls());
+    }
+
+    pub async fn set_title(&self, title: &str) {
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, FakeSni>(DEFAULT_ITEM_PATH)
+            .await
+            .expect("exported item");
+        iface.get_mut().await.item.title = title.to_string();
+        FakeSni::new_title(iface.signal_context()).await.expect("NewTitle");
+    }
+
+    pub async fn set_status(&self, status: &str) {
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, FakeSni>(DEFAULT_ITEM_PATH)
+            .await
+            .expect("exported item");
+        iface.get_mut().await.item.status = status.to_string();
+        FakeSni::new_status(iface.signal_context(), status).await.expect("NewStatus");
+    }
+
+    /// Replace the menu and announce the new layout
+    pub async fn set_menu(&self, entries: Vec<FakeMenuEntry>) {
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, FakeMenu>(MENU_PATH)
+            .await
+            .expect("exported menu");
+        let revision = {
+            let mut menu = iface.get_mut().await;
+            menu.entries = entries;
+            menu.revision += 1;
+            menu.revision
+        };
+        FakeMenu::layout_updated(iface.signal_context(), revision, 0)
+            .await
+            .expect("LayoutUpdated");
+    }
+
+    /// Give up the item's bus name, which watchers take as the application quitting
+    pub async fn close(self) {
+        self.connection
+            .release_name(self.address.as_str())
+            .await
+            .expect("release item name");
+    }
+}
+
+struct FakeSni {
+    item: FakeItem,
+    calls: CallLog,
+}
+
+impl FakeSni {
+    fn record(&self, call: ItemCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+#[dbus_interface(name = "org.kde.StatusNotifierItem")]
+impl FakeSni {
+    fn activate(&self, x: i32, y: i32) {
+        self.record(ItemCall::Activate { x, y });
+    }
+
+    fn secondary_activate(&self, x: i32, y: i32) {
+        self.record(ItemCall::SecondaryActivate { x, y });
+    }
+
+    fn context_menu(&self, x: i32, y: i32) {
+        self.record(ItemCall::ContextMenu { x, y });
+    }
+
+    fn scroll(&self, delta: i32, orientation: String) {
+        self.record(ItemCall::Scroll { delta, orientation });
+    }
+
+    #[dbus_interface(property)]
+    fn id(&self) -> String {
+        self.item.id.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn title(&self) -> String {
+        self.item.title.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        self.item.status.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn category(&self) -> String {
+        self.item.category.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn window_id(&self) -> i32 {
+        0
+    }
+
+    #[dbus_interface(property)]
+    fn icon_name(&self) -> String {
+        self.item.icon_name.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn icon_pixmap(&self) -> Vec<(i32, i32, Vec<u8>)> {
+        vec![]
+    }
+
+    #[dbus_interface(property)]
+    fn item_is_menu(&self) -> bool {
+        self.item.item_is_menu
+    }
+
+    #[dbus_interface(property)]
+    fn menu(&self) -> ObjectPath<'static> {
+        let path = if self.item.menu.is_some() { MENU_PATH } else { "/NO_DBUSMENU" };
+        ObjectPath::try_from(path).unwrap()
+    }
+
+    #[dbus_interface(signal)]
+    async fn new_title(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_status(ctxt: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+}
+
+struct FakeMenu {
+    revision: u32,
+    entries: Vec<FakeMenuEntry>,
+    calls: CallLog,
+}
+
+#[dbus_interface(name = "com.canonical.dbusmenu")]
+impl FakeMenu {
+    /// Always the whole tree, which hosts are allowed to get
+    fn get_layout(
+        &self,
+        _parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> (u32, Layout) {
+        let mut props: HashMap<String, OwnedValue> = HashMap::new();
+        props.insert("children-display".into(), Value::from("submenu").into());
+
+        (self.revision, (0, props, layout_children(&self.entries)))
+    }
+
+    fn event(&self, id: i32, event_id: String, _data: OwnedValue, _timestamp: u32) {
+        self.calls.lock().unwrap().push(ItemCall::MenuEvent { id, event: event_id });
+    }
+
+    fn about_to_show(&self, id: i32) -> bool {
+        self.calls.lock().unwrap().push(ItemCall::AboutToShow { id });
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        "normal".to_string()
+    }
+
+    #[dbus_interface(signal)]
+    async fn layout_updated(ctxt: &SignalContext<'_>, revision: u32, parent: i32) -> zbus::Result<()>;
+}
+
+/// Poll `check` until it holds, `None` after [`TIMEOUT`]
+pub async fn eventually<F, Fut>(mut check: F) -> Option<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if check().await {
+            return Some(());
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    None
+}
+
+/// Wait for the host to report an item matching `query` that passes `ready`
+pub async fn wait_for_item(handle: &HostHandle, query: &str, ready: impl Fn(&NotifierInfo) -> bool) -> NotifierInfo {
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    loop {
+        if let Some(info) = handle.find_item(query).await.filter(|info| ready(info)) {
+            return info;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            panic!("host never saw {}, it has {:?}", query, handle.items().await);
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// Next event passing `matches`, skipping others
+pub async fn wait_for_event(
+    events: &mut broadcast::Receiver<HostEvent>,
+    matches: impl Fn(&HostEvent) -> bool,
+) -> HostEvent {
+    let wait = async {
+        loop {
+            match events.recv().await {
+                Ok(event) if matches(&event) => return event,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => panic!("host stopped"),
+            }
+        }
+    };
+
+    tokio::time::timeout(TIMEOUT, wait).await.expect("event did not arrive")
+}
@@ -0,0 +1,284 @@
+//! Private X server and a stand-in system tray manager for XEmbed tests.
+//!
+//! [`Xvfb::start`] runs a virtual X server on a free display. [`StandInManager`]
+//! owns the tray selection on it and embeds every icon that asks to dock, the
+//! way panels like stalonetray do. Set `XVFB` to use an `Xvfb` that is not on
+//! `PATH`; without one the tests print a note and pass, unless `REQUIRE_XVFB=1`
+//! makes them fail.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    AtomEnum, ButtonPressEvent, ClientMessageEvent, ConfigureWindowAux, ConnectionExt as _, CreateWindowAux, EventMask,
+    KeyButMask, KeyPressEvent, MapState, PropMode, SetMode, Window, WindowClass, BUTTON_PRESS_EVENT, KEY_PRESS_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, CURRENT_TIME};
+use super::{skip, TIMEOUT};
+
+/// Side the stand-in manager gives every icon
+pub const ICON_SIZE: u16 = 24;
+
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+const XEMBED_EMBEDDED_NOTIFY: u32 = 0;
+
+/// An `Xvfb` private to one test, stopped on drop
+pub struct Xvfb {
+    server: Child,
+    display: String,
+}
+
+impl Xvfb {
+    /// Start a server on a free display, `None` if there is no `Xvfb` to run
+    /// and `REQUIRE_XVFB=1` is not set
+    pub fn start() -> Option<Self> {
+        let program = std::env::var("XVFB").unwrap_or_else(|_| "Xvfb".to_string());
+        let mut server = match Command::new(&program)
+            .args(["-displayfd", "1", "-screen", "0", "640x480x24", "-nolisten", "tcp"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(server) => server,
+            Err(e) => {
+                if std::env::var_os("REQUIRE_XVFB").is_some_and(|value| value == "1") {
+                    panic!("REQUIRE_XVFB=1 but {} cannot run: {}", program, e);
+                }
+                skip(format_args!("cannot run {}: {}", program, e));
+                return None;
+            }
+        };
+
+        // The server writes the display number once it accepts connections
+        let mut number = String::new();
+        let stdout = server.stdout.take().expect("Xvfb stdout");
+        BufReader::new(stdout).read_line(&mut number).expect("display number");
+        let number = number.trim();
+        assert!(!number.is_empty(), "{} printed no display", program);
+
+        Some(Self {
+            server,
+            display: format!(":{}", number),
+        })
+    }
+
+    pub fn display(&self) -> &str {
+        &self.display
+    }
+
+    pub fn connect(&self) -> RustConnection {
+        x11rb::connect(Some(&self.display)).expect("X connection").0
+    }
+}
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+    }
+}
+
+/// A minimal tray manager, embedding icons side by side
+pub struct StandInManager {
+    conn: Arc<RustConnection>,
+    window: Window,
+    quit: u32,
+    docked: Arc<Mutex<Vec<Window>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StandInManager {
+    /// Take the tray selection, announcing a 32 bit visual if `argb` is set
+    /// and the server has one
+    pub fn start(xvfb: &Xvfb, argb: bool) -> Self {
+        let conn = Arc::new(xvfb.connect());
+        let screen = conn.setup().roots[0].clone();
+        let atom = |name: &str| conn.intern_atom(false, name.as_bytes()).unwrap().reply().unwrap().atom;
+        let selection = atom("_NET_SYSTEM_TRAY_S0");
+        let opcode = atom("_NET_SYSTEM_TRAY_OPCODE");
+        let manager = atom("MANAGER");
+        let xembed = atom("_XEMBED");
+        let quit = atom("_STAND_IN_QUIT");
+
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            0,
+            0,
+            200,
+            ICON_SIZE,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            COPY_FROM_PARENT,
+            &CreateWindowAux::new().background_pixel(screen.black_pixel),
+        )
+        .unwrap();
+        conn.map_window(window).unwrap();
+
+        if argb {
+            let visual = screen
+                .allowed_depths
+                .iter()
+                .find(|depth| depth.depth == 32)
+                .and_then(|depth| depth.visuals.first());
+            if let Some(visual) = visual {
+                conn.change_property32(
+                    PropMode::REPLACE,
+                    window,
+                    atom("_NET_SYSTEM_TRAY_VISUAL"),
+                    AtomEnum::VISUALID,
+                    &[visual.visual_id],
+                )
+                .unwrap();
+            }
+        }
+
+        conn.set_selection_owner(window, selection, CURRENT_TIME).unwrap();
+        let announce = ClientMessageEvent::new(32, screen.root, manager, [CURRENT_TIME, selection, window, 0, 0]);
+        conn.send_event(false, screen.root, EventMask::STRUCTURE_NOTIFY, announce).unwrap();
+        conn.flush().unwrap();
+
+        let docked = Arc::new(Mutex::new(Vec::new()));
+        let thread = {
+            let (conn, docked) = (conn.clone(), docked.clone());
+            std::thread::spawn(move || loop {
+                let Ok(event) = conn.wait_for_event() else { return };
+                let Event::ClientMessage(message) = event else { continue };
+                if message.type_ == quit {
+                    return;
+                }
+                let data = message.data.as_data32();
+                if message.type_ != opcode || data[1] != SYSTEM_TRAY_REQUEST_DOCK {
+                    continue;
+                }
+
+                let icon = data[2];
+                let x = (docked.lock().unwrap().len() as u16 * ICON_SIZE) as i16;
+                // Keep the icon alive if we go away, as real managers do
+                conn.change_save_set(SetMode::INSERT, icon).unwrap();
+                conn.reparent_window(icon, window, x, 0).unwrap();
+                conn.configure_window(
+                    icon,
+                    &ConfigureWindowAux::new().width(ICON_SIZE as u32).height(ICON_SIZE as u32),
+                )
+                .unwrap();
+                conn.map_window(icon).unwrap();
+                let notify = ClientMessageEvent::new(32, icon, xembed, [CURRENT_TIME, XEMBED_EMBEDDED_NOTIFY, 0, window, 0]);
+                conn.send_event(false, icon, EventMask::NO_EVENT, notify).unwrap();
+                conn.flush().unwrap();
+                docked.lock().unwrap().push(icon);
+            })
+        };
+
+        Self {
+            conn,
+            window,
+            quit,
+            docked,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
+    /// Icons embedded so far, in docking order
+    pub fn docked(&self) -> Vec<Window> {
+        self.docked.lock().unwrap().clone()
+    }
+
+    /// Wait until `icon` has been embedded
+    pub fn wait_for_dock(&self, icon: Window) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !self.docked().contains(&icon) {
+            assert!(Instant::now() < deadline, "icon {:#x} was never docked", icon);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Disconnect like a crashing panel: the selection is freed and docked
+    /// icons go back to the root window
+    pub fn kill(mut self) {
+        let quit = ClientMessageEvent::new(32, self.window, self.quit, [0; 5]);
+        self.conn.send_event(false, self.window, EventMask::NO_EVENT, quit).unwrap();
+        self.conn.flush().unwrap();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// Send a press of `button` over `window`, at (30, 5) on the root window
+pub fn press(conn: &impl Connection, window: u32, button: u8) {
+    let event = ButtonPressEvent {
+        response_type: BUTTON_PRESS_EVENT,
+        detail: button,
+        sequence: 0,
+        time: 0,
+        root: conn.setup().roots[0].root,
+        event: window,
+        child: 0,
+        root_x: 30,
+        root_y: 5,
+        event_x: 6,
+        event_y: 5,
+        state: KeyButMask::default(),
+        same_screen: true,
+    };
+    conn.send_event(false, window, EventMask::BUTTON_PRESS, event).unwrap();
+    conn.flush().unwrap();
+}
+
+/// Wait for a popup menu window other than `previous` to be mapped
+pub fn wait_for_menu(conn: &impl Connection, previous: Option<Window>) -> Window {
+    let root = conn.setup().roots[0].root;
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        for window in conn.query_tree(root).unwrap().reply().unwrap().children {
+            let attributes = conn.get_window_attributes(window).unwrap().reply().unwrap();
+            if attributes.override_redirect && attributes.map_state == MapState::VIEWABLE && Some(window) != previous {
+                return window;
+            }
+        }
+        assert!(Instant::now() < deadline, "no menu shown in time");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Send key presses for `keysyms` to `window`
+pub fn type_keys(conn: &impl Connection, window: Window, keysyms: &[u32]) {
+    let setup = conn.setup();
+    let count = setup.max_keycode - setup.min_keycode + 1;
+    let mapping = conn.get_keyboard_mapping(setup.min_keycode, count).unwrap().reply().unwrap();
+    let per_keycode = mapping.keysyms_per_keycode as usize;
+
+    for keysym in keysyms {
+        let index = mapping.keysyms.iter().position(|sym| sym == keysym).expect("keysym not in the keymap");
+        let event = KeyPressEvent {
+            response_type: KEY_PRESS_EVENT,
+            detail: setup.min_keycode + (index / per_keycode) as u8,
+            sequence: 0,
+            time: 0,
+            root: setup.roots[0].root,
+            event: window,
+            child: 0,
+            root_x: 0,
+            root_y: 0,
+            event_x: 0,
+            event_y: 0,
+            state: KeyButMask::default(),
+            same_screen: true,
+        };
+        conn.send_event(false, window, EventMask::KEY_PRESS, event).unwrap();
+    }
+    conn.flush().unwrap();
+}
@@ -0,0 +1,76 @@
+use system_tray_linux_aio::backend::{HeadlessBackend, TrayChange};
+use system_tray_linux_aio::config::{IconState, ItemStatus};
+use system_tray_linux_aio::menu::build_popup;
+use system_tray_linux_aio::tray::TrayEvent;
+use system_tray_linux_aio::{AppConfig, MenuAction, TrayIcon};
+
+fn config() -> AppConfig {
+    let mut config = AppConfig {
+        tooltip: "Idle".to_string(),
+        icon_path: "does-not-exist.png".into(),
+        ..Default::default()
+    };
+    config.states.insert(
+        "alert".to_string(),
+        IconState {
+            icon_text: Some("!".to_string()),
+            tooltip: Some("Needs you".to_string()),
+            status: ItemStatus::NeedsAttention,
+            ..Default::default()
+        },
+    );
+    config
+}
+
+#[tokio::test]
+async fn test_tray_icon_changes_are_recorded() {
+    let tray = HeadlessBackend::default();
+    let mut icon = TrayIcon::with_component(config(), tray.clone()).await.unwrap();
+
+    icon.initialize().await.unwrap();
+    let changes = tray.changes();
+    assert!(matches!(&changes[0], TrayChange::Icon(image) if !image.is_empty()));
+    assert_eq!(changes[1], TrayChange::Tooltip("Idle".into()));
+    assert_eq!(tray.tooltip(), "Idle");
+
+    tray.clear();
+    icon.set_state("alert").await.unwrap();
+    icon.show_info_bubble("Backup", "Disk almost full").await;
+    icon.show_menu();
+
+    let changes = tray.changes();
+    assert_eq!(
+        &changes[..3],
+        &[
+            TrayChange::Tooltip("Needs you".into()),
+            TrayChange::Status(ItemStatus::NeedsAttention),
+            TrayChange::Highlighted(true),
+        ]
+    );
+    assert!(matches!(changes[3], TrayChange::Icon(_)));
+    assert_eq!(
+        &changes[4..],
+        &[
+            TrayChange::BubbleShown { title: "Backup".into(), content: "Disk almost full".into() },
+            TrayChange::MenuShown(build_popup(&config().menu_config)),
+        ]
+    );
+    assert_eq!(tray.status(), ItemStatus::NeedsAttention);
+    assert_eq!(tray.bubble(), Some(("Backup".into(), "Disk almost full".into())));
+}
+
+#[tokio::test]
+async fn test_injected_events_reach_the_app() {
+    let tray = HeadlessBackend::default();
+    let mut icon = TrayIcon::with_component(AppConfig::default(), tray.clone()).await.unwrap();
+    assert_eq!(icon.poll_event(), None);
+
+    tray.right_click(640, 0);
+    // About, Settings and Quit follow the custom items, which are none here
+    tray.choose_menu_item(3);
+
+    assert_eq!(icon.poll_event(), Some(TrayEvent::ContextMenu { x: 640, y: 0 }));
+    let Some(TrayEvent::MenuItem(result)) = icon.poll_event() else { panic!("no menu event") };
+    assert_eq!(icon.menu_action(result).await.unwrap(), Some(MenuAction::Quit));
+    assert_eq!(icon.poll_event(), None);
+}
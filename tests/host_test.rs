@@ -0,0 +1,95 @@
+mod common;
+
+use common::{wait_for_event, wait_for_item, FakeItem, FakeMenuEntry, ItemCall, TestBus};
+use system_tray_linux_aio::config::ItemStatus;
+use system_tray_linux_aio::stray_impl::{HostEvent, ItemField, StrayTrayApp};
+use system_tray_linux_aio::AppConfig;
+
+fn network_menu() -> Vec<FakeMenuEntry> {
+    vec![
+        FakeMenuEntry::new(1, "_Settings").submenu(vec![
+            FakeMenuEntry::new(2, "Preferences"),
+            FakeMenuEntry::new(3, "Advanced").disabled(),
+        ]),
+        FakeMenuEntry::separator(4),
+        FakeMenuEntry::new(5, "Quit"),
+    ]
+}
+
+#[tokio::test]
+async fn test_host_sees_fake_item() {
+    let Some(bus) = TestBus::start() else { return };
+    let host = StrayTrayApp::new(AppConfig::default()).spawn();
+
+    let item = FakeItem::new("fake-network")
+        .title("Network")
+        .status("NeedsAttention")
+        .icon_name("network-wireless")
+        .menu(network_menu())
+        .spawn(&bus)
+        .await;
+
+    let info = wait_for_item(&host, "fake-network", |info| info.menu.is_some()).await;
+    assert_eq!(info.address, item.address());
+    assert_eq!(info.title, "Network");
+    assert_eq!(info.status, ItemStatus::NeedsAttention);
+    assert_eq!(info.icon_name.as_deref(), Some("network-wireless"));
+
+    let menu = info.menu.unwrap();
+    let labels: Vec<&str> = menu.children.iter().map(|entry| entry.label.as_str()).collect();
+    assert_eq!(labels, vec!["Settings", "", "Quit"]);
+    assert!(!menu.find_path(&["Settings", "Advanced"]).unwrap().enabled);
+
+    host.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_clicks_reach_the_item() {
+    let Some(bus) = TestBus::start() else { return };
+    let host = StrayTrayApp::new(AppConfig::default()).spawn();
+    let item = FakeItem::new("fake-network").menu(network_menu()).spawn(&bus).await;
+    let info = wait_for_item(&host, "fake-network", |info| info.menu.is_some()).await;
+
+    host.click_by_path(&info.address, &["Settings", "Preferences"]).await.unwrap();
+    item.wait_for_call(ItemCall::MenuEvent { id: 2, event: "clicked".into() }).await;
+
+    host.activate(&info.address, 10, 20).await.unwrap();
+    item.wait_for_call(ItemCall::Activate { x: 10, y: 20 }).await;
+
+    // Disabled entries are refused before anything is sent
+    assert!(host.click_by_path(&info.address, &["Settings", "Advanced"]).await.is_err());
+    assert!(!item.calls().contains(&ItemCall::MenuEvent { id: 3, event: "clicked".into() }));
+
+    host.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_item_updates_and_removal() {
+    let Some(bus) = TestBus::start() else { return };
+    let host = StrayTrayApp::new(AppConfig::default()).spawn();
+    let mut events = host.subscribe();
+    let item = FakeItem::new("fake-volume").menu(network_menu()).spawn(&bus).await;
+    let address = item.address().to_string();
+    wait_for_item(&host, "fake-volume", |info| info.menu.is_some()).await;
+
+    item.set_title("Muted").await;
+    let event = wait_for_event(&mut events, |event| {
+        matches!(event, HostEvent::Changed { fields, .. } if fields.contains(&ItemField::Title))
+    })
+    .await;
+    assert_eq!(event.info().title, "Muted");
+
+    item.set_menu(vec![FakeMenuEntry::new(7, "Unmute")]).await;
+    let event = wait_for_event(&mut events, |event| {
+        matches!(event, HostEvent::Changed { fields, .. } if fields.contains(&ItemField::Menu))
+    })
+    .await;
+    assert_eq!(event.info().menu.as_ref().unwrap().children[0].label, "Unmute");
+
+    item.close().await;
+    let event = wait_for_event(&mut events, |event| matches!(event, HostEvent::Removed(_))).await;
+    assert_eq!(event.address(), address);
+    assert!(host.item(&address).await.is_none());
+
+    host.stop().await.unwrap();
+}
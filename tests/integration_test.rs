@@ -1,4 +1,4 @@
-use system_tray_linux_aio::{AppConfig, TrayIcon};
+use system_tray_linux_aio::{AppConfig, TrayError, TrayIcon};
 
 #[tokio::test]
 async fn test_tray_creation() {
@@ -18,4 +18,13 @@ fn test_config_creation() {
     assert_eq!(config.app_name, "Integration Test App");
     assert_eq!(config.tooltip, "Test tooltip");
     assert!(config.start_minimized);
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_unknown_state() {
+    let config = AppConfig::default();
+    let mut tray = TrayIcon::new(config).await.unwrap();
+
+    let result = tray.set_state("busy").await;
+    assert!(matches!(result, Err(TrayError::UnknownState(name)) if name == "busy"));
+    assert!(tray.get_state().is_none());
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use std::collections::HashMap;
+use common::{eventually, FakeItem, FakeMenuEntry, ItemCall, TestBus};
+use system_tray_linux_aio::config::{ItemMatcher, ItemOverride, ItemsConfig};
+use system_tray_linux_aio::stray_impl::WATCHER_PATH;
+use system_tray_linux_aio::watcher::{ProxyWatcher, WatcherFlavor};
+use system_tray_linux_aio::AppConfig;
+use zbus::zvariant::{OwnedValue, Value};
+
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+fn curated_config() -> AppConfig {
+    AppConfig {
+        items: ItemsConfig {
+            block: vec![ItemMatcher { id: Some("fake-updates".into()), ..Default::default() }],
+            order: vec!["fake-volume".into(), "fake-network".into()],
+            overrides: vec![ItemOverride {
+                matcher: ItemMatcher { id: Some("fake-network".into()), ..Default::default() },
+                title: Some("Network".into()),
+                icon: Some("network-wireless".into()),
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// A copy as the panel sees it; items announce changes by signal, so
+/// zbus must not answer from a property cache
+async fn copy_proxy(panel: &zbus::Connection, address: &str) -> zbus::Proxy<'static> {
+    zbus::ProxyBuilder::new_bare(panel)
+        .destination(address.to_string())
+        .unwrap()
+        .path("/StatusNotifierItem")
+        .unwrap()
+        .interface("org.kde.StatusNotifierItem")
+        .unwrap()
+        .cache_properties(zbus::CacheProperties::No)
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_panel_sees_the_curated_items() {
+    let Some(bus) = TestBus::start() else { return };
+    let proxy = ProxyWatcher::start(curated_config()).await.unwrap();
+
+    let network = FakeItem::new("fake-network")
+        .menu(vec![FakeMenuEntry::new(1, "Connect"), FakeMenuEntry::new(2, "Quit")])
+        .spawn(&bus)
+        .await;
+    let _updates = FakeItem::new("fake-updates").spawn(&bus).await;
+    let volume = FakeItem::new("fake-volume").spawn(&bus).await;
+
+    let panel = bus.connect().await;
+    let name = WatcherFlavor::Kde.name();
+    let watcher = zbus::Proxy::new(&panel, name, WATCHER_PATH, name).await.unwrap();
+    let (watcher, curated) = (&watcher, &proxy);
+    eventually(|| async move {
+        let listed: Vec<String> = watcher.get_property("RegisteredStatusNotifierItems").await.unwrap_or_default();
+        let copies: Vec<String> = curated.items().iter().map(|copy| format!("{}/StatusNotifierItem", copy.address)).collect();
+        listed.len() == 2 && listed == copies
+    })
+    .await
+    .expect("copies listed");
+
+    // Ordered by config, with the real items and the blocked one out of sight
+    let copies = proxy.items();
+    assert_eq!(copies[0].source, volume.address());
+    assert_eq!(copies[1].source, network.address());
+
+    let copy = copy_proxy(&panel, &copies[1].address).await;
+    assert_eq!(copy.get_property::<String>("Title").await.unwrap(), "Network");
+    assert_eq!(copy.get_property::<String>("IconName").await.unwrap(), "network-wireless");
+
+    copy.call_method("Activate", &(3i32, 4i32)).await.unwrap();
+    network.wait_for_call(ItemCall::Activate { x: 3, y: 4 }).await;
+
+    let menu = zbus::Proxy::new(&panel, copies[1].address.clone(), "/MenuBar", "com.canonical.dbusmenu")
+        .await
+        .unwrap();
+    let menu = &menu;
+    eventually(|| async move {
+        let layout: zbus::Result<(u32, Layout)> = menu.call("GetLayout", &(0i32, -1i32, Vec::<&str>::new())).await;
+        layout.is_ok_and(|(_, (_, _, children))| children.len() == 2)
+    })
+    .await
+    .expect("menu copied");
+    menu.call_method("Event", &(2i32, "clicked", Value::I32(0), 0u32)).await.unwrap();
+    network.wait_for_call(ItemCall::MenuEvent { id: 2, event: "clicked".into() }).await;
+
+    network.set_title("Offline").await;
+    volume.set_title("Muted").await;
+    let volume_copy = &copy_proxy(&panel, &copies[0].address).await;
+    eventually(|| async move { volume_copy.get_property::<String>("Title").await.is_ok_and(|title| title == "Muted") })
+        .await
+        .expect("title followed");
+    assert_eq!(copy.get_property::<String>("Title").await.unwrap(), "Network", "the override still applies");
+
+    volume.close().await;
+    eventually(|| async move {
+        let listed: Vec<String> = watcher.get_property("RegisteredStatusNotifierItems").await.unwrap_or_default();
+        listed.len() == 1 && curated.items().len() == 1
+    })
+    .await
+    .expect("copy withdrawn");
+
+    proxy.stop().await.unwrap();
+    let bus_names = zbus::fdo::DBusProxy::new(&panel).await.unwrap();
+    let owned = bus_names.name_has_owner(name.try_into().unwrap()).await.unwrap();
+    assert!(!owned, "the watcher names are given up");
+}
+
+#[tokio::test]
+async fn test_proxy_needs_the_watcher_names() {
+    let Some(bus) = TestBus::start() else { return };
+    let _watcher = system_tray_linux_aio::watcher::StatusNotifierWatcher::start(&bus.connect().await)
+        .await
+        .unwrap();
+    assert!(ProxyWatcher::start(AppConfig::default()).await.is_err());
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use common::{eventually, TestBus, TIMEOUT};
+use system_tray_linux_aio::config::ItemStatus;
+use system_tray_linux_aio::stray_impl::ItemCategory;
+use system_tray_linux_aio::tray::{ItemProperties, SniEvent, SniItem};
+use system_tray_linux_aio::watcher::{StatusNotifierWatcher, WatcherFlavor};
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+use zbus::zvariant::OwnedObjectPath;
+
+fn properties() -> ItemProperties {
+    ItemProperties {
+        id: "test-app".into(),
+        title: "Test App".into(),
+        icon_name: "applications-system".into(),
+        ..Default::default()
+    }
+}
+
+async fn next_event(events: &mut broadcast::Receiver<SniEvent>) -> SniEvent {
+    tokio::time::timeout(TIMEOUT, events.recv())
+        .await
+        .expect("no item event in time")
+        .expect("item events closed")
+}
+
+async fn item_proxy(connection: &zbus::Connection, item: &SniItem) -> zbus::Proxy<'static> {
+    zbus::Proxy::new(
+        connection,
+        item.address().to_string(),
+        "/StatusNotifierItem",
+        "org.kde.StatusNotifierItem",
+    )
+    .await
+    .expect("item proxy")
+}
+
+#[tokio::test]
+async fn test_item_registers_and_answers_the_host() {
+    let Some(bus) = TestBus::start() else { return };
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let item = SniItem::publish_on(bus.connect().await, properties()).await.unwrap();
+    let mut events = item.subscribe();
+
+    assert!(item.is_registered());
+    assert_eq!(watcher.items(), vec![format!("{}/StatusNotifierItem", item.address())]);
+
+    let host = bus.connect().await;
+    let proxy = item_proxy(&host, &item).await;
+    assert_eq!(proxy.get_property::<String>("Id").await.unwrap(), "test-app");
+    assert_eq!(proxy.get_property::<String>("Status").await.unwrap(), "Active");
+
+    proxy.call_method("Activate", &(4i32, 2i32)).await.unwrap();
+    assert_eq!(next_event(&mut events).await, SniEvent::Activate { x: 4, y: 2 });
+
+    let mut new_status = proxy.receive_signal("NewStatus").await.unwrap();
+    item.set_status(ItemStatus::NeedsAttention).await.unwrap();
+    let signal = tokio::time::timeout(TIMEOUT, new_status.next()).await.unwrap().unwrap();
+    assert_eq!(signal.body::<String>().unwrap(), "NeedsAttention");
+
+    item.close().await.unwrap();
+    let watcher = &watcher;
+    eventually(|| async move { watcher.items().is_empty() }).await.expect("item still registered");
+}
+
+#[tokio::test]
+async fn test_item_announces_properties_without_signals() {
+    let Some(bus) = TestBus::start() else { return };
+    let _watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let item = SniItem::publish_on(bus.connect().await, properties()).await.unwrap();
+
+    let host = bus.connect().await;
+    let proxy = item_proxy(&host, &item).await;
+    assert_eq!(proxy.get_property::<String>("Category").await.unwrap(), "ApplicationStatus");
+    assert_eq!(proxy.get_property::<String>("OverlayIconName").await.unwrap(), "");
+
+    // The proxy caches properties, so it only sees what PropertiesChanged tells it
+    item.set_category(ItemCategory::Hardware).await.unwrap();
+    item.set_menu(Some("/MenuBar".into())).await.unwrap();
+    item.set_item_is_menu(true).await.unwrap();
+    let proxy = &proxy;
+    eventually(|| async move {
+        let category = proxy.get_property::<String>("Category").await.unwrap();
+        let menu = proxy.get_property::<OwnedObjectPath>("Menu").await.unwrap();
+        let item_is_menu = proxy.get_property::<bool>("ItemIsMenu").await.unwrap();
+        category == "Hardware" && menu.as_str() == "/MenuBar" && item_is_menu
+    })
+    .await
+    .expect("properties not updated");
+
+    let mut new_overlay = proxy.receive_signal("NewOverlayIcon").await.unwrap();
+    item.set_overlay_icon("emblem-important", Vec::new()).await.unwrap();
+    tokio::time::timeout(TIMEOUT, new_overlay.next()).await.unwrap().unwrap();
+    assert_eq!(item.properties().overlay_icon_name, "emblem-important");
+
+    item.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_item_survives_a_watcher_restart() {
+    let Some(bus) = TestBus::start() else { return };
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let item = SniItem::publish_on(bus.connect().await, properties()).await.unwrap();
+    let mut events = item.subscribe();
+    let entry = format!("{}/StatusNotifierItem", item.address());
+
+    // The panel crashes
+    watcher.stop().await.unwrap();
+    assert_eq!(next_event(&mut events).await, SniEvent::WatcherLost);
+    assert!(!item.is_registered());
+
+    // Property changes while nobody watches are not lost
+    item.set_title("Renamed").await.unwrap();
+
+    let host = bus.connect().await;
+    let proxy = item_proxy(&host, &item).await;
+    let mut new_title = proxy.receive_signal("NewTitle").await.unwrap();
+
+    // ...and comes back
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    assert_eq!(next_event(&mut events).await, SniEvent::WatcherRestored);
+    assert!(item.is_registered());
+    assert_eq!(watcher.items(), vec![entry]);
+
+    // Hosts of the new watcher are told to read everything again
+    tokio::time::timeout(TIMEOUT, new_title.next()).await.unwrap().unwrap();
+    assert_eq!(proxy.get_property::<String>("Title").await.unwrap(), "Renamed");
+
+    item.close().await.unwrap();
+    watcher.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_item_waits_for_a_watcher() {
+    let Some(bus) = TestBus::start() else { return };
+    let item = SniItem::publish_on(bus.connect().await, properties()).await.unwrap();
+    let mut events = item.subscribe();
+    assert!(!item.is_registered());
+
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    assert_eq!(next_event(&mut events).await, SniEvent::WatcherRestored);
+    assert_eq!(watcher.items().len(), 1);
+
+    item.close().await.unwrap();
+    watcher.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_item_uses_a_freedesktop_only_watcher() {
+    let Some(bus) = TestBus::start() else { return };
+    let freedesktop = [WatcherFlavor::Freedesktop];
+    let watcher = StatusNotifierWatcher::start_with(&bus.connect().await, &freedesktop).await.unwrap();
+    let item = SniItem::publish_on(bus.connect().await, properties()).await.unwrap();
+    let mut events = item.subscribe();
+
+    assert!(item.is_registered());
+    assert_eq!(watcher.items().len(), 1);
+
+    // Losing and regaining that watcher is followed too
+    watcher.stop().await.unwrap();
+    assert_eq!(next_event(&mut events).await, SniEvent::WatcherLost);
+    let watcher = StatusNotifierWatcher::start_with(&bus.connect().await, &freedesktop).await.unwrap();
+    assert_eq!(next_event(&mut events).await, SniEvent::WatcherRestored);
+    assert_eq!(watcher.items().len(), 1);
+
+    item.close().await.unwrap();
+    watcher.stop().await.unwrap();
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{eventually, FakeItem, TestBus, TIMEOUT};
+use system_tray_linux_aio::stray_impl::WATCHER_PATH;
+use system_tray_linux_aio::watcher::{StatusNotifierWatcher, WatcherFlavor};
+use tokio_stream::StreamExt;
+
+async fn watcher_proxy(connection: &zbus::Connection, flavor: WatcherFlavor) -> zbus::Proxy<'static> {
+    zbus::Proxy::new(connection, flavor.name(), WATCHER_PATH, flavor.name())
+        .await
+        .expect("watcher proxy")
+}
+
+#[tokio::test]
+async fn test_watcher_registers_items_under_both_names() {
+    let Some(bus) = TestBus::start() else { return };
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let client = bus.connect().await;
+    let kde = watcher_proxy(&client, WatcherFlavor::Kde).await;
+    let freedesktop = watcher_proxy(&client, WatcherFlavor::Freedesktop).await;
+    let mut registered = freedesktop.receive_signal("StatusNotifierItemRegistered").await.unwrap();
+
+    assert_eq!(kde.get_property::<i32>("ProtocolVersion").await.unwrap(), 0);
+    assert!(!kde.get_property::<bool>("IsStatusNotifierHostRegistered").await.unwrap());
+
+    let item = FakeItem::new("fake-network").spawn(&bus).await;
+    let entry = format!("{}/StatusNotifierItem", item.address());
+
+    let signal = tokio::time::timeout(TIMEOUT, registered.next()).await.unwrap().unwrap();
+    assert_eq!(signal.body::<String>().unwrap(), entry);
+
+    let items: Vec<String> = freedesktop.get_property("RegisteredStatusNotifierItems").await.unwrap();
+    assert_eq!(items, vec![entry.clone()]);
+    assert_eq!(watcher.items(), vec![entry]);
+
+    // Registering again is not a second item
+    kde.call_method("RegisterStatusNotifierItem", &(item.address(),)).await.unwrap();
+    assert_eq!(watcher.items().len(), 1);
+
+    watcher.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_watcher_forgets_items_and_hosts_that_leave() {
+    let Some(bus) = TestBus::start() else { return };
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let client = bus.connect().await;
+    let kde = watcher_proxy(&client, WatcherFlavor::Kde).await;
+    let mut unregistered = kde.receive_signal("StatusNotifierItemUnregistered").await.unwrap();
+    let mut host_gone = kde.receive_signal("StatusNotifierHostUnregistered").await.unwrap();
+
+    let host = bus.connect().await;
+    let host_name = "org.kde.StatusNotifierHost-test";
+    host.request_name(host_name).await.unwrap();
+    watcher_proxy(&host, WatcherFlavor::Kde)
+        .await
+        .call_method("RegisterStatusNotifierHost", &(host_name,))
+        .await
+        .unwrap();
+    assert!(kde.get_property::<bool>("IsStatusNotifierHostRegistered").await.unwrap());
+    assert_eq!(watcher.hosts(), vec![host_name.to_string()]);
+
+    let item = FakeItem::new("fake-volume").spawn(&bus).await;
+    let entry = format!("{}/StatusNotifierItem", item.address());
+    item.close().await;
+
+    let signal = tokio::time::timeout(TIMEOUT, unregistered.next()).await.unwrap().unwrap();
+    assert_eq!(signal.body::<String>().unwrap(), entry);
+    assert!(watcher.items().is_empty());
+
+    host.release_name(host_name).await.unwrap();
+    tokio::time::timeout(TIMEOUT, host_gone.next()).await.unwrap().unwrap();
+    let watcher = &watcher;
+    eventually(|| async move { !watcher.is_host_registered() }).await.expect("host still registered");
+
+    // Names nobody owns are refused
+    assert!(kde
+        .call_method("RegisterStatusNotifierItem", &("org.kde.StatusNotifierItem-gone",))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_watcher_does_not_replace_another() {
+    let Some(bus) = TestBus::start() else { return };
+    let first = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let connection = bus.connect().await;
+    let error = StatusNotifierWatcher::start(&connection).await.err().unwrap();
+    assert!(error.to_string().contains("already has an owner"), "{}", error);
+
+    // Once the first one stops the names are free again, and the failed
+    // attempt left nothing exported that would get in the way
+    first.stop().await.unwrap();
+    let second = StatusNotifierWatcher::start_with(&connection, &[WatcherFlavor::Freedesktop])
+        .await
+        .unwrap();
+    second.stop().await.unwrap();
+}
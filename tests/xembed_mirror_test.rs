@@ -0,0 +1,83 @@
+mod common;
+
+use common::x11::{press, type_keys, wait_for_menu, StandInManager, Xvfb};
+use common::{eventually, wait_for_item, FakeItem, FakeMenuEntry, ItemCall, TestBus};
+use system_tray_linux_aio::bridge::SniToXEmbed;
+use system_tray_linux_aio::stray_impl::StrayTrayApp;
+use system_tray_linux_aio::AppConfig;
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+
+fn window_name(conn: &RustConnection, window: u32) -> String {
+    let reply = conn
+        .get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, 1024)
+        .unwrap()
+        .reply()
+        .unwrap();
+    String::from_utf8(reply.value).unwrap()
+}
+
+#[tokio::test]
+async fn test_items_are_mirrored_in_the_xembed_tray() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let Some(bus) = TestBus::start() else { return };
+    let manager = StandInManager::start(&xvfb, false);
+    let host = StrayTrayApp::new(AppConfig::default()).spawn();
+    let item = FakeItem::new("fake-network")
+        .title("Network")
+        .menu(vec![
+            FakeMenuEntry::new(1, "Settings").submenu(vec![FakeMenuEntry::new(2, "Preferences")]),
+            FakeMenuEntry::separator(3),
+            FakeMenuEntry::new(4, "Quit"),
+        ])
+        .spawn(&bus)
+        .await;
+    wait_for_item(&host, "fake-network", |info| info.menu.is_some()).await;
+
+    let mirror = SniToXEmbed::start(Some(xvfb.display()), host.handle()).await.unwrap();
+    let mut window = None;
+    eventually(|| {
+        window = mirror.items().first().and_then(|item| item.window);
+        let docked = window.is_some_and(|window| manager.docked().contains(&window));
+        async move { docked }
+    })
+    .await
+    .expect("item docked");
+    let window = window.unwrap();
+    assert_eq!(mirror.items()[0].address, item.address());
+
+    let conn = xvfb.connect();
+    assert_eq!(window_name(&conn, window), "Network");
+
+    press(&conn, window, 1);
+    item.wait_for_call(ItemCall::Activate { x: 30, y: 5 }).await;
+
+    // The right button opens the item's menu; Down twice skips the
+    // separator and Return chooses Quit
+    press(&conn, window, 3);
+    let user = tokio::task::spawn_blocking(move || {
+        let menu = wait_for_menu(&conn, None);
+        type_keys(&conn, menu, &[0xff54, 0xff54, 0xff0d]);
+        conn
+    });
+    let conn = user.await.unwrap();
+    item.wait_for_call(ItemCall::MenuEvent { id: 4, event: "clicked".into() }).await;
+
+    item.set_title("Renamed").await;
+    eventually(|| async { window_name(&conn, window) == "Renamed" }).await.expect("tooltip updated");
+
+    item.close().await;
+    eventually(|| async { mirror.items().is_empty() }).await.expect("mirror removed");
+    assert!(conn.get_window_attributes(window).unwrap().reply().is_err(), "icon window destroyed");
+
+    mirror.stop().await.unwrap();
+    host.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_no_xembed_tray_is_an_error() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let host = StrayTrayApp::new(AppConfig::default()).spawn();
+    assert!(SniToXEmbed::start(Some(xvfb.display()), host.handle()).await.is_err());
+    host.stop().await.unwrap();
+}
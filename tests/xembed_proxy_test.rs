@@ -0,0 +1,92 @@
+mod common;
+
+use std::sync::mpsc;
+use std::time::Duration;
+use common::x11::Xvfb;
+use common::{eventually, TestBus};
+use image::{Rgba, RgbaImage};
+use system_tray_linux_aio::bridge::XEmbedToSni;
+use system_tray_linux_aio::tray::linux_impl::{LinuxTrayIcon, XEmbedEvent};
+use system_tray_linux_aio::tray::Image;
+use system_tray_linux_aio::watcher::StatusNotifierWatcher;
+use system_tray_linux_aio::AppConfig;
+
+type Pixmaps = Vec<(i32, i32, Vec<u8>)>;
+
+/// A red XEmbed icon docking on `display` from a thread of its own,
+/// reporting its events until `stop` is dropped
+fn spawn_client(display: String, events: mpsc::Sender<XEmbedEvent>, stop: mpsc::Receiver<()>) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut icon = LinuxTrayIcon::connect(Some(&display), AppConfig::default()).unwrap();
+        icon.set_tooltip("Legacy").unwrap();
+        let red = RgbaImage::from_pixel(24, 24, Rgba([255, 0, 0, 255]));
+        icon.set_icon(&Image::from_pixmaps(vec![red])).unwrap();
+        icon.dock().unwrap();
+
+        while let Err(mpsc::TryRecvError::Empty) = stop.try_recv() {
+            match icon.poll_event().unwrap() {
+                Some(event) => {
+                    let _ = events.send(event);
+                }
+                None => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    })
+}
+
+#[tokio::test]
+async fn test_docked_icons_become_items() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let Some(bus) = TestBus::start() else { return };
+    let watcher = StatusNotifierWatcher::start(&bus.connect().await).await.unwrap();
+    let proxy = XEmbedToSni::start(Some(xvfb.display()), Some(bus.address())).await.unwrap();
+
+    let (events, client_events) = mpsc::channel();
+    let (stop_client, stop) = mpsc::channel();
+    let client = spawn_client(xvfb.display().to_string(), events, stop);
+
+    eventually(|| async { watcher.items().len() == 1 }).await.expect("item registered");
+    let icons = proxy.icons();
+    assert_eq!(icons.len(), 1);
+    assert_eq!(icons[0].title, "Legacy");
+
+    let connection = bus.connect().await;
+    let item = zbus::Proxy::new(&connection, icons[0].address.clone(), "/StatusNotifierItem", "org.kde.StatusNotifierItem")
+        .await
+        .unwrap();
+    let title: String = item.get_property("Title").await.unwrap();
+    assert_eq!(title, "Legacy");
+
+    // Network order ARGB, captured from what the client drew
+    eventually(|| async {
+        let pixmaps: Pixmaps = item.get_property("IconPixmap").await.unwrap_or_default();
+        pixmaps.first().is_some_and(|(w, h, pixels)| (*w, *h) == (24, 24) && pixels[..4] == [255, 255, 0, 0])
+    })
+    .await
+    .expect("icon captured");
+
+    item.call_method("Activate", &(10i32, 20i32)).await.unwrap();
+    let mut clicked = false;
+    eventually(|| {
+        clicked |= client_events
+            .try_iter()
+            .any(|event| matches!(event, XEmbedEvent::Click { button: 1, x: 10, y: 20 }));
+        let found = clicked;
+        async move { found }
+    })
+    .await
+    .expect("click forwarded");
+
+    assert!(
+        XEmbedToSni::start(Some(xvfb.display()), Some(bus.address())).await.is_err(),
+        "the tray already has a manager"
+    );
+
+    drop(stop_client);
+    client.join().unwrap();
+    eventually(|| async { watcher.items().is_empty() }).await.expect("item closed");
+    assert!(proxy.icons().is_empty());
+
+    proxy.stop().await.unwrap();
+    watcher.stop().await.unwrap();
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use std::time::{Duration, Instant};
+use common::x11::{press, type_keys, wait_for_menu, StandInManager, Xvfb, ICON_SIZE};
+use common::{skip, TIMEOUT};
+use image::{Rgba, RgbaImage};
+use system_tray_linux_aio::stray_impl::ScrollOrientation;
+use system_tray_linux_aio::tray::linux_impl::{LinuxTrayIcon, XEmbedEvent, SCROLL_STEP};
+use system_tray_linux_aio::backend::XEmbedBackend;
+use system_tray_linux_aio::tray::{Image, PopupMenu, Rectangle, ScreenEdge, SystemTrayIconComponent, TrayEvent};
+use system_tray_linux_aio::{AppConfig, MenuAction, TrayIcon};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    ButtonPressEvent, ConfigureWindowAux, ConnectionExt as _, EventMask, ImageFormat, KeyButMask, BUTTON_PRESS_EVENT,
+};
+
+/// Pump the icon until it reports an event matching `matches`
+fn wait_for(icon: &mut LinuxTrayIcon, matches: impl Fn(&XEmbedEvent) -> bool) -> XEmbedEvent {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match icon.poll_event().unwrap() {
+            Some(event) if matches(&event) => return event,
+            Some(_) => continue,
+            None => {
+                assert!(Instant::now() < deadline, "no matching tray icon event in time");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+fn docked_icon(xvfb: &Xvfb, manager: &StandInManager) -> LinuxTrayIcon {
+    let mut icon = LinuxTrayIcon::connect(Some(xvfb.display()), AppConfig::default()).unwrap();
+    icon.dock().unwrap();
+    manager.wait_for_dock(icon.window());
+    wait_for(&mut icon, |event| matches!(event, XEmbedEvent::Embedded { .. }));
+    icon
+}
+
+#[test]
+fn test_icon_docks_with_the_manager() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let manager = StandInManager::start(&xvfb, false);
+    let icon = docked_icon(&xvfb, &manager);
+
+    assert!(icon.is_embedded());
+    assert_eq!(icon.manager(), Some(manager.window()));
+    assert_eq!(manager.docked(), vec![icon.window()]);
+    assert!(!icon.is_argb());
+    assert_eq!(icon.size(), (ICON_SIZE, ICON_SIZE));
+}
+
+#[test]
+fn test_no_manager_is_an_error() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let mut icon = LinuxTrayIcon::connect(Some(xvfb.display()), AppConfig::default()).unwrap();
+    assert!(icon.dock().is_err());
+}
+
+#[test]
+fn test_buttons_and_wheel_reach_the_icon() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let manager = StandInManager::start(&xvfb, false);
+    let mut icon = docked_icon(&xvfb, &manager);
+    let conn = xvfb.connect();
+
+    press(&conn, icon.window(), 1);
+    let event = wait_for(&mut icon, |event| matches!(event, XEmbedEvent::Click { .. }));
+    assert_eq!(event, XEmbedEvent::Click { button: 1, x: 30, y: 5 });
+
+    press(&conn, icon.window(), 5);
+    let event = wait_for(&mut icon, |event| matches!(event, XEmbedEvent::Scroll { .. }));
+    assert_eq!(event, XEmbedEvent::Scroll { delta: -SCROLL_STEP, orientation: ScrollOrientation::Vertical });
+}
+
+#[test]
+fn test_argb_icon_keeps_its_alpha() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let manager = StandInManager::start(&xvfb, true);
+    let mut icon = docked_icon(&xvfb, &manager);
+    if !icon.is_argb() {
+        skip("the X server has no 32 bit visual");
+        return;
+    }
+
+    let red = RgbaImage::from_pixel(ICON_SIZE as u32, ICON_SIZE as u32, Rgba([255, 0, 0, 128]));
+    icon.set_icon(&Image::from_pixmaps(vec![red])).unwrap();
+
+    let conn = xvfb.connect();
+    let pixel = conn
+        .get_image(ImageFormat::Z_PIXMAP, icon.window(), 0, 0, 1, 1, !0)
+        .unwrap()
+        .reply()
+        .unwrap()
+        .data;
+    // Premultiplied BGRA on a little endian server
+    assert_eq!(pixel, vec![0, 0, 128, 128]);
+}
+
+#[test]
+fn test_icon_docks_again_after_a_manager_restart() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let manager = StandInManager::start(&xvfb, false);
+    let mut icon = docked_icon(&xvfb, &manager);
+
+    manager.kill();
+    wait_for(&mut icon, |event| *event == XEmbedEvent::ManagerLost);
+    assert!(!icon.is_embedded());
+
+    let manager = StandInManager::start(&xvfb, false);
+    wait_for(&mut icon, |event| *event == XEmbedEvent::ManagerRestored);
+    manager.wait_for_dock(icon.window());
+    wait_for(&mut icon, |event| matches!(event, XEmbedEvent::Embedded { .. }));
+    assert_eq!(icon.manager(), Some(manager.window()));
+}
+
+#[test]
+fn test_destroyed_icon_window_is_docked_again() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let manager = StandInManager::start(&xvfb, false);
+    let mut icon = docked_icon(&xvfb, &manager);
+    let first = icon.window();
+
+    // A manager that forgets the save-set takes the icon window down with it
+    let conn = xvfb.connect();
+    conn.destroy_window(first).unwrap();
+    conn.flush().unwrap();
+
+    wait_for(&mut icon, |event| matches!(event, XEmbedEvent::Embedded { .. }));
+    assert_ne!(icon.window(), first);
+    manager.wait_for_dock(icon.window());
+    assert_eq!(icon.manager(), Some(manager.window()));
+}
+
+#[test]
+fn test_menu_is_drawn_and_driven_from_the_keyboard() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let mut icon = LinuxTrayIcon::connect(Some(xvfb.display()), AppConfig::default()).unwrap();
+    let mut menu = PopupMenu::new();
+    menu.add_item(1, "Pause", true, false);
+    menu.add_item(2, "Quit", true, false);
+    let anchor = Rectangle::new(0, 0, ICON_SIZE as i32, ICON_SIZE as i32);
+
+    let user = xvfb.connect();
+    let typing = std::thread::spawn(move || {
+        // Down twice and Return choose the second entry
+        let first = wait_for_menu(&user, None);
+        type_keys(&user, first, &[0xff54, 0xff54, 0xff0d]);
+
+        // A click away from the menu closes it
+        let window = wait_for_menu(&user, Some(first));
+        let event = ButtonPressEvent {
+            response_type: BUTTON_PRESS_EVENT,
+            detail: 1,
+            sequence: 0,
+            time: 0,
+            root: user.setup().roots[0].root,
+            event: window,
+            child: 0,
+            root_x: 600,
+            root_y: 400,
+            event_x: 600,
+            event_y: 400,
+            state: KeyButMask::default(),
+            same_screen: true,
+        };
+        user.send_event(false, window, EventMask::BUTTON_PRESS, event).unwrap();
+        user.flush().unwrap();
+    });
+
+    assert_eq!(icon.show_menu(&menu, anchor).unwrap(), Some(2));
+    assert_eq!(icon.show_menu(&menu, anchor).unwrap(), None);
+    typing.join().unwrap();
+}
+
+#[tokio::test]
+async fn test_tray_icon_menu_is_drawn_on_xembed_trays() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let _manager = StandInManager::start(&xvfb, false);
+    let config = AppConfig::default();
+    let backend = XEmbedBackend::connect(Some(xvfb.display()), &config).unwrap();
+    let component = SystemTrayIconComponent::with_backend(Box::new(backend));
+    let mut icon = TrayIcon::with_component(config, component).await.unwrap();
+
+    let user = xvfb.connect();
+    let typing = std::thread::spawn(move || {
+        // About, Settings, Quit: down three times and Return choose Quit
+        let menu = wait_for_menu(&user, None);
+        type_keys(&user, menu, &[0xff54, 0xff54, 0xff54, 0xff0d]);
+    });
+
+    icon.show_menu();
+    typing.join().unwrap();
+    assert_eq!(icon.poll_event(), Some(TrayEvent::MenuItem(3)));
+    assert_eq!(icon.menu_action(3).await.unwrap(), Some(MenuAction::Quit));
+}
+
+#[test]
+fn test_menu_closer_dismisses_an_open_menu() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let mut icon = LinuxTrayIcon::connect(Some(xvfb.display()), AppConfig::default()).unwrap();
+    let mut menu = PopupMenu::new();
+    menu.add_item(1, "Quit", true, false);
+    let anchor = Rectangle::new(0, 0, ICON_SIZE as i32, ICON_SIZE as i32);
+
+    let closer = icon.menu_closer().unwrap();
+    let user = xvfb.connect();
+    let closing = std::thread::spawn(move || {
+        wait_for_menu(&user, None);
+        closer.close().unwrap();
+        closer
+    });
+
+    assert_eq!(icon.show_menu(&menu, anchor).unwrap(), None);
+    let _closer = closing.join().unwrap();
+    // Menus asked for after closing are not shown at all
+    assert_eq!(icon.show_menu(&menu, anchor).unwrap(), None);
+}
+
+#[test]
+fn test_bounds_follow_the_panel() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let manager = StandInManager::start(&xvfb, false);
+    let icon = docked_icon(&xvfb, &manager);
+    let size = ICON_SIZE as i32;
+    assert_eq!(icon.bounds().unwrap(), Rectangle::new(0, 0, size, size));
+
+    // Moving the panel to the bottom moves the icon without telling it
+    let conn = xvfb.connect();
+    conn.configure_window(manager.window(), &ConfigureWindowAux::new().x(300).y(480 - ICON_SIZE as i32)).unwrap();
+    conn.get_input_focus().unwrap().reply().unwrap();
+    let bounds = icon.bounds().unwrap();
+    assert_eq!(bounds, Rectangle::new(300, 480 - size, size, size));
+
+    let screen = icon.screen_bounds();
+    assert_eq!(screen, Rectangle::new(0, 0, 640, 480));
+    assert_eq!(bounds.nearest_edge(&screen), ScreenEdge::Bottom);
+}